@@ -0,0 +1,243 @@
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Cursor, IsTerminal, Read, Write};
+use std::process::ExitCode;
+use luar::{parse, vm::ExeState};
+use luar::value::{Value, Table};
+use luar::error::{Error, Result};
+
+const PROGNAME: &str = "luar";
+
+fn usage() {
+    eprintln!("usage: {PROGNAME} [options] [script [args]]
+Available options are:
+  -e stat   execute string 'stat'
+  -i        enter interactive mode after executing 'script'
+  -l mod    require library 'mod' into global 'mod'
+  -v        show version information
+  --        stop handling options
+  -         stop handling options and execute stdin");
+}
+
+fn version() {
+    println!("Luar {}", env!("CARGO_PKG_VERSION"));
+}
+
+// print error message and traceback to stderr
+fn report(err: &Error) {
+    match err {
+        // the position has been added into the error object
+        Error::Value { msg, .. } => eprintln!("{PROGNAME}: {msg}"),
+        _ => eprintln!("{PROGNAME}: {err}"),
+    }
+    if !err.traceback().frames.is_empty() {
+        eprintln!("{}", err.traceback());
+    }
+}
+
+// call the main chunk with @args, return all results
+fn do_chunk(state: &mut ExeState, proto: parse::FuncProto, args: &[Value]) -> Result<Vec<Value>> {
+    let f = state.new_chunk(proto, None);
+    state.call_multi(f, args)
+}
+
+// load chunk from file or stdin("-"). Skip the first line if it
+// starts with '#'
+fn load_file(fname: &str) -> Result<parse::FuncProto> {
+    let (mut src, chunkname) = if fname == "-" {
+        let mut src = Vec::new();
+        io::stdin().read_to_end(&mut src)
+            .map_err(|e| Error::runtime(format!("cannot read stdin: {e}")))?;
+        (src, "stdin")
+    } else {
+        let src = fs::read(fname)
+            .map_err(|e| Error::runtime(format!("cannot open {fname}: {e}")))?;
+        (src, fname)
+    };
+    parse::skip_comment(&mut src);
+    parse::load_with_name(Cursor::new(src), chunkname)
+        .map_err(|e| chunk_error(chunkname, e))
+}
+
+// syntax errors carry only the line, so add the chunk name before it,
+// as "chunkname:line: msg"
+fn chunk_error(chunkname: &str, err: Error) -> Error {
+    match err {
+        Error::Syntax { line, .. } if line > 0 => Error::syntax(format!("{chunkname}:{err}"), 0),
+        err => err,
+    }
+}
+
+fn do_string(state: &mut ExeState, s: &str) -> Result<()> {
+    let proto = parse::load_with_name(Cursor::new(s.to_string()), "(command line)")
+        .map_err(|e| chunk_error("(command line)", e))?;
+    do_chunk(state, proto, &[])?;
+    Ok(())
+}
+
+// search 'mod.lua' (with '.' in name as directory separator) in the
+// current directory, and set its result to global 'mod'
+fn do_library(state: &mut ExeState, name: &str) -> Result<()> {
+    let fname = format!("{}.lua", name.replace('.', "/"));
+    let proto = load_file(&fname)?;
+    let v = match do_chunk(state, proto, &[name.into()])?.into_iter().next() {
+        None | Some(Value::Nil) => Value::Boolean(true),
+        Some(v) => v,
+    };
+    state.set_global(name, v);
+    Ok(())
+}
+
+// global 'arg': script name at index 0, script arguments at positive
+// indexes, and interpreter name and options at negative indexes
+fn create_arg_table(state: &mut ExeState, argv: &[String], script: usize) {
+    let mut t = Table::new(argv.len(), 0);
+    for (i, s) in argv.iter().enumerate() {
+        t.new_index(Value::Integer(i as i64 - script as i64), s.as_str().into());
+    }
+    let t = Value::Table(state.heap().alloc_cell(t));
+    state.set_global("arg", t);
+}
+
+fn read_line(prompt: &str) -> Option<String> {
+    print!("{prompt}");
+    io::stdout().flush().ok()?;
+    let mut line = String::new();
+    match io::stdin().lock().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim_end_matches(['\n', '\r']).to_string()),
+    }
+}
+
+// read a complete chunk. Try it as an expression first to print its
+// value, as "return <line>"; a leading '=' is accepted for the same
+// purpose. Otherwise as a statement, reading more lines if incomplete.
+fn load_line() -> Option<Result<parse::FuncProto>> {
+    let line = read_line("> ")?;
+    let line = line.strip_prefix('=').unwrap_or(&line);
+    let load = |src: &str| parse::load_with_name(Cursor::new(src.to_string()), "stdin");
+
+    if let Ok(proto) = load(&format!("return {line}")) {
+        return Some(Ok(proto));
+    }
+    let mut src = line.to_string();
+    loop {
+        match load(&src) {
+            Err(err) if err.is_eof() => match read_line(">> ") {
+                Some(more) => {
+                    src.push('\n');
+                    src.push_str(&more);
+                }
+                None => return Some(Err(chunk_error("stdin", err))),
+            }
+            r => return Some(r.map_err(|e| chunk_error("stdin", e))),
+        }
+    }
+}
+
+fn repl(state: &mut ExeState) {
+    while let Some(proto) = load_line() {
+        let rets = proto.and_then(|proto| do_chunk(state, proto, &[]))
+            .and_then(|rets| rets.iter().map(|v| state.tostring(v)).collect::<Result<Vec<_>>>());
+        match rets {
+            Ok(rets) if rets.is_empty() => (),
+            Ok(rets) => {
+                let rets: Vec<String> = rets.iter().map(|v| v.to_string()).collect();
+                println!("{}", rets.join("\t"));
+            }
+            Err(err) => report(&err),
+        }
+    }
+    println!();
+}
+
+fn main() -> ExitCode {
+    let argv: Vec<String> = env::args().collect();
+
+    // scan options
+    let mut has_e = false;
+    let mut has_i = false;
+    let mut has_v = false;
+    let mut script = argv.len();
+    let mut i = 1;
+    while i < argv.len() {
+        match argv[i].as_str() {
+            "--" => {
+                script = i + 1;
+                break;
+            }
+            "-" => {
+                script = i;
+                break;
+            }
+            "-e" | "-l" => {
+                if i + 1 == argv.len() {
+                    eprintln!("{PROGNAME}: '{}' needs argument", argv[i]);
+                    usage();
+                    return ExitCode::FAILURE;
+                }
+                has_e |= argv[i] == "-e";
+                i += 1;
+            }
+            "-i" => {
+                has_i = true;
+                has_v = true;
+            }
+            "-v" => has_v = true,
+            opt if opt.starts_with('-') => {
+                eprintln!("{PROGNAME}: unrecognized option '{opt}'");
+                usage();
+                return ExitCode::FAILURE;
+            }
+            _ => {
+                script = i;
+                break;
+            }
+        }
+        i += 1;
+    }
+    let script = script.min(argv.len());
+
+    let mut state = ExeState::new();
+    if has_v {
+        version();
+    }
+    create_arg_table(&mut state, &argv, script);
+
+    // run -e and -l in order
+    let mut i = 1;
+    while i < script {
+        let r = match argv[i].as_str() {
+            "-e" => do_string(&mut state, &argv[i + 1]),
+            "-l" => do_library(&mut state, &argv[i + 1]),
+            _ => Ok(()),
+        };
+        if let Err(err) = r {
+            report(&err);
+            return ExitCode::FAILURE;
+        }
+        i += if matches!(argv[i].as_str(), "-e" | "-l") { 2 } else { 1 };
+    }
+
+    if script < argv.len() {
+        let args: Vec<Value> = argv[script + 1 ..].iter().map(|s| s.as_str().into()).collect();
+        let r = load_file(&argv[script]).and_then(|proto| do_chunk(&mut state, proto, &args));
+        if let Err(err) = r {
+            report(&err);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    if has_i {
+        repl(&mut state);
+    } else if script == argv.len() && !has_e && !has_v {
+        if io::stdin().is_terminal() {
+            version();
+            repl(&mut state);
+        } else if let Err(err) = load_file("-").and_then(|proto| do_chunk(&mut state, proto, &[])) {
+            report(&err);
+            return ExitCode::FAILURE;
+        }
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,222 @@
+use std::env;
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::process::ExitCode;
+use luar::parse::{self, FuncProto, UpIndex};
+use luar::bytecode::ByteCode;
+use luar::value::Value;
+
+const PROGNAME: &str = "luarc";
+
+fn usage() {
+    eprintln!("usage: {PROGNAME} [options] [filenames]
+Available options are:
+  -l       list (default if no -o)
+  -o name  output binary chunk to file 'name'
+  -p       parse only
+  -s       strip debug information
+  --       stop handling options
+  -        stop handling options and process stdin");
+}
+
+fn load(fname: &str) -> Result<FuncProto, String> {
+    let (src, chunkname) = if fname == "-" {
+        let mut src = Vec::new();
+        io::stdin().read_to_end(&mut src).map_err(|e| format!("cannot read stdin: {e}"))?;
+        (src, "stdin")
+    } else {
+        let src = fs::read(fname).map_err(|e| format!("cannot open {fname}: {e}"))?;
+        (src, fname)
+    };
+    parse::load_with_name(Cursor::new(src), chunkname)
+        .map_err(|e| format!("{chunkname}:{e}"))
+}
+
+fn dump(proto: &FuncProto, output: &str, strip: bool) -> Result<(), String> {
+    let file = fs::File::create(output).map_err(|e| format!("cannot open {output}: {e}"))?;
+    proto.dump(file, strip).map_err(|e| format!("{output}: {e}"))
+}
+
+// "main <file:0>" or "function <file:12> name"
+fn func_header(proto: &FuncProto) -> String {
+    if proto.linedefined == 0 {
+        format!("main <{}:0>", proto.source)
+    } else if proto.name.is_empty() {
+        format!("function <{}:{}>", proto.source, proto.linedefined)
+    } else {
+        format!("function <{}:{}> {}", proto.source, proto.linedefined, proto.name)
+    }
+}
+
+// the absolute target of jumps, since byte codes are relative
+fn jump_target(pc: usize, jmp: isize) -> String {
+    format!("to {}", pc as isize + 1 + jmp)
+}
+
+// constant values and jump targets in byte code
+fn comment(proto: &FuncProto, pc: usize, code: &ByteCode) -> Option<String> {
+    let k = |i: u8| format!("{:?}", proto.constants[i as usize]);
+    let s = match *code {
+        ByteCode::LoadConst(_, i) => format!("{:?}", proto.constants[i as usize]),
+        ByteCode::Closure(_, i) | ByteCode::ExtraArg(i) => match &proto.constants[i as usize] {
+            Value::LuaFunction(f) => func_header(f),
+            v => format!("{v:?}"),
+        },
+
+        ByteCode::GetField(_, _, i) | ByteCode::GetFieldSelf(_, _, i) |
+        ByteCode::GetUpField(_, _, i) |
+        ByteCode::SetField(_, i, _) | ByteCode::SetUpField(_, i, _) => k(i),
+
+        ByteCode::SetFieldConst(_, i, v) | ByteCode::SetUpFieldConst(_, i, v) =>
+            format!("{} {}", k(i), k(v)),
+        ByteCode::SetTableConst(_, _, v) | ByteCode::SetIntConst(_, _, v) => k(v),
+
+        ByteCode::AddConst(_, _, i) | ByteCode::SubConst(_, _, i) |
+        ByteCode::MulConst(_, _, i) | ByteCode::ModConst(_, _, i) |
+        ByteCode::DivConst(_, _, i) | ByteCode::IdivConst(_, _, i) |
+        ByteCode::PowConst(_, _, i) | ByteCode::BitAndConst(_, _, i) |
+        ByteCode::BitXorConst(_, _, i) | ByteCode::BitOrConst(_, _, i) |
+        ByteCode::ShiftLConst(_, _, i) | ByteCode::ShiftRConst(_, _, i) => k(i),
+
+        ByteCode::EqualConst(_, i, _) | ByteCode::NotEqConst(_, i, _) |
+        ByteCode::LesEqConst(_, i, _) | ByteCode::GreEqConst(_, i, _) |
+        ByteCode::LessConst(_, i, _) | ByteCode::GreaterConst(_, i, _) => k(i),
+
+        ByteCode::Jump(jmp) => jump_target(pc, jmp as isize),
+        ByteCode::ForPrepare(_, jmp) => jump_target(pc, jmp as isize),
+        ByteCode::ForLoop(_, jmp) => jump_target(pc, -(jmp as isize)),
+        ByteCode::ForCallLoop(_, _, jmp) if jmp > 0 => jump_target(pc, -(jmp as isize)),
+        _ => return None,
+    };
+    Some(s)
+}
+
+// "LoadConst(0, 1)" => "LoadConst       0 1"
+fn format_code(code: &ByteCode) -> String {
+    let s = format!("{code:?}");
+    match s.split_once('(') {
+        Some((name, args)) => {
+            let args = args.trim_end_matches(')').replace(", ", " ");
+            format!("{name:<16}{args}")
+        }
+        None => s,
+    }
+}
+
+// list the function and its nested functions, like "luac -l -l"
+fn list(proto: &FuncProto) {
+    let nfunc = proto.constants.iter()
+        .filter(|v| matches!(v, Value::LuaFunction(_)))
+        .count();
+
+    println!("\n{} ({} instructions)", func_header(proto), proto.byte_codes.len());
+    println!("{}{} params, {} upvalues, {} constants, {} functions",
+        proto.nparam, if proto.has_varargs { "+" } else { "" },
+        proto.upindexes.len(), proto.constants.len(), nfunc);
+
+    for (pc, code) in proto.byte_codes.iter().enumerate() {
+        let line = proto.lineinfo.get(pc).copied().unwrap_or(0);
+        // the listed functions are parsed or checked by undump()
+        let Some(code) = ByteCode::decode(*code) else {
+            println!("\t{pc}\t[{line}]\tinvalid {code:#010x}");
+            continue;
+        };
+        let code_str = format_code(&code);
+        match comment(proto, pc, &code) {
+            Some(c) => println!("\t{pc}\t[{line}]\t{code_str:<28}; {c}"),
+            None => println!("\t{pc}\t[{line}]\t{code_str}"),
+        }
+    }
+
+    println!("constants ({}):", proto.constants.len());
+    for (i, v) in proto.constants.iter().enumerate() {
+        match v {
+            Value::LuaFunction(f) => println!("\t{i}\t{}", func_header(f)),
+            _ => println!("\t{i}\t{:?}", v),
+        }
+    }
+
+    println!("upvalues ({}):", proto.upindexes.len());
+    for (i, up) in proto.upindexes.iter().enumerate() {
+        match up {
+            UpIndex::Local(ilocal) => println!("\t{i}\tlocal\t{ilocal}"),
+            UpIndex::Upvalue(iup) => println!("\t{i}\tupvalue\t{iup}"),
+        }
+    }
+
+    for v in proto.constants.iter() {
+        if let Value::LuaFunction(f) = v {
+            list(f);
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let argv: Vec<String> = env::args().collect();
+
+    let mut listing = None;
+    let mut output = None;
+    let mut strip = false;
+    let mut files = Vec::new();
+    let mut i = 1;
+    while i < argv.len() {
+        match argv[i].as_str() {
+            "--" => {
+                files.extend_from_slice(&argv[i+1..]);
+                break;
+            }
+            "-l" => listing = Some(true),
+            "-p" => listing = Some(false),
+            "-s" => strip = true,
+            "-o" => {
+                i += 1;
+                match argv.get(i) {
+                    Some(fname) => output = Some(fname.clone()),
+                    None => {
+                        eprintln!("{PROGNAME}: '-o' needs argument");
+                        usage();
+                        return ExitCode::FAILURE;
+                    }
+                }
+            }
+            "-" => files.push(argv[i].clone()),
+            opt if opt.starts_with('-') => {
+                eprintln!("{PROGNAME}: unrecognized option '{opt}'");
+                usage();
+                return ExitCode::FAILURE;
+            }
+            fname => files.push(fname.to_string()),
+        }
+        i += 1;
+    }
+    if files.is_empty() {
+        eprintln!("{PROGNAME}: no input files given");
+        usage();
+        return ExitCode::FAILURE;
+    }
+    if output.is_some() && files.len() > 1 {
+        eprintln!("{PROGNAME}: '-o' needs exactly one input file");
+        return ExitCode::FAILURE;
+    }
+    let listing = listing.unwrap_or(output.is_none());
+
+    for fname in &files {
+        let proto = match load(fname) {
+            Ok(proto) => proto,
+            Err(msg) => {
+                eprintln!("{PROGNAME}: {msg}");
+                return ExitCode::FAILURE;
+            }
+        };
+        if listing {
+            list(&proto);
+        }
+        if let Some(output) = &output {
+            if let Err(msg) = dump(&proto, output, strip) {
+                eprintln!("{PROGNAME}: {msg}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    ExitCode::SUCCESS
+}
@@ -1,189 +1,248 @@
-// #[derive(Debug)]
-// pub enum ByteCode {
-//     /// ## GetGlobal(dst, name_idx)
-//     /// 
-//     /// GetGlobal will use name_idx to search name in constants table
-//     /// then use name to get Value in globals, finally, push the value to 
-//     /// stack 
-//     ///  
-//     /// - dst: target index in stack  
-//     /// - name_idx: index of Global's name in constants table
-//     GetGlobal(u8, u8),
-
-//     /// ## SetGlobal(key_idx, src)
-//     /// 
-//     /// SetGlobal will use key_idx to search key name in constants table, then 
-//     /// `clone` the Value at stack\[src\], and set to Globals table with given 
-//     /// name({key:stack\[src\]})
-//     ///  
-//     /// - key_idx: index of name in constants table
-//     /// - src: index of value which will be add to Globals table 
-//     SetGlobal(u8, u8),
-
-//     /// ## SetGlobalConst(key_idx, src)
-//     /// 
-//     /// SetGlobalConst will use key_idx to search key name in constants table, then 
-//     /// `clone` the Value at stack\[src\], and set to Globals table with given 
-//     /// name({key:stack\[src\]})
-//     ///  
-//     /// - key_idx: index of name in constants table
-//     /// - src: index of value which will be add to Globals table 
-//     SetGlobalConst(u8, u8),
-
-//     /// ## SetGlobalGlobal(name_idx, src_idx)
-//     /// 
-//     /// SetGlobalGloabl assign a global value to a global value
-//     /// 
-//     /// - name_idx: index of Value::String(new_global_name) in constants table
-//     /// - src_idx: index of Value::String(source_global_name) in constants table
-//     SetGlobalGlobal(u8, u8),
-
-//     /// ## LoadConst(dst, const_idx)
-//     /// 
-//     /// LoadConst push the Value at constants table\[const_idx\] to stack[dst]
-//     LoadConst(u8, u16),
-
-//     /// ## LoadNil(dst)
-//     /// 
-//     /// LoadNil will push a Nil to stack[dst]
-//     LoadNil(u8),
-
-//     /// ## LoadBool(dst,b)
-//     /// 
-//     /// LoadBool will push a boolean value b to stack
-//     LoadBool(u8, bool),
-//     LoadInt(u8, i16),
-
-//     /// ## Move(dst,src)
-//     /// 
-//     /// Move will Clone the Value at stack[src] and push to stack[dst]
-//     Move(u8, u8),
-
-//     /// ## Call(func_idx,_) //TODO ignore the second params now
-//     /// 
-//     /// Call the function at stack[func_index]
-//     Call(u8, u8),
-// }  
-
-#[derive(Debug)]
-pub enum ByteCode {
+// Packed 32-bit instruction, following Lua 5.4:
+//
+//   31       24 23      16  15  14       7 6      0
+//  |     C     |    B     | k |     A     |   op   |  iABC
+//  |          Bx              |     A     |   op   |  iABx
+//  |          sBx             |     A     |   op   |  iAsBx
+//  |                 Ax/sJ                |   op   |  iAx/isJ
+//
+// The signed sBx and sJ are stored with an offset of half their range.
+pub type Instruction = u32;
+
+const POS_A: u32 = 7;
+const POS_K: u32 = 15;
+const POS_B: u32 = 16;
+const POS_C: u32 = 24;
+const POS_BX: u32 = 15;
+const MASK_OP: u32 = (1 << 7) - 1;
+
+pub const MAX_BX: u32 = (1 << 17) - 1;
+pub const MAX_AX: u32 = (1 << 25) - 1;
+const OFFSET_SBX: i32 = (MAX_BX >> 1) as i32;
+const OFFSET_SJ: i32 = (MAX_AX >> 1) as i32;
+
+// fields of Instruction, also used by the VM which dispatches on the
+// opcode without decoding into ByteCode
+pub(crate) fn get_op(code: u32) -> u32 { code & MASK_OP }
+pub(crate) fn arg_a(code: u32) -> u8 { (code >> POS_A) as u8 }
+pub(crate) fn arg_b(code: u32) -> u8 { (code >> POS_B) as u8 }
+pub(crate) fn arg_c(code: u32) -> u8 { (code >> POS_C) as u8 }
+pub(crate) fn arg_k(code: u32) -> bool { code & (1 << POS_K) != 0 }
+pub(crate) fn arg_bx(code: u32) -> u32 { code >> POS_BX }
+pub(crate) fn arg_sbx(code: u32) -> i16 { ((code >> POS_BX) as i32 - OFFSET_SBX) as i16 }
+pub(crate) fn arg_ax(code: u32) -> u32 { code >> POS_A }
+pub(crate) fn arg_sj(code: u32) -> i32 { (code >> POS_A) as i32 - OFFSET_SJ }
+
+fn fit_sj(sj: i32) -> Option<u32> {
+    sj.checked_add(OFFSET_SJ)
+        .and_then(|n| u32::try_from(n).ok())
+        .filter(|&n| n <= MAX_AX)
+}
+
+// pattern, encoder and decoder of each instruction format
+macro_rules! bc_format {
+    (pat ABC, $name:ident, $a:ident, $b:ident, $c:ident) => { ByteCode::$name($a, $b, $c) };
+    (pat ABk, $name:ident, $a:ident, $b:ident, $c:ident) => { ByteCode::$name($a, $b, $c) };
+    (pat AB, $name:ident, $a:ident, $b:ident, $c:ident) => { ByteCode::$name($a, $b) };
+    (pat Ak, $name:ident, $a:ident, $b:ident, $c:ident) => { ByteCode::$name($a, $b) };
+    (pat A, $name:ident, $a:ident, $b:ident, $c:ident) => { ByteCode::$name($a) };
+    (pat ABx, $name:ident, $a:ident, $b:ident, $c:ident) => { ByteCode::$name($a, $b) };
+    (pat AsBx, $name:ident, $a:ident, $b:ident, $c:ident) => { ByteCode::$name($a, $b) };
+    (pat Ax, $name:ident, $a:ident, $b:ident, $c:ident) => { ByteCode::$name($a) };
+    (pat sJ, $name:ident, $a:ident, $b:ident, $c:ident) => { ByteCode::$name($a) };
+    (pat N, $name:ident, $a:ident, $b:ident, $c:ident) => { ByteCode::$name };
+
+    (enc ABC, $op:expr, $a:ident, $b:ident, $c:ident) =>
+        { Some($op | ($a as u32) << POS_A | ($b as u32) << POS_B | ($c as u32) << POS_C) };
+    (enc ABk, $op:expr, $a:ident, $b:ident, $c:ident) =>
+        { Some($op | ($a as u32) << POS_A | ($b as u32) << POS_B | ($c as u32) << POS_K) };
+    (enc AB, $op:expr, $a:ident, $b:ident, $c:ident) =>
+        { Some($op | ($a as u32) << POS_A | ($b as u32) << POS_B) };
+    (enc Ak, $op:expr, $a:ident, $b:ident, $c:ident) =>
+        { Some($op | ($a as u32) << POS_A | ($b as u32) << POS_K) };
+    (enc A, $op:expr, $a:ident, $b:ident, $c:ident) =>
+        { Some($op | ($a as u32) << POS_A) };
+    (enc ABx, $op:expr, $a:ident, $b:ident, $c:ident) =>
+        { ($b <= MAX_BX).then(|| $op | ($a as u32) << POS_A | $b << POS_BX) };
+    (enc AsBx, $op:expr, $a:ident, $b:ident, $c:ident) =>
+        { Some($op | ($a as u32) << POS_A | (($b as i32 + OFFSET_SBX) as u32) << POS_BX) };
+    (enc Ax, $op:expr, $a:ident, $b:ident, $c:ident) =>
+        { ($a <= MAX_AX).then(|| $op | $a << POS_A) };
+    (enc sJ, $op:expr, $a:ident, $b:ident, $c:ident) =>
+        { fit_sj($a).map(|n| $op | n << POS_A) };
+    (enc N, $op:expr, $a:ident, $b:ident, $c:ident) =>
+        { Some($op) };
+
+    (dec ABC, $name:ident, $code:ident) => { ByteCode::$name(arg_a($code), arg_b($code), arg_c($code)) };
+    (dec ABk, $name:ident, $code:ident) => { ByteCode::$name(arg_a($code), arg_b($code), arg_k($code)) };
+    (dec AB, $name:ident, $code:ident) => { ByteCode::$name(arg_a($code), arg_b($code)) };
+    (dec Ak, $name:ident, $code:ident) => { ByteCode::$name(arg_a($code), arg_k($code)) };
+    (dec A, $name:ident, $code:ident) => { ByteCode::$name(arg_a($code)) };
+    (dec ABx, $name:ident, $code:ident) => { ByteCode::$name(arg_a($code), arg_bx($code)) };
+    (dec AsBx, $name:ident, $code:ident) => { ByteCode::$name(arg_a($code), arg_sbx($code)) };
+    (dec Ax, $name:ident, $code:ident) => { ByteCode::$name(arg_ax($code)) };
+    (dec sJ, $name:ident, $code:ident) => { ByteCode::$name(arg_sj($code)) };
+    (dec N, $name:ident, $code:ident) => { ByteCode::$name };
+}
+
+// define ByteCode, and its opcodes in order, encoder and decoder
+macro_rules! define_bytecode {
+    ($( $name:ident $( ( $($ty:ty),+ ) )? = $fmt:ident, )+) => {
+        // The decoded view of Instruction, used by the parser and tools.
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        pub enum ByteCode {
+            $( $name $( ( $($ty),+ ) )?, )+
+        }
+
+        #[allow(non_upper_case_globals)]
+        pub(crate) mod opcode {
+            enum OpCode { $( $name, )+ }
+            $( pub const $name: u32 = OpCode::$name as u32; )+
+        }
+
+        impl ByteCode {
+            // return None if any argument is out of the range of its field
+            pub fn encode(self) -> Option<Instruction> {
+                match self {
+                    $( bc_format!(pat $fmt, $name, a, b, c) => bc_format!(enc $fmt, opcode::$name, a, b, c), )+
+                }
+            }
+
+            // return None if the opcode is invalid
+            pub fn decode(code: Instruction) -> Option<ByteCode> {
+                match get_op(code) {
+                    $( opcode::$name => Some(bc_format!(dec $fmt, $name, code)), )+
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+define_bytecode! {
     // local variable
-    LoadConst(u8, u16),
-    LoadNil(u8, u8),
-    LoadBool(u8, bool),
-    LoadInt(u8, i16),
-    Move(u8, u8),
+    LoadConst(u8, u32) = ABx,
+    LoadConstX(u8) = A, // with the constant index in following ExtraArg
+    LoadNil(u8, u8) = AB,
+    LoadBool(u8, bool) = Ak,
+    LoadInt(u8, i16) = AsBx,
+    Move(u8, u8) = AB,
 
     // upvalues
-    GetUpvalue(u8, u8),
-    SetUpvalue(u8, u8),
-    SetUpvalueConst(u8, u8),
-    Close(u8),
+    GetUpvalue(u8, u8) = AB,
+    SetUpvalue(u8, u8) = AB,
+    SetUpvalueConst(u8, u8) = AB,
+    Close(u8) = A,
+    Tbc(u8) = A, // mark a to-be-closed local variable
 
     // table
-    NewTable(u8, u8, u8),
-    SetTable(u8, u8, u8),
-    SetField(u8, u8, u8),
-    SetInt(u8, u8, u8),
-    SetTableConst(u8, u8, u8),
-    SetFieldConst(u8, u8, u8),
-    SetIntConst(u8, u8, u8),
-    SetList(u8, u8),
-    GetTable(u8, u8, u8),
-    GetField(u8, u8, u8),
-    GetInt(u8, u8, u8),
-    GetFieldSelf(u8, u8, u8),
+    NewTable(u8, u8, u8) = ABC,
+    SetTable(u8, u8, u8) = ABC,
+    SetField(u8, u8, u8) = ABC,
+    SetInt(u8, u8, u8) = ABC,
+    SetTableConst(u8, u8, u8) = ABC,
+    SetFieldConst(u8, u8, u8) = ABC,
+    SetIntConst(u8, u8, u8) = ABC,
+    SetList(u8, u8) = AB,
+    GetTable(u8, u8, u8) = ABC,
+    GetField(u8, u8, u8) = ABC,
+    GetInt(u8, u8, u8) = ABC,
+    GetFieldSelf(u8, u8, u8) = ABC,
 
     // upvalue table, covers global variables
-    SetUpField(u8, u8, u8),
-    SetUpFieldConst(u8, u8, u8),
-    GetUpField(u8, u8, u8),
-
-    // condition structures
-    Jump(i16),
-    TestAndJump(u8, i16),
-    TestOrJump(u8, i16),
-    TestAndSetJump(u8, u8, u8),
-    TestOrSetJump(u8, u8, u8),
+    SetUpField(u8, u8, u8) = ABC,
+    SetUpFieldConst(u8, u8, u8) = ABC,
+    GetUpField(u8, u8, u8) = ABC,
+
+    // condition structures. The tests are followed by Jump, and skip
+    // it if not jump
+    Jump(i32) = sJ,
+    TestAndJump(u8) = A,
+    TestOrJump(u8) = A,
+    TestAndSetJump(u8, u8) = AB,
+    TestOrSetJump(u8, u8) = AB,
 
     // for-loop
-    ForPrepare(u8, u16),
-    ForLoop(u8, u16),
-    ForCallLoop(u8, u8, u8),
+    ForPrepare(u8, u32) = ABx,
+    ForLoop(u8, u32) = ABx,
+    ForCallLoop(u8, u8, u8) = ABC,
 
     // function call
-    Closure(u8, u16),
-    Call(u8, u8, u8),
-    CallSet(u8, u8, u8),
-    TailCall(u8, u8),
-    Return0,
-    Return(u8, u8),
-    VarArgs(u8, u8),
+    Closure(u8, u32) = ABx,
+    ClosureX(u8) = A, // with the constant index in following ExtraArg
+    Call(u8, u8, u8) = ABC,
+    CallSet(u8, u8, u8) = ABC,
+    TailCall(u8, u8) = AB,
+    Return0 = N,
+    Return(u8, u8) = AB,
+    VarArgs(u8, u8) = AB,
 
     // unops
-    Neg(u8, u8),
-    Not(u8, u8),
-    BitNot(u8, u8),
-    Len(u8, u8),
+    Neg(u8, u8) = AB,
+    Not(u8, u8) = AB,
+    BitNot(u8, u8) = AB,
+    Len(u8, u8) = AB,
 
     // binops
-    Add(u8, u8, u8),
-    AddConst(u8, u8, u8),
-    AddInt(u8, u8, u8),
-    Sub(u8, u8, u8),
-    SubInt(u8, u8, u8),
-    SubConst(u8, u8, u8),
-    Mul(u8, u8, u8),
-    MulInt(u8, u8, u8),
-    MulConst(u8, u8, u8),
-    Mod(u8, u8, u8),
-    ModInt(u8, u8, u8),
-    ModConst(u8, u8, u8),
-    Div(u8, u8, u8),
-    DivInt(u8, u8, u8),
-    DivConst(u8, u8, u8),
-    Idiv(u8, u8, u8),
-    IdivInt(u8, u8, u8),
-    IdivConst(u8, u8, u8),
-    Pow(u8, u8, u8),
-    PowInt(u8, u8, u8),
-    PowConst(u8, u8, u8),
-    BitAnd(u8, u8, u8),
-    BitAndInt(u8, u8, u8),
-    BitAndConst(u8, u8, u8),
-    BitXor(u8, u8, u8),
-    BitXorInt(u8, u8, u8),
-    BitXorConst(u8, u8, u8),
-    BitOr(u8, u8, u8),
-    BitOrInt(u8, u8, u8),
-    BitOrConst(u8, u8, u8),
-    ShiftL(u8, u8, u8),
-    ShiftLInt(u8, u8, u8),
-    ShiftLConst(u8, u8, u8),
-    ShiftR(u8, u8, u8),
-    ShiftRInt(u8, u8, u8),
-    ShiftRConst(u8, u8, u8),
-
-    Equal(u8, u8, bool),
-    EqualInt(u8, u8, bool),
-    EqualConst(u8, u8, bool),
-    NotEq(u8, u8, bool),
-    NotEqInt(u8, u8, bool),
-    NotEqConst(u8, u8, bool),
-    LesEq(u8, u8, bool),
-    LesEqInt(u8, u8, bool),
-    LesEqConst(u8, u8, bool),
-    GreEq(u8, u8, bool),
-    GreEqInt(u8, u8, bool),
-    GreEqConst(u8, u8, bool),
-    Less(u8, u8, bool),
-    LessInt(u8, u8, bool),
-    LessConst(u8, u8, bool),
-    Greater(u8, u8, bool),
-    GreaterInt(u8, u8, bool),
-    GreaterConst(u8, u8, bool),
-
-    SetFalseSkip(u8),
-
-    Concat(u8, u8, u8),
-}
\ No newline at end of file
+    Add(u8, u8, u8) = ABC,
+    AddConst(u8, u8, u8) = ABC,
+    AddInt(u8, u8, u8) = ABC,
+    Sub(u8, u8, u8) = ABC,
+    SubInt(u8, u8, u8) = ABC,
+    SubConst(u8, u8, u8) = ABC,
+    Mul(u8, u8, u8) = ABC,
+    MulInt(u8, u8, u8) = ABC,
+    MulConst(u8, u8, u8) = ABC,
+    Mod(u8, u8, u8) = ABC,
+    ModInt(u8, u8, u8) = ABC,
+    ModConst(u8, u8, u8) = ABC,
+    Div(u8, u8, u8) = ABC,
+    DivInt(u8, u8, u8) = ABC,
+    DivConst(u8, u8, u8) = ABC,
+    Idiv(u8, u8, u8) = ABC,
+    IdivInt(u8, u8, u8) = ABC,
+    IdivConst(u8, u8, u8) = ABC,
+    Pow(u8, u8, u8) = ABC,
+    PowInt(u8, u8, u8) = ABC,
+    PowConst(u8, u8, u8) = ABC,
+    BitAnd(u8, u8, u8) = ABC,
+    BitAndInt(u8, u8, u8) = ABC,
+    BitAndConst(u8, u8, u8) = ABC,
+    BitXor(u8, u8, u8) = ABC,
+    BitXorInt(u8, u8, u8) = ABC,
+    BitXorConst(u8, u8, u8) = ABC,
+    BitOr(u8, u8, u8) = ABC,
+    BitOrInt(u8, u8, u8) = ABC,
+    BitOrConst(u8, u8, u8) = ABC,
+    ShiftL(u8, u8, u8) = ABC,
+    ShiftLInt(u8, u8, u8) = ABC,
+    ShiftLConst(u8, u8, u8) = ABC,
+    ShiftR(u8, u8, u8) = ABC,
+    ShiftRInt(u8, u8, u8) = ABC,
+    ShiftRConst(u8, u8, u8) = ABC,
+
+    Equal(u8, u8, bool) = ABk,
+    EqualInt(u8, u8, bool) = ABk,
+    EqualConst(u8, u8, bool) = ABk,
+    NotEq(u8, u8, bool) = ABk,
+    NotEqInt(u8, u8, bool) = ABk,
+    NotEqConst(u8, u8, bool) = ABk,
+    LesEq(u8, u8, bool) = ABk,
+    LesEqInt(u8, u8, bool) = ABk,
+    LesEqConst(u8, u8, bool) = ABk,
+    GreEq(u8, u8, bool) = ABk,
+    GreEqInt(u8, u8, bool) = ABk,
+    GreEqConst(u8, u8, bool) = ABk,
+    Less(u8, u8, bool) = ABk,
+    LessInt(u8, u8, bool) = ABk,
+    LessConst(u8, u8, bool) = ABk,
+    Greater(u8, u8, bool) = ABk,
+    GreaterInt(u8, u8, bool) = ABk,
+    GreaterConst(u8, u8, bool) = ABk,
+
+    SetFalseSkip(u8) = A,
+
+    Concat(u8, u8, u8) = ABC,
+
+    ExtraArg(u32) = Ax,
+}
@@ -0,0 +1,507 @@
+use std::io::{self, Read, Write};
+use std::mem::size_of;
+use std::rc::Rc;
+use crate::bytecode::{ByteCode, Instruction};
+use crate::error::{Error, Result};
+use crate::parse::{FuncProto, LocVar, UpIndex, MAX_LOCALS, MAX_REGISTERS, MAX_UPVALUES};
+use crate::value::Value;
+
+// Binary chunk, dumped from FuncProto and loaded back without parsing.
+//
+// The header is like Lua 5.4's, but with luar's own format, since the
+// byte codes are not compatible with the official Lua:
+//
+//   signature "\x1bLua", version, format, DATA, size of Instruction,
+//   integer and float, CHECK_INT and CHECK_NUM
+//
+// The numbers are in native endianness, and CHECK_INT and CHECK_NUM
+// detect mismatch. Sizes and counts are in LEB128.
+pub const SIGNATURE: &[u8] = b"\x1bLua";
+const VERSION: u8 = 0x54;
+const FORMAT: u8 = b'r';
+const DATA: &[u8] = b"\x19\x93\r\n\x1a\n";
+const CHECK_INT: i64 = 0x5678;
+const CHECK_NUM: f64 = 370.5;
+
+// tags of constants
+const TAG_NIL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_INTEGER: u8 = 3;
+const TAG_FLOAT: u8 = 4;
+const TAG_STRING: u8 = 5;
+const TAG_FUNCTION: u8 = 6;
+
+// tags of upvalue indexes
+const TAG_LOCAL: u8 = 0;
+const TAG_UPVALUE: u8 = 1;
+
+// source name of stripped main function
+const STRIPPED_SOURCE: &str = "?";
+
+impl FuncProto {
+    // dump into binary chunk, with nested functions. Debug information,
+    // source, line numbers, function names and local variable names, is
+    // omitted if @strip.
+    pub fn dump(&self, mut w: impl Write, strip: bool) -> Result<()> {
+        let mut d = Dumper { buf: Vec::new(), strip };
+        d.header();
+        d.function(self, "");
+        w.write_all(&d.buf)
+            .map_err(|e| Error::runtime(format!("write error: {e}")))
+    }
+
+    // load binary chunk dumped by dump()
+    pub fn undump(r: impl Read) -> Result<FuncProto> {
+        let mut u = Undumper { r };
+        u.header()?;
+        u.function(STRIPPED_SOURCE)
+    }
+}
+
+struct Dumper {
+    buf: Vec<u8>,
+    strip: bool,
+}
+
+impl Dumper {
+    fn size(&mut self, mut n: usize) {
+        while n >= 0x80 {
+            self.buf.push(n as u8 | 0x80);
+            n >>= 7;
+        }
+        self.buf.push(n as u8);
+    }
+
+    fn string(&mut self, s: &[u8]) {
+        self.size(s.len());
+        self.buf.extend_from_slice(s);
+    }
+
+    fn header(&mut self) {
+        self.buf.extend_from_slice(SIGNATURE);
+        self.buf.push(VERSION);
+        self.buf.push(FORMAT);
+        self.buf.extend_from_slice(DATA);
+        self.buf.push(size_of::<Instruction>() as u8);
+        self.buf.push(size_of::<i64>() as u8);
+        self.buf.push(size_of::<f64>() as u8);
+        self.buf.extend_from_slice(&CHECK_INT.to_ne_bytes());
+        self.buf.extend_from_slice(&CHECK_NUM.to_ne_bytes());
+    }
+
+    fn function(&mut self, proto: &FuncProto, psource: &str) {
+        // omit the source if same with the parent's
+        if self.strip || proto.source == psource {
+            self.string(b"");
+        } else {
+            self.string(proto.source.as_bytes());
+        }
+        self.size(proto.linedefined);
+        self.string(if self.strip { b"" } else { proto.name.as_bytes() });
+        self.size(proto.nparam);
+        self.buf.push(proto.has_varargs as u8);
+
+        self.size(proto.byte_codes.len());
+        for code in proto.byte_codes.iter() {
+            self.buf.extend_from_slice(&code.to_ne_bytes());
+        }
+
+        self.size(proto.constants.len());
+        for v in proto.constants.iter() {
+            match v {
+                Value::Nil => self.buf.push(TAG_NIL),
+                Value::Boolean(false) => self.buf.push(TAG_FALSE),
+                Value::Boolean(true) => self.buf.push(TAG_TRUE),
+                Value::Integer(i) => {
+                    self.buf.push(TAG_INTEGER);
+                    self.buf.extend_from_slice(&i.to_ne_bytes());
+                }
+                Value::Float(f) => {
+                    self.buf.push(TAG_FLOAT);
+                    self.buf.extend_from_slice(&f.to_ne_bytes());
+                }
+                Value::ShortStr(_, _) | Value::MidStr(_) | Value::LongStr(_) => {
+                    self.buf.push(TAG_STRING);
+                    self.string(v.as_ref());
+                }
+                Value::LuaFunction(f) => {
+                    self.buf.push(TAG_FUNCTION);
+                    self.function(f, &proto.source);
+                }
+                _ => unreachable!("invalid constant {v:?}"),
+            }
+        }
+
+        self.size(proto.upindexes.len());
+        for up in proto.upindexes.iter() {
+            match up {
+                UpIndex::Local(i) => {
+                    self.buf.push(TAG_LOCAL);
+                    self.size(*i);
+                }
+                UpIndex::Upvalue(i) => {
+                    self.buf.push(TAG_UPVALUE);
+                    self.size(*i);
+                }
+            }
+        }
+
+        if self.strip {
+            self.size(0);
+            self.size(0);
+        } else {
+            self.size(proto.lineinfo.len());
+            for &line in proto.lineinfo.iter() {
+                self.size(line);
+            }
+            self.size(proto.locvars.len());
+            for var in proto.locvars.iter() {
+                self.string(var.name.as_bytes());
+                self.size(var.startpc);
+                self.size(var.endpc);
+            }
+        }
+    }
+}
+
+struct Undumper<R: Read> {
+    r: R,
+}
+
+fn bad_format(why: &str) -> Error {
+    Error::syntax(format!("bad binary format ({why})"), 0)
+}
+
+impl<R: Read> Undumper<R> {
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut buf = [0; N];
+        self.r.read_exact(&mut buf).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => bad_format("truncated chunk"),
+            _ => Error::syntax(format!("read error: {e}"), 0),
+        })?;
+        Ok(buf)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn size(&mut self) -> Result<usize> {
+        let mut n = 0;
+        let mut shift = 0;
+        loop {
+            let b = self.byte()?;
+            if shift >= usize::BITS || (b & 0x7f) as usize > usize::MAX >> shift {
+                return Err(bad_format("integer overflow"));
+            }
+            n |= ((b & 0x7f) as usize) << shift;
+            if b & 0x80 == 0 {
+                return Ok(n);
+            }
+            shift += 7;
+        }
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>> {
+        let len = self.size()?;
+        // do not trust @len to allocate
+        let mut s = Vec::new();
+        (&mut self.r).take(len as u64).read_to_end(&mut s)
+            .map_err(|e| Error::syntax(format!("read error: {e}"), 0))?;
+        if s.len() < len {
+            return Err(bad_format("truncated chunk"));
+        }
+        Ok(s)
+    }
+
+    fn header(&mut self) -> Result<()> {
+        if self.bytes::<4>()? != SIGNATURE {
+            return Err(bad_format("not a binary chunk"));
+        }
+        if self.byte()? != VERSION {
+            return Err(bad_format("version mismatch"));
+        }
+        if self.byte()? != FORMAT {
+            return Err(bad_format("format mismatch"));
+        }
+        if self.bytes::<6>()? != DATA {
+            return Err(bad_format("corrupted chunk"));
+        }
+        if self.byte()? as usize != size_of::<Instruction>() {
+            return Err(bad_format("Instruction size mismatch"));
+        }
+        if self.byte()? as usize != size_of::<i64>() {
+            return Err(bad_format("integer size mismatch"));
+        }
+        if self.byte()? as usize != size_of::<f64>() {
+            return Err(bad_format("float size mismatch"));
+        }
+        if i64::from_ne_bytes(self.bytes()?) != CHECK_INT {
+            return Err(bad_format("integer format mismatch"));
+        }
+        if f64::from_ne_bytes(self.bytes()?) != CHECK_NUM {
+            return Err(bad_format("float format mismatch"));
+        }
+        Ok(())
+    }
+
+    fn function(&mut self, psource: &str) -> Result<FuncProto> {
+        let source = match self.string()? {
+            s if s.is_empty() => psource.to_string(),
+            s => String::from_utf8_lossy(&s).into_owned(),
+        };
+        let linedefined = self.size()?;
+        let name = String::from_utf8_lossy(&self.string()?).into_owned();
+        let nparam = self.size()?;
+        let has_varargs = self.byte()? != 0;
+
+        let mut byte_codes = Vec::new();
+        for _ in 0..self.size()? {
+            byte_codes.push(Instruction::from_ne_bytes(self.bytes()?));
+        }
+
+        let mut constants = Vec::new();
+        for _ in 0..self.size()? {
+            let v = match self.byte()? {
+                TAG_NIL => Value::Nil,
+                TAG_FALSE => Value::Boolean(false),
+                TAG_TRUE => Value::Boolean(true),
+                TAG_INTEGER => Value::Integer(i64::from_ne_bytes(self.bytes()?)),
+                TAG_FLOAT => Value::Float(f64::from_ne_bytes(self.bytes()?)),
+                TAG_STRING => self.string()?.into(),
+                TAG_FUNCTION => Value::LuaFunction(Rc::new(self.function(&source)?)),
+                _ => return Err(bad_format("invalid constant")),
+            };
+            constants.push(v);
+        }
+
+        let mut upindexes = Vec::new();
+        for _ in 0..self.size()? {
+            let up = match self.byte()? {
+                TAG_LOCAL => UpIndex::Local(self.size()?),
+                TAG_UPVALUE => UpIndex::Upvalue(self.size()?),
+                _ => return Err(bad_format("invalid upvalue")),
+            };
+            upindexes.push(up);
+        }
+
+        // empty if stripped
+        let mut lineinfo = Vec::new();
+        for _ in 0..self.size()? {
+            lineinfo.push(self.size()?);
+        }
+        if !lineinfo.is_empty() && lineinfo.len() != byte_codes.len() {
+            return Err(bad_format("invalid line information"));
+        }
+        let mut locvars = Vec::new();
+        for _ in 0..self.size()? {
+            let name = String::from_utf8_lossy(&self.string()?).into_owned();
+            let (startpc, endpc) = (self.size()?, self.size()?);
+            if startpc > endpc || endpc > byte_codes.len() {
+                return Err(bad_format("invalid local variable"));
+            }
+            locvars.push(LocVar { name, startpc, endpc });
+        }
+
+        let proto = FuncProto {
+            has_varargs,
+            nparam,
+            constants,
+            upindexes,
+            byte_codes,
+            source,
+            name,
+            linedefined,
+            lineinfo,
+            locvars,
+        };
+        check(&proto)?;
+        Ok(proto)
+    }
+}
+
+// Check the operands of byte codes, since the VM trusts them as they
+// are generated by the parser, and the loaded chunks may be corrupted
+// or malicious.
+fn check(proto: &FuncProto) -> Result<()> {
+    if proto.nparam > MAX_LOCALS {
+        return Err(bad_format("too many parameters"));
+    }
+    if proto.upindexes.len() > MAX_UPVALUES {
+        return Err(bad_format("too many upvalues"));
+    }
+
+    // upvalues of nested functions refer to the locals or upvalues here
+    for c in &proto.constants {
+        if let Value::LuaFunction(f) = c {
+            for up in &f.upindexes {
+                match *up {
+                    UpIndex::Local(i) if i >= MAX_REGISTERS => return Err(bad_format("invalid upvalue")),
+                    UpIndex::Upvalue(i) if i >= proto.upindexes.len() => return Err(bad_format("invalid upvalue")),
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    let codes = proto.byte_codes.iter()
+        .map(|&c| ByteCode::decode(c).ok_or_else(|| bad_format("invalid instruction")))
+        .collect::<Result<Vec<_>>>()?;
+    if codes.is_empty() {
+        return Err(bad_format("no byte codes"));
+    }
+
+    // functions without upvalues are constants, while others must be
+    // created by Closure
+    let constant = |i: u32| match proto.constants.get(i as usize) {
+        Some(Value::LuaFunction(f)) if !f.upindexes.is_empty() => Err(bad_format("invalid constant index")),
+        Some(_) => Ok(()),
+        None => Err(bad_format("invalid constant index")),
+    };
+    let function = |i: u32| match proto.constants.get(i as usize) {
+        Some(Value::LuaFunction(_)) => Ok(()),
+        _ => Err(bad_format("invalid function index")),
+    };
+    let upvalue = |i: u8| match (i as usize) < proto.upindexes.len() {
+        true => Ok(()),
+        false => Err(bad_format("invalid upvalue index")),
+    };
+    // the ExtraArg is only executed as the argument of the previous code
+    let target = |pc: isize| match usize::try_from(pc).ok().and_then(|pc| codes.get(pc)) {
+        Some(ByteCode::ExtraArg(_)) | None => Err(bad_format("invalid jump")),
+        Some(_) => Ok(()),
+    };
+
+    for (pc, code) in codes.iter().enumerate() {
+        let ipc = pc as isize;
+        let extra = || match codes.get(pc + 1) {
+            Some(&ByteCode::ExtraArg(arg)) => Ok(arg),
+            _ => Err(bad_format("missing ExtraArg")),
+        };
+
+        // the highest register used, including the implicit ones
+        let reg = match *code {
+            ByteCode::LoadConst(a, c) => { constant(c)?; a as usize }
+            ByteCode::LoadConstX(a) => { constant(extra()?)?; a as usize }
+            ByteCode::LoadNil(a, n) => (a as usize + n as usize).saturating_sub(1),
+            ByteCode::LoadBool(a, _) | ByteCode::LoadInt(a, _) |
+            ByteCode::Close(a) | ByteCode::Tbc(a) | ByteCode::NewTable(a, _, _) |
+            ByteCode::TestAndJump(a) | ByteCode::TestOrJump(a) |
+            ByteCode::SetFalseSkip(a) | ByteCode::VarArgs(a, _) |
+            ByteCode::TailCall(a, _) | ByteCode::Return(a, _) => a as usize,
+            ByteCode::SetList(a, n) => a as usize + n as usize,
+            ByteCode::Move(a, b) | ByteCode::TestAndSetJump(a, b) | ByteCode::TestOrSetJump(a, b) |
+            ByteCode::Neg(a, b) | ByteCode::Not(a, b) | ByteCode::BitNot(a, b) | ByteCode::Len(a, b) |
+            ByteCode::CallSet(a, b, _) => a.max(b) as usize,
+
+            ByteCode::GetUpvalue(a, up) => { upvalue(up)?; a as usize }
+            ByteCode::SetUpvalue(up, b) => { upvalue(up)?; b as usize }
+            ByteCode::SetUpvalueConst(up, c) => { upvalue(up)?; constant(c as u32)?; 0 }
+
+            ByteCode::SetTable(t, k, v) => t.max(k).max(v) as usize,
+            ByteCode::SetField(t, k, v) => { constant(k as u32)?; t.max(v) as usize }
+            ByteCode::SetInt(t, _, v) => t.max(v) as usize,
+            ByteCode::SetTableConst(t, k, v) => { constant(v as u32)?; t.max(k) as usize }
+            ByteCode::SetFieldConst(t, k, v) => { constant(k as u32)?; constant(v as u32)?; t as usize }
+            ByteCode::SetIntConst(t, _, v) => { constant(v as u32)?; t as usize }
+            ByteCode::GetTable(a, t, k) => a.max(t).max(k) as usize,
+            ByteCode::GetField(a, t, k) => { constant(k as u32)?; a.max(t) as usize }
+            ByteCode::GetInt(a, t, _) => a.max(t) as usize,
+            ByteCode::GetFieldSelf(a, t, k) => { constant(k as u32)?; (a as usize + 1).max(t as usize) }
+            ByteCode::SetUpField(up, k, v) => { upvalue(up)?; constant(k as u32)?; v as usize }
+            ByteCode::SetUpFieldConst(up, k, v) => { upvalue(up)?; constant(k as u32)?; constant(v as u32)?; 0 }
+            ByteCode::GetUpField(a, up, k) => { upvalue(up)?; constant(k as u32)?; a as usize }
+
+            ByteCode::ForPrepare(a, _) | ByteCode::ForLoop(a, _) => a as usize + 2,
+            ByteCode::ForCallLoop(a, nvar, _) => a as usize + 2 + nvar as usize,
+            ByteCode::Closure(a, f) => { function(f)?; a as usize }
+            ByteCode::ClosureX(a) => { function(extra()?)?; a as usize }
+            ByteCode::Call(f, _, _) => f as usize,
+            ByteCode::Jump(_) | ByteCode::Return0 => 0,
+            ByteCode::ExtraArg(_) => match pc.checked_sub(1).map(|i| codes[i]) {
+                Some(ByteCode::LoadConstX(_) | ByteCode::ClosureX(_)) => 0,
+                _ => return Err(bad_format("misplaced ExtraArg")),
+            }
+
+            ByteCode::AddConst(a, b, c) | ByteCode::SubConst(a, b, c) |
+            ByteCode::MulConst(a, b, c) | ByteCode::ModConst(a, b, c) |
+            ByteCode::IdivConst(a, b, c) | ByteCode::DivConst(a, b, c) |
+            ByteCode::PowConst(a, b, c) | ByteCode::BitAndConst(a, b, c) |
+            ByteCode::BitOrConst(a, b, c) | ByteCode::BitXorConst(a, b, c) |
+            ByteCode::ShiftLConst(a, b, c) | ByteCode::ShiftRConst(a, b, c) =>
+                { constant(c as u32)?; a.max(b) as usize }
+            ByteCode::AddInt(a, b, _) | ByteCode::SubInt(a, b, _) |
+            ByteCode::MulInt(a, b, _) | ByteCode::ModInt(a, b, _) |
+            ByteCode::IdivInt(a, b, _) | ByteCode::DivInt(a, b, _) |
+            ByteCode::PowInt(a, b, _) | ByteCode::BitAndInt(a, b, _) |
+            ByteCode::BitOrInt(a, b, _) | ByteCode::BitXorInt(a, b, _) |
+            ByteCode::ShiftLInt(a, b, _) | ByteCode::ShiftRInt(a, b, _) => a.max(b) as usize,
+            ByteCode::Add(a, b, c) | ByteCode::Sub(a, b, c) |
+            ByteCode::Mul(a, b, c) | ByteCode::Mod(a, b, c) |
+            ByteCode::Idiv(a, b, c) | ByteCode::Div(a, b, c) |
+            ByteCode::Pow(a, b, c) | ByteCode::BitAnd(a, b, c) |
+            ByteCode::BitOr(a, b, c) | ByteCode::BitXor(a, b, c) |
+            ByteCode::ShiftL(a, b, c) | ByteCode::ShiftR(a, b, c) |
+            ByteCode::Concat(a, b, c) => a.max(b).max(c) as usize,
+
+            ByteCode::EqualConst(a, b, _) | ByteCode::NotEqConst(a, b, _) |
+            ByteCode::LesEqConst(a, b, _) | ByteCode::GreEqConst(a, b, _) |
+            ByteCode::LessConst(a, b, _) | ByteCode::GreaterConst(a, b, _) =>
+                { constant(b as u32)?; a as usize }
+            ByteCode::EqualInt(a, _, _) | ByteCode::NotEqInt(a, _, _) |
+            ByteCode::LesEqInt(a, _, _) | ByteCode::GreEqInt(a, _, _) |
+            ByteCode::LessInt(a, _, _) | ByteCode::GreaterInt(a, _, _) => a as usize,
+            ByteCode::Equal(a, b, _) | ByteCode::NotEq(a, b, _) |
+            ByteCode::LesEq(a, b, _) | ByteCode::GreEq(a, b, _) |
+            ByteCode::Less(a, b, _) | ByteCode::Greater(a, b, _) => a.max(b) as usize,
+        };
+        if reg >= MAX_REGISTERS {
+            return Err(bad_format("invalid register"));
+        }
+
+        // the following codes, which must be executable
+        match *code {
+            ByteCode::Return0 | ByteCode::Return(..) | ByteCode::TailCall(..) => (),
+            ByteCode::LoadConstX(_) | ByteCode::ClosureX(_) => target(ipc + 2)?,
+            ByteCode::Jump(jmp) => target(ipc + 1 + jmp as isize)?,
+            ByteCode::ForPrepare(_, jmp) => {
+                target(ipc + 1)?;
+                target(ipc + 1 + jmp as isize)?;
+            }
+            // jump back by subtraction, which must not overflow
+            ByteCode::ForLoop(_, jmp) => {
+                if jmp as usize > pc {
+                    return Err(bad_format("invalid jump"));
+                }
+                target(ipc + 1)?;
+                target(ipc + 1 - jmp as isize)?;
+            }
+            ByteCode::ForCallLoop(_, _, jmp) => {
+                if jmp as usize > pc {
+                    return Err(bad_format("invalid jump"));
+                }
+                target(ipc + 1)?;
+                target(ipc + 1 - jmp as isize)?;
+                if jmp == 0 {
+                    target(ipc + 2)?;
+                }
+            }
+            // skip the following code
+            ByteCode::TestAndJump(_) | ByteCode::TestOrJump(_) |
+            ByteCode::TestAndSetJump(..) | ByteCode::TestOrSetJump(..) |
+            ByteCode::SetFalseSkip(_) |
+            ByteCode::Equal(..) | ByteCode::EqualInt(..) | ByteCode::EqualConst(..) |
+            ByteCode::NotEq(..) | ByteCode::NotEqInt(..) | ByteCode::NotEqConst(..) |
+            ByteCode::LesEq(..) | ByteCode::LesEqInt(..) | ByteCode::LesEqConst(..) |
+            ByteCode::GreEq(..) | ByteCode::GreEqInt(..) | ByteCode::GreEqConst(..) |
+            ByteCode::Less(..) | ByteCode::LessInt(..) | ByteCode::LessConst(..) |
+            ByteCode::Greater(..) | ByteCode::GreaterInt(..) | ByteCode::GreaterConst(..) => {
+                target(ipc + 1)?;
+                target(ipc + 2)?;
+            }
+            _ => target(ipc + 1)?,
+        }
+    }
+    Ok(())
+}
@@ -1,7 +1,201 @@
+use std::fmt;
+use crate::value::Value;
+
+// errors raised when loading or executing a chunk
+//
+// Each variant carries a message and the source line where the error
+// happens. Line 0 means unknown.
+//
+// Lua code can raise any value as error by `error()`, and catch it by
+// `pcall()`. Errors raised by the VM and libraries are caught as their
+// message strings.
+#[derive(Debug, Clone, PartialEq)]
 pub enum Error {
+    // invalid source code, raised by lexer or parser. @eof is set if
+    // the input ends before the error, so more input may complete it.
+    Syntax { msg: String, line: usize, eof: bool, traceback: Traceback },
+
+    // operation on a value of wrong type, e.g. index a nil value
+    Type { msg: String, line: usize, traceback: Traceback },
+
+    // arithmetic error, e.g. arithmetic on a table
+    Arith { msg: String, line: usize, traceback: Traceback },
+
+    // call a value which is not a function
+    Call { msg: String, line: usize, traceback: Traceback },
+
+    // other errors, e.g. raised by library functions
+    Runtime { msg: String, line: usize, traceback: Traceback },
+
+    // error object raised by Lua code, whose position has been added
+    // in @value if needed. @msg describes the value.
+    Value { value: Value, msg: String, line: usize, traceback: Traceback },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn syntax(msg: impl Into<String>, line: usize) -> Self {
+        Error::Syntax { msg: msg.into(), line, eof: false, traceback: Traceback::default() }
+    }
+    pub(crate) fn syntax_eof(msg: impl Into<String>, line: usize) -> Self {
+        Error::Syntax { msg: msg.into(), line, eof: true, traceback: Traceback::default() }
+    }
+    pub fn type_error(msg: impl Into<String>) -> Self {
+        Error::Type { msg: msg.into(), line: 0, traceback: Traceback::default() }
+    }
+    pub fn arith(msg: impl Into<String>) -> Self {
+        Error::Arith { msg: msg.into(), line: 0, traceback: Traceback::default() }
+    }
+    pub fn call(msg: impl Into<String>) -> Self {
+        Error::Call { msg: msg.into(), line: 0, traceback: Traceback::default() }
+    }
+    pub fn runtime(msg: impl Into<String>) -> Self {
+        Error::Runtime { msg: msg.into(), line: 0, traceback: Traceback::default() }
+    }
+    pub fn value(value: Value) -> Self {
+        let msg = if value.is_string() {
+            value.to_string()
+        } else {
+            format!("(error object is a {} value)", value.ty())
+        };
+        Error::Value { value, msg, line: 0, traceback: Traceback::default() }
+    }
+
+    // the value caught by pcall()
+    pub fn to_value(&self) -> Value {
+        match self {
+            Error::Value { value, .. } => value.clone(),
+            _ => self.to_string().into(),
+        }
+    }
+
+    // replace the error object, keeping the traceback. Used by message
+    // handlers of xpcall()
+    pub(crate) fn with_value(self, value: Value) -> Self {
+        let line = self.line();
+        let traceback = self.traceback().clone();
+        match Error::value(value) {
+            Error::Value { value, msg, .. } => Error::Value { value, msg, line, traceback },
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn msg(&self) -> &str {
+        match self {
+            Error::Syntax { msg, .. } |
+            Error::Type { msg, .. } |
+            Error::Arith { msg, .. } |
+            Error::Call { msg, .. } |
+            Error::Runtime { msg, .. } |
+            Error::Value { msg, .. } => msg,
+        }
+    }
+
+    pub fn line(&self) -> usize {
+        match self {
+            Error::Syntax { line, .. } |
+            Error::Type { line, .. } |
+            Error::Arith { line, .. } |
+            Error::Call { line, .. } |
+            Error::Runtime { line, .. } |
+            Error::Value { line, .. } => *line,
+        }
+    }
 
+    // syntax error at the end of the input, e.g. an unfinished block
+    // or long string. Used by REPL to read more lines.
+    pub fn is_eof(&self) -> bool {
+        matches!(self, Error::Syntax { eof: true, .. })
+    }
+
+    // frames active when the error was raised, innermost first
+    pub fn traceback(&self) -> &Traceback {
+        match self {
+            Error::Syntax { traceback, .. } |
+            Error::Type { traceback, .. } |
+            Error::Arith { traceback, .. } |
+            Error::Call { traceback, .. } |
+            Error::Runtime { traceback, .. } |
+            Error::Value { traceback, .. } => traceback,
+        }
+    }
+
+    // called by VM when the error leaves a frame. The current line of
+    // the innermost frame is taken as the error position. Errors raised
+    // by Rust functions take the line of the calling Lua function, like
+    // luaL_where(1), except error objects which are positioned by
+    // error() itself and syntax errors of loaded chunks.
+    pub(crate) fn with_frame(mut self, frame: TraceFrame) -> Self {
+        let positioned = matches!(self, Error::Syntax { .. } | Error::Value { .. });
+        let (line, traceback) = match &mut self {
+            Error::Syntax { line, traceback, .. } |
+            Error::Type { line, traceback, .. } |
+            Error::Arith { line, traceback, .. } |
+            Error::Call { line, traceback, .. } |
+            Error::Runtime { line, traceback, .. } |
+            Error::Value { line, traceback, .. } => (line, traceback),
+        };
+        match traceback.frames.as_slice() {
+            [] if *line == 0 => *line = frame.line,
+            [inner] if *line == 0 && inner.is_rust() && !positioned => *line = frame.line,
+            _ => (),
+        }
+        traceback.frames.push(frame);
+        self
+    }
+}
+
+// "chunkname:line: msg" for runtime errors, where the position is of
+// the innermost Lua frame. Syntax errors are "line: msg", and the
+// loaders add the chunk name.
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let frame = self.traceback().frames.iter().find(|frame| !frame.is_rust());
+        match (self, self.line(), frame) {
+            (_, 0, _) => write!(f, "{}", self.msg()),
+            (Error::Syntax { .. }, line, _) | (_, line, None) => write!(f, "{line}: {}", self.msg()),
+            (_, line, Some(frame)) => write!(f, "{}:{line}: {}", frame.source, self.msg()),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// one active function when error raised
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    pub source: String, // chunk name, or "[Rust]" for Rust functions
+    pub line: usize, // current line, 0 for Rust functions
+    pub name: String, // function description, e.g. "function 'foo'"
 }
 
+const RUST_SOURCE: &str = "[Rust]";
+
+impl TraceFrame {
+    pub(crate) fn rust() -> Self {
+        TraceFrame { source: RUST_SOURCE.into(), line: 0, name: "?".into() }
+    }
+    pub fn is_rust(&self) -> bool {
+        self.source == RUST_SOURCE
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct Traceback {
-    
-}
\ No newline at end of file
+    pub frames: Vec<TraceFrame>,
+}
+
+impl fmt::Display for Traceback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "stack traceback:")?;
+        for frame in self.frames.iter() {
+            if frame.line == 0 {
+                write!(f, "\n\t{}: in {}", frame.source, frame.name)?;
+            } else {
+                write!(f, "\n\t{}:{}: in {}", frame.source, frame.line, frame.name)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,6 +1,10 @@
 use std::mem;
-use std::io::{Read, Bytes};
+use std::fmt;
+use std::io::{Read, Bytes, BufReader};
 use std::iter::Peekable;
+use crate::error::{Error, Result};
+use crate::value::Value;
+use crate::utils::str_to_number;
 
 #[derive(Debug, PartialEq)]
 pub enum Token {
@@ -33,43 +37,163 @@ pub enum Token {
     Eos,
 }
 
+// source spelling in quotes, or <eof>, used in syntax errors
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Token::And => "and",
+            Token::Break => "break",
+            Token::Do => "do",
+            Token::Else => "else",
+            Token::Elseif => "elseif",
+            Token::End => "end",
+            Token::False => "false",
+            Token::For => "for",
+            Token::Function => "function",
+            Token::Goto => "goto",
+            Token::If => "if",
+            Token::In => "in",
+            Token::Local => "local",
+            Token::Nil => "nil",
+            Token::Not => "not",
+            Token::Or => "or",
+            Token::Repeat => "repeat",
+            Token::Return => "return",
+            Token::Then => "then",
+            Token::True => "true",
+            Token::Until => "until",
+            Token::While => "while",
+            Token::Add => "+",
+            Token::Sub => "-",
+            Token::Mul => "*",
+            Token::Div => "/",
+            Token::Mod => "%",
+            Token::Pow => "^",
+            Token::Len => "#",
+            Token::BitAnd => "&",
+            Token::BitNot => "~",
+            Token::BitOr => "|",
+            Token::ShiftL => "<<",
+            Token::ShiftR => ">>",
+            Token::Idiv => "//",
+            Token::Equal => "==",
+            Token::NotEq => "~=",
+            Token::LesEq => "<=",
+            Token::GreEq => ">=",
+            Token::Less => "<",
+            Token::Greater => ">",
+            Token::Assign => "=",
+            Token::ParL => "(",
+            Token::ParR => ")",
+            Token::CurlyL => "{",
+            Token::CurlyR => "}",
+            Token::SqurL => "[",
+            Token::SqurR => "]",
+            Token::DoubColon => "::",
+            Token::SemiColon => ";",
+            Token::Colon => ":",
+            Token::Comma => ",",
+            Token::Dot => ".",
+            Token::Concat => "..",
+            Token::Dots => "...",
+            Token::Integer(i) => return write!(f, "'{i}'"),
+            Token::Float(n) => return write!(f, "'{}'", Value::Float(*n)),
+            Token::String(s) => return write!(f, "'\"{}\"'", String::from_utf8_lossy(s)),
+            Token::Name(name) => return write!(f, "'{name}'"),
+            Token::Eos => return write!(f, "<eof>"),
+        };
+        write!(f, "'{s}'")
+    }
+}
+
 #[derive(Debug)]
 pub struct Lex<R: Read> {
-    input: Peekable::<Bytes::<R>>,
+    input: Peekable::<Bytes::<BufReader<R>>>,
     ahead: Token,
+
+    // position of next byte to read, both start from 1
+    line: usize,
+    column: usize,
+
+    // start positions of: the token in reading, the token returned
+    // by last next(), and the ahead token
+    start: (usize, usize),
+    current: (usize, usize),
+    ahead_start: (usize, usize),
 }
 
 impl<R: Read> Lex<R> {
     pub fn new(input: R) -> Self {
         Lex {
-            input: input.bytes().peekable(),
+            input: BufReader::new(input).bytes().peekable(),
             ahead: Token::Eos,
+            line: 1,
+            column: 1,
+            start: (1, 1),
+            current: (1, 1),
+            ahead_start: (1, 1),
         }
     }
 
-    pub fn next(&mut self) -> Token {
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Token> {
         if self.ahead == Token::Eos {
-            self.do_next()
+            let t = self.do_next()?;
+            self.current = self.start;
+            Ok(t)
         } else {
-            mem::replace(&mut self.ahead, Token::Eos)
+            self.current = self.ahead_start;
+            Ok(mem::replace(&mut self.ahead, Token::Eos))
         }
     }
 
-    pub fn peek(&mut self) -> &Token {
+    pub fn peek(&mut self) -> Result<&Token> {
         if self.ahead == Token::Eos {
-            self.ahead = self.do_next();
+            self.ahead = self.do_next()?;
+            self.ahead_start = self.start;
         }
-        &self.ahead
+        Ok(&self.ahead)
+    }
+
+    pub fn expect(&mut self, t: Token) -> Result<()> {
+        let got = self.next()?;
+        if got == t {
+            Ok(())
+        } else {
+            Err(self.error_near(format!("{t} expected"), &got))
+        }
+    }
+
+    // line of the token returned by last next(), starts from 1
+    pub fn line(&self) -> usize {
+        self.current.0
+    }
+    // column of the token returned by last next(), starts from 1
+    pub fn column(&self) -> usize {
+        self.current.1
     }
-//
-    pub fn expect(&mut self, t: Token) {
-        assert_eq!(self.next(), t);
+
+    // syntax error at current reading line
+    pub fn error(&self, msg: impl Into<String>) -> Error {
+        Error::syntax(msg, self.line)
+    }
+
+    // syntax error at token @t. It is at the end of the input if @t
+    // is Eos, so more input may complete it.
+    pub fn error_near(&self, msg: impl Into<String>, t: &Token) -> Error {
+        let msg = format!("{} near {t}", msg.into());
+        if *t == Token::Eos {
+            Error::syntax_eof(msg, self.line)
+        } else {
+            Error::syntax(msg, self.line)
+        }
     }
 
-    fn do_next(&mut self) -> Token {
-        if let Some(byt) = self.next_byte() {
-            match byt {
-                b'\n' | b'\r' | b'\t' | b' ' => self.do_next(),
+    fn do_next(&mut self) -> Result<Token> {
+        self.start = (self.line, self.column);
+        if let Some(byt) = self.next_byte()? {
+            let t = match byt {
+                b'\n' | b'\r' | b'\t' | b' ' => return self.do_next(),
                 b'+' => Token::Add,
                 b'*' => Token::Mul,
                 b'%' => Token::Mod,
@@ -81,119 +205,232 @@ impl<R: Read> Lex<R> {
                 b')' => Token::ParR,
                 b'{' => Token::CurlyL,
                 b'}' => Token::CurlyR,
-                b'[' => Token::SqurL,
+                b'[' => match self.read_long_open()? {
+                    (0, false) => Token::SqurL,
+                    (_, false) => return Err(self.error("invalid long string delimiter")),
+                    (level, true) => Token::String(self.read_long_string(level, "string")?),
+                }
                 b']' => Token::SqurR,
                 b';' => Token::SemiColon,
                 b',' => Token::Comma,
-                b'/' => self.check_ahead(b'/', Token::Idiv, Token::Div),
-                b'=' => self.check_ahead(b'=', Token::Equal, Token::Assign),
-                b'~' => self.check_ahead(b'=', Token::NotEq, Token::BitNot),
-                b':' => self.check_ahead(b':', Token::DoubColon, Token::Colon),
-                b'<' => self.check_ahead2(b'=', Token::LesEq, b'<', Token::ShiftL, Token::Less),
-                b'>' => self.check_ahead2(b'=', Token::GreEq, b'>', Token::ShiftR, Token::Greater),
-                b'\'' | b'"' => self.read_string(byt),
-                b'.' => match self.peek_byte() {
+                b'/' => self.check_ahead(b'/', Token::Idiv, Token::Div)?,
+                b'=' => self.check_ahead(b'=', Token::Equal, Token::Assign)?,
+                b'~' => self.check_ahead(b'=', Token::NotEq, Token::BitNot)?,
+                b':' => self.check_ahead(b':', Token::DoubColon, Token::Colon)?,
+                b'<' => self.check_ahead2(b'=', Token::LesEq, b'<', Token::ShiftL, Token::Less)?,
+                b'>' => self.check_ahead2(b'=', Token::GreEq, b'>', Token::ShiftR, Token::Greater)?,
+                b'\'' | b'"' => self.read_string(byt)?,
+                b'.' => match self.peek_byte()? {
                     b'.' => {
-                        self.next_byte();
-                        if self.peek_byte() == b'.' {
-                            self.next_byte();
+                        self.next_byte()?;
+                        if self.peek_byte()? == b'.' {
+                            self.next_byte()?;
                             Token::Dots
                         } else {
                             Token::Concat
                         }
                     }
-                    b'0'..=b'9' => self.read_decimal('.'),
+                    b'0'..=b'9' => self.read_number(b'.')?,
                     _ => Token::Dot,
                 }
                 b'-' => {
-                    if self.peek_byte() == b'-' {
-                        self.next_byte();
-                        self.read_comment();
-                        self.do_next()
+                    if self.peek_byte()? == b'-' {
+                        self.next_byte()?;
+                        self.read_comment()?;
+                        return self.do_next();
                     } else {
                         Token::Sub
                     }
                 }
-                ch@b'0'..=b'9' => self.read_decimal(ch as char),
-                b'A'..=b'Z' | b'a'..=b'z' | b'_' => self.read_name(byt),
-                _ => panic!("invalid char {byt}"),
-            }
+                ch@b'0'..=b'9' => self.read_number(ch)?,
+                b'A'..=b'Z' | b'a'..=b'z' | b'_' => self.read_name(byt)?,
+                _ => return Err(self.error(format!("invalid char {byt}"))),
+            };
+            Ok(t)
         } else {
-            Token::Eos
+            Ok(Token::Eos)
         }
     }
 
-    fn peek_byte(&mut self) -> u8 {
+    fn peek_byte(&mut self) -> Result<u8> {
         match self.input.peek() {
-            Some(Ok(byt)) => *byt,
-            Some(_) => panic!("lex peek error"),
-            None => b'\0', // good for usage
+            Some(Ok(byt)) => Ok(*byt),
+            Some(Err(e)) => Err(Error::syntax(format!("read error: {e}"), self.line)),
+            None => Ok(b'\0'), // good for usage
         }
     }
-    fn next_byte(&mut self) -> Option<u8> {
-        self.input.next().map(|r| r.unwrap())
+    fn next_byte(&mut self) -> Result<Option<u8>> {
+        match self.input.next() {
+            Some(Ok(byt)) => {
+                if byt == b'\n' {
+                    self.line += 1;
+                    self.column = 1;
+                } else {
+                    self.column += 1;
+                }
+                Ok(Some(byt))
+            }
+            Some(Err(e)) => Err(self.error(format!("read error: {e}"))),
+            None => Ok(None),
+        }
     }
 
-    fn check_ahead(&mut self, ahead: u8, long: Token, short: Token) -> Token {
-        if self.peek_byte() == ahead {
-            self.next_byte();
-            long
+    fn check_ahead(&mut self, ahead: u8, long: Token, short: Token) -> Result<Token> {
+        if self.peek_byte()? == ahead {
+            self.next_byte()?;
+            Ok(long)
         } else {
-            short
+            Ok(short)
         }
     }
-    fn check_ahead2(&mut self, ahead1: u8, long1: Token, ahead2: u8, long2: Token, short: Token) -> Token {
-        let byt = self.peek_byte();
+    fn check_ahead2(&mut self, ahead1: u8, long1: Token, ahead2: u8, long2: Token, short: Token) -> Result<Token> {
+        let byt = self.peek_byte()?;
         if byt == ahead1 {
-            self.next_byte();
-            long1
+            self.next_byte()?;
+            Ok(long1)
         } else if byt == ahead2 {
-            self.next_byte();
-            long2
+            self.next_byte()?;
+            Ok(long2)
         } else {
-            short
+            Ok(short)
         }
     }
 
-    fn read_decimal(&mut self, ahead: char) -> Token {
-        // TODO heximal
-        let mut is_float = ahead == '.';
+    fn read_number(&mut self, first: u8) -> Result<Token> {
         let mut buf = String::new();
-        buf.push(ahead);
+        buf.push(first as char);
+
+        // exponent mark
+        let mut expo = b'e';
+        if first == b'0' && matches!(self.peek_byte()?, b'x' | b'X') {
+            buf.push(self.next_byte()?.unwrap() as char);
+            expo = b'p';
+        }
+
         loop {
-            let byt = self.peek_byte();
-            match byt {
-                b'0' ..= b'9' => buf.push(byt as char),
-                b'.' | b'e' | b'E' | b'+' | b'-' => {
-                    buf.push(byt as char);
-                    is_float = true;
+            let byt = self.peek_byte()?;
+            if byt.to_ascii_lowercase() == expo {
+                self.next_byte()?;
+                buf.push(byt as char);
+                // optional exponent sign
+                if matches!(self.peek_byte()?, b'+' | b'-') {
+                    buf.push(self.next_byte()?.unwrap() as char);
                 }
-                _ => break,
+            } else if byt.is_ascii_hexdigit() || byt == b'.' {
+                self.next_byte()?;
+                buf.push(byt as char);
+            } else {
+                break;
             }
-            self.next_byte();
         }
 
-        if is_float {
-            Token::Float(buf.parse::<f64>().unwrap())
+        // numeral touching a letter, e.g. `3x`, is malformed
+        let byt = self.peek_byte()?;
+        if byt.is_ascii_alphabetic() || byt == b'_' {
+            self.next_byte()?;
+            buf.push(byt as char);
+        }
+
+        match str_to_number(&buf) {
+            Some(Value::Integer(i)) => Ok(Token::Integer(i)),
+            Some(Value::Float(f)) => Ok(Token::Float(f)),
+            _ => Err(self.error(format!("malformed number near '{buf}'"))),
+        }
+    }
+
+    fn read_string(&mut self, quote: u8) -> Result<Token> {
+        let mut s = Vec::new();
+        loop {
+            match self.next_byte()? {
+                None | Some(b'\n') => return Err(self.error("unfinished string")),
+                Some(b'\\') => self.read_escape(&mut s)?,
+                Some(byt) if byt == quote => break,
+                Some(byt) => s.push(byt),
+            }
+        }
+        Ok(Token::String(s))
+    }
+    // The first '[' of long bracket has been read. Read the following
+    // '='s and the second '[', return the level, and whether it is a
+    // long bracket.
+    fn read_long_open(&mut self) -> Result<(usize, bool)> {
+        let mut level = 0;
+        while self.peek_byte()? == b'=' {
+            self.next_byte()?;
+            level += 1;
+        }
+        if self.peek_byte()? == b'[' {
+            self.next_byte()?;
+            Ok((level, true))
         } else {
-            Token::Integer(buf.parse::<i64>().unwrap())
+            Ok((level, false))
         }
     }
 
-    fn read_string(&mut self, quote: u8) -> Token {
+    // content of long string or long comment, after the opening bracket
+    fn read_long_string(&mut self, level: usize, what: &str) -> Result<Vec<u8>> {
+        let start_line = self.line;
+
+        // skip the first newline
+        match self.peek_byte()? {
+            b'\r' => {
+                self.next_byte()?;
+                if self.peek_byte()? == b'\n' {
+                    self.next_byte()?;
+                }
+            }
+            b'\n' => {
+                self.next_byte()?;
+                if self.peek_byte()? == b'\r' {
+                    self.next_byte()?;
+                }
+            }
+            _ => (),
+        }
+
         let mut s = Vec::new();
         loop {
-            match self.next_byte().expect("unfinished string") {
-                b'\n' => panic!("unfinished string"),
-                b'\\' => s.push(self.read_escape()),
-                byt if byt == quote => break,
-                byt => s.push(byt),
+            match self.next_byte()? {
+                None => return Err(Error::syntax_eof(format!("unfinished long {what} (starting at line {start_line})"), self.line)),
+                Some(b']') => {
+                    let mut n = 0;
+                    while self.peek_byte()? == b'=' {
+                        self.next_byte()?;
+                        n += 1;
+                    }
+                    if n == level && self.peek_byte()? == b']' {
+                        self.next_byte()?;
+                        return Ok(s);
+                    }
+                    // not the closing bracket, and the following ']'
+                    // may start it
+                    s.push(b']');
+                    s.resize(s.len() + n, b'=');
+                }
+                // any kind of newline sequence is converted to '\n'
+                Some(b'\r') => {
+                    s.push(b'\n');
+                    if self.peek_byte()? == b'\n' {
+                        self.next_byte()?;
+                    }
+                }
+                Some(b'\n') => {
+                    s.push(b'\n');
+                    if self.peek_byte()? == b'\r' {
+                        self.next_byte()?;
+                    }
+                }
+                Some(byt) => s.push(byt),
             }
         }
-        Token::String(s)
     }
-    fn read_escape(&mut self) -> u8 {
-        match self.next_byte().expect("string escape") {
+
+    // '\\' has been read, push the escaped bytes into @s
+    fn read_escape(&mut self, s: &mut Vec<u8>) -> Result<()> {
+        let Some(byt) = self.next_byte()? else {
+            return Err(self.error("unfinished string"));
+        };
+        let b = match byt {
             b'a' => 0x07,
             b'b' => 0x08,
             b'f' => 0x0c,
@@ -204,42 +441,92 @@ impl<R: Read> Lex<R> {
             b'\\' => b'\\',
             b'"' => b'"',
             b'\'' => b'\'',
+            b'\n' | b'\r' => { // line continuation, as a newline
+                let other = if byt == b'\n' { b'\r' } else { b'\n' };
+                if self.peek_byte()? == other {
+                    self.next_byte()?;
+                }
+                b'\n'
+            }
+            b'z' => { // skip the following white spaces
+                while self.peek_byte()?.is_ascii_whitespace() || self.peek_byte()? == 0x0b {
+                    self.next_byte()?;
+                }
+                return Ok(());
+            }
             b'x' => { // format: \xXX
-                let n1 = char::to_digit(self.next_byte().unwrap() as char, 16).unwrap();
-                let n2 = char::to_digit(self.next_byte().unwrap() as char, 16).unwrap();
+                let n1 = self.read_hex_digit()?;
+                let n2 = self.read_hex_digit()?;
                 (n1 * 16 + n2) as u8
             }
+            b'u' => { // format: \u{XXX}
+                let n = self.read_utf8_escape()?;
+                utf8_encode(n, s);
+                return Ok(());
+            }
             ch@b'0'..=b'9' => { // format: \d[d[d]]
-                let mut n = char::to_digit(ch as char, 10).unwrap(); // TODO no unwrap
-                if let Some(d) = char::to_digit(self.peek_byte() as char, 10) {
-                    self.next_byte();
+                let mut n = (ch - b'0') as u32;
+                if let Some(d) = char::to_digit(self.peek_byte()? as char, 10) {
+                    self.next_byte()?;
                     n = n * 10 + d;
-                    if let Some(d) = char::to_digit(self.peek_byte() as char, 10) {
-                        self.next_byte();
+                    if let Some(d) = char::to_digit(self.peek_byte()? as char, 10) {
+                        self.next_byte()?;
                         n = n * 10 + d;
                     }
                 }
-                u8::try_from(n).expect("decimal escape too large")
+                u8::try_from(n).map_err(|_| self.error(format!("decimal escape too large near '\\{n}'")))?
             }
-            _ => panic!("invalid string escape")
+            _ => return Err(self.error(format!("invalid escape sequence '\\{}'", byt as char))),
+        };
+        s.push(b);
+        Ok(())
+    }
+    // 'u' has been read
+    fn read_utf8_escape(&mut self) -> Result<u32> {
+        if self.next_byte()? != Some(b'{') {
+            return Err(self.error("missing '{' in \\u{xxxx}"));
         }
+        let mut n = self.read_hex_digit()?;
+        loop {
+            match self.peek_byte()? {
+                b'}' => {
+                    self.next_byte()?;
+                    return Ok(n);
+                }
+                byt => {
+                    let Some(d) = char::to_digit(byt as char, 16) else {
+                        return Err(self.error("missing '}' in \\u{xxxx}"));
+                    };
+                    if n > 0x7FFFFFFF >> 4 {
+                        return Err(self.error("UTF-8 value too large"));
+                    }
+                    self.next_byte()?;
+                    n = n * 16 + d;
+                }
+            }
+        }
+    }
+    fn read_hex_digit(&mut self) -> Result<u32> {
+        self.next_byte()?
+            .and_then(|byt| char::to_digit(byt as char, 16))
+            .ok_or_else(|| self.error("hexadecimal digit expected"))
     }
 
-    fn read_name(&mut self, first: u8) -> Token {
+    fn read_name(&mut self, first: u8) -> Result<Token> {
         let mut s = String::new();
         s.push(first as char);
 
         loop {
-            let ch = self.peek_byte() as char;
+            let ch = self.peek_byte()? as char;
             if ch.is_alphanumeric() || ch == '_' {
-                self.next_byte();
+                self.next_byte()?;
                 s.push(ch);
             } else {
                 break;
             }
         }
 
-        match &s as &str {
+        let t = match &s as &str {
             "and"      => Token::And,
             "break"    => Token::Break,
             "do"       => Token::Do,
@@ -263,21 +550,52 @@ impl<R: Read> Lex<R> {
             "until"    => Token::Until,
             "while"    => Token::While,
             _          => Token::Name(s),
-        }
+        };
+        Ok(t)
     }
 
     // '--' has been read
-    fn read_comment(&mut self) {
-        match self.next_byte() {
-            None => (),
-            Some(b'[') => todo!("long comment"),
-            Some(_) => { // line comment
-                while let Some(byt) = self.next_byte() {
-                    if byt == b'\n' {
-                        break;
-                    }
+    fn read_comment(&mut self) -> Result<()> {
+        match self.next_byte()? {
+            None | Some(b'\n') => (),
+            Some(b'[') => {
+                if let (level, true) = self.read_long_open()? {
+                    self.read_long_string(level, "comment")?;
+                } else {
+                    self.skip_line()?;
                 }
             }
+            Some(_) => self.skip_line()?,
         }
+        Ok(())
     }
-}
\ No newline at end of file
+
+    // line comment
+    fn skip_line(&mut self) -> Result<()> {
+        while let Some(byt) = self.next_byte()? {
+            if byt == b'\n' {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+// encode @n into UTF-8, with the original 6-byte scheme for values up
+// to 2^31 as Lua does
+fn utf8_encode(n: u32, s: &mut Vec<u8>) {
+    if n < 0x80 {
+        s.push(n as u8);
+        return;
+    }
+    let mut buf = Vec::new();
+    let mut n = n;
+    let mut mfb = 0x3f; // max that fits in the first byte
+    while n > mfb {
+        buf.push(0x80 | (n & 0x3f) as u8);
+        n >>= 6;
+        mfb >>= 1;
+    }
+    buf.push(((!mfb << 1) | n) as u8);
+    s.extend(buf.iter().rev());
+}
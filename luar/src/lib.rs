@@ -5,6 +5,7 @@ pub mod lex;
 pub mod value;
 pub mod lualib;
 pub mod error;
+mod dump;
 mod macros;
 mod vhash;
 mod utils;
\ No newline at end of file
@@ -1,27 +1,98 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::Cell, rc::Rc};
 
-use crate::{value::Value, vm::ExeState};
+use luargc::GcCell;
+use crate::{value::{Value, Table}, vm::{ExeState, Thread}};
+use crate::error::{Error, Result};
+use crate::utils::ftoi;
 
-pub fn test_new_counter(state: &mut ExeState) -> i32 {
-    let mut i = 0_i32;
+// error for the @iarg-th argument of library function @fname
+pub fn arg_error(iarg: usize, fname: &str, msg: &str) -> Error {
+    Error::runtime(format!("bad argument #{iarg} to '{fname}' ({msg})"))
+}
+
+pub fn check_table(state: &ExeState, iarg: usize, fname: &str) -> Result<GcCell<Table>> {
+    match state.get::<&Value>(iarg) {
+        Value::Table(t) => Ok(t.clone()),
+        _ => Err(arg_error(iarg, fname, &format!("table expected, got {}", type_name(state, iarg)))),
+    }
+}
+
+pub fn check_thread(state: &ExeState, iarg: usize, fname: &str) -> Result<GcCell<Thread>> {
+    match state.get::<&Value>(iarg) {
+        Value::Thread(t) => Ok(t.clone()),
+        _ => Err(arg_error(iarg, fname, &format!("coroutine expected, got {}", type_name(state, iarg)))),
+    }
+}
+
+pub fn check_function(state: &ExeState, iarg: usize, fname: &str) -> Result<Value> {
+    match state.get::<&Value>(iarg) {
+        f if f.is_function() => Ok(f.clone()),
+        _ => Err(arg_error(iarg, fname, &format!("function expected, got {}", type_name(state, iarg)))),
+    }
+}
+
+// string argument, numbers are converted
+pub fn check_string(state: &ExeState, iarg: usize, fname: &str) -> Result<Value> {
+    match state.get::<&Value>(iarg) {
+        v if v.is_string() => Ok(v.clone()),
+        v@(Value::Integer(_) | Value::Float(_)) => Ok(v.to_string().into()),
+        _ => Err(arg_error(iarg, fname, &format!("string expected, got {}", type_name(state, iarg)))),
+    }
+}
+
+// integer argument, strings are converted
+pub fn check_integer(state: &ExeState, iarg: usize, fname: &str) -> Result<i64> {
+    match state.get::<&Value>(iarg).to_number() {
+        Some(Value::Integer(i)) => Ok(i),
+        Some(Value::Float(f)) => ftoi(f).ok_or_else(||
+            arg_error(iarg, fname, "number has no integer representation")),
+        _ => Err(arg_error(iarg, fname, &format!("number expected, got {}", type_name(state, iarg)))),
+    }
+}
+
+pub fn opt_integer(state: &ExeState, iarg: usize, fname: &str, default: i64) -> Result<i64> {
+    match state.get::<&Value>(iarg) {
+        Value::Nil => Ok(default),
+        _ => check_integer(state, iarg, fname),
+    }
+}
+
+// float argument, strings are converted
+pub fn check_number(state: &ExeState, iarg: usize, fname: &str) -> Result<f64> {
+    match state.get::<&Value>(iarg).to_number() {
+        Some(Value::Integer(i)) => Ok(i as f64),
+        Some(Value::Float(f)) => Ok(f),
+        _ => Err(arg_error(iarg, fname, &format!("number expected, got {}", type_name(state, iarg)))),
+    }
+}
+
+// "no value" for absent arguments, which is different from nil
+fn type_name(state: &ExeState, iarg: usize) -> &'static str {
+    if iarg > state.get_top() {
+        "no value"
+    } else {
+        state.get::<&Value>(iarg).ty()
+    }
+}
+
+pub fn test_new_counter(state: &mut ExeState) -> Result<i32> {
+    let i = Cell::new(0_i32);
     let c = move |_: &mut ExeState| {
-        i += 1;
-        println!("counter: {i}");
-        0
+        i.set(i.get() + 1);
+        println!("counter: {}", i.get());
+        Ok(0)
     };
-    state.push(Value::RustClosure(Rc::new(RefCell::new(Box::new(c)))));
-    1
+    state.push(Value::RustClosure(Rc::new(Box::new(c))));
+    Ok(1)
 }
 
-pub fn ipairs_aux(state: &mut ExeState) -> i32 {
-    let table = match state.get::<&Value>(1) {
-        Value::Table(t) => t.borrow(),
-        _ => panic!("ipairs non-table"),
-    };
+pub fn ipairs_aux(state: &mut ExeState) -> Result<i32> {
+    let table = check_table(state, 1, "ipairs")?;
+    let i = check_integer(state, 2, "ipairs")?;
+    let table = table.borrow();
 
-    let i: i64 = state.get(2);
     if i < 0 || i as usize >= table.array.len() {
-        return 0;
+        return Ok(0);
     }
 
     let v = table.array[i as usize].clone();
@@ -29,12 +100,12 @@ pub fn ipairs_aux(state: &mut ExeState) -> i32 {
 
     state.push(i + 1);
     state.push(v);
-    2
+    Ok(2)
 }
 
-pub fn ipairs(state: &mut ExeState) -> i32 {
+pub fn ipairs(state: &mut ExeState) -> Result<i32> {
     state.push(Value::RustFunction(ipairs_aux));
     state.push(state.get::<&Value>(1).clone());
     state.push(0);
-    3
+    Ok(3)
 }
@@ -1,17 +1,426 @@
-use crate::{value::Value, vm::ExeState};
+use std::fs;
+use std::io::{self, Cursor, Read};
+use crate::{value::Value, vm::{ExeState, check_key}};
+use crate::error::{Error, Result};
+use crate::parse::{self, FuncProto};
+use crate::lualib::auxlib::{arg_error, check_table, check_function, check_integer, opt_integer, check_string};
 
-pub fn lua_print(state: &mut ExeState) -> i32 {
+// max length of the source shown in chunk names of string chunks
+const MAX_CHUNKID_SRC: usize = 40;
+
+pub fn lua_print(state: &mut ExeState) -> Result<i32> {
     for i in 1 ..= state.get_top() {
         if i != 1 {
             print!("\t");
         }
-        print!("{}", state.get::<&Value>(i).to_string());
+        let v = state.get::<&Value>(i).clone();
+        print!("{}", state.tostring(&v)?);
     }
-    println!("");
-    0
+    println!();
+    Ok(0)
 }
-pub fn lua_type(state: &mut ExeState) -> i32 {
+pub fn lua_type(state: &mut ExeState) -> Result<i32> {
     let ty = state.get::<&Value>(1).ty();
     state.push(ty);
-    1
-}
\ No newline at end of file
+    Ok(1)
+}
+// tonumber(e [, base])
+pub fn lua_tonumber(state: &mut ExeState) -> Result<i32> {
+    let v = match state.get::<&Value>(2) {
+        Value::Nil => {
+            if state.get_top() == 0 {
+                return Err(arg_error(1, "tonumber", "value expected"));
+            }
+            state.get::<&Value>(1).to_number().unwrap_or(Value::Nil)
+        }
+        _ => {
+            let base = check_integer(state, 2, "tonumber")?;
+            let s = match state.get::<&Value>(1) {
+                v if v.is_string() => v.clone(),
+                _ => return Err(arg_error(1, "tonumber", &format!("string expected, got {}",
+                        state.get::<&Value>(1).ty()))),
+            };
+            if !(2..=36).contains(&base) {
+                return Err(arg_error(2, "tonumber", "base out of range"));
+            }
+            str_to_int_base(s.as_ref(), base as u32).map_or(Value::Nil, Value::Integer)
+        }
+    };
+    state.push(v);
+    Ok(1)
+}
+
+// integer numeral in @base, with optional spaces and minus sign; wraps around
+fn str_to_int_base(s: &[u8], base: u32) -> Option<i64> {
+    let s = s.trim_ascii();
+    let (neg, digits) = match s.strip_prefix(b"-") {
+        Some(digits) => (true, digits),
+        None => (false, s),
+    };
+    if digits.is_empty() {
+        return None;
+    }
+    let n = digits.iter().try_fold(0_i64, |n, &b| {
+        let d = (b as char).to_digit(base)?;
+        Some(n.wrapping_mul(base as i64).wrapping_add(d as i64))
+    })?;
+    Some(if neg { n.wrapping_neg() } else { n })
+}
+
+pub fn lua_tostring(state: &mut ExeState) -> Result<i32> {
+    if state.get_top() == 0 {
+        return Err(arg_error(1, "tostring", "value expected"));
+    }
+    let v = state.get::<&Value>(1).clone();
+    let s = state.tostring(&v)?;
+    state.push(s);
+    Ok(1)
+}
+
+pub fn lua_assert(state: &mut ExeState) -> Result<i32> {
+    match state.get::<&Value>(1) {
+        Value::Nil | Value::Boolean(false) => {
+            match state.get::<&Value>(2) {
+                Value::Nil => Err(Error::runtime("assertion failed!")),
+                v => Err(Error::value(v.clone())),
+            }
+        }
+        _ => Ok(state.get_top() as i32),
+    }
+}
+
+pub fn lua_setmetatable(state: &mut ExeState) -> Result<i32> {
+    let t = check_table(state, 1, "setmetatable")?;
+    let mt = match state.get::<&Value>(2) {
+        Value::Nil => None,
+        Value::Table(mt) => Some(mt.clone()),
+        _ => return Err(arg_error(2, "setmetatable", "nil or table expected")),
+    };
+    if let Some(old) = &t.borrow().metatable {
+        if old.borrow().map.contains_key(&"__metatable".into()) {
+            return Err(Error::runtime("cannot change a protected metatable"));
+        }
+    }
+    t.borrow_mut().metatable = mt;
+    state.push(Value::Table(t));
+    Ok(1)
+}
+pub fn lua_getmetatable(state: &mut ExeState) -> Result<i32> {
+    let v = match state.get_metatable(state.get::<&Value>(1)) {
+        Some(mt) => {
+            // protected metatable
+            let protected = mt.borrow().map.get(&"__metatable".into()).cloned();
+            protected.unwrap_or(Value::Table(mt))
+        }
+        None => Value::Nil,
+    };
+    state.push(v);
+    Ok(1)
+}
+
+pub fn lua_rawget(state: &mut ExeState) -> Result<i32> {
+    let t = check_table(state, 1, "rawget")?;
+    let v = t.borrow().index(state.get::<&Value>(2)).clone();
+    state.push(v);
+    Ok(1)
+}
+pub fn lua_rawset(state: &mut ExeState) -> Result<i32> {
+    let t = check_table(state, 1, "rawset")?;
+    let key = state.get::<&Value>(2).clone();
+    check_key(&key)?;
+    let value = state.get::<&Value>(3).clone();
+    t.borrow_mut().new_index(key, value);
+    state.push(Value::Table(t));
+    Ok(1)
+}
+pub fn lua_rawequal(state: &mut ExeState) -> Result<i32> {
+    let eq = state.get::<&Value>(1) == state.get::<&Value>(2);
+    state.push(eq);
+    Ok(1)
+}
+pub fn lua_rawlen(state: &mut ExeState) -> Result<i32> {
+    let len = match state.get::<&Value>(1) {
+        Value::Table(t) => t.borrow().len(),
+        v if v.is_string() => AsRef::<[u8]>::as_ref(v).len(),
+        _ => return Err(arg_error(1, "rawlen", "table or string expected")),
+    };
+    state.push(len as i64);
+    Ok(1)
+}
+
+pub fn lua_next(state: &mut ExeState) -> Result<i32> {
+    let t = check_table(state, 1, "next")?;
+    let next = t.borrow().next(state.get::<&Value>(2))?;
+    match next {
+        Some((k, v)) => {
+            state.push(k);
+            state.push(v);
+            Ok(2)
+        }
+        None => {
+            state.push(Value::Nil);
+            Ok(1)
+        }
+    }
+}
+pub fn lua_pairs(state: &mut ExeState) -> Result<i32> {
+    if state.get_top() == 0 {
+        return Err(arg_error(1, "pairs", "value expected"));
+    }
+    let t = state.get::<&Value>(1).clone();
+    let mm = state.get_metatable(&t)
+        .and_then(|mt| mt.borrow().map.get(&"__pairs".into()).cloned());
+    match mm {
+        Some(mm) => {
+            // the first 3 results of __pairs(t)
+            let mut rets = state.call_multi(mm, &[t])?;
+            rets.resize(3, Value::Nil);
+            for v in rets {
+                state.push(v);
+            }
+        }
+        None => {
+            state.push(Value::RustFunction(lua_next));
+            state.push(t);
+            state.push(Value::Nil);
+        }
+    }
+    Ok(3)
+}
+
+// error(message [, level]), position is added to string message
+pub fn lua_error(state: &mut ExeState) -> Result<i32> {
+    let v = state.get::<&Value>(1).clone();
+    let level = opt_integer(state, 2, "error", 1)?;
+    match state.location(level) {
+        Some((source, line)) if v.is_string() => {
+            let msg = [format!("{source}:{line}: ").as_bytes(), v.as_ref()].concat();
+            Err(Error::value(msg.into()))
+        }
+        _ => Err(Error::value(v)),
+    }
+}
+
+// push the status and the results of protected call
+fn push_pcall_results(state: &mut ExeState, r: Result<Vec<Value>>) -> i32 {
+    match r {
+        Ok(rets) => {
+            state.push(true);
+            let n = rets.len();
+            for v in rets {
+                state.push(v);
+            }
+            n as i32 + 1
+        }
+        Err(err) => {
+            state.push(false);
+            state.push(err.to_value());
+            2
+        }
+    }
+}
+
+pub fn lua_pcall(state: &mut ExeState) -> Result<i32> {
+    if state.get_top() == 0 {
+        return Err(arg_error(1, "pcall", "value expected"));
+    }
+    let f = state.get::<&Value>(1).clone();
+    let args: Vec<Value> = (2..=state.get_top()).map(|i| state.get::<&Value>(i).clone()).collect();
+    let r = state.pcall(f, &args, Value::Nil);
+    Ok(push_pcall_results(state, r))
+}
+
+// xpcall(f, msgh, ...)
+pub fn lua_xpcall(state: &mut ExeState) -> Result<i32> {
+    let f = state.get::<&Value>(1).clone();
+    let msgh = check_function(state, 2, "xpcall")?;
+    let args: Vec<Value> = (3..=state.get_top()).map(|i| state.get::<&Value>(i).clone()).collect();
+    let r = state.pcall(f, &args, msgh);
+    Ok(push_pcall_results(state, r))
+}
+
+pub fn lua_collectgarbage(state: &mut ExeState) -> Result<i32> {
+    let opt = match state.get::<&Value>(1) {
+        Value::Nil => "collect".to_string(),
+        v if v.is_string() => v.to_string(),
+        v => return Err(arg_error(1, "collectgarbage", &format!("string expected, got {}", v.ty()))),
+    };
+    match opt.as_str() {
+        "collect" => {
+            state.heap().collect();
+            state.push(0);
+        }
+        "step" => { // no incremental collection, so finish a cycle
+            state.heap().collect();
+            state.push(true);
+        }
+        "count" => {
+            let kb = state.heap().memory() as f64 / 1024.0;
+            state.push(kb);
+        }
+        "stop" => {
+            state.heap().set_auto(false);
+            state.push(0);
+        }
+        "restart" => {
+            state.heap().set_auto(true);
+            state.push(0);
+        }
+        "isrunning" => {
+            let auto = state.heap().is_auto();
+            state.push(auto);
+        }
+        _ => return Err(arg_error(1, "collectgarbage", &format!("invalid option '{opt}'"))),
+    }
+    Ok(1)
+}
+
+// chunk name like luaO_chunkid(): with a leading '=' or '@' removed,
+// otherwise the first line of source in [string "..."]
+fn chunkid(name: &[u8]) -> String {
+    match name.first() {
+        Some(b'=' | b'@') => String::from_utf8_lossy(&name[1..]).into_owned(),
+        _ => {
+            let line = name.split(|&b| b == b'\n').next().unwrap_or_default();
+            let text = String::from_utf8_lossy(line);
+            let short: String = text.chars().take(MAX_CHUNKID_SRC).collect();
+            if line.len() < name.len() || short.len() < text.len() {
+                format!("[string \"{short}...\"]")
+            } else {
+                format!("[string \"{short}\"]")
+            }
+        }
+    }
+}
+
+// message returned by load() and loadfile(), with the chunk name
+fn load_error(chunkname: &str, err: &Error) -> String {
+    match err.line() {
+        0 => err.to_string(),
+        _ => format!("{chunkname}:{err}"),
+    }
+}
+
+// optional string argument @iarg
+fn opt_string(state: &ExeState, iarg: usize, fname: &str, default: &str) -> Result<String> {
+    match state.get::<&Value>(iarg) {
+        Value::Nil => Ok(default.into()),
+        _ => Ok(check_string(state, iarg, fname)?.to_string()),
+    }
+}
+
+// push the loaded chunk, or nil and the error message
+fn push_load_results(state: &mut ExeState, r: std::result::Result<FuncProto, String>, env: Option<Value>) -> i32 {
+    match r {
+        Ok(proto) => {
+            let f = state.new_chunk(proto, env);
+            state.push(f);
+            1
+        }
+        Err(msg) => {
+            state.push(Value::Nil);
+            state.push(msg);
+            2
+        }
+    }
+}
+
+// call the reader function until it returns nil or an empty string
+fn read_chunk(state: &mut ExeState, reader: Value) -> std::result::Result<Vec<u8>, String> {
+    let mut src = Vec::new();
+    loop {
+        match state.call(reader.clone(), &[]).map_err(|e| e.to_value().to_string())? {
+            Value::Nil => return Ok(src),
+            piece if piece.is_string() => {
+                let piece: &[u8] = piece.as_ref();
+                if piece.is_empty() {
+                    return Ok(src);
+                }
+                src.extend_from_slice(piece);
+            }
+            _ => return Err("reader function must return a string".into()),
+        }
+    }
+}
+
+// load(chunk [, chunkname [, mode [, env]]])
+//
+// The chunk is a string, or a function which returns the pieces of it.
+// The env, even nil, is set as the _ENV of the chunk if given.
+pub fn lua_load(state: &mut ExeState) -> Result<i32> {
+    let chunk = state.get::<&Value>(1).clone();
+    let mode = opt_string(state, 3, "load", "bt")?;
+    let env = (state.get_top() >= 4).then(|| state.get::<&Value>(4).clone());
+
+    let (src, chunkname) = if chunk.is_string() {
+        let name = match state.get::<&Value>(2) {
+            Value::Nil => chunk.clone(),
+            _ => check_string(state, 2, "load")?,
+        };
+        (AsRef::<[u8]>::as_ref(&chunk).to_vec(), chunkid(name.as_ref()))
+    } else if chunk.is_function() {
+        let name = match state.get::<&Value>(2) {
+            Value::Nil => "=(load)".into(),
+            _ => check_string(state, 2, "load")?,
+        };
+        let chunkname = chunkid(name.as_ref());
+        match read_chunk(state, chunk) {
+            Ok(src) => (src, chunkname),
+            Err(msg) => return Ok(push_load_results(state, Err(msg), None)),
+        }
+    } else {
+        return Err(arg_error(1, "load", &format!("string expected, got {}", chunk.ty())));
+    };
+
+    let r = parse::load_with_mode(Cursor::new(src), &chunkname, &mode)
+        .map_err(|e| load_error(&chunkname, &e));
+    Ok(push_load_results(state, r, env))
+}
+
+// load the file, or stdin if @fname is None. Skip the first line if it
+// starts with '#'
+fn load_file(fname: Option<&str>, mode: &str) -> std::result::Result<FuncProto, String> {
+    let (mut src, chunkname) = match fname {
+        None => {
+            let mut src = Vec::new();
+            io::stdin().read_to_end(&mut src).map_err(|e| format!("cannot read stdin: {e}"))?;
+            (src, "stdin")
+        }
+        Some(fname) => {
+            let src = fs::read(fname).map_err(|e| format!("cannot open {fname}: {e}"))?;
+            (src, fname)
+        }
+    };
+    parse::skip_comment(&mut src);
+    parse::load_with_mode(Cursor::new(src), chunkname, mode)
+        .map_err(|e| load_error(chunkname, &e))
+}
+
+fn opt_fname(state: &ExeState, fname: &str) -> Result<Option<String>> {
+    match state.get::<&Value>(1) {
+        Value::Nil => Ok(None),
+        _ => Ok(Some(check_string(state, 1, fname)?.to_string())),
+    }
+}
+
+// loadfile([filename [, mode [, env]]])
+pub fn lua_loadfile(state: &mut ExeState) -> Result<i32> {
+    let fname = opt_fname(state, "loadfile")?;
+    let mode = opt_string(state, 2, "loadfile", "bt")?;
+    let env = (state.get_top() >= 3).then(|| state.get::<&Value>(3).clone());
+    let r = load_file(fname.as_deref(), &mode);
+    Ok(push_load_results(state, r, env))
+}
+
+// dofile([filename]), return all results of the chunk
+pub fn lua_dofile(state: &mut ExeState) -> Result<i32> {
+    let fname = opt_fname(state, "dofile")?;
+    let proto = load_file(fname.as_deref(), "bt").map_err(Error::runtime)?;
+    let f = state.new_chunk(proto, None);
+    let rets = state.call_multi(f, &[])?;
+    let n = rets.len();
+    for v in rets {
+        state.push(v);
+    }
+    Ok(n as i32)
+}
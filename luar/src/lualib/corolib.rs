@@ -0,0 +1,133 @@
+use std::rc::Rc;
+
+use crate::value::{Value, Table, RustFunction};
+use crate::vm::{ExeState, ThreadStatus};
+use crate::error::Result;
+use crate::lualib::auxlib::{check_thread, check_function};
+
+// register the `coroutine` table
+pub fn open(state: &mut ExeState) {
+    let funcs: [(&str, RustFunction); 8] = [
+        ("close", co_close),
+        ("create", co_create),
+        ("isyieldable", co_isyieldable),
+        ("resume", co_resume),
+        ("running", co_running),
+        ("status", co_status),
+        ("wrap", co_wrap),
+        ("yield", co_yield),
+    ];
+    let mut lib = Table::new(0, funcs.len());
+    for (name, f) in funcs {
+        lib.map.insert(name.into(), Value::RustFunction(f));
+    }
+    let lib = Value::Table(state.heap().alloc_cell(lib));
+    state.set_global("coroutine", lib);
+}
+
+// arguments from @i to the top
+fn args_from(state: &ExeState, i: usize) -> Vec<Value> {
+    (i..=state.get_top()).map(|i| state.get::<&Value>(i).clone()).collect()
+}
+
+fn co_create(state: &mut ExeState) -> Result<i32> {
+    let f = check_function(state, 1, "create")?;
+    let co = state.new_thread(f);
+    state.push(Value::Thread(co));
+    Ok(1)
+}
+
+fn co_resume(state: &mut ExeState) -> Result<i32> {
+    let co = check_thread(state, 1, "resume")?;
+    let args = args_from(state, 2);
+    match state.resume(&co, &args) {
+        Ok(rets) => {
+            state.push(true);
+            let n = rets.len();
+            for v in rets {
+                state.push(v);
+            }
+            Ok(n as i32 + 1)
+        }
+        Err(err) => {
+            state.push(false);
+            state.push(err.to_value());
+            Ok(2)
+        }
+    }
+}
+
+fn co_yield(state: &mut ExeState) -> Result<i32> {
+    let n = state.get_top();
+    state.yield_values(n as i32)
+}
+
+fn co_status(state: &mut ExeState) -> Result<i32> {
+    let co = check_thread(state, 1, "status")?;
+    let status = match co.borrow().status() {
+        ThreadStatus::Suspended => "suspended",
+        ThreadStatus::Running => "running",
+        ThreadStatus::Normal => "normal",
+        ThreadStatus::Dead => "dead",
+    };
+    state.push(status);
+    Ok(1)
+}
+
+fn co_running(state: &mut ExeState) -> Result<i32> {
+    let (co, ismain) = state.running();
+    state.push(Value::Thread(co));
+    state.push(ismain);
+    Ok(2)
+}
+
+fn co_isyieldable(state: &mut ExeState) -> Result<i32> {
+    let yieldable = match state.get::<&Value>(1) {
+        Value::Nil => state.is_yieldable(),
+        _ => {
+            let co = check_thread(state, 1, "isyieldable")?;
+            if co.borrow().status() == ThreadStatus::Running {
+                state.is_yieldable()
+            } else {
+                co.borrow().is_yieldable()
+            }
+        }
+    };
+    state.push(yieldable);
+    Ok(1)
+}
+
+fn co_close(state: &mut ExeState) -> Result<i32> {
+    let co = check_thread(state, 1, "close")?;
+    let r = state.close_thread(&co)?;
+    match r {
+        None => {
+            state.push(true);
+            Ok(1)
+        }
+        Some(err) => {
+            state.push(false);
+            state.push(err.to_value());
+            Ok(2)
+        }
+    }
+}
+
+// like resume(), but errors are propagated
+fn co_wrap(state: &mut ExeState) -> Result<i32> {
+    let f = check_function(state, 1, "wrap")?;
+    let co = state.new_thread(f);
+    let c = move |state: &mut ExeState| {
+        let args = args_from(state, 1);
+        // resume() checks the status first, e.g. raise error if called
+        // in the coroutine itself
+        let rets = state.resume(&co, &args)?;
+        let n = rets.len();
+        for v in rets {
+            state.push(v);
+        }
+        Ok(n as i32)
+    };
+    state.push(Value::RustClosure(Rc::new(Box::new(c))));
+    Ok(1)
+}
@@ -0,0 +1,359 @@
+use std::{cell::RefCell, rc::Rc};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::value::{Value, Table, RustFunction};
+use crate::vm::ExeState;
+use crate::error::{Error, Result};
+use crate::utils::ftoi;
+use crate::lualib::auxlib::{arg_error, check_integer, opt_integer, check_number};
+
+// register the `math` table
+pub fn open(state: &mut ExeState) {
+    let funcs: [(&str, RustFunction); 21] = [
+        ("abs", math_abs),
+        ("ceil", math_ceil),
+        ("floor", math_floor),
+        ("fmod", math_fmod),
+        ("modf", math_modf),
+        ("sqrt", math_sqrt),
+        ("exp", math_exp),
+        ("log", math_log),
+        ("sin", math_sin),
+        ("cos", math_cos),
+        ("tan", math_tan),
+        ("asin", math_asin),
+        ("acos", math_acos),
+        ("atan", math_atan),
+        ("tointeger", math_tointeger),
+        ("type", math_type),
+        ("ult", math_ult),
+        ("max", math_max),
+        ("min", math_min),
+        ("rad", math_rad),
+        ("deg", math_deg),
+    ];
+    let mut lib = Table::new(0, funcs.len() + 8);
+    for (name, f) in funcs {
+        lib.map.insert(name.into(), Value::RustFunction(f));
+    }
+    lib.map.insert("pi".into(), Value::Float(std::f64::consts::PI));
+    lib.map.insert("huge".into(), Value::Float(f64::INFINITY));
+    lib.map.insert("maxinteger".into(), Value::Integer(i64::MAX));
+    lib.map.insert("mininteger".into(), Value::Integer(i64::MIN));
+
+    // random() and randomseed() share the generator
+    let rng = Rc::new(RefCell::new(Xoshiro256::from_time()));
+    let rng2 = rng.clone();
+    let random = move |state: &mut ExeState| math_random(state, &mut rng.borrow_mut());
+    let randomseed = move |state: &mut ExeState| math_randomseed(state, &mut rng2.borrow_mut());
+    lib.map.insert("random".into(), Value::RustClosure(Rc::new(Box::new(random))));
+    lib.map.insert("randomseed".into(), Value::RustClosure(Rc::new(Box::new(randomseed))));
+
+    let lib = Value::Table(state.heap().alloc_cell(lib));
+    state.set_global("math", lib);
+}
+
+// the argument must be a number (strings are converted), and keep its type
+fn check_num_value(state: &ExeState, iarg: usize, fname: &str) -> Result<Value> {
+    check_number(state, iarg, fname)?;
+    Ok(state.get::<&Value>(iarg).to_number().unwrap())
+}
+
+// convert float to integer if it fits, used by floor() and ceil()
+fn float_to_value(f: f64) -> Value {
+    match ftoi(f) {
+        Some(i) => Value::Integer(i),
+        None => Value::Float(f),
+    }
+}
+
+fn math_abs(state: &mut ExeState) -> Result<i32> {
+    let v = match check_num_value(state, 1, "abs")? {
+        Value::Integer(i) => Value::Integer(i.wrapping_abs()),
+        Value::Float(f) => Value::Float(f.abs()),
+        _ => unreachable!(),
+    };
+    state.push(v);
+    Ok(1)
+}
+
+fn math_floor(state: &mut ExeState) -> Result<i32> {
+    let v = match check_num_value(state, 1, "floor")? {
+        Value::Float(f) => float_to_value(f.floor()),
+        v => v, // integer is its own floor
+    };
+    state.push(v);
+    Ok(1)
+}
+
+fn math_ceil(state: &mut ExeState) -> Result<i32> {
+    let v = match check_num_value(state, 1, "ceil")? {
+        Value::Float(f) => float_to_value(f.ceil()),
+        v => v,
+    };
+    state.push(v);
+    Ok(1)
+}
+
+fn math_fmod(state: &mut ExeState) -> Result<i32> {
+    let a = check_num_value(state, 1, "fmod")?;
+    let b = check_num_value(state, 2, "fmod")?;
+    let v = match (a, b) {
+        (Value::Integer(a), Value::Integer(b)) => match b {
+            0 => return Err(arg_error(2, "fmod", "zero")),
+            -1 => Value::Integer(0), // avoid overflow with mininteger
+            _ => Value::Integer(a % b), // truncated, same as C
+        }
+        _ => Value::Float(check_number(state, 1, "fmod")? % check_number(state, 2, "fmod")?),
+    };
+    state.push(v);
+    Ok(1)
+}
+
+// integral part (rounds toward zero) and fractional part
+fn math_modf(state: &mut ExeState) -> Result<i32> {
+    match check_num_value(state, 1, "modf")? {
+        Value::Integer(i) => {
+            state.push(i);
+            state.push(0.0);
+        }
+        _ => {
+            let n = check_number(state, 1, "modf")?;
+            let ip = n.trunc();
+            state.push(ip);
+            // test needed for inf/-inf
+            state.push(if n == ip { 0.0 } else { n - ip });
+        }
+    }
+    Ok(2)
+}
+
+fn math_sqrt(state: &mut ExeState) -> Result<i32> {
+    let f = check_number(state, 1, "sqrt")?;
+    state.push(f.sqrt());
+    Ok(1)
+}
+
+fn math_exp(state: &mut ExeState) -> Result<i32> {
+    let f = check_number(state, 1, "exp")?;
+    state.push(f.exp());
+    Ok(1)
+}
+
+fn math_log(state: &mut ExeState) -> Result<i32> {
+    let x = check_number(state, 1, "log")?;
+    let r = match state.get::<&Value>(2) {
+        Value::Nil => x.ln(),
+        _ => match check_number(state, 2, "log")? {
+            2.0 => x.log2(),
+            10.0 => x.log10(),
+            base => x.ln() / base.ln(),
+        }
+    };
+    state.push(r);
+    Ok(1)
+}
+
+fn math_sin(state: &mut ExeState) -> Result<i32> {
+    let f = check_number(state, 1, "sin")?;
+    state.push(f.sin());
+    Ok(1)
+}
+
+fn math_cos(state: &mut ExeState) -> Result<i32> {
+    let f = check_number(state, 1, "cos")?;
+    state.push(f.cos());
+    Ok(1)
+}
+
+fn math_tan(state: &mut ExeState) -> Result<i32> {
+    let f = check_number(state, 1, "tan")?;
+    state.push(f.tan());
+    Ok(1)
+}
+
+fn math_asin(state: &mut ExeState) -> Result<i32> {
+    let f = check_number(state, 1, "asin")?;
+    state.push(f.asin());
+    Ok(1)
+}
+
+fn math_acos(state: &mut ExeState) -> Result<i32> {
+    let f = check_number(state, 1, "acos")?;
+    state.push(f.acos());
+    Ok(1)
+}
+
+fn math_atan(state: &mut ExeState) -> Result<i32> {
+    let y = check_number(state, 1, "atan")?;
+    let x = match state.get::<&Value>(2) {
+        Value::Nil => 1.0,
+        _ => check_number(state, 2, "atan")?,
+    };
+    state.push(y.atan2(x));
+    Ok(1)
+}
+
+fn math_rad(state: &mut ExeState) -> Result<i32> {
+    let f = check_number(state, 1, "rad")?;
+    state.push(f.to_radians());
+    Ok(1)
+}
+
+fn math_deg(state: &mut ExeState) -> Result<i32> {
+    let f = check_number(state, 1, "deg")?;
+    state.push(f.to_degrees());
+    Ok(1)
+}
+
+fn math_tointeger(state: &mut ExeState) -> Result<i32> {
+    let v = match *state.get::<&Value>(1) {
+        Value::Integer(i) => Value::Integer(i),
+        Value::Float(f) => ftoi(f).map_or(Value::Nil, Value::Integer),
+        _ if state.get_top() == 0 => return Err(arg_error(1, "tointeger", "value expected")),
+        _ => Value::Nil,
+    };
+    state.push(v);
+    Ok(1)
+}
+
+fn math_type(state: &mut ExeState) -> Result<i32> {
+    let v = match state.get::<&Value>(1) {
+        Value::Integer(_) => Value::from("integer"),
+        Value::Float(_) => Value::from("float"),
+        _ if state.get_top() == 0 => return Err(arg_error(1, "type", "value expected")),
+        _ => Value::Nil,
+    };
+    state.push(v);
+    Ok(1)
+}
+
+fn math_ult(state: &mut ExeState) -> Result<i32> {
+    let a = check_integer(state, 1, "ult")?;
+    let b = check_integer(state, 2, "ult")?;
+    state.push((a as u64) < (b as u64));
+    Ok(1)
+}
+
+fn math_max(state: &mut ExeState) -> Result<i32> {
+    min_max(state, "max", |state, a, b| state.less_than(a, b))
+}
+
+fn math_min(state: &mut ExeState) -> Result<i32> {
+    min_max(state, "min", |state, a, b| state.less_than(b, a))
+}
+
+// keep the argument for which @replace(current, arg) is true
+fn min_max(state: &mut ExeState, fname: &str,
+        replace: fn(&mut ExeState, &Value, &Value) -> Result<bool>) -> Result<i32> {
+
+    let mut best = check_num_value(state, 1, fname)?;
+    for i in 2 ..= state.get_top() {
+        let v = check_num_value(state, i, fname)?;
+        if replace(state, &best, &v)? {
+            best = v;
+        }
+    }
+    state.push(best);
+    Ok(1)
+}
+
+// xoshiro256**, the pseudo-random generator of reference Lua 5.4
+struct Xoshiro256 {
+    s: [u64; 4],
+}
+
+impl Xoshiro256 {
+    fn new(n1: u64, n2: u64) -> Self {
+        // avoid a zero state
+        let mut rng = Xoshiro256 { s: [n1, 0xff, n2, 0] };
+        // discard initial values to "spread" seed
+        for _ in 0..16 {
+            rng.next();
+        }
+        rng
+    }
+
+    fn from_time() -> Self {
+        let d = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let addr = &d as *const _ as u64;
+        Self::new(d.as_secs() ^ d.subsec_nanos() as u64, addr)
+    }
+
+    fn next(&mut self) -> u64 {
+        let s = &mut self.s;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+
+    // project a random integer into [0, n]
+    fn project(&mut self, ran: u64, n: u64) -> u64 {
+        // is 'n + 1' a power of 2?
+        if n & n.wrapping_add(1) == 0 {
+            return ran & n;
+        }
+        // the smallest (2^b - 1) not smaller than n
+        let lim = u64::MAX >> n.leading_zeros();
+        let mut ran = ran & lim;
+        while ran > n {
+            ran = self.next() & lim;
+        }
+        ran
+    }
+}
+
+// float in [0, 1), with the higher 53 bits
+fn i2d(x: u64) -> f64 {
+    (x >> 11) as f64 * (0.5 / (1_u64 << 52) as f64)
+}
+
+fn math_random(state: &mut ExeState, rng: &mut Xoshiro256) -> Result<i32> {
+    let rv = rng.next();
+    let (low, up) = match state.get_top() {
+        0 => {
+            state.push(i2d(rv));
+            return Ok(1);
+        }
+        1 => {
+            let up = check_integer(state, 1, "random")?;
+            if up == 0 {
+                // full random integer
+                state.push(rv as i64);
+                return Ok(1);
+            }
+            (1, up)
+        }
+        2 => (check_integer(state, 1, "random")?, check_integer(state, 2, "random")?),
+        _ => return Err(Error::runtime("wrong number of arguments")),
+    };
+    if low > up {
+        return Err(arg_error(1, "random", "interval is empty"));
+    }
+    let p = rng.project(rv, (up as u64).wrapping_sub(low as u64));
+    state.push(p.wrapping_add(low as u64) as i64);
+    Ok(1)
+}
+
+fn math_randomseed(state: &mut ExeState, rng: &mut Xoshiro256) -> Result<i32> {
+    let (n1, n2) = if state.get_top() == 0 {
+        let d = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        (d.as_secs() as i64 ^ d.subsec_nanos() as i64, rng.next() as i64)
+    } else {
+        let n1 = match *state.get::<&Value>(1) {
+            Value::Float(f) => f as i64,
+            _ => check_integer(state, 1, "randomseed")?,
+        };
+        (n1, opt_integer(state, 2, "randomseed", 0)?)
+    };
+    *rng = Xoshiro256::new(n1 as u64, n2 as u64);
+    state.push(n1);
+    state.push(n2);
+    Ok(2)
+}
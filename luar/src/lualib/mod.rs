@@ -1,2 +1,7 @@
 pub mod baselib;
-pub mod auxlib;
\ No newline at end of file
+pub mod auxlib;
+pub mod strlib;
+pub mod tablib;
+pub mod mathlib;
+pub mod corolib;
+mod pattern;
\ No newline at end of file
@@ -0,0 +1,341 @@
+// Lua patterns, ported from lstrlib.c of the official implementation.
+//
+// All positions are byte indexes into the subject or pattern.
+
+use crate::value::Value;
+use crate::error::{Error, Result};
+
+const MAX_CALLS: usize = 200;
+const MAX_CAPTURES: usize = 32;
+
+const CAP_UNFINISHED: isize = -1;
+const CAP_POSITION: isize = -2;
+
+const L_ESC: u8 = b'%';
+const SPECIALS: &[u8] = b"^$*+?.([%-";
+
+pub struct MatchState<'a> {
+    src: &'a [u8],
+    pat: &'a [u8],
+    matchdepth: usize,
+    level: usize,
+    capture: [(usize, isize); MAX_CAPTURES], // (start, length)
+}
+
+impl<'a> MatchState<'a> {
+    pub fn new(src: &'a [u8], pat: &'a [u8]) -> Self {
+        MatchState {
+            src,
+            pat,
+            matchdepth: MAX_CALLS,
+            level: 0,
+            capture: [(0, 0); MAX_CAPTURES],
+        }
+    }
+
+    // reset before each matching try
+    pub fn reprepstate(&mut self) {
+        self.level = 0;
+        self.matchdepth = MAX_CALLS;
+    }
+
+    // match src[s..] with pat[p..], return the end of match
+    pub fn do_match(&mut self, s: usize, p: usize) -> Result<Option<usize>> {
+        if self.matchdepth == 0 {
+            return Err(Error::runtime("pattern too complex"));
+        }
+        self.matchdepth -= 1;
+        let ret = self.match_loop(s, p);
+        self.matchdepth += 1;
+        ret
+    }
+
+    fn match_loop(&mut self, mut s: usize, mut p: usize) -> Result<Option<usize>> {
+        let pat = self.pat;
+        loop {
+            if p == pat.len() {
+                return Ok(Some(s));
+            }
+            match (pat[p], pat.get(p + 1).copied()) {
+                (b'(', Some(b')')) => return self.start_capture(s, p + 2, CAP_POSITION),
+                (b'(', _) => return self.start_capture(s, p + 1, CAP_UNFINISHED),
+                (b')', _) => return self.end_capture(s, p + 1),
+                (b'$', None) => {
+                    return Ok((s == self.src.len()).then_some(s));
+                }
+                (L_ESC, Some(b'b')) => match self.match_balance(s, p + 2)? {
+                    Some(s1) => {
+                        s = s1;
+                        p += 4;
+                    }
+                    None => return Ok(None),
+                }
+                (L_ESC, Some(b'f')) => {
+                    p += 2;
+                    if pat.get(p) != Some(&b'[') {
+                        return Err(Error::runtime("missing '[' after '%f' in pattern"));
+                    }
+                    let ep = self.class_end(p)?;
+                    let prev = if s == 0 { 0 } else { self.src[s - 1] };
+                    let cur = self.src.get(s).copied().unwrap_or(0);
+                    if !self.match_bracket_class(prev, p, ep - 1)
+                            && self.match_bracket_class(cur, p, ep - 1) {
+                        p = ep;
+                    } else {
+                        return Ok(None);
+                    }
+                }
+                (L_ESC, Some(l@b'0'..=b'9')) => match self.match_capture(s, l)? {
+                    Some(s1) => {
+                        s = s1;
+                        p += 2;
+                    }
+                    None => return Ok(None),
+                }
+                _ => {
+                    let ep = self.class_end(p)?;
+                    let epc = pat.get(ep).copied();
+                    if !self.single_match(s, p, ep) {
+                        if let Some(b'*' | b'?' | b'-') = epc {
+                            // accept empty
+                            p = ep + 1;
+                        } else {
+                            return Ok(None);
+                        }
+                    } else {
+                        match epc {
+                            Some(b'?') => match self.do_match(s + 1, ep + 1)? {
+                                Some(r) => return Ok(Some(r)),
+                                None => p = ep + 1,
+                            }
+                            Some(b'+') => return self.max_expand(s + 1, p, ep),
+                            Some(b'*') => return self.max_expand(s, p, ep),
+                            Some(b'-') => return self.min_expand(s, p, ep),
+                            _ => {
+                                s += 1;
+                                p = ep;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // end of the single char class starting at @p
+    fn class_end(&self, mut p: usize) -> Result<usize> {
+        let pat = self.pat;
+        let c = pat[p];
+        p += 1;
+        if c == L_ESC {
+            if p >= pat.len() {
+                return Err(Error::runtime("malformed pattern (ends with '%')"));
+            }
+            return Ok(p + 1);
+        }
+        if c == b'[' {
+            if pat.get(p) == Some(&b'^') {
+                p += 1;
+            }
+            // look for a ']'
+            loop {
+                if p >= pat.len() {
+                    return Err(Error::runtime("malformed pattern (missing ']')"));
+                }
+                let c = pat[p];
+                p += 1;
+                if c == L_ESC && p < pat.len() {
+                    p += 1; // skip escapes (e.g. '%]')
+                }
+                if pat.get(p) == Some(&b']') {
+                    break;
+                }
+            }
+            return Ok(p + 1);
+        }
+        Ok(p)
+    }
+
+    fn single_match(&self, s: usize, p: usize, ep: usize) -> bool {
+        let Some(&c) = self.src.get(s) else {
+            return false;
+        };
+        match self.pat[p] {
+            b'.' => true,
+            L_ESC => match_class(c, self.pat[p + 1]),
+            b'[' => self.match_bracket_class(c, p, ep - 1),
+            pc => pc == c,
+        }
+    }
+
+    // @p points to '[' and @ec points to ']'
+    fn match_bracket_class(&self, c: u8, mut p: usize, ec: usize) -> bool {
+        let pat = self.pat;
+        let mut sig = true;
+        if pat[p + 1] == b'^' {
+            sig = false;
+            p += 1;
+        }
+        p += 1;
+        while p < ec {
+            if pat[p] == L_ESC {
+                p += 1;
+                if match_class(c, pat[p]) {
+                    return sig;
+                }
+            } else if pat[p + 1] == b'-' && p + 2 < ec {
+                if pat[p] <= c && c <= pat[p + 2] {
+                    return sig;
+                }
+                p += 2;
+            } else if pat[p] == c {
+                return sig;
+            }
+            p += 1;
+        }
+        !sig
+    }
+
+    fn max_expand(&mut self, s: usize, p: usize, ep: usize) -> Result<Option<usize>> {
+        let mut i = 0;
+        while self.single_match(s + i, p, ep) {
+            i += 1;
+        }
+        // try with maximum repetitions, then less
+        loop {
+            if let Some(r) = self.do_match(s + i, ep + 1)? {
+                return Ok(Some(r));
+            }
+            if i == 0 {
+                return Ok(None);
+            }
+            i -= 1;
+        }
+    }
+
+    fn min_expand(&mut self, mut s: usize, p: usize, ep: usize) -> Result<Option<usize>> {
+        loop {
+            if let Some(r) = self.do_match(s, ep + 1)? {
+                return Ok(Some(r));
+            } else if self.single_match(s, p, ep) {
+                s += 1;
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn start_capture(&mut self, s: usize, p: usize, what: isize) -> Result<Option<usize>> {
+        if self.level >= MAX_CAPTURES {
+            return Err(Error::runtime("too many captures"));
+        }
+        self.capture[self.level] = (s, what);
+        self.level += 1;
+        let ret = self.do_match(s, p)?;
+        if ret.is_none() {
+            self.level -= 1; // undo capture
+        }
+        Ok(ret)
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> Result<Option<usize>> {
+        let l = self.capture_to_close()?;
+        self.capture[l].1 = (s - self.capture[l].0) as isize;
+        let ret = self.do_match(s, p)?;
+        if ret.is_none() {
+            self.capture[l].1 = CAP_UNFINISHED; // undo capture
+        }
+        Ok(ret)
+    }
+
+    fn match_balance(&self, s: usize, p: usize) -> Result<Option<usize>> {
+        if p + 1 >= self.pat.len() {
+            return Err(Error::runtime("malformed pattern (missing arguments to '%b')"));
+        }
+        let (b, e) = (self.pat[p], self.pat[p + 1]);
+        if self.src.get(s) != Some(&b) {
+            return Ok(None);
+        }
+        let mut cont = 1;
+        for (i, &c) in self.src.iter().enumerate().skip(s + 1) {
+            if c == e {
+                cont -= 1;
+                if cont == 0 {
+                    return Ok(Some(i + 1));
+                }
+            } else if c == b {
+                cont += 1;
+            }
+        }
+        Ok(None)
+    }
+
+    // back reference %1-%9
+    fn match_capture(&self, s: usize, l: u8) -> Result<Option<usize>> {
+        let l = self.check_capture(l)?;
+        let (start, len) = self.capture[l];
+        let len = len as usize;
+        if self.src.len() - s >= len && self.src[start..start+len] == self.src[s..s+len] {
+            Ok(Some(s + len))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn check_capture(&self, l: u8) -> Result<usize> {
+        let l = l.wrapping_sub(b'1') as usize;
+        if l >= self.level || self.capture[l].1 == CAP_UNFINISHED {
+            return Err(Error::runtime(format!("invalid capture index %{}", l.wrapping_add(1))));
+        }
+        Ok(l)
+    }
+
+    fn capture_to_close(&self) -> Result<usize> {
+        (0..self.level).rev()
+            .find(|&l| self.capture[l].1 == CAP_UNFINISHED)
+            .ok_or_else(|| Error::runtime("invalid pattern capture"))
+    }
+
+    // the @i-th capture, or the whole match src[s..e] if there is no capture
+    pub fn get_capture(&self, i: usize, s: usize, e: usize) -> Result<Value> {
+        if i >= self.level {
+            if i != 0 {
+                return Err(Error::runtime(format!("invalid capture index %{}", i + 1)));
+            }
+            return Ok(self.src[s..e].into());
+        }
+        match self.capture[i] {
+            (_, CAP_UNFINISHED) => Err(Error::runtime("unfinished capture")),
+            (start, CAP_POSITION) => Ok(Value::Integer(start as i64 + 1)),
+            (start, len) => Ok(self.src[start..start + len as usize].into()),
+        }
+    }
+
+    // all captures, or the whole match if there is no capture and @whole
+    pub fn get_captures(&self, s: usize, e: usize, whole: bool) -> Result<Vec<Value>> {
+        let n = if self.level == 0 && whole { 1 } else { self.level };
+        (0..n).map(|i| self.get_capture(i, s, e)).collect()
+    }
+}
+
+pub fn no_specials(pat: &[u8]) -> bool {
+    !pat.iter().any(|c| SPECIALS.contains(c))
+}
+
+// character classes in C locale
+fn match_class(c: u8, cl: u8) -> bool {
+    let res = match cl.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'g' => c.is_ascii_graphic(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        b's' => matches!(c, b' ' | b'\t' | b'\n' | b'\r' | 0x0b | 0x0c),
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        _ => return cl == c,
+    };
+    if cl.is_ascii_uppercase() { !res } else { res }
+}
@@ -0,0 +1,1117 @@
+use std::{cell::Cell, rc::Rc};
+
+use crate::value::{Value, Table, RustFunction};
+use crate::vm::ExeState;
+use crate::error::{Error, Result};
+use crate::lualib::auxlib::{arg_error, check_string, check_integer, opt_integer, check_number, check_function};
+use crate::lualib::pattern::{MatchState, no_specials};
+
+// limit of strings created by rep() and pack()
+const MAX_STR_LEN: usize = i32::MAX as usize;
+
+// register the `string` table, and the metatable for all strings
+pub fn open(state: &mut ExeState) {
+    let funcs: [(&str, RustFunction); 17] = [
+        ("len", str_len),
+        ("sub", str_sub),
+        ("upper", str_upper),
+        ("lower", str_lower),
+        ("rep", str_rep),
+        ("reverse", str_reverse),
+        ("byte", str_byte),
+        ("char", str_char),
+        ("format", str_format),
+        ("find", str_find),
+        ("match", str_match),
+        ("gmatch", str_gmatch),
+        ("gsub", str_gsub),
+        ("pack", str_pack),
+        ("unpack", str_unpack),
+        ("packsize", str_packsize),
+        ("dump", str_dump),
+    ];
+    let mut lib = Table::new(0, funcs.len());
+    for (name, f) in funcs {
+        lib.map.insert(name.into(), Value::RustFunction(f));
+    }
+    let lib = Value::Table(state.heap().alloc_cell(lib));
+
+    // so `s:upper()` works
+    let mut meta = Table::new(0, 1);
+    meta.map.insert("__index".into(), lib.clone());
+    let meta = state.heap().alloc_cell(meta);
+    state.set_string_metatable(Some(meta));
+
+    state.set_global("string", lib);
+}
+
+// translate a relative initial position: negative means counting back
+// from the end, and the result is clipped to [1, inf)
+fn start_pos(pos: i64, len: usize) -> usize {
+    if pos > 0 {
+        pos as usize
+    } else if pos == 0 || pos < -(len as i64) {
+        1
+    } else {
+        (len as i64 + pos + 1) as usize
+    }
+}
+
+// translate a relative end position, clipped to [0, len]
+fn end_pos(pos: i64, len: usize) -> usize {
+    if pos > len as i64 {
+        len
+    } else if pos >= 0 {
+        pos as usize
+    } else if pos < -(len as i64) {
+        0
+    } else {
+        (len as i64 + pos + 1) as usize
+    }
+}
+
+fn str_len(state: &mut ExeState) -> Result<i32> {
+    let s = check_string(state, 1, "len")?;
+    state.push(AsRef::<[u8]>::as_ref(&s).len() as i64);
+    Ok(1)
+}
+
+fn str_sub(state: &mut ExeState) -> Result<i32> {
+    let s = check_string(state, 1, "sub")?;
+    let s: &[u8] = s.as_ref();
+    let i = start_pos(check_integer(state, 2, "sub")?, s.len());
+    let j = end_pos(opt_integer(state, 3, "sub", -1)?, s.len());
+    let sub = if i > j { &[] } else { &s[i-1..j] };
+    state.push(sub);
+    Ok(1)
+}
+
+fn str_upper(state: &mut ExeState) -> Result<i32> {
+    let s = check_string(state, 1, "upper")?;
+    state.push(AsRef::<[u8]>::as_ref(&s).to_ascii_uppercase());
+    Ok(1)
+}
+
+fn str_lower(state: &mut ExeState) -> Result<i32> {
+    let s = check_string(state, 1, "lower")?;
+    state.push(AsRef::<[u8]>::as_ref(&s).to_ascii_lowercase());
+    Ok(1)
+}
+
+fn str_rep(state: &mut ExeState) -> Result<i32> {
+    let s = check_string(state, 1, "rep")?;
+    let s: &[u8] = s.as_ref();
+    let n = check_integer(state, 2, "rep")?;
+    let sep = match state.get::<&Value>(3) {
+        Value::Nil => Value::from(""),
+        _ => check_string(state, 3, "rep")?,
+    };
+    let sep: &[u8] = sep.as_ref();
+
+    if n <= 0 {
+        state.push("");
+        return Ok(1);
+    }
+    let total = (s.len() + sep.len()).checked_mul(n as usize)
+        .filter(|&total| total - sep.len() <= MAX_STR_LEN)
+        .ok_or_else(|| Error::runtime("resulting string too large"))?;
+
+    let mut buf = Vec::with_capacity(total - sep.len());
+    for i in 0..n {
+        if i != 0 {
+            buf.extend_from_slice(sep);
+        }
+        buf.extend_from_slice(s);
+    }
+    state.push(buf);
+    Ok(1)
+}
+
+fn str_reverse(state: &mut ExeState) -> Result<i32> {
+    let s = check_string(state, 1, "reverse")?;
+    let mut s = AsRef::<[u8]>::as_ref(&s).to_vec();
+    s.reverse();
+    state.push(s);
+    Ok(1)
+}
+
+fn str_byte(state: &mut ExeState) -> Result<i32> {
+    let s = check_string(state, 1, "byte")?;
+    let s: &[u8] = s.as_ref();
+    let pi = opt_integer(state, 2, "byte", 1)?;
+    let i = start_pos(pi, s.len());
+    let j = end_pos(opt_integer(state, 3, "byte", pi)?, s.len());
+    if i > j {
+        return Ok(0);
+    }
+    for &b in &s[i-1..j] {
+        state.push(b as i64);
+    }
+    Ok((j - i + 1) as i32)
+}
+
+fn str_char(state: &mut ExeState) -> Result<i32> {
+    let mut buf = Vec::new();
+    for iarg in 1 ..= state.get_top() {
+        let c = check_integer(state, iarg, "char")?;
+        let c = u8::try_from(c).map_err(|_| arg_error(iarg, "char", "value out of range"))?;
+        buf.push(c);
+    }
+    state.push(buf);
+    Ok(1)
+}
+
+fn str_find(state: &mut ExeState) -> Result<i32> {
+    str_find_aux(state, true)
+}
+
+fn str_match(state: &mut ExeState) -> Result<i32> {
+    str_find_aux(state, false)
+}
+
+fn str_find_aux(state: &mut ExeState, find: bool) -> Result<i32> {
+    let fname = if find { "find" } else { "match" };
+    let s = check_string(state, 1, fname)?;
+    let s: &[u8] = s.as_ref();
+    let p = check_string(state, 2, fname)?;
+    let p: &[u8] = p.as_ref();
+    let init = start_pos(opt_integer(state, 3, fname, 1)?, s.len()) - 1;
+    if init > s.len() {
+        state.push(());
+        return Ok(1);
+    }
+
+    // explicit request or no special characters
+    if find && (state.get::<bool>(4) || no_specials(p)) {
+        return match find_plain(&s[init..], p) {
+            Some(start) => {
+                state.push((init + start + 1) as i64);
+                state.push((init + start + p.len()) as i64);
+                Ok(2)
+            }
+            None => {
+                state.push(());
+                Ok(1)
+            }
+        };
+    }
+
+    let anchor = p.first() == Some(&b'^');
+    let pstart = anchor as usize;
+    let mut ms = MatchState::new(s, p);
+    let mut s1 = init;
+    loop {
+        ms.reprepstate();
+        if let Some(e) = ms.do_match(s1, pstart)? {
+            let mut n = 0;
+            if find {
+                state.push((s1 + 1) as i64);
+                state.push(e as i64);
+                n = 2;
+            }
+            for cap in ms.get_captures(s1, e, !find)? {
+                state.push(cap);
+                n += 1;
+            }
+            return Ok(n);
+        }
+        s1 += 1;
+        if anchor || s1 > s.len() {
+            break;
+        }
+    }
+    state.push(());
+    Ok(1)
+}
+
+fn find_plain(s: &[u8], p: &[u8]) -> Option<usize> {
+    if p.is_empty() {
+        return Some(0);
+    }
+    s.windows(p.len()).position(|w| w == p)
+}
+
+fn str_gmatch(state: &mut ExeState) -> Result<i32> {
+    let s = check_string(state, 1, "gmatch")?;
+    let p = check_string(state, 2, "gmatch")?;
+    let len = AsRef::<[u8]>::as_ref(&s).len();
+    let init = start_pos(opt_integer(state, 3, "gmatch", 1)?, len) - 1;
+
+    // start matching from @pos, and avoid matching the empty string
+    // at @lastmatch again
+    let pos = Cell::new(init);
+    let lastmatch = Cell::new(None);
+    let f = move |state: &mut ExeState| {
+        let src: &[u8] = s.as_ref();
+        let mut ms = MatchState::new(src, p.as_ref());
+        while pos.get() <= src.len() {
+            ms.reprepstate();
+            match ms.do_match(pos.get(), 0)? {
+                Some(e) if Some(e) != lastmatch.get() => {
+                    let caps = ms.get_captures(pos.get(), e, true)?;
+                    let n = caps.len();
+                    pos.set(e);
+                    lastmatch.set(Some(e));
+                    for cap in caps {
+                        state.push(cap);
+                    }
+                    return Ok(n as i32);
+                }
+                _ => pos.set(pos.get() + 1),
+            }
+        }
+        Ok(0)
+    };
+    state.push(Value::RustClosure(Rc::new(Box::new(f))));
+    Ok(1)
+}
+
+fn str_gsub(state: &mut ExeState) -> Result<i32> {
+    let s = check_string(state, 1, "gsub")?;
+    let src: &[u8] = s.as_ref();
+    let p = check_string(state, 2, "gsub")?;
+    let p: &[u8] = p.as_ref();
+    let repl = match state.get::<&Value>(3) {
+        Value::Integer(_) | Value::Float(_) => check_string(state, 3, "gsub")?,
+        v if v.is_string() || v.is_function() || matches!(v, Value::Table(_)) => v.clone(),
+        v => return Err(arg_error(3, "gsub",
+                &format!("string/function/table expected, got {}", v.ty()))),
+    };
+    let max_n = opt_integer(state, 4, "gsub", src.len() as i64 + 1)?;
+
+    let anchor = p.first() == Some(&b'^');
+    let pstart = anchor as usize;
+    let mut ms = MatchState::new(src, p);
+    let mut buf = Vec::new();
+    let mut s1 = 0;
+    let mut lastmatch = None;
+    let mut n = 0;
+    while n < max_n {
+        ms.reprepstate();
+        match ms.do_match(s1, pstart)? {
+            Some(e) if Some(e) != lastmatch => {
+                n += 1;
+                add_value(state, &ms, &mut buf, src, s1, e, &repl)?;
+                s1 = e;
+                lastmatch = Some(e);
+            }
+            _ if s1 < src.len() => {
+                buf.push(src[s1]);
+                s1 += 1;
+            }
+            _ => break,
+        }
+        if anchor {
+            break;
+        }
+    }
+    buf.extend_from_slice(&src[s1..]);
+
+    state.push(buf);
+    state.push(n);
+    Ok(2)
+}
+
+// add the replacement of match src[s..e] into @buf
+fn add_value(state: &mut ExeState, ms: &MatchState, buf: &mut Vec<u8>,
+        src: &[u8], s: usize, e: usize, repl: &Value) -> Result<()> {
+
+    let v = match repl {
+        Value::Table(_) => {
+            let key = ms.get_capture(0, s, e)?;
+            state.index(repl.clone(), &key)?
+        }
+        f if f.is_function() => {
+            let args = ms.get_captures(s, e, true)?;
+            state.call(f.clone(), &args)?
+        }
+        _ => return add_s(ms, buf, src, s, e, repl.as_ref()),
+    };
+
+    match v {
+        // keep the original text
+        Value::Nil | Value::Boolean(false) => buf.extend_from_slice(&src[s..e]),
+        v if v.is_string() => buf.extend_from_slice(v.as_ref()),
+        Value::Integer(_) | Value::Float(_) => buf.extend_from_slice(v.to_string().as_bytes()),
+        v => return Err(Error::runtime(format!("invalid replacement value (a {})", v.ty()))),
+    }
+    Ok(())
+}
+
+// replacement string with %0-%9 and %%
+fn add_s(ms: &MatchState, buf: &mut Vec<u8>, src: &[u8], s: usize, e: usize, repl: &[u8]) -> Result<()> {
+    let mut iter = repl.iter();
+    while let Some(&c) = iter.next() {
+        if c != b'%' {
+            buf.push(c);
+            continue;
+        }
+        match iter.next() {
+            Some(b'%') => buf.push(b'%'),
+            Some(b'0') => buf.extend_from_slice(&src[s..e]),
+            Some(&d@b'1'..=b'9') => match ms.get_capture((d - b'1') as usize, s, e)? {
+                Value::Integer(i) => buf.extend_from_slice(i.to_string().as_bytes()),
+                cap => buf.extend_from_slice(cap.as_ref()),
+            }
+            _ => return Err(Error::runtime("invalid use of '%' in replacement string")),
+        }
+    }
+    Ok(())
+}
+
+// flags, width and precision of a format specification
+#[derive(Default)]
+struct FormatSpec {
+    left: bool,  // '-'
+    plus: bool,  // '+'
+    space: bool, // ' '
+    alt: bool,   // '#'
+    zero: bool,  // '0'
+    width: usize,
+    precision: Option<usize>,
+}
+
+// max length of format specification, including '%' and conversion
+const MAX_FORMAT: usize = 22;
+
+fn str_format(state: &mut ExeState) -> Result<i32> {
+    let fmt = check_string(state, 1, "format")?;
+    let fmt: &[u8] = fmt.as_ref();
+    let mut buf = Vec::new();
+    let mut iarg = 1;
+    let mut i = 0;
+    while i < fmt.len() {
+        let c = fmt[i];
+        i += 1;
+        if c != b'%' {
+            buf.push(c);
+            continue;
+        }
+        if fmt.get(i) == Some(&b'%') {
+            buf.push(b'%');
+            i += 1;
+            continue;
+        }
+
+        iarg += 1;
+        if iarg > state.get_top() {
+            return Err(arg_error(iarg, "format", "no value"));
+        }
+
+        // the specification: '%', flags, width, precision and conversion
+        let start = i - 1;
+        while i < fmt.len() && b"-+ #0123456789.".contains(&fmt[i]) {
+            i += 1;
+        }
+        if i - start >= MAX_FORMAT {
+            return Err(Error::runtime("invalid format string to 'format'"));
+        }
+        let conv = fmt.get(i).copied().unwrap_or(0);
+        i = (i + 1).min(fmt.len());
+        let form = &fmt[start..i];
+
+        match conv {
+            b'c' => {
+                let spec = check_format(form, b"-", false)?;
+                let c = check_integer(state, iarg, "format")?;
+                pad(&mut buf, &spec, b"", &[c as u8], false);
+            }
+            b'd' | b'i' => {
+                let spec = check_format(form, b"-+0 ", true)?;
+                let n = check_integer(state, iarg, "format")?;
+                let sign = sign_prefix(&spec, n < 0);
+                format_int(&mut buf, &spec, sign, n.unsigned_abs().to_string());
+            }
+            b'u' => {
+                let spec = check_format(form, b"-0", true)?;
+                let n = check_integer(state, iarg, "format")?;
+                format_int(&mut buf, &spec, b"", (n as u64).to_string());
+            }
+            b'o' => {
+                let spec = check_format(form, b"-#0", true)?;
+                let n = check_integer(state, iarg, "format")?;
+                let mut digits = format!("{:o}", n as u64);
+                if spec.alt && !digits.starts_with('0') {
+                    digits.insert(0, '0');
+                }
+                format_int(&mut buf, &spec, b"", digits);
+            }
+            b'x' | b'X' => {
+                let spec = check_format(form, b"-#0", true)?;
+                let n = check_integer(state, iarg, "format")?;
+                let (digits, prefix) = if conv == b'x' {
+                    (format!("{:x}", n as u64), b"0x")
+                } else {
+                    (format!("{:X}", n as u64), b"0X")
+                };
+                let prefix: &[u8] = if spec.alt && n != 0 { prefix } else { b"" };
+                format_int(&mut buf, &spec, prefix, digits);
+            }
+            b'a' | b'A' | b'e' | b'E' | b'f' | b'F' | b'g' | b'G' => {
+                let spec = check_format(form, b"-+ #0", true)?;
+                let f = check_number(state, iarg, "format")?;
+                format_float(&mut buf, &spec, conv, f);
+            }
+            b'p' => {
+                let spec = check_format(form, b"-", false)?;
+                let p = match to_pointer(state.get::<&Value>(iarg)) {
+                    Some(p) => format!("{p:p}"),
+                    None => "(null)".into(),
+                };
+                pad(&mut buf, &spec, b"", p.as_bytes(), false);
+            }
+            b'q' => {
+                if form.len() > 2 {
+                    return Err(Error::runtime("specifier '%q' cannot have modifiers"));
+                }
+                add_literal(state, &mut buf, iarg)?;
+            }
+            b's' => {
+                let spec = check_format(form, b"-", true)?;
+                let v = state.get::<&Value>(iarg).clone();
+                let s = state.tostring(&v)?;
+                let s: &[u8] = s.as_ref();
+                if spec.precision.is_none() && s.len() >= 100 {
+                    // no precision and string is too long to be formatted
+                    buf.extend_from_slice(s);
+                } else {
+                    if s.contains(&0) {
+                        return Err(arg_error(iarg, "format", "string contains zeros"));
+                    }
+                    let len = spec.precision.map_or(s.len(), |p| p.min(s.len()));
+                    pad(&mut buf, &spec, b"", &s[..len], false);
+                }
+            }
+            _ => return Err(invalid_conversion(form)),
+        }
+    }
+    state.push(buf);
+    Ok(1)
+}
+
+fn invalid_conversion(form: &[u8]) -> Error {
+    Error::runtime(format!("invalid conversion '{}' to 'format'", String::from_utf8_lossy(form)))
+}
+
+// parse the @form with allowed @flags, and precision if @precision
+fn check_format(form: &[u8], flags: &[u8], precision: bool) -> Result<FormatSpec> {
+    let mut spec = FormatSpec::default();
+    let mut i = 1;
+    while i < form.len() && flags.contains(&form[i]) {
+        match form[i] {
+            b'-' => spec.left = true,
+            b'+' => spec.plus = true,
+            b' ' => spec.space = true,
+            b'#' => spec.alt = true,
+            _ => spec.zero = true,
+        }
+        i += 1;
+    }
+    if form.get(i) != Some(&b'0') {
+        spec.width = get_2digits(form, &mut i);
+        if form.get(i) == Some(&b'.') && precision {
+            i += 1;
+            spec.precision = Some(get_2digits(form, &mut i));
+        }
+    }
+    if i + 1 != form.len() || !form[i].is_ascii_alphabetic() {
+        return Err(invalid_conversion(form));
+    }
+    Ok(spec)
+}
+
+fn get_2digits(form: &[u8], i: &mut usize) -> usize {
+    let mut n = 0;
+    for _ in 0..2 {
+        match form.get(*i) {
+            Some(d) if d.is_ascii_digit() => {
+                n = n * 10 + (d - b'0') as usize;
+                *i += 1;
+            }
+            _ => break,
+        }
+    }
+    n
+}
+
+fn sign_prefix(spec: &FormatSpec, negative: bool) -> &'static [u8] {
+    if negative {
+        b"-"
+    } else if spec.plus {
+        b"+"
+    } else if spec.space {
+        b" "
+    } else {
+        b""
+    }
+}
+
+// write @prefix (sign or "0x") and @body, padded to the width
+fn pad(buf: &mut Vec<u8>, spec: &FormatSpec, prefix: &[u8], body: &[u8], zero: bool) {
+    let fill = spec.width.saturating_sub(prefix.len() + body.len());
+    if spec.left {
+        buf.extend_from_slice(prefix);
+        buf.extend_from_slice(body);
+        buf.resize(buf.len() + fill, b' ');
+    } else if zero && spec.zero {
+        buf.extend_from_slice(prefix);
+        buf.resize(buf.len() + fill, b'0');
+        buf.extend_from_slice(body);
+    } else {
+        buf.resize(buf.len() + fill, b' ');
+        buf.extend_from_slice(prefix);
+        buf.extend_from_slice(body);
+    }
+}
+
+fn format_int(buf: &mut Vec<u8>, spec: &FormatSpec, prefix: &[u8], mut digits: String) {
+    if let Some(p) = spec.precision {
+        if p == 0 && digits == "0" {
+            digits.clear();
+        } else if digits.len() < p {
+            digits.insert_str(0, &"0".repeat(p - digits.len()));
+        }
+    }
+    // '0' flag is ignored if precision is given
+    pad(buf, spec, prefix, digits.as_bytes(), spec.precision.is_none());
+}
+
+fn format_float(buf: &mut Vec<u8>, spec: &FormatSpec, conv: u8, f: f64) {
+    let mut prefix = sign_prefix(spec, f.is_sign_negative()).to_vec();
+    let abs = f.abs();
+    let mut body = if f.is_nan() {
+        "nan".to_string()
+    } else if f.is_infinite() {
+        "inf".to_string()
+    } else {
+        match conv.to_ascii_lowercase() {
+            b'a' => {
+                prefix.extend_from_slice(b"0x");
+                fmt_hex(abs, spec.precision, spec.alt)
+            }
+            b'e' => fmt_exp(abs, spec.precision.unwrap_or(6), spec.alt),
+            b'f' => fmt_fixed(abs, spec.precision.unwrap_or(6), spec.alt),
+            _ => fmt_general(abs, spec.precision, spec.alt),
+        }
+    };
+    if conv.is_ascii_uppercase() {
+        prefix.make_ascii_uppercase();
+        body.make_ascii_uppercase();
+    }
+    pad(buf, spec, &prefix, body.as_bytes(), f.is_finite());
+}
+
+// %f
+fn fmt_fixed(f: f64, prec: usize, alt: bool) -> String {
+    let mut s = format!("{f:.prec$}");
+    if alt && prec == 0 {
+        s.push('.');
+    }
+    s
+}
+
+// %e, with at least 2 digits exponent
+fn fmt_exp(f: f64, prec: usize, alt: bool) -> String {
+    let s = format!("{f:.prec$e}");
+    let (mant, exp) = s.split_once('e').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+    let dot = if alt && prec == 0 { "." } else { "" };
+    let sign = if exp < 0 { '-' } else { '+' };
+    format!("{mant}{dot}e{sign}{:02}", exp.abs())
+}
+
+// %g, %f or %e style depending on the exponent
+fn fmt_general(f: f64, prec: Option<usize>, alt: bool) -> String {
+    let p = match prec {
+        None => 6,
+        Some(0) => 1,
+        Some(p) => p,
+    };
+    let x = if f == 0.0 {
+        0
+    } else {
+        let s = format!("{:.*e}", p - 1, f);
+        s.split_once('e').unwrap().1.parse::<i32>().unwrap()
+    };
+    let s = if x < p as i32 && x >= -4 {
+        fmt_fixed(f, (p as i32 - 1 - x) as usize, alt)
+    } else {
+        fmt_exp(f, p - 1, alt)
+    };
+    if alt {
+        return s;
+    }
+
+    // remove trailing zeros in the fraction
+    let (mant, exp) = match s.find('e') {
+        Some(i) => s.split_at(i),
+        None => (s.as_str(), ""),
+    };
+    if !mant.contains('.') {
+        return s;
+    }
+    let mant = mant.trim_end_matches('0').trim_end_matches('.');
+    format!("{mant}{exp}")
+}
+
+// %a, without the "0x" prefix
+fn fmt_hex(f: f64, prec: Option<usize>, alt: bool) -> String {
+    let bits = f.to_bits();
+    let exp_bits = ((bits >> 52) & 0x7ff) as i32;
+    let mut mant = bits & ((1 << 52) - 1);
+    let (mut lead, exp) = if f == 0.0 {
+        (0, 0)
+    } else if exp_bits == 0 {
+        (0, -1022) // subnormal
+    } else {
+        (1, exp_bits - 1023)
+    };
+
+    let mut ndigits = 13;
+    match prec {
+        Some(p) if p < 13 => {
+            // round half to even
+            let shift = (13 - p) * 4;
+            let full = (lead << 52) | mant;
+            let rest = full & ((1 << shift) - 1);
+            let half = 1 << (shift - 1);
+            let mut kept = full >> shift;
+            if rest > half || (rest == half && kept & 1 == 1) {
+                kept += 1;
+            }
+            lead = kept >> (p * 4);
+            mant = kept & ((1 << (p * 4)) - 1);
+            ndigits = p;
+        }
+        Some(_) => (),
+        None => {
+            while ndigits > 0 && mant & 0xf == 0 {
+                mant >>= 4;
+                ndigits -= 1;
+            }
+        }
+    }
+
+    let mut s = format!("{lead}");
+    if ndigits > 0 || alt {
+        s.push('.');
+    }
+    if ndigits > 0 {
+        s.push_str(&format!("{mant:0ndigits$x}"));
+    }
+    if let Some(p) = prec {
+        s.extend(std::iter::repeat_n('0', p.saturating_sub(13)));
+    }
+    s.push_str(&format!("p{exp:+}"));
+    s
+}
+
+// %q
+fn add_literal(state: &ExeState, buf: &mut Vec<u8>, iarg: usize) -> Result<()> {
+    match state.get::<&Value>(iarg) {
+        v if v.is_string() => add_quoted(buf, v.as_ref()),
+        &Value::Integer(i) => {
+            let s = if i == i64::MIN {
+                "0x8000000000000000".into()
+            } else {
+                i.to_string()
+            };
+            buf.extend_from_slice(s.as_bytes());
+        }
+        &Value::Float(f) => {
+            let s = if f == f64::INFINITY {
+                "1e9999".into()
+            } else if f == f64::NEG_INFINITY {
+                "-1e9999".into()
+            } else if f.is_nan() {
+                "(0/0)".into()
+            } else {
+                let sign = if f.is_sign_negative() { "-" } else { "" };
+                format!("{sign}0x{}", fmt_hex(f.abs(), None, false))
+            };
+            buf.extend_from_slice(s.as_bytes());
+        }
+        v@(Value::Nil | Value::Boolean(_)) => buf.extend_from_slice(v.to_string().as_bytes()),
+        _ => return Err(arg_error(iarg, "format", "value has no literal form")),
+    }
+    Ok(())
+}
+
+fn add_quoted(buf: &mut Vec<u8>, s: &[u8]) {
+    buf.push(b'"');
+    for (i, &c) in s.iter().enumerate() {
+        match c {
+            b'"' | b'\\' | b'\n' => {
+                buf.push(b'\\');
+                buf.push(c);
+            }
+            b'\r' => buf.extend_from_slice(b"\\r"),
+            c if c.is_ascii_control() => {
+                let next_digit = s.get(i + 1).is_some_and(u8::is_ascii_digit);
+                let esc = if next_digit { format!("\\{c:03}") } else { format!("\\{c}") };
+                buf.extend_from_slice(esc.as_bytes());
+            }
+            c => buf.push(c),
+        }
+    }
+    buf.push(b'"');
+}
+
+// address for %p, None for values not allocated
+fn to_pointer(v: &Value) -> Option<*const ()> {
+    match v {
+        Value::MidStr(s) => Some(Rc::as_ptr(s) as *const ()),
+        Value::LongStr(s) => Some(Rc::as_ptr(s) as *const ()),
+        Value::Table(t) => Some(t.as_ptr() as *const ()),
+        Value::RustFunction(f) => Some(*f as *const ()),
+        Value::RustClosure(c) => Some(Rc::as_ptr(c) as *const ()),
+        Value::LuaFunction(f) => Some(Rc::as_ptr(f) as *const ()),
+        Value::LuaClosure(c) => Some(c.as_ptr() as *const ()),
+        _ => None,
+    }
+}
+
+// options of pack format
+#[derive(Clone, Copy, PartialEq)]
+enum KOption {
+    Int(bool), // signed or not
+    Float,     // f32 or f64 by size
+    Char,      // fixed-size string
+    String,    // string preceded by length
+    Zstr,      // zero-terminated string
+    Padding,   // padding byte
+    PaddAlign, // padding for alignment
+    Nop,       // no-op (configuration or spaces)
+}
+
+const MAX_INT_SIZE: usize = 16;
+const NATIVE_ALIGN: usize = 8;
+
+// endianness and max alignment, changed by options in format
+struct PackHeader {
+    islittle: bool,
+    maxalign: usize,
+}
+
+impl PackHeader {
+    fn new() -> Self {
+        PackHeader {
+            islittle: cfg!(target_endian = "little"),
+            maxalign: 1,
+        }
+    }
+
+    // read an option from fmt[*i..], return the option and its size
+    fn get_option(&mut self, fmt: &[u8], i: &mut usize) -> Result<(KOption, usize)> {
+        let opt = fmt[*i];
+        *i += 1;
+        let ret = match opt {
+            b'b' => (KOption::Int(true), 1),
+            b'B' => (KOption::Int(false), 1),
+            b'h' => (KOption::Int(true), 2),
+            b'H' => (KOption::Int(false), 2),
+            b'l' | b'j' => (KOption::Int(true), 8),
+            b'L' | b'J' | b'T' => (KOption::Int(false), 8),
+            b'f' => (KOption::Float, 4),
+            b'n' | b'd' => (KOption::Float, 8),
+            b'i' => (KOption::Int(true), get_num_limit(fmt, i, 4)?),
+            b'I' => (KOption::Int(false), get_num_limit(fmt, i, 4)?),
+            b's' => (KOption::String, get_num_limit(fmt, i, 8)?),
+            b'c' => match get_num(fmt, i) {
+                Some(size) => (KOption::Char, size),
+                None => return Err(Error::runtime("missing size for format option 'c'")),
+            }
+            b'z' => (KOption::Zstr, 0),
+            b'x' => (KOption::Padding, 1),
+            b'X' => (KOption::PaddAlign, 0),
+            b' ' => (KOption::Nop, 0),
+            b'<' => {
+                self.islittle = true;
+                (KOption::Nop, 0)
+            }
+            b'>' => {
+                self.islittle = false;
+                (KOption::Nop, 0)
+            }
+            b'=' => {
+                self.islittle = cfg!(target_endian = "little");
+                (KOption::Nop, 0)
+            }
+            b'!' => {
+                self.maxalign = get_num_limit(fmt, i, NATIVE_ALIGN)?;
+                (KOption::Nop, 0)
+            }
+            c => return Err(Error::runtime(format!("invalid format option '{}'", c as char))),
+        };
+        Ok(ret)
+    }
+
+    // read an option, return the option, its size, and the padding
+    // needed to align it after @totalsize bytes
+    fn get_details(&mut self, fname: &str, totalsize: usize, fmt: &[u8], i: &mut usize)
+            -> Result<(KOption, usize, usize)> {
+
+        let (opt, size) = self.get_option(fmt, i)?;
+        let mut align = size;
+        if opt == KOption::PaddAlign {
+            let invalid = || arg_error(1, fname, "invalid next option for option 'X'");
+            if *i >= fmt.len() {
+                return Err(invalid());
+            }
+            let (next, next_size) = self.get_option(fmt, i)?;
+            if next == KOption::Char || next_size == 0 {
+                return Err(invalid());
+            }
+            align = next_size;
+        }
+        if align <= 1 || opt == KOption::Char {
+            return Ok((opt, size, 0));
+        }
+        let align = align.min(self.maxalign);
+        if !align.is_power_of_two() {
+            return Err(arg_error(1, fname, "format asks for alignment not power of 2"));
+        }
+        Ok((opt, size, (align - (totalsize & (align - 1))) & (align - 1)))
+    }
+}
+
+fn get_num(fmt: &[u8], i: &mut usize) -> Option<usize> {
+    if !fmt.get(*i)?.is_ascii_digit() {
+        return None;
+    }
+    let mut n = 0_usize;
+    while let Some(d) = fmt.get(*i).filter(|d| d.is_ascii_digit()) {
+        if n > (MAX_STR_LEN - 9) / 10 {
+            break;
+        }
+        n = n * 10 + (d - b'0') as usize;
+        *i += 1;
+    }
+    Some(n)
+}
+
+fn get_num_limit(fmt: &[u8], i: &mut usize, default: usize) -> Result<usize> {
+    let n = get_num(fmt, i).unwrap_or(default);
+    if n == 0 || n > MAX_INT_SIZE {
+        return Err(Error::runtime(format!("integral size ({n}) out of limits [1,{MAX_INT_SIZE}]")));
+    }
+    Ok(n)
+}
+
+fn pack_int(buf: &mut Vec<u8>, n: u64, islittle: bool, size: usize, negative: bool) {
+    let mut bytes = [0; MAX_INT_SIZE];
+    for (k, b) in bytes[..size].iter_mut().enumerate() {
+        *b = if k < 8 {
+            (n >> (k * 8)) as u8
+        } else if negative {
+            0xff // sign extension
+        } else {
+            0
+        };
+    }
+    let bytes = &mut bytes[..size];
+    if !islittle {
+        bytes.reverse();
+    }
+    buf.extend_from_slice(bytes);
+}
+
+fn unpack_int(data: &[u8], islittle: bool, size: usize, signed: bool) -> Result<i64> {
+    let byte = |k: usize| if islittle { data[k] } else { data[size - 1 - k] };
+    let limit = size.min(8);
+    let mut n = 0_u64;
+    for k in (0..limit).rev() {
+        n = (n << 8) | byte(k) as u64;
+    }
+    if size < 8 {
+        if signed {
+            let mask = 1_u64 << (size * 8 - 1);
+            n = (n ^ mask).wrapping_sub(mask); // sign extension
+        }
+    } else if size > 8 {
+        // the extra bytes must be the sign extension
+        let ext = if signed && (n as i64) < 0 { 0xff } else { 0 };
+        if (limit..size).any(|k| byte(k) != ext) {
+            return Err(Error::runtime(format!("{size}-byte integer does not fit into Lua Integer")));
+        }
+    }
+    Ok(n as i64)
+}
+
+fn str_pack(state: &mut ExeState) -> Result<i32> {
+    let fmt = check_string(state, 1, "pack")?;
+    let fmt: &[u8] = fmt.as_ref();
+    let mut h = PackHeader::new();
+    let mut buf = Vec::new();
+    let mut iarg = 1;
+    let mut i = 0;
+    while i < fmt.len() {
+        let (opt, size, ntoalign) = h.get_details("pack", buf.len(), fmt, &mut i)?;
+        buf.resize(buf.len() + ntoalign, 0);
+        match opt {
+            KOption::Int(signed) => {
+                iarg += 1;
+                let n = check_integer(state, iarg, "pack")?;
+                if size < 8 {
+                    let lim = 1_i64 << (size * 8 - 1);
+                    if signed && !(-lim <= n && n < lim) {
+                        return Err(arg_error(iarg, "pack", "integer overflow"));
+                    }
+                    if !signed && (n as u64) >= (lim as u64) << 1 {
+                        return Err(arg_error(iarg, "pack", "unsigned overflow"));
+                    }
+                }
+                pack_int(&mut buf, n as u64, h.islittle, size, n < 0);
+            }
+            KOption::Float => {
+                iarg += 1;
+                let f = check_number(state, iarg, "pack")?;
+                match (size, h.islittle) {
+                    (4, true) => buf.extend_from_slice(&(f as f32).to_le_bytes()),
+                    (4, false) => buf.extend_from_slice(&(f as f32).to_be_bytes()),
+                    (_, true) => buf.extend_from_slice(&f.to_le_bytes()),
+                    (_, false) => buf.extend_from_slice(&f.to_be_bytes()),
+                }
+            }
+            KOption::Char => {
+                iarg += 1;
+                let s = check_string(state, iarg, "pack")?;
+                let s: &[u8] = s.as_ref();
+                if s.len() > size {
+                    return Err(arg_error(iarg, "pack", "string longer than given size"));
+                }
+                buf.extend_from_slice(s);
+                buf.resize(buf.len() + size - s.len(), 0);
+            }
+            KOption::String => {
+                iarg += 1;
+                let s = check_string(state, iarg, "pack")?;
+                let s: &[u8] = s.as_ref();
+                if size < 8 && s.len() >= 1 << (size * 8) {
+                    return Err(arg_error(iarg, "pack", "string length does not fit in given size"));
+                }
+                pack_int(&mut buf, s.len() as u64, h.islittle, size, false);
+                buf.extend_from_slice(s);
+            }
+            KOption::Zstr => {
+                iarg += 1;
+                let s = check_string(state, iarg, "pack")?;
+                let s: &[u8] = s.as_ref();
+                if s.contains(&0) {
+                    return Err(arg_error(iarg, "pack", "string contains zeros"));
+                }
+                buf.extend_from_slice(s);
+                buf.push(0);
+            }
+            KOption::Padding => buf.push(0),
+            KOption::PaddAlign | KOption::Nop => (),
+        }
+        if buf.len() > MAX_STR_LEN {
+            return Err(Error::runtime("resulting string too large"));
+        }
+    }
+    state.push(buf);
+    Ok(1)
+}
+
+fn str_packsize(state: &mut ExeState) -> Result<i32> {
+    let fmt = check_string(state, 1, "packsize")?;
+    let fmt: &[u8] = fmt.as_ref();
+    let mut h = PackHeader::new();
+    let mut totalsize = 0;
+    let mut i = 0;
+    while i < fmt.len() {
+        let (opt, size, ntoalign) = h.get_details("packsize", totalsize, fmt, &mut i)?;
+        if matches!(opt, KOption::String | KOption::Zstr) {
+            return Err(arg_error(1, "packsize", "variable-length format"));
+        }
+        let size = size + ntoalign;
+        if totalsize > MAX_STR_LEN - size {
+            return Err(arg_error(1, "packsize", "format result too large"));
+        }
+        totalsize += size;
+    }
+    state.push(totalsize as i64);
+    Ok(1)
+}
+
+fn str_unpack(state: &mut ExeState) -> Result<i32> {
+    let fmt = check_string(state, 1, "unpack")?;
+    let fmt: &[u8] = fmt.as_ref();
+    let data = check_string(state, 2, "unpack")?;
+    let data: &[u8] = data.as_ref();
+    let ld = data.len();
+    let mut pos = start_pos(opt_integer(state, 3, "unpack", 1)?, ld) - 1;
+    if pos > ld {
+        return Err(arg_error(3, "unpack", "initial position out of string"));
+    }
+
+    let mut h = PackHeader::new();
+    let mut n = 0;
+    let mut i = 0;
+    while i < fmt.len() {
+        let (opt, size, ntoalign) = h.get_details("unpack", pos, fmt, &mut i)?;
+        if ntoalign + size > ld - pos {
+            return Err(arg_error(2, "unpack", "data string too short"));
+        }
+        pos += ntoalign;
+        let v = match opt {
+            KOption::Int(signed) => {
+                Value::Integer(unpack_int(&data[pos..], h.islittle, size, signed)?)
+            }
+            KOption::Float => {
+                let f = if size == 4 {
+                    let bytes = data[pos..pos+4].try_into().unwrap();
+                    let f = if h.islittle { f32::from_le_bytes(bytes) } else { f32::from_be_bytes(bytes) };
+                    f as f64
+                } else {
+                    let bytes = data[pos..pos+8].try_into().unwrap();
+                    if h.islittle { f64::from_le_bytes(bytes) } else { f64::from_be_bytes(bytes) }
+                };
+                Value::Float(f)
+            }
+            KOption::Char => data[pos..pos+size].into(),
+            KOption::String => {
+                let len = unpack_int(&data[pos..], h.islittle, size, false)? as u64 as usize;
+                if len > ld - pos - size {
+                    return Err(arg_error(2, "unpack", "data string too short"));
+                }
+                let v = data[pos+size..pos+size+len].into();
+                pos += len;
+                v
+            }
+            KOption::Zstr => {
+                let Some(len) = data[pos..].iter().position(|&b| b == 0) else {
+                    return Err(arg_error(2, "unpack", "unfinished string for format 'z'"));
+                };
+                let v = data[pos..pos+len].into();
+                pos += len + 1;
+                v
+            }
+            KOption::Padding | KOption::PaddAlign | KOption::Nop => {
+                pos += size;
+                continue;
+            }
+        };
+        state.push(v);
+        n += 1;
+        pos += size;
+    }
+    state.push((pos + 1) as i64);
+    Ok(n + 1)
+}
+
+// binary chunk of Lua function, without its upvalues
+fn str_dump(state: &mut ExeState) -> Result<i32> {
+    let f = check_function(state, 1, "dump")?;
+    let strip = bool::from(state.get::<&Value>(2));
+    let proto = match &f {
+        Value::LuaFunction(proto) => proto,
+        Value::LuaClosure(c) => c.proto(),
+        _ => return Err(Error::runtime("unable to dump given function")),
+    };
+    let mut buf = Vec::new();
+    proto.dump(&mut buf, strip)?;
+    state.push(buf);
+    Ok(1)
+}
@@ -0,0 +1,331 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::value::{Value, Table, RustFunction};
+use crate::vm::ExeState;
+use crate::error::{Error, Result};
+use crate::lualib::auxlib::{arg_error, check_table, check_integer, opt_integer, check_string};
+
+// register the `table` table
+pub fn open(state: &mut ExeState) {
+    let funcs: [(&str, RustFunction); 7] = [
+        ("concat", tab_concat),
+        ("insert", tab_insert),
+        ("move", tab_move),
+        ("pack", tab_pack),
+        ("unpack", tab_unpack),
+        ("remove", tab_remove),
+        ("sort", tab_sort),
+    ];
+    let mut lib = Table::new(0, funcs.len());
+    for (name, f) in funcs {
+        lib.map.insert(name.into(), Value::RustFunction(f));
+    }
+    let lib = Value::Table(state.heap().alloc_cell(lib));
+    state.set_global("table", lib);
+}
+
+// elements are accessed with metamethods, like lua_geti/lua_seti
+fn geti(state: &mut ExeState, t: &Value, i: i64) -> Result<Value> {
+    state.index(t.clone(), &Value::Integer(i))
+}
+fn seti(state: &mut ExeState, t: &Value, i: i64, v: Value) -> Result<()> {
+    state.new_index(t.clone(), Value::Integer(i), v)
+}
+
+// the table argument and its length
+fn check_tab_len(state: &mut ExeState, iarg: usize, fname: &str) -> Result<(Value, i64)> {
+    let t = Value::Table(check_table(state, iarg, fname)?);
+    let n = length(state, &t)?;
+    Ok((t, n))
+}
+
+fn length(state: &mut ExeState, t: &Value) -> Result<i64> {
+    match state.len(t)? {
+        Value::Integer(n) => Ok(n),
+        _ => Err(Error::runtime("object length is not an integer")),
+    }
+}
+
+fn tab_insert(state: &mut ExeState) -> Result<i32> {
+    let (t, n) = check_tab_len(state, 1, "insert")?;
+    let e = n.wrapping_add(1); // first empty element
+    let pos = match state.get_top() {
+        2 => e,
+        3 => {
+            let pos = check_integer(state, 2, "insert")?;
+            // check whether 'pos' is in [1, e]
+            if (pos as u64).wrapping_sub(1) >= e as u64 {
+                return Err(arg_error(2, "insert", "position out of bounds"));
+            }
+            // move up elements
+            let mut i = e;
+            while i > pos {
+                let v = geti(state, &t, i - 1)?;
+                seti(state, &t, i, v)?;
+                i -= 1;
+            }
+            pos
+        }
+        _ => return Err(Error::runtime("wrong number of arguments to 'insert'")),
+    };
+    let v = state.get::<&Value>(state.get_top()).clone();
+    seti(state, &t, pos, v)?;
+    Ok(0)
+}
+
+fn tab_remove(state: &mut ExeState) -> Result<i32> {
+    let (t, size) = check_tab_len(state, 1, "remove")?;
+    let mut pos = opt_integer(state, 2, "remove", size)?;
+    // validate 'pos' if given, and 'size + 1' is allowed
+    if pos != size && (pos as u64).wrapping_sub(1) > size as u64 {
+        return Err(arg_error(2, "remove", "position out of bounds"));
+    }
+    let v = geti(state, &t, pos)?;
+    while pos < size {
+        let next = geti(state, &t, pos + 1)?;
+        seti(state, &t, pos, next)?;
+        pos += 1;
+    }
+    seti(state, &t, pos, Value::Nil)?;
+    state.push(v);
+    Ok(1)
+}
+
+// table.move(a1, f, e, t [,a2])
+fn tab_move(state: &mut ExeState) -> Result<i32> {
+    let a1 = Value::Table(check_table(state, 1, "move")?);
+    let f = check_integer(state, 2, "move")?;
+    let e = check_integer(state, 3, "move")?;
+    let t = check_integer(state, 4, "move")?;
+    let a2 = match state.get::<&Value>(5) {
+        Value::Nil => a1.clone(),
+        _ => Value::Table(check_table(state, 5, "move")?),
+    };
+
+    if e >= f {
+        if !(f > 0 || e < i64::MAX + f) {
+            return Err(arg_error(3, "move", "too many elements to move"));
+        }
+        let n = e - f;
+        if t > i64::MAX - n {
+            return Err(arg_error(4, "move", "destination wrap around"));
+        }
+        if t > e || t <= f || a1 != a2 {
+            for i in 0..=n {
+                let v = geti(state, &a1, f + i)?;
+                seti(state, &a2, t + i, v)?;
+            }
+        } else {
+            // overlapping, move backward
+            for i in (0..=n).rev() {
+                let v = geti(state, &a1, f + i)?;
+                seti(state, &a2, t + i, v)?;
+            }
+        }
+    }
+    state.push(a2);
+    Ok(1)
+}
+
+fn tab_concat(state: &mut ExeState) -> Result<i32> {
+    let t = Value::Table(check_table(state, 1, "concat")?);
+    let sep = match state.get::<&Value>(2) {
+        Value::Nil => Value::from(""),
+        _ => check_string(state, 2, "concat")?,
+    };
+    let sep: &[u8] = sep.as_ref();
+    let mut i = opt_integer(state, 3, "concat", 1)?;
+    let last = match state.get::<&Value>(4) {
+        Value::Nil => length(state, &t)?,
+        _ => check_integer(state, 4, "concat")?,
+    };
+
+    let mut buf = Vec::new();
+    while i <= last {
+        match geti(state, &t, i)? {
+            v if v.is_string() => buf.extend_from_slice(v.as_ref()),
+            v@(Value::Integer(_) | Value::Float(_)) => buf.extend_from_slice(v.to_string().as_bytes()),
+            _ => return Err(Error::runtime(format!("invalid value (at index {i}) in table for 'concat'"))),
+        }
+        if i == last {
+            break;
+        }
+        buf.extend_from_slice(sep);
+        i += 1;
+    }
+    state.push(buf);
+    Ok(1)
+}
+
+fn tab_pack(state: &mut ExeState) -> Result<i32> {
+    let n = state.get_top();
+    let mut t = Table::new(n, 1);
+    for i in 1..=n {
+        t.array.push(state.get::<&Value>(i).clone());
+    }
+    t.map.insert("n".into(), Value::Integer(n as i64));
+    let t = state.heap().alloc_cell(t);
+    state.push(Value::Table(t));
+    Ok(1)
+}
+
+fn tab_unpack(state: &mut ExeState) -> Result<i32> {
+    let t = state.get::<&Value>(1).clone();
+    let i = opt_integer(state, 2, "unpack", 1)?;
+    let e = match state.get::<&Value>(3) {
+        Value::Nil => length(state, &t)?,
+        _ => check_integer(state, 3, "unpack")?,
+    };
+    if i > e {
+        return Ok(0);
+    }
+    let n = (e as u64).wrapping_sub(i as u64);
+    if n >= i32::MAX as u64 {
+        return Err(Error::runtime("too many results to unpack"));
+    }
+    for k in 0..=n as i64 {
+        let v = geti(state, &t, i + k)?;
+        state.push(v);
+    }
+    Ok(n as i32 + 1)
+}
+
+// sort, ported from ltablib.c of the official implementation
+
+// larger arrays use a randomized pivot
+const RANLIMIT: usize = 100;
+
+fn tab_sort(state: &mut ExeState) -> Result<i32> {
+    let (t, n) = check_tab_len(state, 1, "sort")?;
+    if n <= 1 {
+        return Ok(0);
+    }
+    if n >= i32::MAX as i64 {
+        return Err(arg_error(1, "sort", "array too big"));
+    }
+    let cmp = match state.get::<&Value>(2) {
+        Value::Nil => Value::Nil,
+        f if f.is_function() => f.clone(),
+        f => return Err(arg_error(2, "sort", &format!("function expected, got {}", f.ty()))),
+    };
+
+    let mut a = Vec::with_capacity(n as usize);
+    for i in 1..=n {
+        a.push(geti(state, &t, i)?);
+    }
+    auxsort(state, &mut a, 0, n as usize - 1, &cmp, 0)?;
+    for (i, v) in a.into_iter().enumerate() {
+        seti(state, &t, i as i64 + 1, v)?;
+    }
+    Ok(0)
+}
+
+fn sort_comp(state: &mut ExeState, cmp: &Value, a: &Value, b: &Value) -> Result<bool> {
+    if cmp == &Value::Nil {
+        state.less_than(a, b)
+    } else {
+        let r = state.call(cmp.clone(), &[a.clone(), b.clone()])?;
+        Ok((&r).into())
+    }
+}
+
+fn order_error() -> Error {
+    Error::runtime("invalid order function for sorting")
+}
+
+// quicksort a[lo..=up], with pivot P at a[up-1]
+fn partition(state: &mut ExeState, a: &mut [Value], lo: usize, up: usize, cmp: &Value) -> Result<usize> {
+    let pivot = a[up - 1].clone();
+    let mut i = lo;
+    let mut j = up - 1;
+    loop {
+        // next loop: repeat ++i while a[i] < P
+        i += 1;
+        while sort_comp(state, cmp, &a[i], &pivot)? {
+            if i == up - 1 {
+                return Err(order_error());
+            }
+            i += 1;
+        }
+        // after the loop, a[i] >= P and a[lo .. i - 1] < P
+
+        // next loop: repeat --j while P < a[j]
+        j -= 1;
+        while sort_comp(state, cmp, &pivot, &a[j])? {
+            if j < i {
+                return Err(order_error());
+            }
+            j -= 1;
+        }
+        // after the loop, a[j] <= P and a[j + 1 .. up] >= P
+
+        if j < i {
+            // no elements to be exchanged, swap pivot to its final place
+            a.swap(up - 1, i);
+            return Ok(i);
+        }
+        a.swap(i, j);
+    }
+}
+
+// pivot in the middle half of [lo, up]
+fn choose_pivot(lo: usize, up: usize, rnd: usize) -> usize {
+    let r4 = (up - lo) / 4;
+    rnd % (r4 * 2) + (lo + r4)
+}
+
+fn auxsort(state: &mut ExeState, a: &mut [Value], mut lo: usize, mut up: usize,
+        cmp: &Value, mut rnd: usize) -> Result<()> {
+
+    // loop for tail recursion
+    while lo < up {
+        // sort elements a[lo], a[p], a[up]
+        if sort_comp(state, cmp, &a[up], &a[lo])? {
+            a.swap(lo, up);
+        }
+        if up - lo == 1 {
+            break; // only 2 elements
+        }
+        let p = if up - lo < RANLIMIT || rnd == 0 {
+            (lo + up) / 2
+        } else {
+            choose_pivot(lo, up, rnd)
+        };
+        if sort_comp(state, cmp, &a[p], &a[lo])? {
+            a.swap(p, lo);
+        } else if sort_comp(state, cmp, &a[up], &a[p])? {
+            a.swap(p, up);
+        }
+        if up - lo == 2 {
+            break; // only 3 elements
+        }
+
+        // move pivot to a[up-1], and partition
+        a.swap(p, up - 1);
+        let p = partition(state, a, lo, up, cmp)?;
+
+        // a[lo .. p - 1] <= a[p] == P <= a[p + 1 .. up],
+        // recurse into the smaller half and loop for the larger one
+        let n;
+        if p - lo < up - p {
+            auxsort(state, a, lo, p - 1, cmp, rnd)?;
+            n = p - lo;
+            lo = p + 1;
+        } else {
+            auxsort(state, a, p + 1, up, cmp, rnd)?;
+            n = up - p;
+            up = p - 1;
+        }
+
+        // partition too imbalanced, try a random pivot
+        if (up - lo) / 128 > n {
+            rnd = random_pivot();
+        }
+    }
+    Ok(())
+}
+
+fn random_pivot() -> usize {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos() as usize ^ d.as_secs() as usize)
+}
@@ -24,8 +24,20 @@ macro_rules! vm_exec_input {
     ($i:expr) => {
         {
             use std::io::BufReader;
-            let proto = parse::load(BufReader::new($i));
-            vm::ExeState::new().execute(&proto, &Vec::new());
+            let proto = parse::load(BufReader::new($i)).unwrap();
+            vm::ExeState::new().execute(&proto, &[]).unwrap();
+        }
+    };
+}
+
+// execute the source string, which must fail, and return the error
+#[macro_export]
+macro_rules! vm_exec_error {
+    ($src:expr) => {
+        {
+            use std::io::Cursor;
+            let proto = parse::load(Cursor::new($src.to_string())).unwrap();
+            vm::ExeState::new().execute(&proto, &[]).unwrap_err()
         }
     };
 }
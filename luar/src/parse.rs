@@ -1,17 +1,28 @@
 use std::rc::Rc;
-use std::io::Read;
+use std::io::{Read, Cursor};
 use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::mem;
 use crate::lex::{Lex, Token};
-use crate::bytecode::ByteCode;
+use crate::bytecode::{ByteCode, Instruction, MAX_BX};
 use crate::value::Value;
-use crate::utils::ftoi;
+use crate::error::{Error, Result};
+use crate::dump;
+use crate::utils::{ftoi, int_idiv, int_mod, float_idiv, float_mod, shift_left};
+
+// limits in each function, since registers and upvalues are encoded
+// in u8 in byte codes
+pub(crate) const MAX_REGISTERS: usize = 255;
+pub(crate) const MAX_LOCALS: usize = 200;
+pub(crate) const MAX_UPVALUES: usize = 255;
 
 type FnBc2u8 = fn(u8, u8) -> ByteCode;
 type FnBc3u8 = fn(u8, u8, u8) -> ByteCode;
 type FnBcBool = fn(u8, u8, bool) -> ByteCode;
 
 // expression description, inner layer between source code and byte code
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 enum ExpDesc {
     // constants
     Nil,
@@ -59,49 +70,125 @@ struct GotoLabel {
     name: String,
     icode: usize,
     nvar: usize,
+    close: bool, // goto leaves a scope with variables to be closed
 }
 
 // index of locals/upvalues in upper functions
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum UpIndex {
     Local(usize),
     Upvalue(usize),
 }
 
+// local variable name, active in byte codes [startpc, endpc)
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocVar {
+    pub name: String,
+    pub startpc: usize,
+    pub endpc: usize,
+}
+
 // core struct, generated in parse phase and executed in VM
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct FuncProto {
     pub has_varargs: bool,
     pub nparam: usize,
     pub constants: Vec<Value>,
     pub upindexes: Vec<UpIndex>,
-    pub byte_codes: Vec<ByteCode>,
+    pub byte_codes: Vec<Instruction>,
+
+    // debug information
+    pub source: String, // chunk name
+    pub name: String, // function name, empty for main chunk and anonymous functions
+    pub linedefined: usize, // 0 for main chunk
+    pub lineinfo: Vec<usize>, // source line of each byte code
+    pub locvars: Vec<LocVar>, // in order of declaration
+}
+
+impl FuncProto {
+    // name of the @n-th (from 0) local variable active at @pc, like
+    // luaF_getlocalname(). None if unknown, e.g. stripped
+    pub fn local_name(&self, n: usize, pc: usize) -> Option<&str> {
+        self.locvars.iter()
+            .filter(|v| v.startpc <= pc && pc < v.endpc)
+            .nth(n)
+            .map(|v| v.name.as_str())
+    }
+}
+
+// attribute of local variables
+#[derive(Debug, PartialEq)]
+enum Attrib {
+    Plain,
+    Const(Option<Value>), // with the value if it is a compile-time constant
+    Close,
+}
+
+#[derive(Debug)]
+struct LocalVar {
+    name: String,
+    referred: bool, // referred as upvalue
+    attrib: Attrib,
+    ilocvar: usize, // index in FuncProto.locvars
+}
+
+impl LocalVar {
+    // whether Close is needed when it expires
+    fn need_close(&self) -> bool {
+        self.referred || self.attrib == Attrib::Close
+    }
 }
 
 // level of inner functions, used for matching upvalue
 #[derive(Debug, Default)]
 struct Level {
-    locals: Vec<(String, bool)>, // (name, referred-as-upvalue)
-    upvalues: Vec<(String, UpIndex)>,
+    locals: Vec<LocalVar>,
+    upvalues: Vec<(String, UpIndex, bool)>, // (name, index, read-only)
 }
 
 #[derive(Debug)]
 struct ParseContext<R: Read> {
     levels: Vec<Level>,
     lex: Lex<R>,
+    source: String,
+}
+
+// key of constants, compared by Value::same()
+#[derive(Debug)]
+struct ConstKey(Value);
+
+impl PartialEq for ConstKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.same(&other.0)
+    }
+}
+
+impl Eq for ConstKey {}
+
+impl Hash for ConstKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(&self.0).hash(state);
+        match self.0 {
+            Value::Float(f) => f.to_bits().hash(state),
+            ref v => v.hash(state),
+        }
+    }
 }
 
 #[derive(Debug)]
 struct ParseProto<'a, R: Read> {
     // return to VM to execute
     fp: FuncProto,
+    constants: HashMap<ConstKey, usize>, // index in fp.constants
 
     // internal stuff for parsing
+    byte_codes: Vec<ByteCode>, // encoded into fp.byte_codes at last
     sp: usize,
-    break_blocks: Vec<Vec<usize>>,
+    break_blocks: Vec<(usize, Vec<usize>)>, // (#locals out of loop, breaks)
     continue_blocks: Vec<Vec<(usize, usize)>>,
     gotos: Vec<GotoLabel>,
     labels: Vec<GotoLabel>,
+    limit_error: Option<Error>, // see check_register()
     ctx: &'a mut ParseContext<R>,
 }
 
@@ -123,61 +210,68 @@ impl<'a, R: Read> ParseProto<'a, R> {
     //     function funcname funcbody |
     //     local function Name funcbody |
     //     local attnamelist [`=` explist]
-    fn block(&mut self) -> Token {
+    fn block(&mut self) -> Result<Token> {
         let nvar = self.local_num();
-        let end_token = self.block_scope();
+        let end_token = self.block_scope()?;
         self.local_expire(nvar);
-        end_token
+        Ok(end_token)
     }
 
     // same with block() but without expiring internal local variables
-    fn block_scope(&mut self) -> Token {
+    fn block_scope(&mut self) -> Result<Token> {
         let igoto = self.gotos.len();
         let ilabel = self.labels.len();
         loop {
+            // raise the error of exceeding limits in last statement
+            if let Some(err) = self.limit_error.take() {
+                return Err(err);
+            }
+
             // reset sp before each statement
             self.sp = self.local_num();
 
-            match self.ctx.lex.next() {
+            match self.ctx.lex.next()? {
                 Token::SemiColon => (),
                 t@Token::Name(_) | t@Token::ParL => {
                     // this is not standard!
-                    if self.try_continue_stat(&t) {
+                    if self.try_continue_stat(&t)? {
                         continue;
                     }
 
                     // functioncall and var-assignment both begin with
                     // `prefixexp` which begins with `Name` or `(`.
-                    let desc = self.prefixexp(t);
+                    let name = if let Token::Name(name) = &t { Some(name.clone()) } else { None };
+                    let desc = self.prefixexp(t)?;
                     if let ExpDesc::Call(ifunc, narg_plus) = desc {
                         // prefixexp() matches the whole functioncall statement.
                         let code = ByteCode::Call(ifunc as u8, narg_plus as u8, 0);
-                        self.fp.byte_codes.push(code);
+                        self.push_code(code);
                     } else {
                         // prefixexp() matches only the first variable, so we
                         // continue the statement
-                        self.assignment(desc);
+                        self.check_assignable(&desc, name.as_deref())?;
+                        self.assignment(desc)?;
                     }
                 }
                 Token::Local =>
-                    if self.ctx.lex.peek() == &Token::Function {
-                        self.local_function()
+                    if self.ctx.lex.peek()? == &Token::Function {
+                        self.local_function()?
                     } else {
-                        self.local_variables()
+                        self.local_variables()?
                     }
-                Token::Function => self.function_stat(),
-                Token::If => self.if_stat(),
-                Token::While => self.while_stat(),
-                Token::Repeat => self.repeat_stat(),
-                Token::For => self.for_stat(),
-                Token::Break => self.break_stat(),
-                Token::Do => self.do_stat(),
-                Token::DoubColon => self.label_stat(igoto),
-                Token::Goto => self.goto_stat(),
-                Token::Return => self.ret_stat(),
+                Token::Function => self.function_stat()?,
+                Token::If => self.if_stat()?,
+                Token::While => self.while_stat()?,
+                Token::Repeat => self.repeat_stat()?,
+                Token::For => self.for_stat()?,
+                Token::Break => self.break_stat()?,
+                Token::Do => self.do_stat()?,
+                Token::DoubColon => self.label_stat(igoto)?,
+                Token::Goto => self.goto_stat()?,
+                Token::Return => self.ret_stat()?,
                 t => {
                     self.labels.truncate(ilabel);
-                    break t;
+                    break Ok(t);
                 }
             }
         }
@@ -186,63 +280,98 @@ impl<'a, R: Read> ParseProto<'a, R> {
     // BNF:
     //   local attnamelist [`=` explist]
     //   attnamelist ::=  Name attrib {`,` Name attrib}
-    fn local_variables(&mut self) {
-        // variable names
-        let mut vars = vec![self.read_name()];
-        while self.ctx.lex.peek() == &Token::Comma {
-            self.ctx.lex.next();
-            vars.push(self.read_name());
+    fn local_variables(&mut self) -> Result<()> {
+        // variable names and attributes
+        let mut vars = vec![(self.read_name()?, self.local_attrib()?)];
+        while self.ctx.lex.peek()? == &Token::Comma {
+            self.ctx.lex.next()?;
+            vars.push((self.read_name()?, self.local_attrib()?));
+        }
+        if vars.iter().filter(|v| v.1 == Attrib::Close).count() > 1 {
+            return Err(self.error("multiple to-be-closed variables in local list"));
         }
 
-        if self.ctx.lex.peek() == &Token::Assign {
+        if self.ctx.lex.peek()? == &Token::Assign {
             // explist
-            self.ctx.lex.next();
-            self.explist_want(vars.len());
+            self.ctx.lex.next()?;
+            let (nexp, last_exp) = self.explist()?;
+
+            // compile-time constant, for single variable only
+            if let ([(_, attrib@Attrib::Const(None))], 0) = (vars.as_mut_slice(), nexp) {
+                *attrib = Attrib::Const(const_value(&last_exp));
+            }
+
+            self.explist_adjust(nexp, last_exp, vars.len());
         } else {
             // no exp, load nils
+            self.check_register(self.sp + vars.len() - 1);
             let code = ByteCode::LoadNil(self.sp as u8, vars.len() as u8);
-            self.fp.byte_codes.push(code);
+            self.push_code(code);
         }
 
         // append vars into self.locals after evaluating explist
-        for var in vars.into_iter() {
-            self.local_new(var);
+        for (var, attrib) in vars.into_iter() {
+            let close = attrib == Attrib::Close;
+            self.local_new_attrib(var, attrib);
+            if close {
+                self.push_code(ByteCode::Tbc(self.local_num() as u8 - 1));
+            }
+        }
+        Ok(())
+    }
+
+    // BNF:
+    //   attrib ::= [`<` Name `>`]
+    fn local_attrib(&mut self) -> Result<Attrib> {
+        if self.ctx.lex.peek()? != &Token::Less {
+            return Ok(Attrib::Plain);
+        }
+        self.ctx.lex.next()?;
+        let name = self.read_name()?;
+        self.ctx.lex.expect(Token::Greater)?;
+        match name.as_str() {
+            "const" => Ok(Attrib::Const(None)),
+            "close" => Ok(Attrib::Close),
+            _ => Err(self.error(format!("unknown attribute '{name}'"))),
         }
     }
 
     // BNF:
     //   local function Name funcbody
-    fn local_function(&mut self) {
-        self.ctx.lex.next();
-        let name = self.read_name();
-        println!("== function: {name}");
+    fn local_function(&mut self) -> Result<()> {
+        self.ctx.lex.next()?;
+        let name = self.read_name()?;
 
         // create `name` local variable before parsing funcbody(),
         // so the function can be called in body as recursion.
-        self.local_new(name);
+        self.local_new(name.clone());
 
-        let f = self.funcbody(false);
+        let f = self.funcbody(false, name)?;
         self.discharge(self.sp, f);
+        Ok(())
     }
 
     // BNF:
     //   function funcname funcbody
     //   funcname = Name {`.` Name} [`:` Name]
-    fn function_stat(&mut self) {
-        let name = self.read_name();
-        let mut desc = self.simple_name(name);
+    fn function_stat(&mut self) -> Result<()> {
+        let name = self.read_name()?;
+        let mut fullname = name.clone();
+        let mut desc = self.simple_name(name.clone());
 
         let with_self = loop {
-            match self.ctx.lex.peek() {
+            match self.ctx.lex.peek()? {
                 Token::Dot => { // `.` Name
-                    self.ctx.lex.next();
-                    let name = self.read_name();
+                    self.ctx.lex.next()?;
+                    let name = self.read_name()?;
+                    fullname = format!("{fullname}.{name}");
                     let t = self.discharge_any(desc);
                     desc = ExpDesc::IndexField(t, self.add_const(name));
                 }
                 Token::Colon => { // `:` Name
-                    self.ctx.lex.next();
-                    let name = self.read_name();
+                    self.ctx.lex.next()?;
+                    let name = self.read_name()?;
+                    fullname = format!("{fullname}:{name}");
                     let t = self.discharge_any(desc);
                     desc = ExpDesc::IndexField(t, self.add_const(name));
 
@@ -254,80 +383,90 @@ impl<'a, R: Read> ParseProto<'a, R> {
             }
         };
 
-        let body = self.funcbody(with_self);
-        self.assign_var(desc, body);
+        if fullname == name {
+            self.check_assignable(&desc, Some(&name))?;
+        }
+        let body = self.funcbody(with_self, fullname)?;
+        self.assign_var(desc, body)
     }
 
     // BNF:
     //   funcbody ::= `(` [parlist] `)` block end
     //   parlist ::= namelist [`,` `...`] | `...`
     //   namelist ::= Name {`,` Name}
-    fn funcbody(&mut self, with_self: bool) -> ExpDesc {
+    fn funcbody(&mut self, with_self: bool, name: String) -> Result<ExpDesc> {
+        let linedefined = self.ctx.lex.line();
+
         // parameter list
         let mut has_varargs = false;
         let mut params = Vec::new();
         if with_self {
             params.push(String::from("self"));
         }
-        self.ctx.lex.expect(Token::ParL);
+        self.ctx.lex.expect(Token::ParL)?;
         loop {
-            match self.ctx.lex.next() {
+            match self.ctx.lex.next()? {
                 Token::Name(name) => {
                     params.push(name);
-                    match self.ctx.lex.next() {
+                    match self.ctx.lex.next()? {
                         Token::Comma => (),
                         Token::ParR => break,
-                        t => panic!("invalid parameter {t:?}"),
+                        t => return Err(self.error_near("')' expected", &t)),
                     }
                 }
                 Token::Dots => {
                     has_varargs = true;
-                    self.ctx.lex.expect(Token::ParR);
+                    self.ctx.lex.expect(Token::ParR)?;
                     break;
                 },
                 Token::ParR => break,
-                t => panic!("invalid parameter {t:?}"),
+                t => return Err(self.error_near("<name> expected", &t)),
             }
         }
 
         // body
-        let proto = chunk(self.ctx, has_varargs, params, Token::End);
+        let mut proto = chunk(self.ctx, has_varargs, params, Token::End)?;
+        proto.name = name;
+        proto.linedefined = linedefined;
 
         let no_upvalue = proto.upindexes.is_empty();
         let iconst = self.add_const(Value::LuaFunction(Rc::new(proto)));
         if no_upvalue {
-            ExpDesc::Function(iconst)
+            Ok(ExpDesc::Function(iconst))
         } else {
-            ExpDesc::Closure(iconst)
+            Ok(ExpDesc::Closure(iconst))
         }
     }
 
     // BNF:
     //   varlist = explist
     //   varlist ::= var {`,` var}
-    fn assignment(&mut self, first_var: ExpDesc) {
+    fn assignment(&mut self, first_var: ExpDesc) -> Result<()> {
         // read varlist into @vars
         let mut vars = vec![first_var];
         loop {
-            match self.ctx.lex.next() {
+            match self.ctx.lex.next()? {
                 Token::Comma => { // more variable
-                    let token = self.ctx.lex.next();
-                    vars.push(self.prefixexp(token));
+                    let token = self.ctx.lex.next()?;
+                    let name = if let Token::Name(name) = &token { Some(name.clone()) } else { None };
+                    let var = self.prefixexp(token)?;
+                    self.check_assignable(&var, name.as_deref())?;
+                    vars.push(var);
                 }
                 Token::Assign => break,
-                t => panic!("invalid assign {t:?}"),
+                t => return Err(self.error_near("'=' expected", &t)),
             }
         }
 
         let sp0 = self.sp;
-        let (mut nexp, last_exp) = self.explist();
+        let (mut nexp, last_exp) = self.explist()?;
 
         // assignment last variable
         match (nexp + 1).cmp(&vars.len()) {
             Ordering::Equal => {
                 // assign last variable directly to avoid potential discharging
                 let last_var = vars.pop().unwrap();
-                self.assign_var(last_var, last_exp);
+                self.assign_var(last_var, last_exp)?;
             }
             Ordering::Less => {
                 // expand last expressions
@@ -343,43 +482,45 @@ impl<'a, R: Read> ParseProto<'a, R> {
         // assign previous variables from tmp registers, in reverse order
         while let Some(var) = vars.pop() {
             nexp -= 1;
-            self.assign_from_stack(var, sp0 + nexp);
+            self.assign_from_stack(var, sp0 + nexp)?;
         }
+        Ok(())
     }
 
     // BNF:
     //   if exp then block {elseif exp then block} [else block] end
-    fn if_stat(&mut self) {
+    fn if_stat(&mut self) -> Result<()> {
         let mut jmp_ends = Vec::new();
 
         // == if exp then block
-        let mut end_token = self.do_if_block(&mut jmp_ends);
+        let mut end_token = self.do_if_block(&mut jmp_ends)?;
 
         // == {elseif exp then block}
         while end_token == Token::Elseif {
-            end_token = self.do_if_block(&mut jmp_ends);
+            end_token = self.do_if_block(&mut jmp_ends)?;
         }
 
         // == [else block]
         if end_token == Token::Else {
-            end_token = self.block();
+            end_token = self.block()?;
         }
 
-        assert_eq!(end_token, Token::End);
+        self.check_block_end(end_token, Token::End)?;
 
-        let iend = self.fp.byte_codes.len() - 1;
+        let iend = self.byte_codes.len() - 1;
         for i in jmp_ends.into_iter() {
-            self.fp.byte_codes[i] = ByteCode::Jump((iend - i) as i16);
+            self.byte_codes[i] = ByteCode::Jump((iend - i) as i32);
         }
+        Ok(())
     }
 
-    fn do_if_block(&mut self, jmp_ends: &mut Vec<usize>) -> Token {
-        let condition = self.exp();
+    fn do_if_block(&mut self, jmp_ends: &mut Vec<usize>) -> Result<Token> {
+        let condition = self.exp()?;
         let false_list = self.test_or_jump(condition);
 
-        self.ctx.lex.expect(Token::Then);
+        self.ctx.lex.expect(Token::Then)?;
 
-        let end_token = self.block();
+        let end_token = self.block()?;
 
         // If there are following 'elseif' or 'else' blocks,
         // jump to the very end of this whole if-statment at the
@@ -387,136 +528,157 @@ impl<'a, R: Read> ParseProto<'a, R> {
         // Make a fake byte-code to hold the place, and fix it
         // at the end of whole if-statment.
         if matches!(end_token, Token::Elseif | Token::Else) {
-            self.fp.byte_codes.push(ByteCode::Jump(0));
-            jmp_ends.push(self.fp.byte_codes.len() - 1);
+            self.push_code(ByteCode::Jump(0));
+            jmp_ends.push(self.byte_codes.len() - 1);
         }
 
         self.fix_test_list(false_list);
 
-        end_token
+        Ok(end_token)
     }
 
     // BNF:
     //   while exp do block end
-    fn while_stat(&mut self) {
-        let istart = self.fp.byte_codes.len();
+    fn while_stat(&mut self) -> Result<()> {
+        let istart = self.byte_codes.len();
 
-        let condition = self.exp();
+        let condition = self.exp()?;
         let false_list = self.test_or_jump(condition);
 
-        self.ctx.lex.expect(Token::Do);
+        self.ctx.lex.expect(Token::Do)?;
 
-        self.push_loop_block();
+        self.push_loop_block(self.local_num());
 
-        assert_eq!(self.block(), Token::End);
+        let end_token = self.block()?;
+        self.check_block_end(end_token, Token::End)?;
 
         // jump back
-        let iend = self.fp.byte_codes.len();
-        self.fp.byte_codes.push(ByteCode::Jump(-((iend - istart) as i16) - 1));
+        let iend = self.byte_codes.len();
+        self.push_code(ByteCode::Jump(-((iend - istart) as i32) - 1));
 
-        self.pop_loop_block(istart);
+        self.pop_loop_block(istart)?;
 
         self.fix_test_list(false_list);
+        Ok(())
     }
 
     // BNF:
     //   repeat block until exp
-    fn repeat_stat(&mut self) {
-        let istart = self.fp.byte_codes.len();
-
-        self.push_loop_block();
+    fn repeat_stat(&mut self) -> Result<()> {
+        let istart = self.byte_codes.len();
 
         let nvar = self.local_num();
 
-        assert_eq!(self.block_scope(), Token::Until);
-        let iend = self.fp.byte_codes.len();
+        self.push_loop_block(nvar);
+
+        let end_token = self.block_scope()?;
+        self.check_block_end(end_token, Token::Until)?;
+        let iend = self.byte_codes.len();
 
-        let condition = self.exp();
+        let condition = self.exp()?;
         let false_list = self.test_or_jump(condition);
-        self.fix_test_list_to(false_list, istart);
 
-        self.pop_loop_block(iend);
+        if self.ctx.levels.last().unwrap().locals[nvar..].iter().any(LocalVar::need_close) {
+            // close the internal local variables before repeating,
+            // while the normal exit jumps over it
+            self.push_code(ByteCode::Jump(0));
+            let iexit = self.byte_codes.len() - 1;
+
+            self.fix_test_list(false_list);
+            self.push_code(ByteCode::Close(nvar as u8));
+            let iback = self.byte_codes.len();
+            self.push_code(ByteCode::Jump(istart as i32 - iback as i32 - 1));
+
+            self.byte_codes[iexit] = ByteCode::Jump((iback - iexit) as i32);
+        } else {
+            self.fix_test_list_to(false_list, istart);
+        }
+
+        self.pop_loop_block(iend)?;
 
         // expire internal local variables AFTER reading condition exp
         // and pop_loop_block()
         self.local_expire(nvar);
+        Ok(())
     }
 
     // * numerical: for Name `=` ...
     // * generic:   for Name {, Name} in ...
-    fn for_stat(&mut self) {
-        let name = self.read_name();
-        if self.ctx.lex.peek() == &Token::Assign {
-            self.numerical_for(name);
+    fn for_stat(&mut self) -> Result<()> {
+        let name = self.read_name()?;
+        if self.ctx.lex.peek()? == &Token::Assign {
+            self.numerical_for(name)
         } else {
-            self.generic_for(name);
+            self.generic_for(name)
         }
     }
 
     // BNF:
     //   for Name `=` exp `,` exp [`,` exp] do block end
-    fn numerical_for(&mut self, name: String) {
-        self.ctx.lex.next(); // skip `=`
+    fn numerical_for(&mut self, name: String) -> Result<()> {
+        self.ctx.lex.next()?; // skip `=`
 
         // 2 or 3 exps
-        let (nexp, last_exp) = self.explist();
+        let (nexp, last_exp) = self.explist()?;
         self.discharge(self.sp, last_exp);
 
         match nexp + 1 {
             2 => self.discharge(self.sp, ExpDesc::Integer(1)),
             3 => (),
-            _ => panic!("invalid numerical for exp"),
+            _ => return Err(self.error("invalid numerical for exp")),
         }
 
         // create 3 local variables: the first is iterator,
         // and the other two to keep stack positions.
+        let nvar = self.local_num();
         self.local_new(name);
         self.local_new(String::from(""));
         self.local_new(String::from(""));
 
-        self.ctx.lex.expect(Token::Do);
+        self.ctx.lex.expect(Token::Do)?;
 
         // ByteCode::ForPrepare, without argument
-        self.fp.byte_codes.push(ByteCode::ForPrepare(0, 0));
-        let iprepare = self.fp.byte_codes.len() - 1;
+        self.push_code(ByteCode::ForPrepare(0, 0));
+        let iprepare = self.byte_codes.len() - 1;
         let iname = self.sp - 3;
 
-        self.push_loop_block();
+        self.push_loop_block(nvar);
 
         // parse block!
-        assert_eq!(self.block(), Token::End);
+        let end_token = self.block()?;
+        self.check_block_end(end_token, Token::End)?;
 
         // expire 3 local variables above, before ByteCode::ForLoop
         self.local_expire(self.local_num() - 3);
 
         // ByteCode::ForLoop, and fix ByteCode::ForPrepare above
-        let d = self.fp.byte_codes.len() - iprepare;
-        self.fp.byte_codes.push(ByteCode::ForLoop(iname as u8, d as u16));
-        self.fp.byte_codes[iprepare] = ByteCode::ForPrepare(iname as u8, d as u16);
+        let d = self.byte_codes.len() - iprepare;
+        self.push_code(ByteCode::ForLoop(iname as u8, d as u32));
+        self.byte_codes[iprepare] = ByteCode::ForPrepare(iname as u8, d as u32);
 
-        self.pop_loop_block(self.fp.byte_codes.len() - 1);
+        self.pop_loop_block(self.byte_codes.len() - 1)
     }
 
     // BNF:
     //   stat ::= for namelist in explist do block end
     //   namelist ::= Name {`,` Name}
-    fn generic_for(&mut self, name: String) {
+    fn generic_for(&mut self, name: String) -> Result<()> {
         // namelist
         let mut vars = vec![name];
         loop {
-            match self.ctx.lex.next() {
-                Token::Comma => continue,
+            match self.ctx.lex.next()? {
+                Token::Comma => vars.push(self.read_name()?),
                 Token::In => break,
-                Token::Name(name) => vars.push(name),
-                _ => panic!("invalid generic_for namelist"),
+                t => return Err(self.error_near("'in' expected", &t)),
             }
         }
 
         // explist
         let iter = self.sp;
-        self.explist_want(3);
+        self.explist_want(3)?;
 
         let nvar = vars.len();
+        let nvar0 = self.local_num();
         self.local_new(String::from("")); // iterator function
         self.local_new(String::from("")); // immutable state
         self.local_new(String::from("")); // control variable
@@ -524,105 +686,114 @@ impl<'a, R: Read> ParseProto<'a, R> {
             self.local_new(var);
         }
 
-        self.ctx.lex.expect(Token::Do);
+        self.ctx.lex.expect(Token::Do)?;
 
         // jump to ByteCode::ForCallLoop at end of block
-        self.fp.byte_codes.push(ByteCode::Jump(0));
-        let ijump = self.fp.byte_codes.len() - 1;
+        self.push_code(ByteCode::Jump(0));
+        let ijump = self.byte_codes.len() - 1;
 
-        self.push_loop_block();
+        self.push_loop_block(nvar0);
 
         // parse block!
-        assert_eq!(self.block(), Token::End);
+        let end_token = self.block()?;
+        self.check_block_end(end_token, Token::End)?;
 
         // expire local variables above, before ByteCode::Jump
         self.local_expire(self.local_num() - 3 - nvar);
 
         // ByteCode::ForCallLoop
         // call the iter function and check the control variable
-        let d = self.fp.byte_codes.len() - ijump;
-        self.fp.byte_codes[ijump] = ByteCode::Jump(d as i16 - 1);
+        let d = self.byte_codes.len() - ijump;
+        self.byte_codes[ijump] = ByteCode::Jump(d as i32 - 1);
         if let Ok(d) = u8::try_from(d) {
-            self.fp.byte_codes.push(ByteCode::ForCallLoop(iter as u8, nvar as u8, d as u8));
+            self.push_code(ByteCode::ForCallLoop(iter as u8, nvar as u8, d));
         } else {
-            self.fp.byte_codes.push(ByteCode::ForCallLoop(iter as u8, nvar as u8, 0));
-            self.fp.byte_codes.push(ByteCode::Jump(-(d as i16) - 1));
+            self.push_code(ByteCode::ForCallLoop(iter as u8, nvar as u8, 0));
+            self.push_code(ByteCode::Jump(-(d as i32) - 1));
         }
 
-        self.pop_loop_block(self.fp.byte_codes.len() - 1);
+        self.pop_loop_block(self.byte_codes.len() - 1)
     }
 
-    fn break_stat(&mut self) {
-        let Some(breaks) = self.break_blocks.last_mut() else {
-            panic!("break outside loop");
+    fn break_stat(&mut self) -> Result<()> {
+        let Some(&(nvar, _)) = self.break_blocks.last() else {
+            return Err(self.error("break outside loop"));
         };
-        self.fp.byte_codes.push(ByteCode::Jump(0));
-        breaks.push(self.fp.byte_codes.len() - 1);
+        self.local_check_close(nvar);
+        self.push_code(ByteCode::Jump(0));
+        let icode = self.byte_codes.len() - 1;
+        self.break_blocks.last_mut().unwrap().1.push(icode);
+        Ok(())
     }
 
-    fn try_continue_stat(&mut self, name: &Token) -> bool {
-        let Token::Name(name) = name else { return false; };
+    fn try_continue_stat(&mut self, name: &Token) -> Result<bool> {
+        let Token::Name(name) = name else { return Ok(false); };
         if name.as_str() != "continue" {
-            return false;
+            return Ok(false);
         }
-        if !matches!(self.ctx.lex.peek(), Token::End | Token::Elseif | Token::Else) {
-            return false;
+        if !matches!(self.ctx.lex.peek()?, Token::End | Token::Elseif | Token::Else) {
+            return Ok(false);
         }
 
         let nvar = self.local_num();
-        let Some(continues) = self.continue_blocks.last_mut() else {
-            panic!("continue outside loop");
+        let Some(&(loop_nvar, _)) = self.break_blocks.last() else {
+            return Err(self.error("continue outside loop"));
         };
-        self.fp.byte_codes.push(ByteCode::Jump(0));
-        continues.push((self.fp.byte_codes.len() - 1, nvar));
-        true
+        self.local_check_close(loop_nvar);
+        self.push_code(ByteCode::Jump(0));
+        let icode = self.byte_codes.len() - 1;
+        self.continue_blocks.last_mut().unwrap().push((icode, nvar));
+        Ok(true)
     }
 
-    // before entering loop block
-    fn push_loop_block(&mut self) {
-        self.break_blocks.push(Vec::new());
+    // before entering loop block, @nvar is the number of local
+    // variables out of the loop
+    fn push_loop_block(&mut self, nvar: usize) {
+        self.break_blocks.push((nvar, Vec::new()));
         self.continue_blocks.push(Vec::new());
     }
     // after leaving loop block, fix `break` and `continue` Jumps
-    fn pop_loop_block(&mut self, icontinue: usize) {
+    fn pop_loop_block(&mut self, icontinue: usize) -> Result<()> {
         // breaks
-        let iend = self.fp.byte_codes.len() - 1;
-        for i in self.break_blocks.pop().unwrap().into_iter() {
-            self.fp.byte_codes[i] = ByteCode::Jump((iend - i) as i16);
+        let iend = self.byte_codes.len() - 1;
+        for i in self.break_blocks.pop().unwrap().1.into_iter() {
+            self.byte_codes[i] = ByteCode::Jump((iend - i) as i32);
         }
 
         // continues
         let end_nvar = self.local_num();
         for (i, i_nvar) in self.continue_blocks.pop().unwrap().into_iter() {
             if i_nvar < end_nvar {
-                panic!("continue jump into local scope");
+                return Err(self.error("continue jump into local scope"));
             }
-            self.fp.byte_codes[i] = ByteCode::Jump((icontinue as isize - i as isize) as i16 - 1);
+            self.byte_codes[i] = ByteCode::Jump((icontinue as isize - i as isize) as i32 - 1);
         }
+        Ok(())
     }
 
     // BNF:
     //   do block end
-    fn do_stat(&mut self) {
-        assert_eq!(self.block(), Token::End);
+    fn do_stat(&mut self) -> Result<()> {
+        let end_token = self.block()?;
+        self.check_block_end(end_token, Token::End)
     }
 
     // BNF:
     //   label ::= `::` Name `::`
-    fn label_stat(&mut self, igoto: usize) {
-        let name = self.read_name();
-        self.ctx.lex.expect(Token::DoubColon);
+    fn label_stat(&mut self, igoto: usize) -> Result<()> {
+        let name = self.read_name()?;
+        self.ctx.lex.expect(Token::DoubColon)?;
 
         // check if this label is at the end of block.
         // ignore void statments: `;` and label.
         let is_last = loop {
-            match self.ctx.lex.peek() {
+            match self.ctx.lex.peek()? {
                 Token::SemiColon => {
-                    self.ctx.lex.next();
+                    self.ctx.lex.next()?;
                 }
                 Token::DoubColon => {
-                    self.ctx.lex.next();
-                    self.label_stat(igoto);
+                    self.ctx.lex.next()?;
+                    self.label_stat(igoto)?;
                 }
                 t => break is_block_end(t),
             }
@@ -630,21 +801,23 @@ impl<'a, R: Read> ParseProto<'a, R> {
 
         // check duplicate
         if self.labels.iter().any(|l|l.name == name) {
-            panic!("duplicate label {name}");
+            return Err(self.error(format!("duplicate label {name}")));
         }
 
-        let icode = self.fp.byte_codes.len();
+        let icode = self.byte_codes.len();
         let nvar = self.local_num();
 
         // match previous gotos
         let mut no_dsts = Vec::new();
+        let mut close = false;
         for goto in self.gotos.drain(igoto..) {
             if goto.name == name {
                 if !is_last && goto.nvar < nvar {
-                    panic!("goto jump into scope {}", goto.name);
+                    return Err(self.ctx.lex.error(format!("goto jump into scope {}", goto.name)));
                 }
                 let dist = icode - goto.icode;
-                self.fp.byte_codes[goto.icode] = ByteCode::Jump(dist as i16 - 1);
+                self.byte_codes[goto.icode] = ByteCode::Jump(dist as i32 - 1);
+                close |= goto.close;
             } else {
                 // no matched label
                 no_dsts.push(goto);
@@ -652,40 +825,49 @@ impl<'a, R: Read> ParseProto<'a, R> {
         }
         self.gotos.append(&mut no_dsts);
 
+        // the gotos jump over the Close at the end of the blocks they
+        // leave, so close here
+        if close {
+            self.push_code(ByteCode::Close(nvar as u8));
+        }
+
         // save the label for following gotos
-        self.labels.push(GotoLabel { name, icode, nvar });
+        self.labels.push(GotoLabel { name, icode, nvar, close: false });
+        Ok(())
     }
 
     // BNF:
     //   goto Name
-    fn goto_stat(&mut self) {
-        let name = self.read_name();
+    fn goto_stat(&mut self) -> Result<()> {
+        let name = self.read_name()?;
 
         // match previous label
         if let Some(label) = self.labels.iter().rev().find(|l|l.name == name) {
             // find label
-            let dist = self.fp.byte_codes.len() - label.icode;
+            let dist = self.byte_codes.len() - label.icode;
             self.local_check_close(label.nvar);
-            self.fp.byte_codes.push(ByteCode::Jump(-(dist as i16) - 1));
+            self.push_code(ByteCode::Jump(-(dist as i32) - 1));
 
         } else {
             // not find label, push a fake byte code and save the goto
-            self.fp.byte_codes.push(ByteCode::Jump(0));
+            self.push_code(ByteCode::Jump(0));
 
             self.gotos.push(GotoLabel {
                 name,
-                icode: self.fp.byte_codes.len() - 1,
+                icode: self.byte_codes.len() - 1,
                 nvar: self.local_num(),
+                close: false,
             });
         }
+        Ok(())
     }
 
     // BNF:
     //   retstat ::= return [explist] [‘;’]
-    fn ret_stat(&mut self) {
-        let code = match self.ctx.lex.peek() {
+    fn ret_stat(&mut self) -> Result<()> {
+        let code = match self.ctx.lex.peek()? {
             Token::SemiColon => {
-                self.ctx.lex.next();
+                self.ctx.lex.next()?;
                 ByteCode::Return0
             }
             t if is_block_end(t) => {
@@ -693,15 +875,15 @@ impl<'a, R: Read> ParseProto<'a, R> {
             }
             _ => { // return values
                 let iret = self.sp;
-                let (nexp, last_exp) = self.explist();
+                let (nexp, last_exp) = self.explist()?;
 
                 // check optional ';'
-                if self.ctx.lex.peek() == &Token::SemiColon {
-                    self.ctx.lex.next();
+                if self.ctx.lex.peek()? == &Token::SemiColon {
+                    self.ctx.lex.next()?;
                 }
                 // check block end
-                if !is_block_end(self.ctx.lex.peek()) {
-                    panic!("'end' expected");
+                if !is_block_end(self.ctx.lex.peek()?) {
+                    return Err(self.error("'end' expected"));
                 }
 
                 if let (0, &ExpDesc::Local(i)) = (nexp, &last_exp) {
@@ -709,8 +891,9 @@ impl<'a, R: Read> ParseProto<'a, R> {
                     // stack top for continuity
                     ByteCode::Return(i as u8, 1)
 
-                } else if let (0, &ExpDesc::Call(func, narg_plus)) = (nexp, &last_exp) {
-                    // tail call
+                } else if let (0, &ExpDesc::Call(func, narg_plus), false) = (nexp, &last_exp, self.has_tbc()) {
+                    // tail call, only if no to-be-closed variable which
+                    // should be closed after the call
                     ByteCode::TailCall(func as u8, narg_plus as u8)
 
                 } else if self.discharge_try_expand(last_exp, 0) {
@@ -723,11 +906,12 @@ impl<'a, R: Read> ParseProto<'a, R> {
                 }
             }
         };
-        self.fp.byte_codes.push(code);
+        self.push_code(code);
+        Ok(())
     }
 
     // process assignment: var = value
-    fn assign_var(&mut self, var: ExpDesc, value: ExpDesc) {
+    fn assign_var(&mut self, var: ExpDesc, value: ExpDesc) -> Result<()> {
         if let ExpDesc::Local(i) = var {
             // self.sp will be set to i+1 in self.discharge(), which is
             // NOT expected, but it's ok because self.sp will not be used
@@ -735,42 +919,86 @@ impl<'a, R: Read> ParseProto<'a, R> {
             self.discharge(i, value);
         } else {
             match self.discharge_const(value) {
-                ConstStack::Const(i) => self.assign_from_const(var, i),
-                ConstStack::Stack(i) => self.assign_from_stack(var, i),
+                ConstStack::Const(i) => self.assign_from_const(var, i)?,
+                ConstStack::Stack(i) => self.assign_from_stack(var, i)?,
             }
         }
+        Ok(())
     }
 
-    fn assign_from_stack(&mut self, var: ExpDesc, value: usize) {
-        let code = match var {
+    fn assign_from_stack(&mut self, var: ExpDesc, value: usize) -> Result<()> {
+        let code = match self.fit_index(var, value) {
             ExpDesc::Local(i) => ByteCode::Move(i as u8, value as u8),
             ExpDesc::Upvalue(i) => ByteCode::SetUpvalue(i as u8, value as u8),
             ExpDesc::Index(t, key) => ByteCode::SetTable(t as u8, key as u8, value as u8),
             ExpDesc::IndexField(t, key) => ByteCode::SetField(t as u8, key as u8, value as u8),
             ExpDesc::IndexInt(t, key) => ByteCode::SetInt(t as u8, key, value as u8),
             ExpDesc::IndexUpField(t, key) => ByteCode::SetUpField(t as u8, key as u8, value as u8),
-            _ => panic!("assign from stack"),
+            _ => return Err(self.error("cannot assign")),
         };
-        self.fp.byte_codes.push(code);
+        self.push_code(code);
+        Ok(())
     }
 
-    fn assign_from_const(&mut self, var: ExpDesc, value: usize) {
-        let code = match var {
+    fn assign_from_const(&mut self, var: ExpDesc, value: usize) -> Result<()> {
+        let code = match self.fit_index(var, 0) {
             ExpDesc::Upvalue(i) => ByteCode::SetUpvalueConst(i as u8, value as u8),
             ExpDesc::Index(t, key) => ByteCode::SetTableConst(t as u8, key as u8, value as u8),
             ExpDesc::IndexField(t, key) => ByteCode::SetFieldConst(t as u8, key as u8, value as u8),
             ExpDesc::IndexInt(t, key) => ByteCode::SetIntConst(t as u8, key, value as u8),
             ExpDesc::IndexUpField(t, key) => ByteCode::SetUpFieldConst(t as u8, key as u8, value as u8),
-            _ => panic!("assign from const"),
+            _ => return Err(self.error("cannot assign")),
+        };
+        self.push_code(code);
+        Ok(())
+    }
+
+    // The constant key index in IndexField and IndexUpField is encoded
+    // in u8. If it overflows, load the key (and the upvalue table) into
+    // free registers above @above, and turn it into Index.
+    fn fit_index(&mut self, desc: ExpDesc, above: usize) -> ExpDesc {
+        let ireg = self.sp.max(above + 1);
+        let desc = match desc {
+            ExpDesc::IndexField(itable, ikey) if ikey > u8::MAX as usize => {
+                self.load_const(ireg, ikey);
+                ExpDesc::Index(itable, ireg)
+            }
+            ExpDesc::IndexUpField(itable, ikey) if ikey > u8::MAX as usize => {
+                self.check_register(ireg);
+                self.push_code(ByteCode::GetUpvalue(ireg as u8, itable as u8));
+                self.load_const(ireg + 1, ikey);
+                ExpDesc::Index(ireg, ireg + 1)
+            }
+            desc => return desc,
         };
-        self.fp.byte_codes.push(code);
+        // keep the registers from following discharging
+        self.sp = ireg + 2;
+        desc
+    }
+
+    fn load_const(&mut self, dst: usize, iconst: usize) {
+        self.check_register(dst);
+        let code = self.code_const(ByteCode::LoadConst, ByteCode::LoadConstX, dst, iconst);
+        self.push_code(code);
+    }
+
+    // Return LoadConst/Closure, or push LoadConstX/ClosureX and return the
+    // following ExtraArg if the constant index does not fit in Bx.
+    fn code_const(&mut self, op: fn(u8, u32) -> ByteCode, opx: fn(u8) -> ByteCode,
+            dst: usize, iconst: usize) -> ByteCode {
+        if iconst <= MAX_BX as usize {
+            op(dst as u8, iconst as u32)
+        } else {
+            self.push_code(opx(dst as u8));
+            ByteCode::ExtraArg(iconst as u32)
+        }
     }
 
     // add the value to constants
     fn add_const(&mut self, c: impl Into<Value>) -> usize {
         let c = c.into();
         let constants = &mut self.fp.constants;
-        constants.iter().position(|v| v.same(&c)).unwrap_or_else(|| {
+        *self.constants.entry(ConstKey(c.clone())).or_insert_with(|| {
             constants.push(c);
             constants.len() - 1
         })
@@ -780,24 +1008,30 @@ impl<'a, R: Read> ParseProto<'a, R> {
     //
     // Read expressions, discharge front ones, and keep last one.
     // Return the number of front expressions and the last expression.
-    fn explist(&mut self) -> (usize, ExpDesc) {
+    fn explist(&mut self) -> Result<(usize, ExpDesc)> {
         let sp0 = self.sp;
         let mut n = 0;
         loop {
-            let desc = self.exp();
-            if self.ctx.lex.peek() != &Token::Comma {
+            let desc = self.exp()?;
+            if self.ctx.lex.peek()? != &Token::Comma {
                 self.sp = sp0 + n;
-                return (n, desc);
+                return Ok((n, desc));
             }
-            self.ctx.lex.next();
+            self.ctx.lex.next()?;
 
             self.discharge(sp0 + n, desc);
             n += 1;
         }
     }
 
-    fn explist_want(&mut self, want: usize) {
-        let (nexp, last_exp) = self.explist();
+    fn explist_want(&mut self, want: usize) -> Result<()> {
+        let (nexp, last_exp) = self.explist()?;
+        self.explist_adjust(nexp, last_exp, want);
+        Ok(())
+    }
+
+    // adjust the result of explist() to @want values
+    fn explist_adjust(&mut self, nexp: usize, last_exp: ExpDesc, want: usize) {
         match (nexp + 1).cmp(&want) {
             Ordering::Equal => {
                 self.discharge(self.sp, last_exp);
@@ -823,17 +1057,17 @@ impl<'a, R: Read> ParseProto<'a, R> {
     //           prefixexp | tableconstructor | unop exp) A'
     // where:
     //   A' ::= binop exp A' | Epsilon
-    fn exp(&mut self) -> ExpDesc {
+    fn exp(&mut self) -> Result<ExpDesc> {
         self.exp_limit(0)
     }
-    fn exp_limit(&mut self, limit: i32) -> ExpDesc {
-        let ahead = self.ctx.lex.next();
+    fn exp_limit(&mut self, limit: i32) -> Result<ExpDesc> {
+        let ahead = self.ctx.lex.next()?;
         self.do_exp(limit, ahead)
     }
-    fn exp_with_ahead(&mut self, ahead: Token) -> ExpDesc {
+    fn exp_with_ahead(&mut self, ahead: Token) -> Result<ExpDesc> {
         self.do_exp(0, ahead)
     }
-    fn do_exp(&mut self, limit: i32, ahead: Token) -> ExpDesc {
+    fn do_exp(&mut self, limit: i32, ahead: Token) -> Result<ExpDesc> {
         // beta
         let mut desc = match ahead {
             Token::Nil => ExpDesc::Nil,
@@ -845,39 +1079,39 @@ impl<'a, R: Read> ParseProto<'a, R> {
 
             Token::Dots => {
                 if !self.fp.has_varargs {
-                    panic!("no varargs");
+                    return Err(self.error("cannot use '...' outside a vararg function"));
                 }
                 ExpDesc::VarArgs
             }
-            Token::Function => self.funcbody(false),
-            Token::CurlyL => self.table_constructor(),
+            Token::Function => self.funcbody(false, String::new())?,
+            Token::CurlyL => self.table_constructor()?,
 
-            Token::Sub => self.unop_neg(),
-            Token::Not => self.unop_not(),
-            Token::BitNot => self.unop_bitnot(),
-            Token::Len => self.unop_len(),
+            Token::Sub => self.unop_neg()?,
+            Token::Not => self.unop_not()?,
+            Token::BitNot => self.unop_bitnot()?,
+            Token::Len => self.unop_len()?,
 
-            t => self.prefixexp(t),
+            t => self.prefixexp(t)?,
         };
 
         // A' = alpha A'
         loop {
             // Expand only if next operator has priority higher than 'limit'.
             // Non-operator tokens' priority is -1(lowest) so they always break here.
-            let (left_pri, right_pri) = binop_pri(self.ctx.lex.peek());
+            let (left_pri, right_pri) = binop_pri(self.ctx.lex.peek()?);
             if left_pri <= limit {
-                return desc;
+                return Ok(desc);
             }
 
-            let binop = self.ctx.lex.next();
+            let binop = self.ctx.lex.next()?;
             desc = self.preprocess_binop_left(desc, &binop);
-            let right_desc = self.exp_limit(right_pri);
+            let right_desc = self.exp_limit(right_pri)?;
             desc = self.process_binop(binop, desc, right_desc);
         }
     }
 
     // used for unary operand
-    fn exp_unop(&mut self) -> ExpDesc {
+    fn exp_unop(&mut self) -> Result<ExpDesc> {
         self.exp_limit(12) // 12 is all unary operators' priority
     }
 
@@ -911,27 +1145,27 @@ impl<'a, R: Read> ParseProto<'a, R> {
     // where:
     //   A' ::= alpha A' | Epsilon
     //        = (`[` exp `]` | `.` Name | args | `:` Name args) A' | Epsilon
-    fn prefixexp(&mut self, ahead: Token) -> ExpDesc {
+    fn prefixexp(&mut self, ahead: Token) -> Result<ExpDesc> {
         let sp0 = self.sp;
 
         // beta
         let mut desc = match ahead {
             Token::Name(name) => self.simple_name(name),
             Token::ParL => { // `(` exp `)`
-                let desc = self.exp();
-                self.ctx.lex.expect(Token::ParR);
+                let desc = self.exp()?;
+                self.ctx.lex.expect(Token::ParR)?;
                 desc
             }
-            t => panic!("invalid prefixexp {t:?}"),
+            t => return Err(self.error_near("unexpected symbol", &t)),
         };
 
         // A' = alpha A'
         loop {
-            match self.ctx.lex.peek() {
+            match self.ctx.lex.peek()? {
                 Token::SqurL => { // `[` exp `]`
-                    self.ctx.lex.next();
-                    let key = self.exp();
-                    self.ctx.lex.expect(Token::SqurR);
+                    self.ctx.lex.next()?;
+                    let key = self.exp()?;
+                    self.ctx.lex.expect(Token::SqurR)?;
 
                     desc = match (desc, key) {
                         // special case: upvalue-table and string-key
@@ -939,22 +1173,25 @@ impl<'a, R: Read> ParseProto<'a, R> {
                             ExpDesc::IndexUpField(itable, self.add_const(key))
                         }
                         // normal case
-                        (table, key) => {
+                        (table, ExpDesc::String(key)) => {
                             let itable = self.discharge_if_need(sp0, table);
-                            match key {
-                                ExpDesc::String(key) =>
-                                    ExpDesc::IndexField(itable, self.add_const(key)),
-                                ExpDesc::Integer(i) if u8::try_from(i).is_ok() =>
-                                    ExpDesc::IndexInt(itable, u8::try_from(i).unwrap()),
-                                _ =>
-                                    ExpDesc::Index(itable, self.discharge_any(key)),
-                            }
+                            ExpDesc::IndexField(itable, self.add_const(key))
+                        }
+                        (table, ExpDesc::Integer(i)) if u8::try_from(i).is_ok() => {
+                            let itable = self.discharge_if_need(sp0, table);
+                            ExpDesc::IndexInt(itable, u8::try_from(i).unwrap())
+                        }
+                        (table, key) => {
+                            // the key may have used the stack from sp0 if the
+                            // table is not discharged yet, so discharge it first
+                            let ikey = self.discharge_any(key);
+                            ExpDesc::Index(self.discharge_any(table), ikey)
                         }
                     };
                 }
                 Token::Dot => { // .Name
-                    self.ctx.lex.next();
-                    let name = self.read_name();
+                    self.ctx.lex.next()?;
+                    let name = self.read_name()?;
                     let ikey = self.add_const(name);
 
                     desc = if let ExpDesc::Upvalue(itable) = desc {
@@ -965,27 +1202,36 @@ impl<'a, R: Read> ParseProto<'a, R> {
                     };
                 }
                 Token::Colon => { // :Name args
-                    self.ctx.lex.next();
-                    let name = self.read_name();
+                    self.ctx.lex.next()?;
+                    let name = self.read_name()?;
                     let ikey = self.add_const(name);
                     let itable = self.discharge_if_need(sp0, desc);
+                    self.check_register(sp0 + 1);
 
                     // GetFieldSelf:
                     //   stack[sp0] := itable[ikey]  # load function
                     //   stack[sp0+1] := itable      # load table as first argument
-                    self.fp.byte_codes.push(
-                        ByteCode::GetFieldSelf(sp0 as u8, itable as u8, ikey as u8));
+                    if let Ok(ikey) = u8::try_from(ikey) {
+                        self.push_code(
+                            ByteCode::GetFieldSelf(sp0 as u8, itable as u8, ikey));
+                    } else {
+                        // the key does not fit in u8, so load it into
+                        // stack[sp0] after moving the table
+                        self.push_code(ByteCode::Move(sp0 as u8 + 1, itable as u8));
+                        self.load_const(sp0, ikey);
+                        self.push_code(ByteCode::GetTable(sp0 as u8, sp0 as u8 + 1, sp0 as u8));
+                    }
 
                     // discharge following arguments begin at sp0+2
                     self.sp = sp0 + 2;
 
-                    desc = self.args(1);
+                    desc = self.args(1)?;
                 }
                 Token::ParL | Token::CurlyL | Token::String(_) => { // args
                     self.discharge(sp0, desc);
-                    desc = self.args(0);
+                    desc = self.args(0)?;
                 }
-                _ => return desc, // Epsilon
+                _ => return Ok(desc), // Epsilon
             }
         }
     }
@@ -995,24 +1241,68 @@ impl<'a, R: Read> ParseProto<'a, R> {
     }
 
     fn local_new(&mut self, name: String) {
-        self.ctx.levels.last_mut().unwrap().locals.push((name, false));
+        self.local_new_attrib(name, Attrib::Plain);
+    }
+    fn local_new_attrib(&mut self, name: String, attrib: Attrib) {
+        if self.local_num() >= MAX_LOCALS {
+            self.set_limit_error(format!("too many local variables (limit is {MAX_LOCALS})"));
+        }
+        let startpc = self.byte_codes.len();
+        let ilocvar = self.fp.locvars.len();
+        self.fp.locvars.push(LocVar { name: name.clone(), startpc, endpc: startpc });
+        let var = LocalVar { name, referred: false, attrib, ilocvar };
+        self.ctx.levels.last_mut().unwrap().locals.push(var);
     }
 
     fn local_expire(&mut self, from: usize) {
         // drop locals
-        let mut vars = self.ctx.levels.last_mut().unwrap().locals.drain(from..);
+        let endpc = self.byte_codes.len();
+        let mut need_close = false;
+        for var in self.ctx.levels.last_mut().unwrap().locals.drain(from..) {
+            self.fp.locvars[var.ilocvar].endpc = endpc;
+
+            // generate Close if any dropped local variable referred as
+            // upvalue or to be closed
+            need_close |= var.need_close();
+        }
+        if need_close {
+            self.push_code(ByteCode::Close(from as u8));
+        }
 
-        // generate Close if any dropped local variable referred as upvalue
-        if vars.any(|v| v.1) {
-            self.fp.byte_codes.push(ByteCode::Close(from as u8));
+        // pending gotos leave the scope
+        for goto in self.gotos.iter_mut().filter(|g| g.nvar > from) {
+            goto.nvar = from;
+            goto.close |= need_close;
         }
     }
 
-    // generate Close if any local variable in [from..] referred as upvalue
+    // generate Close if any local variable in [from..] referred as
+    // upvalue or to be closed
     fn local_check_close(&mut self, from: usize) {
         let mut vars = self.ctx.levels.last().unwrap().locals[from..].iter();
-        if vars.any(|v| v.1) {
-            self.fp.byte_codes.push(ByteCode::Close(from as u8));
+        if vars.any(LocalVar::need_close) {
+            self.push_code(ByteCode::Close(from as u8));
+        }
+    }
+
+    // whether any to-be-closed variable in current function
+    fn has_tbc(&self) -> bool {
+        self.ctx.levels.last().unwrap().locals.iter().any(|v| v.attrib == Attrib::Close)
+    }
+
+    // <const> and <close> variables can not be assigned. @name is the
+    // name if @var is a single name, which is needed for compile-time
+    // constants since they have been replaced by values.
+    fn check_assignable(&self, var: &ExpDesc, name: Option<&str>) -> Result<()> {
+        let level = self.ctx.levels.last().unwrap();
+        let readonly = match var {
+            ExpDesc::Local(i) => level.locals[*i].attrib != Attrib::Plain,
+            ExpDesc::Upvalue(i) => level.upvalues[*i].2,
+            _ => const_value(var).is_some() && name.is_some(),
+        };
+        match name {
+            Some(name) if readonly => Err(self.error(format!("attempt to assign to const variable '{name}'"))),
+            _ => Ok(()),
         }
     }
 
@@ -1022,8 +1312,11 @@ impl<'a, R: Read> ParseProto<'a, R> {
 
         // search from locals and upvalues in current level
         let level = level_iter.next().unwrap();
-        if let Some(i) = level.locals.iter().rposition(|v| v.0 == name) {
+        if let Some(i) = level.locals.iter().rposition(|v| v.name == name) {
             // search reversely, so new variable covers old one with same name
+            if let Attrib::Const(Some(v)) = &level.locals[i].attrib {
+                return const_desc(v);
+            }
             return ExpDesc::Local(i);
         }
         if let Some(i) = level.upvalues.iter().position(|v| v.0 == name) {
@@ -1032,12 +1325,18 @@ impl<'a, R: Read> ParseProto<'a, R> {
 
         // search in upper levels
         for (depth, level) in level_iter.enumerate() {
-            if let Some(i) = level.locals.iter().rposition(|v| v.0 == name) {
-                level.locals[i].1 = true; // mark it referred as upvalue
-                return self.create_upvalue(name, UpIndex::Local(i), depth);
+            if let Some(i) = level.locals.iter().rposition(|v| v.name == name) {
+                let var = &mut level.locals[i];
+                if let Attrib::Const(Some(v)) = &var.attrib {
+                    return const_desc(v); // no need upvalue
+                }
+                var.referred = true; // mark it referred as upvalue
+                let readonly = var.attrib != Attrib::Plain;
+                return self.create_upvalue(name, UpIndex::Local(i), readonly, depth);
             }
             if let Some(i) = level.upvalues.iter().position(|v| v.0 == name) {
-                return self.create_upvalue(name, UpIndex::Upvalue(i), depth);
+                let readonly = level.upvalues[i].2;
+                return self.create_upvalue(name, UpIndex::Upvalue(i), readonly, depth);
             }
         }
 
@@ -1046,60 +1345,77 @@ impl<'a, R: Read> ParseProto<'a, R> {
         match self.simple_name("_ENV".into()) {
             ExpDesc::Local(i) => ExpDesc::IndexField(i, iname),
             ExpDesc::Upvalue(i) => ExpDesc::IndexUpField(i, iname),
-            _ => panic!("no here"), // because "_ENV" must exist!
+            // constant _ENV, e.g. `local _ENV <const> = nil`
+            env => ExpDesc::IndexField(self.discharge_any(env), iname),
         }
     }
 
-    fn create_upvalue(&mut self, name: String, mut upidx: UpIndex, depth: usize) -> ExpDesc {
+    fn create_upvalue(&mut self, name: String, mut upidx: UpIndex, readonly: bool, depth: usize) -> ExpDesc {
         let levels = &mut self.ctx.levels;
         let last = levels.len() - 1;
 
         // create upvalue in middle levels, if any
+        let mut overflow = false;
         for Level { upvalues, .. } in levels[last-depth .. last].iter_mut() {
-            upvalues.push((name.clone(), upidx));
+            upvalues.push((name.clone(), upidx, readonly));
             upidx = UpIndex::Upvalue(upvalues.len() - 1);
+            overflow |= upvalues.len() > MAX_UPVALUES;
         }
 
         // create upvalue in current level
         let upvalues = &mut levels[last].upvalues;
-        upvalues.push((name, upidx));
-        ExpDesc::Upvalue(upvalues.len() - 1)
+        upvalues.push((name, upidx, readonly));
+        let iup = upvalues.len() - 1;
+
+        if overflow || iup >= MAX_UPVALUES {
+            self.set_limit_error(format!("too many upvalues (limit is {MAX_UPVALUES})"));
+        }
+        ExpDesc::Upvalue(iup)
     }
 
     // unop `-`
-    fn unop_neg(&mut self) -> ExpDesc {
-        match self.exp_unop() {
-            ExpDesc::Integer(i) => ExpDesc::Integer(-i),
+    fn unop_neg(&mut self) -> Result<ExpDesc> {
+        let desc = match self.exp_unop()? {
+            ExpDesc::Integer(i) => ExpDesc::Integer(i.wrapping_neg()),
             ExpDesc::Float(f) => ExpDesc::Float(-f),
-            ExpDesc::Nil | ExpDesc::Boolean(_) | ExpDesc::String(_) => panic!("invalid - operator"),
+            ExpDesc::Nil | ExpDesc::Boolean(_) => return Err(self.error("invalid - operator")),
             desc => ExpDesc::UnaryOp(ByteCode::Neg, self.discharge_any(desc))
-        }
+        };
+        Ok(desc)
     }
 
     // unop `not`
-    fn unop_not(&mut self) -> ExpDesc {
-        match self.exp_unop() {
+    fn unop_not(&mut self) -> Result<ExpDesc> {
+        let desc = match self.exp_unop()? {
             ExpDesc::Nil => ExpDesc::Boolean(true),
             ExpDesc::Boolean(b) => ExpDesc::Boolean(!b),
             ExpDesc::Integer(_) | ExpDesc::Float(_) | ExpDesc::String(_) => ExpDesc::Boolean(false),
             desc => ExpDesc::UnaryOp(ByteCode::Not, self.discharge_any(desc)),
-        }
+        };
+        Ok(desc)
     }
     // unop `~`
-    fn unop_bitnot(&mut self) -> ExpDesc {
-        match self.exp_unop() {
+    fn unop_bitnot(&mut self) -> Result<ExpDesc> {
+        let desc = match self.exp_unop()? {
             ExpDesc::Integer(i) => ExpDesc::Integer(!i),
-            ExpDesc::Nil | ExpDesc::Boolean(_) | ExpDesc::Float(_) | ExpDesc::String(_) => panic!("invalid ~ operator"),
+            ExpDesc::Float(f) => match ftoi(f) {
+                Some(i) => ExpDesc::Integer(!i),
+                // leave the float without integer representation to raise error at runtime
+                None => ExpDesc::UnaryOp(ByteCode::BitNot, self.discharge_any(ExpDesc::Float(f))),
+            },
+            ExpDesc::Nil | ExpDesc::Boolean(_) => return Err(self.error("invalid ~ operator")),
             desc => ExpDesc::UnaryOp(ByteCode::BitNot, self.discharge_any(desc)),
-        }
+        };
+        Ok(desc)
     }
     // unop `#`
-    fn unop_len(&mut self) -> ExpDesc {
-        match self.exp_unop() {
+    fn unop_len(&mut self) -> Result<ExpDesc> {
+        let desc = match self.exp_unop()? {
             ExpDesc::String(s) => ExpDesc::Integer(s.len() as i64),
-            ExpDesc::Nil | ExpDesc::Boolean(_) | ExpDesc::Integer(_) | ExpDesc::Float(_) => panic!("invalid ~ operator"),
+            ExpDesc::Nil | ExpDesc::Boolean(_) | ExpDesc::Integer(_) | ExpDesc::Float(_) => return Err(self.error("invalid # operator")),
             desc => ExpDesc::UnaryOp(ByteCode::Len, self.discharge_any(desc)),
-        }
+        };
+        Ok(desc)
     }
 
     fn preprocess_binop_left(&mut self, left: ExpDesc, binop: &Token) -> ExpDesc {
@@ -1129,6 +1445,25 @@ impl<'a, R: Read> ParseProto<'a, R> {
             return r;
         }
 
+        // The left-const-operand is not discharged yet. Discharge the
+        // right operand first if it has jump lists, otherwise the left
+        // one would be discharged after and skipped by the jumps.
+        let right = if matches!(right, ExpDesc::Test(..) | ExpDesc::Compare(..))
+                && !matches!(binop, Token::And | Token::Or) {
+            ExpDesc::Local(self.discharge_any(right))
+        } else {
+            right
+        };
+
+        // swap the left-const-operand to right for commutative operators,
+        // in order to use opi/opk in do_binop() and do_compare()
+        let (left, right) = if matches!(binop, Token::Add | Token::Mul | Token::Equal | Token::NotEq)
+                && matches!(left, ExpDesc::Integer(_) | ExpDesc::Float(_)) {
+            (right, left)
+        } else {
+            (left, right)
+        };
+
         match binop {
             Token::Add => self.do_binop(left, right, ByteCode::Add, ByteCode::AddInt, ByteCode::AddConst),
             Token::Sub => self.do_binop(left, right, ByteCode::Sub, ByteCode::SubInt, ByteCode::SubConst),
@@ -1180,61 +1515,37 @@ impl<'a, R: Read> ParseProto<'a, R> {
         }
     }
 
-    fn do_binop(&mut self, mut left: ExpDesc, mut right: ExpDesc,
+    fn do_binop(&mut self, left: ExpDesc, right: ExpDesc,
             opr: FnBc3u8, opi: FnBc3u8, opk: FnBc3u8) -> ExpDesc {
 
-        if opr == ByteCode::Add || opr == ByteCode::Mul { // commutative
-            if matches!(left, ExpDesc::Integer(_) | ExpDesc::Float(_)) {
-                // swap the left-const-operand to right, in order to use opi/opk
-                (left, right) = (right, left);
-            }
-        }
-
         let left = self.discharge_any(left);
 
         let (op, right) = match right {
-            ExpDesc::Integer(i) =>
-                if let Ok(i) = u8::try_from(i) {
-                    (opi, i as usize)
-                } else {
-                    (opk, self.add_const(i))
-                }
-            ExpDesc::Float(f) => (opk, self.add_const(f)),
+            ExpDesc::Integer(i) if u8::try_from(i).is_ok() => (opi, i as usize),
+            ExpDesc::Integer(_) | ExpDesc::Float(_) => self.const_operand(right, opr, opk),
             _ => (opr, self.discharge_any(right)),
         };
 
         ExpDesc::BinaryOp(op, left, right)
     }
 
-    fn do_compare(&mut self, mut left: ExpDesc, mut right: ExpDesc,
+    fn do_compare(&mut self, left: ExpDesc, right: ExpDesc,
             opr: FnBcBool, opi: FnBcBool, opk: FnBcBool) -> ExpDesc {
 
-        if opr == ByteCode::Equal || opr == ByteCode::NotEq { // commutative
-            if matches!(left, ExpDesc::Integer(_) | ExpDesc::Float(_)) {
-                // swap the left-const-operand to right, in order to use opi/opk
-                (left, right) = (right, left);
-            }
-        }
-
         let left = self.discharge_any(left);
 
         let (op, right) = match right {
-            ExpDesc::Integer(i) =>
-                if let Ok(i) = u8::try_from(i) {
-                    (opi, i as usize)
-                } else {
-                    (opk, self.add_const(i))
-                }
-            ExpDesc::Float(f) => (opk, self.add_const(f)),
-            ExpDesc::String(s) => (opk, self.add_const(s)),
+            ExpDesc::Integer(i) if u8::try_from(i).is_ok() => (opi, i as usize),
+            ExpDesc::Integer(_) | ExpDesc::Float(_) | ExpDesc::String(_) =>
+                self.const_operand(right, opr, opk),
             _ => (opr, self.discharge_any(right)),
         };
 
         ExpDesc::Compare(op, left, right, Vec::new(), Vec::new())
     }
 
-    // Generate a TestOrJump: test @condition or jump to somewhere unknown.
-    // Link the new code to previous false-list if any.
+    // Generate a TestOrJump and Jump: test @condition or jump to somewhere
+    // unknown. Link the new Jump to previous false-list if any.
     // Close true-list if any.
     // Return false-list to be fixed later in fix_test_list()
     fn test_or_jump(&mut self, condition: ExpDesc) -> Vec<usize> {
@@ -1244,22 +1555,22 @@ impl<'a, R: Read> ParseProto<'a, R> {
                 return Vec::new();
             }
             ExpDesc::Compare(op, left, right, true_list, false_list) => {
-                self.fp.byte_codes.push(op(left as u8, right as u8, true));
-                (ByteCode::Jump(0), Some(true_list), false_list)
+                (op(left as u8, right as u8, true), Some(true_list), false_list)
             }
             ExpDesc::Test(condition, true_list, false_list) => {
                 let icondition = self.discharge_any(*condition);
-                (ByteCode::TestOrJump(icondition as u8, 0), Some(true_list), false_list)
+                (ByteCode::TestOrJump(icondition as u8), Some(true_list), false_list)
             }
             _ => {
                 let icondition = self.discharge_any(condition);
-                (ByteCode::TestOrJump(icondition as u8, 0), None, Vec::new())
+                (ByteCode::TestOrJump(icondition as u8), None, Vec::new())
             }
         };
 
-        self.fp.byte_codes.push(code);
+        self.push_code(code);
+        self.push_code(ByteCode::Jump(0));
 
-        false_list.push(self.fp.byte_codes.len() - 1);
+        false_list.push(self.byte_codes.len() - 1);
 
         if let Some(true_list) = true_list {
             // close true_list to jump here, after TestOrJump
@@ -1277,22 +1588,22 @@ impl<'a, R: Read> ParseProto<'a, R> {
                 return Vec::new();
             }
             ExpDesc::Compare(op, left, right, true_list, false_list) => {
-                self.fp.byte_codes.push(op(left as u8, right as u8, false));
-                (ByteCode::Jump(0), true_list, Some(false_list))
+                (op(left as u8, right as u8, false), true_list, Some(false_list))
             }
             ExpDesc::Test(condition, true_list, false_list) => {
                 let icondition = self.discharge_any(*condition);
-                (ByteCode::TestAndJump(icondition as u8, 0), true_list, Some(false_list))
+                (ByteCode::TestAndJump(icondition as u8), true_list, Some(false_list))
             }
             _ => {
                 let icondition = self.discharge_any(condition);
-                (ByteCode::TestAndJump(icondition as u8, 0), Vec::new(), None)
+                (ByteCode::TestAndJump(icondition as u8), Vec::new(), None)
             }
         };
 
-        self.fp.byte_codes.push(code);
+        self.push_code(code);
+        self.push_code(ByteCode::Jump(0));
 
-        true_list.push(self.fp.byte_codes.len() - 1);
+        true_list.push(self.byte_codes.len() - 1);
 
         if let Some(false_list) = false_list {
             // close false_list to jump here, after TestAndJump
@@ -1302,94 +1613,89 @@ impl<'a, R: Read> ParseProto<'a, R> {
         true_list
     }
 
-    // fix TestAndJump/TestOrJump list to jump to current place
+    // fix the Jumps in test list to jump to current place
     fn fix_test_list(&mut self, list: Vec<usize>) {
-        let here = self.fp.byte_codes.len();
+        let here = self.byte_codes.len();
         self.fix_test_list_to(list, here);
     }
 
-    // fix TestAndJump/TestOrJump list to jump to $to
+    // fix the Jumps in test list to jump to $to
     fn fix_test_list_to(&mut self, list: Vec<usize>, to: usize) {
         for i in list.into_iter() {
-            let jmp = (to as isize - i as isize - 1) as i16;
-            let code = match self.fp.byte_codes[i] {
-                ByteCode::Jump(0) => ByteCode::Jump(jmp),
-                ByteCode::TestOrJump(icondition, 0) => ByteCode::TestOrJump(icondition, jmp),
-                ByteCode::TestAndJump(icondition, 0) => ByteCode::TestAndJump(icondition, jmp),
-                _ => panic!("invalid Test"),
+            let jmp = (to as isize - i as isize - 1) as i32;
+            let ByteCode::Jump(0) = self.byte_codes[i] else {
+                panic!("invalid Test");
             };
-            self.fp.byte_codes[i] = code;
+            self.byte_codes[i] = ByteCode::Jump(jmp);
         }
     }
 
-    // fix TestAndJump/TestOrJump list to TestAndSetJump/TestOrSetJump
+    // fix the Jumps in test list to jump to current place, and their
+    // TestAndJump/TestOrJump to TestAndSetJump/TestOrSetJump
     fn fix_test_set_list(&mut self, list: Vec<usize>, dst: usize) {
-        let here = self.fp.byte_codes.len();
         let dst = dst as u8;
-        for i in list.into_iter() {
-            let jmp = here - i - 1; // should not be negative
-            let code = match self.fp.byte_codes[i] {
-                ByteCode::Jump(0) => ByteCode::Jump(jmp as i16),
-                ByteCode::TestOrJump(icondition, 0) =>
-                    if icondition == dst {
-                        ByteCode::TestOrJump(icondition, jmp as i16)
-                    } else {
-                        ByteCode::TestOrSetJump(dst as u8, icondition, jmp as u8)
-                    }
-                ByteCode::TestAndJump(icondition, 0) =>
-                    if icondition == dst {
-                        ByteCode::TestAndJump(icondition, jmp as i16)
-                    } else {
-                        ByteCode::TestAndSetJump(dst as u8, icondition, jmp as u8)
-                    }
-                _ => panic!("invalid Test"),
+        for &i in list.iter() {
+            let code = match self.byte_codes[i - 1] {
+                ByteCode::TestOrJump(icondition) if icondition != dst =>
+                    ByteCode::TestOrSetJump(dst, icondition),
+                ByteCode::TestAndJump(icondition) if icondition != dst =>
+                    ByteCode::TestAndSetJump(dst, icondition),
+                _ => continue,
             };
-            self.fp.byte_codes[i] = code;
+            self.byte_codes[i - 1] = code;
         }
+        self.fix_test_list(list);
     }
 
     // args ::= `(` [explist] `)` | tableconstructor | LiteralString
-    fn args(&mut self, implicit_argn: usize) -> ExpDesc {
+    fn args(&mut self, implicit_argn: usize) -> Result<ExpDesc> {
         let ifunc = self.sp - 1 - implicit_argn;
-        let narg = match self.ctx.lex.next() {
+        let narg = match self.ctx.lex.next()? {
             Token::ParL => {
-                if self.ctx.lex.peek() != &Token::ParR {
-                    let (nexp, last_exp) = self.explist();
-                    self.ctx.lex.expect(Token::ParR);
+                if self.ctx.lex.peek()? != &Token::ParR {
+                    let (nexp, last_exp) = self.explist()?;
+                    self.ctx.lex.expect(Token::ParR)?;
                     if self.discharge_try_expand(last_exp, 0) {
                         None // variable arguments
                     } else {
                         Some(nexp + 1)
                     }
                 } else {
-                    self.ctx.lex.next();
+                    self.ctx.lex.next()?;
                     Some(0)
                 }
             }
             Token::CurlyL => {
-                self.table_constructor();
+                self.table_constructor()?;
                 Some(1)
             }
             Token::String(s) => {
                 self.discharge(ifunc+1, ExpDesc::String(s));
                 Some(1)
             }
-            t => panic!("invalid args {t:?}"),
+            t => return Err(self.error_near("function arguments expected", &t)),
         };
 
         // n+1: for fixed #n arguments
         //   0: for variable arguments
         let narg_plus = if let Some(n) = narg { n + implicit_argn + 1 } else { 0 };
 
-        ExpDesc::Call(ifunc, narg_plus)
+        Ok(ExpDesc::Call(ifunc, narg_plus))
     }
 
     // discharge @desc into the top of stack, if need
     fn discharge_any(&mut self, desc: ExpDesc) -> usize {
-        let dst = if let &ExpDesc::Call(ifunc, _) = &desc {
-            ifunc
-        } else {
-            self.sp
+        let dst = match &desc {
+            &ExpDesc::Call(ifunc, _) => ifunc,
+
+            // reuse the register of temporary operand, which is free after
+            // the operation, to not use one more register for each operator
+            // in long expressions, e.g. `a + b + c + ...`. The right operand
+            // of BinaryOp may be a constant, so only check the left one.
+            &ExpDesc::UnaryOp(_, i) | &ExpDesc::BinaryOp(_, i, _)
+                if i >= self.local_num() => i,
+
+            _ => self.sp,
         };
         self.discharge_if_need(dst, desc)
     }
@@ -1406,6 +1712,8 @@ impl<'a, R: Read> ParseProto<'a, R> {
 
     // discharge @desc into @dst, and update self.sp=dst+1
     fn discharge(&mut self, dst: usize, desc: ExpDesc) {
+        self.check_register(dst);
+        let desc = self.fit_index(desc, dst);
         let code = match desc {
             ExpDesc::Nil => ByteCode::LoadNil(dst as u8, 1),
             ExpDesc::Boolean(b) => ByteCode::LoadBool(dst as u8, b),
@@ -1413,10 +1721,17 @@ impl<'a, R: Read> ParseProto<'a, R> {
                 if let Ok(i) = i16::try_from(i) {
                     ByteCode::LoadInt(dst as u8, i)
                 } else {
-                    ByteCode::LoadConst(dst as u8, self.add_const(i) as u16)
+                    let iconst = self.add_const(i);
+                    self.code_const(ByteCode::LoadConst, ByteCode::LoadConstX, dst, iconst)
                 }
-            ExpDesc::Float(f) => ByteCode::LoadConst(dst as u8, self.add_const(f) as u16),
-            ExpDesc::String(s) => ByteCode::LoadConst(dst as u8, self.add_const(s) as u16),
+            ExpDesc::Float(f) => {
+                let iconst = self.add_const(f);
+                self.code_const(ByteCode::LoadConst, ByteCode::LoadConstX, dst, iconst)
+            }
+            ExpDesc::String(s) => {
+                let iconst = self.add_const(s);
+                self.code_const(ByteCode::LoadConst, ByteCode::LoadConstX, dst, iconst)
+            }
             ExpDesc::Local(src) =>
                 if dst != src {
                     ByteCode::Move(dst as u8, src as u8)
@@ -1429,8 +1744,8 @@ impl<'a, R: Read> ParseProto<'a, R> {
             ExpDesc::IndexInt(itable, ikey) => ByteCode::GetInt(dst as u8, itable as u8, ikey),
             ExpDesc::IndexUpField(itable, ikey) => ByteCode::GetUpField(dst as u8, itable as u8, ikey as u8),
             ExpDesc::VarArgs => ByteCode::VarArgs(dst as u8, 1),
-            ExpDesc::Function(f) => ByteCode::LoadConst(dst as u8, f as u16),
-            ExpDesc::Closure(f) => ByteCode::Closure(dst as u8, f as u16),
+            ExpDesc::Function(f) => self.code_const(ByteCode::LoadConst, ByteCode::LoadConstX, dst, f),
+            ExpDesc::Closure(f) => self.code_const(ByteCode::Closure, ByteCode::ClosureX, dst, f),
             ExpDesc::Call(ifunc, narg_plus) => ByteCode::CallSet(dst as u8, ifunc as u8, narg_plus as u8),
             ExpDesc::UnaryOp(op, i) => op(dst as u8, i as u8),
             ExpDesc::BinaryOp(op, left, right) => op(dst as u8, left as u8, right as u8),
@@ -1442,43 +1757,52 @@ impl<'a, R: Read> ParseProto<'a, R> {
                 return;
             }
             ExpDesc::Compare(op, left, right, true_list, false_list) => {
-                self.fp.byte_codes.push(op(left as u8, right as u8, false));
-                self.fp.byte_codes.push(ByteCode::Jump(1));
+                self.push_code(op(left as u8, right as u8, false));
+                self.push_code(ByteCode::Jump(1));
 
                 // terminate false-list to SetFalseSkip
                 self.fix_test_list(false_list);
-                self.fp.byte_codes.push(ByteCode::SetFalseSkip(dst as u8));
+                self.push_code(ByteCode::SetFalseSkip(dst as u8));
                 // terminate true-list to LoadBool(true)
                 self.fix_test_list(true_list);
                 ByteCode::LoadBool(dst as u8, true)
             }
         };
-        self.fp.byte_codes.push(code);
+        self.push_code(code);
         self.sp = dst + 1;
     }
 
     // for constant types, add @desc to constants;
-    // otherwise, discharge @desc into stack
+    // otherwise, or if the constant index does not fit in u8,
+    // discharge @desc into stack
     fn discharge_const(&mut self, desc: ExpDesc) -> ConstStack {
-        match desc {
-            // add const
-            ExpDesc::Nil => ConstStack::Const(self.add_const(())),
-            ExpDesc::Boolean(b) => ConstStack::Const(self.add_const(b)),
-            ExpDesc::Integer(i) => ConstStack::Const(self.add_const(i)),
-            ExpDesc::Float(f) => ConstStack::Const(self.add_const(f)),
-            ExpDesc::String(s) => ConstStack::Const(self.add_const(s)),
-            ExpDesc::Function(f) => ConstStack::Const(f),
-
-            // discharge to stack
+        let iconst = match &desc {
+            &ExpDesc::Function(f) => Some(f),
+            desc => const_value(desc).map(|v| self.add_const(v)),
+        };
+        match iconst {
+            Some(i) if i <= u8::MAX as usize => ConstStack::Const(i),
             _ => ConstStack::Stack(self.discharge_any(desc)),
         }
     }
 
+    // choose the constant operand version @opk if the constant index
+    // fits in u8, otherwise load the constant into stack and use @opr
+    fn const_operand<F>(&mut self, desc: ExpDesc, opr: F, opk: F) -> (F, usize) {
+        let iconst = self.add_const(const_value(&desc).unwrap());
+        if iconst <= u8::MAX as usize {
+            (opk, iconst)
+        } else {
+            (opr, self.discharge_any(desc))
+        }
+    }
+
     fn discharge_expand_want(&mut self, desc: ExpDesc, want: usize) {
         debug_assert!(want > 1);
+        self.check_register(self.sp + want - 1);
         if !self.discharge_try_expand(desc, want) {
             let code = ByteCode::LoadNil(self.sp as u8, want as u8 - 1);
-            self.fp.byte_codes.push(code);
+            self.push_code(code);
         }
     }
 
@@ -1488,12 +1812,12 @@ impl<'a, R: Read> ParseProto<'a, R> {
         match desc {
             ExpDesc::Call(ifunc, narg_plus) => {
                 let code = ByteCode::Call(ifunc as u8, narg_plus as u8, want as u8);
-                self.fp.byte_codes.push(code);
+                self.push_code(code);
                 true
             }
             ExpDesc::VarArgs => {
                 let code = ByteCode::VarArgs(self.sp as u8, want as u8);
-                self.fp.byte_codes.push(code);
+                self.push_code(code);
                 true
             }
             _ => {
@@ -1503,12 +1827,12 @@ impl<'a, R: Read> ParseProto<'a, R> {
         }
     }
 
-    fn table_constructor(&mut self) -> ExpDesc {
+    fn table_constructor(&mut self) -> Result<ExpDesc> {
         let table = self.sp;
         self.sp += 1;
 
-        let inew = self.fp.byte_codes.len();
-        self.fp.byte_codes.push(ByteCode::NewTable(table as u8, 0, 0));
+        let inew = self.byte_codes.len();
+        self.push_code(ByteCode::NewTable(table as u8, 0, 0));
 
         enum TableEntry {
             Map((FnBc3u8, FnBc3u8, usize)),
@@ -1522,170 +1846,317 @@ impl<'a, R: Read> ParseProto<'a, R> {
         let mut narray: usize = 0;
         let mut nmap: usize = 0;
         loop {
+            if self.ctx.lex.peek()? == &Token::CurlyR { // `}`
+                self.ctx.lex.next()?;
+                break;
+            }
+
+            // discharge the previous array entry since it is not the last
+            // one, before the new entry uses the stack
+            if let Some(last) = last_array_entry.take() {
+                self.discharge(table + 1 + narray % 50, last);
+
+                narray += 1;
+                if narray.is_multiple_of(50) { // reset the array members every 50
+                    self.push_code(ByteCode::SetList(table as u8, 50));
+                    self.sp = table + 1;
+                }
+            }
+
             let sp0 = self.sp;
 
             // parse entry of map or array?
-            let entry = match self.ctx.lex.peek() {
-                Token::CurlyR => { // `}`
-                    self.ctx.lex.next();
-                    break;
-                }
+            let entry = match self.ctx.lex.peek()? {
                 Token::SqurL => { // `[` exp `]` `=` exp
-                    self.ctx.lex.next();
+                    self.ctx.lex.next()?;
 
-                    let key = self.exp(); // key
-                    self.ctx.lex.expect(Token::SqurR); // `]`
-                    self.ctx.lex.expect(Token::Assign); // `=`
+                    let key = self.exp()?; // key
+                    self.ctx.lex.expect(Token::SqurR)?; // `]`
+                    self.ctx.lex.expect(Token::Assign)?; // `=`
 
                     TableEntry::Map(match key {
                         ExpDesc::Local(i) =>
                             (ByteCode::SetTable, ByteCode::SetTableConst, i),
-                        ExpDesc::String(s) =>
-                            (ByteCode::SetField, ByteCode::SetFieldConst, self.add_const(s)),
+                        ExpDesc::String(s) => self.table_field_key(s),
                         ExpDesc::Integer(i) if u8::try_from(i).is_ok() =>
                             (ByteCode::SetInt, ByteCode::SetIntConst, i as usize),
                         ExpDesc::Nil =>
-                            panic!("nil can not be table key"),
+                            return Err(self.error("nil can not be table key")),
                         ExpDesc::Float(f) if f.is_nan() =>
-                            panic!("NaN can not be table key"),
+                            return Err(self.error("NaN can not be table key")),
                         _ => (ByteCode::SetTable, ByteCode::SetTableConst, self.discharge_any(key)),
                     })
                 }
                 Token::Name(_) => {
-                    let name = self.read_name();
-                    if self.ctx.lex.peek() == &Token::Assign { // Name `=` exp
-                        self.ctx.lex.next();
-                        TableEntry::Map((ByteCode::SetField, ByteCode::SetFieldConst, self.add_const(name)))
+                    let name = self.read_name()?;
+                    if self.ctx.lex.peek()? == &Token::Assign { // Name `=` exp
+                        self.ctx.lex.next()?;
+                        TableEntry::Map(self.table_field_key(name.into_bytes()))
                     } else { // Name
-                        TableEntry::Array(self.exp_with_ahead(Token::Name(name)))
+                        TableEntry::Array(self.exp_with_ahead(Token::Name(name))?)
                     }
                 }
                 _ => { // exp
-                    TableEntry::Array(self.exp())
+                    TableEntry::Array(self.exp()?)
                 }
             };
 
             // insert the entry into table
             match entry {
                 TableEntry::Map((op, opk, key)) => {
-                    let value = self.exp();
+                    let value = self.exp()?;
                     let code = match self.discharge_const(value) {
                         ConstStack::Const(i) => opk(table as u8, key as u8, i as u8),
                         ConstStack::Stack(i) => op(table as u8, key as u8, i as u8),
                     };
-                    self.fp.byte_codes.push(code);
+                    self.push_code(code);
 
                     nmap += 1;
                     self.sp = sp0;
                 }
-                TableEntry::Array(desc) => {
-                    if let Some(last) = last_array_entry.replace(desc) {
-                        self.discharge(sp0, last);
-
-                        narray += 1;
-                        if narray % 50 == 0 { // reset the array members every 50
-                            self.fp.byte_codes.push(ByteCode::SetList(table as u8, 50));
-                            self.sp = table + 1;
-                        }
-                    }
-                }
+                TableEntry::Array(desc) => last_array_entry = Some(desc),
             }
 
             // any more entry?
-            match self.ctx.lex.next() {
+            match self.ctx.lex.next()? {
                 Token::SemiColon | Token::Comma => (), // yes
                 Token::CurlyR => break, // no
-                t => panic!("invalid table {t:?}"),
+                t => return Err(self.error_near("'}' expected", &t)),
             }
         }
 
-        if let Some(last) = last_array_entry {
-            let num = if self.discharge_try_expand(last, 0) {
+        let num = match last_array_entry {
+            Some(last @ (ExpDesc::Call(..) | ExpDesc::VarArgs)) => {
+                self.discharge_try_expand(last, 0);
                 // do not update @narray
-                0 // 0 is special, means all following values in stack
-            } else {
+                Some(0) // 0 is special, means all following values in stack
+            }
+            Some(last) => {
+                self.discharge(table + 1 + narray % 50, last);
                 narray += 1;
-                (self.sp - (table + 1)) as u8
-            };
-            self.fp.byte_codes.push(ByteCode::SetList(table as u8, num));
+                Some((self.sp - (table + 1)) as u8)
+            }
+            // array entries followed by map entries
+            None if self.sp > table + 1 => Some((self.sp - (table + 1)) as u8),
+            None => None,
+        };
+        if let Some(num) = num {
+            self.push_code(ByteCode::SetList(table as u8, num));
         }
 
         // reset narray and nmap
-        self.fp.byte_codes[inew] = ByteCode::NewTable(table as u8,
+        self.byte_codes[inew] = ByteCode::NewTable(table as u8,
             u8::try_from(narray).unwrap_or(255),
             u8::try_from(nmap).unwrap_or(255));
 
         self.sp = table + 1;
-        ExpDesc::Local(table)
+        Ok(ExpDesc::Local(table))
+    }
+
+    // SetField if the constant key index fits in u8, otherwise load the
+    // key into stack and use SetTable
+    fn table_field_key(&mut self, key: Vec<u8>) -> (FnBc3u8, FnBc3u8, usize) {
+        let ikey = self.add_const(key.as_slice());
+        if ikey <= u8::MAX as usize {
+            (ByteCode::SetField, ByteCode::SetFieldConst, ikey)
+        } else {
+            (ByteCode::SetTable, ByteCode::SetTableConst, self.discharge_any(ExpDesc::String(key)))
+        }
+    }
+
+    fn read_name(&mut self) -> Result<String> {
+        match self.ctx.lex.next()? {
+            Token::Name(name) => Ok(name),
+            t => Err(self.error_near("<name> expected", &t)),
+        }
     }
 
-    fn read_name(&mut self) -> String {
-        if let Token::Name(name) = self.ctx.lex.next() {
-            name
+    // check the token ending a block
+    fn check_block_end(&self, got: Token, want: Token) -> Result<()> {
+        if got == want {
+            Ok(())
         } else {
-            panic!("expect name");
+            Err(self.error_near(format!("{want} expected"), &got))
+        }
+    }
+
+    // add byte code, with current line
+    fn push_code(&mut self, code: ByteCode) {
+        self.byte_codes.push(code);
+        self.fp.lineinfo.push(self.ctx.lex.line());
+    }
+
+    // syntax error at current line
+    fn error(&self, msg: impl Into<String>) -> Error {
+        self.ctx.lex.error(msg)
+    }
+    fn error_near(&self, msg: impl Into<String>, t: &Token) -> Error {
+        self.ctx.lex.error_near(msg, t)
+    }
+
+    // Most discharging functions do not return Result, so record the
+    // first error of exceeding limits and raise it after the statement.
+    fn set_limit_error(&mut self, msg: String) {
+        if self.limit_error.is_none() {
+            self.limit_error = Some(self.error(msg));
+        }
+    }
+
+    // registers are encoded in u8 in byte codes
+    fn check_register(&mut self, ireg: usize) {
+        if ireg >= MAX_REGISTERS {
+            self.set_limit_error("function or expression needs too many registers".into());
+        }
+    }
+}
+
+pub fn load(input: impl Read) -> Result<FuncProto> {
+    load_with_name(input, "?")
+}
+
+// @source is the chunk name, used in traceback
+pub fn load_with_name(input: impl Read, source: &str) -> Result<FuncProto> {
+    load_with_mode(input, source, "bt")
+}
+
+// @mode controls whether the chunk can be text ("t") or binary ("b")
+// or both ("bt"). Binary chunks are dumped by FuncProto::dump().
+pub fn load_with_mode(mut input: impl Read, source: &str, mode: &str) -> Result<FuncProto> {
+    let mut first = [0; 1];
+    let n = input.read(&mut first)
+        .map_err(|e| Error::syntax(format!("read error: {e}"), 0))?;
+    let input = Cursor::new(&first[..n]).chain(input);
+
+    if n == 1 && first[0] == dump::SIGNATURE[0] {
+        if !mode.contains('b') {
+            return Err(Error::syntax(format!("attempt to load a binary chunk (mode is '{mode}')"), 0));
+        }
+        FuncProto::undump(input)
+    } else {
+        if !mode.contains('t') {
+            return Err(Error::syntax(format!("attempt to load a text chunk (mode is '{mode}')"), 0));
         }
+        parse_text(input, source)
     }
 }
 
-pub fn load(input: impl Read) -> FuncProto {
+// skip the first line of a file if it starts with '#', e.g.
+// "#!/usr/bin/env luar", but keep the newline to keep line numbers
+pub fn skip_comment(src: &mut Vec<u8>) {
+    if src.first() == Some(&b'#') {
+        let end = src.iter().position(|&b| b == b'\n').unwrap_or(src.len());
+        src.drain(..end);
+    }
+}
+
+fn parse_text(input: impl Read, source: &str) -> Result<FuncProto> {
     let mut ctx = ParseContext {
         lex: Lex::new(input),
         levels: Default::default(),
+        source: source.into(),
     };
-    chunk(&mut ctx, false, vec!["_ENV".into()], Token::Eos) // XXX has_varargs->true
+    // main chunk takes the command line arguments as varargs
+    chunk(&mut ctx, true, Vec::new(), Token::Eos)
 }
 
-fn chunk(ctx: &mut ParseContext<impl Read>, has_varargs: bool, params: Vec<String>, end_token: Token) -> FuncProto {
+fn chunk(ctx: &mut ParseContext<impl Read>, has_varargs: bool, params: Vec<String>, end_token: Token) -> Result<FuncProto> {
     // prepare
     let fp = FuncProto {
-        has_varargs: has_varargs,
+        has_varargs,
         nparam: params.len(),
+        source: ctx.source.clone(),
         ..Default::default()
     };
 
-    ctx.levels.push(Level {
-        locals: params.into_iter().map(|p|(p, false)).collect(),
-        upvalues: Vec::new(),
-    });
+    if params.len() > MAX_LOCALS {
+        return Err(ctx.lex.error(format!("too many local variables (limit is {MAX_LOCALS})")));
+    }
+    // the main chunk has _ENV as its only upvalue, which is set when
+    // the chunk is loaded by ExeState::new_chunk()
+    let upvalues = if ctx.levels.is_empty() {
+        vec![("_ENV".into(), UpIndex::Local(0), false)]
+    } else {
+        Vec::new()
+    };
+    ctx.levels.push(Level { locals: Vec::new(), upvalues });
 
     let mut proto = ParseProto {
+        constants: HashMap::new(),
+        byte_codes: Vec::new(),
         sp: 0,
         break_blocks: Vec::new(),
         continue_blocks: Vec::new(),
         gotos: Vec::new(),
         labels: Vec::new(),
+        limit_error: None,
 
         fp,
         ctx,
     };
+    for name in params {
+        proto.local_new(name);
+    }
 
     // parse!
     // use `block_scope()` because local variables will be dropped
     // after function, and upvalues will be closed in `Return`
     // byte code.
-    assert_eq!(proto.block_scope(), end_token);
+    let got = proto.block_scope()?;
+    proto.check_block_end(got, end_token)?;
 
     if let Some(goto) = proto.gotos.first() {
-        panic!("goto {} no destination", &goto.name);
+        return Err(proto.error(format!("no visible label '{}' for goto", &goto.name)));
     }
 
     // clear
-    let ParseProto { mut fp, ctx, ..} = proto;
+    let ParseProto { mut fp, mut byte_codes, ctx, ..} = proto;
 
     let level = ctx.levels.pop().unwrap();
     fp.upindexes = level.upvalues.into_iter().map(|u| u.1).collect();
 
-    fp.byte_codes.push(ByteCode::Return0);
+    byte_codes.push(ByteCode::Return0);
+    fp.lineinfo.push(ctx.lex.line());
 
-    println!("constants: {:?}", &fp.constants);
-    println!("upindexes: {:?}", &fp.upindexes);
-    println!("byte_codes:");
-    for (i,c) in fp.byte_codes.iter().enumerate() {
-        println!("  {i}\t{c:?}");
+    // parameters are active till the end
+    for var in level.locals {
+        fp.locvars[var.ilocvar].endpc = byte_codes.len();
     }
 
-    fp
+    // encode, and check the arguments in wide fields
+    for (code, &line) in byte_codes.into_iter().zip(fp.lineinfo.iter()) {
+        let Some(code) = code.encode() else {
+            let msg = match code {
+                ByteCode::ExtraArg(_) => "too many constants",
+                _ => "control structure too long",
+            };
+            return Err(Error::syntax(msg, line));
+        };
+        fp.byte_codes.push(code);
+    }
+
+    Ok(fp)
+}
+
+// value of constant expression
+fn const_value(desc: &ExpDesc) -> Option<Value> {
+    match desc {
+        ExpDesc::Nil => Some(Value::Nil),
+        &ExpDesc::Boolean(b) => Some(Value::Boolean(b)),
+        &ExpDesc::Integer(i) => Some(Value::Integer(i)),
+        &ExpDesc::Float(f) => Some(Value::Float(f)),
+        ExpDesc::String(s) => Some(s.as_slice().into()),
+        _ => None,
+    }
+}
+
+fn const_desc(v: &Value) -> ExpDesc {
+    match v {
+        Value::Nil => ExpDesc::Nil,
+        &Value::Boolean(b) => ExpDesc::Boolean(b),
+        &Value::Integer(i) => ExpDesc::Integer(i),
+        &Value::Float(f) => ExpDesc::Float(f),
+        s => ExpDesc::String(AsRef::<[u8]>::as_ref(s).to_vec()),
+    }
 }
 
 // priorities of binops
@@ -1713,11 +2184,14 @@ fn is_block_end(t: &Token) -> bool {
 
 fn fold_const(binop: &Token, left: &ExpDesc, right: &ExpDesc) -> Option<ExpDesc> {
     match binop {
-        Token::Add => do_fold_const(left, right, |a,b|a+b, |a,b|a+b),
-        Token::Sub => do_fold_const(left, right, |a,b|a-b, |a,b|a-b),
-        Token::Mul => do_fold_const(left, right, |a,b|a*b, |a,b|a*b),
-        Token::Mod => do_fold_const(left, right, |a,b|a%b, |a,b|a%b),
-        Token::Idiv => do_fold_const(left, right, |a,b|a/b, |a,b|a/b),
+        Token::Add => do_fold_const(left, right, i64::wrapping_add, |a,b|a+b),
+        Token::Sub => do_fold_const(left, right, i64::wrapping_sub, |a,b|a-b),
+        Token::Mul => do_fold_const(left, right, i64::wrapping_mul, |a,b|a*b),
+
+        // leave the division by zero to raise error at runtime
+        Token::Mod | Token::Idiv if matches!(right, ExpDesc::Integer(0)) => None,
+        Token::Mod => do_fold_const(left, right, int_mod, float_mod),
+        Token::Idiv => do_fold_const(left, right, int_idiv, float_idiv),
 
         Token::Div => do_fold_const_float(left, right, |a,b|a/b),
         Token::Pow => do_fold_const_float(left, right, |a,b|a.powf(b)),
@@ -1725,8 +2199,8 @@ fn fold_const(binop: &Token, left: &ExpDesc, right: &ExpDesc) -> Option<ExpDesc>
         Token::BitAnd => do_fold_const_int(left, right, |a,b|a&b),
         Token::BitNot => do_fold_const_int(left, right, |a,b|a^b),
         Token::BitOr  => do_fold_const_int(left, right, |a,b|a|b),
-        Token::ShiftL => do_fold_const_int(left, right, |a,b|a<<b),
-        Token::ShiftR => do_fold_const_int(left, right, |a,b|a>>b),
+        Token::ShiftL => do_fold_const_int(left, right, shift_left),
+        Token::ShiftR => do_fold_const_int(left, right, |a,b|shift_left(a, b.wrapping_neg())),
 
         Token::Concat => {
             if let (ExpDesc::String(s1), ExpDesc::String(s2)) = (left, right) {
@@ -1753,9 +2227,10 @@ fn do_fold_const(left: &ExpDesc, right: &ExpDesc, arith_i: fn(i64,i64)->i64, ari
 fn do_fold_const_int(left: &ExpDesc, right: &ExpDesc, arith_i: fn(i64,i64)->i64) -> Option<ExpDesc> {
     let (i1, i2) = match (left, right) {
         (&ExpDesc::Integer(i1), &ExpDesc::Integer(i2)) => (i1, i2),
-        (&ExpDesc::Float(f1), &ExpDesc::Float(f2)) => (ftoi(f1).unwrap(), ftoi(f2).unwrap()),
-        (&ExpDesc::Float(f1), &ExpDesc::Integer(i2)) => (ftoi(f1).unwrap(), i2),
-        (&ExpDesc::Integer(i1), &ExpDesc::Float(f2)) => (i1, ftoi(f2).unwrap()),
+        // leave the floats without integer representation to raise error at runtime
+        (&ExpDesc::Float(f1), &ExpDesc::Float(f2)) => (ftoi(f1)?, ftoi(f2)?),
+        (&ExpDesc::Float(f1), &ExpDesc::Integer(i2)) => (ftoi(f1)?, i2),
+        (&ExpDesc::Integer(i1), &ExpDesc::Float(f2)) => (i1, ftoi(f2)?),
         (_, _) => return None,
     };
     Some(ExpDesc::Integer(arith_i(i1, i2)))
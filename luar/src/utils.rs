@@ -3,13 +3,43 @@ use crate::value::Value;
 
 pub fn ftoi(f: f64) -> Option<i64> {
     let i = f as i64;
-    if i as f64 != f {
+    // `as` saturates, so 2^63 would be converted to i64::MAX
+    if i as f64 != f || f >= 9223372036854775808.0 {
         None
     } else {
         Some(i)
     }
 }
 
+// floor division and modulo, the divisor must not be 0
+pub fn int_idiv(a: i64, b: i64) -> i64 {
+    let q = a.wrapping_div(b);
+    if a.wrapping_rem(b) != 0 && (a ^ b) < 0 { q - 1 } else { q }
+}
+pub fn int_mod(a: i64, b: i64) -> i64 {
+    let r = a.wrapping_rem(b);
+    if r != 0 && (r ^ b) < 0 { r + b } else { r }
+}
+pub fn float_idiv(a: f64, b: f64) -> f64 {
+    (a / b).floor()
+}
+// the result has the same sign as the divisor, same with luai_nummod
+pub fn float_mod(a: f64, b: f64) -> f64 {
+    let r = a % b;
+    if (r > 0.0 && b < 0.0) || (r < 0.0 && b != r) { r + b } else { r }
+}
+
+// logical shift, shift right if @n is negative
+pub fn shift_left(a: i64, n: i64) -> i64 {
+    if n <= -64 || n >= 64 {
+        0
+    } else if n >= 0 {
+        ((a as u64) << n) as i64
+    } else {
+        ((a as u64) >> -n) as i64
+    }
+}
+
 pub fn set_vec(vec: &mut Vec<Value>, i: usize, value: Value) {
     match i.cmp(&vec.len()) {
         Ordering::Less => vec[i] = value,
@@ -19,4 +49,138 @@ pub fn set_vec(vec: &mut Vec<Value>, i: usize, value: Value) {
             vec.push(value);
         }
     }
-}
\ No newline at end of file
+}
+// convert string to number, following the Lua lexical conventions of
+// numerals. Leading and trailing spaces, and a sign are allowed.
+pub fn str_to_number(s: &str) -> Option<Value> {
+    let s = s.trim_matches(|c: char| c.is_ascii_whitespace() || c == '\x0b');
+    if let Some(i) = str_to_int(s) {
+        Some(Value::Integer(i))
+    } else {
+        str_to_float(s).map(Value::Float)
+    }
+}
+
+fn split_sign(s: &str) -> (bool, &str) {
+    if let Some(s) = s.strip_prefix('-') {
+        (true, s)
+    } else {
+        (false, s.strip_prefix('+').unwrap_or(s))
+    }
+}
+
+fn strip_hex_prefix(s: &str) -> Option<&str> {
+    s.strip_prefix("0x").or_else(|| s.strip_prefix("0X"))
+}
+
+// hexadecimal integers wrap around, while decimal integers which
+// overflow are not accepted, and will be read as floats
+fn str_to_int(s: &str) -> Option<i64> {
+    let (neg, s) = split_sign(s);
+    let n = if let Some(hex) = strip_hex_prefix(s) {
+        if hex.is_empty() {
+            return None;
+        }
+        hex.chars().try_fold(0_u64, |n, c| {
+            Some(n.wrapping_mul(16).wrapping_add(c.to_digit(16)? as u64))
+        })?
+    } else {
+        if s.is_empty() {
+            return None;
+        }
+        let max = if neg { 1 << 63 } else { i64::MAX as u64 };
+        s.chars().try_fold(0_u64, |n, c| {
+            let n = n.checked_mul(10)?.checked_add(c.to_digit(10)? as u64)?;
+            (n <= max).then_some(n)
+        })?
+    };
+    Some(if neg { n.wrapping_neg() as i64 } else { n as i64 })
+}
+
+fn str_to_float(s: &str) -> Option<f64> {
+    // reject 'inf' and 'nan', which are accepted by Rust
+    if s.contains(['n', 'N']) {
+        return None;
+    }
+    let (neg, s) = split_sign(s);
+    let f = match strip_hex_prefix(s) {
+        Some(hex) => hex_to_float(hex)?,
+        None => s.parse::<f64>().ok()?,
+    };
+    Some(if neg { -f } else { f })
+}
+
+// format: hex-digits[.hex-digits][p[sign]decimal-digits]
+fn hex_to_float(s: &str) -> Option<f64> {
+    let (mantissa, exp) = match s.find(['p', 'P']) {
+        Some(i) => {
+            let (sign, digits) = split_sign(&s[i+1..]);
+            if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            let e = digits.parse::<i32>().unwrap_or(i32::MAX);
+            (&s[..i], if sign { -e } else { e })
+        }
+        None => (s, 0),
+    };
+
+    let mut f = 0.0;
+    let mut exp = exp as i64;
+    let mut ndigit = 0;
+    let mut seen_dot = false;
+    for c in mantissa.chars() {
+        if c == '.' {
+            if seen_dot {
+                return None;
+            }
+            seen_dot = true;
+        } else {
+            f = f * 16.0 + c.to_digit(16)? as f64;
+            ndigit += 1;
+            if seen_dot {
+                exp -= 4;
+            }
+        }
+    }
+    if ndigit == 0 {
+        return None;
+    }
+    Some(f * 2.0_f64.powi(exp.clamp(i32::MIN as i64, i32::MAX as i64) as i32))
+}
+
+// format float as C's "%.14g", and append ".0" if it looks like an integer
+pub fn float_to_str(f: f64) -> String {
+    let s = if f.is_nan() {
+        (if f.is_sign_negative() { "-nan" } else { "nan" }).to_string()
+    } else if f.is_infinite() {
+        (if f < 0.0 { "-inf" } else { "inf" }).to_string()
+    } else if f == 0.0 {
+        (if f.is_sign_negative() { "-0" } else { "0" }).to_string()
+    } else {
+        // the exponent after rounding to 14 significant digits
+        let e = format!("{f:.13e}");
+        let (mantissa, exp) = e.split_once('e').unwrap();
+        let exp: i32 = exp.parse().unwrap();
+        if !(-4..14).contains(&exp) {
+            let mantissa = trim_zeros(mantissa);
+            let sign = if exp < 0 { '-' } else { '+' };
+            format!("{mantissa}e{sign}{:02}", exp.abs())
+        } else {
+            trim_zeros(&format!("{f:.*}", (13 - exp) as usize)).to_string()
+        }
+    };
+    if s.bytes().all(|b| b == b'-' || b.is_ascii_digit()) {
+        s + ".0"
+    } else {
+        s
+    }
+}
+
+// remove trailing zeros after the decimal point, and the point itself
+fn trim_zeros(s: &str) -> &str {
+    if s.contains('.') {
+        s.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        s
+    }
+}
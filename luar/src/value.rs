@@ -1,12 +1,13 @@
 use std::fmt;
 use std::mem;
 use std::rc::Rc;
-use std::cell::RefCell;
 use std::hash::{Hash, Hasher};
-use std::collections::HashMap;
 use crate::parse::FuncProto;
-use crate::vm::{ExeState, LuaClosure};
-use crate::utils::{ftoi, set_vec};
+use crate::vm::{ExeState, LuaClosure, Thread};
+use crate::error::{Error, Result};
+use crate::utils::{ftoi, set_vec, str_to_number, float_to_str};
+use crate::vhash::ValueHashMap;
+use luargc::{Gc, GcCell, Trace, Tracer};
 
 const SHORT_STR_MAX: usize = 14; // sizeof(Value) - 1(tag) - 1(len)
 const MID_STR_MAX: usize = 48 - 1;
@@ -20,26 +21,42 @@ pub enum Value {
     ShortStr(u8, [u8; SHORT_STR_MAX]),
     MidStr(Rc<(u8, [u8; MID_STR_MAX])>),
     LongStr(Rc<Vec<u8>>),
-    Table(Rc<RefCell<Table>>),
-    RustFunction(fn (&mut ExeState) -> i32),
-    RustClosure(Rc<RefCell<Box<dyn FnMut (&mut ExeState) -> i32>>>),
+    Table(GcCell<Table>),
+    RustFunction(RustFunction),
+    RustClosure(Rc<RustClosure>),
     LuaFunction(Rc<FuncProto>),
-    LuaClosure(Rc<LuaClosure>),
+    LuaClosure(Gc<LuaClosure>),
+    Thread(GcCell<Thread>),
 }
 
+pub type RustFunction = fn (&mut ExeState) -> Result<i32>;
+// called by shared reference, so it can be re-entered, e.g. a wrap
+// function called in its own coroutine. Keep states in cells.
+pub type RustClosure = Box<dyn Fn (&mut ExeState) -> Result<i32>>;
+
+#[derive(Trace)]
 pub struct Table {
     pub array: Vec<Value>,
-    pub map: HashMap<Value, Value>,
+    pub map: ValueHashMap,
+    pub metatable: Option<GcCell<Table>>,
+}
+
+impl Default for Table {
+    fn default() -> Self {
+        Self::new(0, 0)
+    }
 }
 
 impl Table {
     pub fn new(narray: usize, nmap: usize) -> Self {
         Table {
             array: Vec::with_capacity(narray),
-            map: HashMap::with_capacity(nmap),
+            map: ValueHashMap::with_capacity(nmap),
+            metatable: None,
         }
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn index(&self, key: &Value) -> &Value {
         match key {
             // TODO float
@@ -48,11 +65,26 @@ impl Table {
         }
     }
     pub fn index_array(&self, i: i64) -> &Value {
-        self.array.get(i as usize - 1)
-            .unwrap_or_else(|| self.map.get(&Value::Integer(i as i64))
+        usize::try_from(i - 1).ok().and_then(|i| self.array.get(i))
+            .unwrap_or_else(|| self.map.get(&Value::Integer(i))
                 .unwrap_or(&Value::Nil))
     }
 
+    // a border, i.e. t[n] ~= nil and t[n+1] == nil
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        let mut n = self.array.len();
+        while n > 0 && self.array[n - 1] == Value::Nil {
+            n -= 1;
+        }
+        if n == self.array.len() {
+            while self.map.contains_key(&Value::Integer(n as i64 + 1)) {
+                n += 1;
+            }
+        }
+        n
+    }
+
     pub fn new_index(&mut self, key: Value, value: Value) {
         match key {
             // TODO float
@@ -63,36 +95,81 @@ impl Table {
         }
     }
     pub fn new_index_array(&mut self, i: i64, value: Value) {
+        // remove trailing nils, so the array part is always a sequence
+        if value == Value::Nil && i > 0 && i as usize == self.array.len() {
+            self.array.pop();
+            while self.array.last() == Some(&Value::Nil) {
+                self.array.pop();
+            }
+            return;
+        }
+
         // this is not same with Lua's official implement
         if i > 0 && (i < 4 || i < self.array.capacity() as i64 * 2) {
-            set_vec(&mut self.array, i as usize - 1, value);
+            let i = i as usize;
+            if i > self.array.len() && !self.map.is_empty() {
+                // move the keys covered by the array part out of the map
+                for k in self.array.len() + 1 .. i {
+                    let v = self.map.remove(&Value::Integer(k as i64));
+                    self.array.push(v);
+                }
+                self.map.remove(&Value::Integer(i as i64));
+            }
+            set_vec(&mut self.array, i - 1, value);
         } else {
             self.map.insert(Value::Integer(i), value);
         }
     }
+
+    // the entry following @key in traversal order: the array part
+    // first, then the hash part; None at the end
+    pub fn next(&self, key: &Value) -> Result<Option<(Value, Value)>> {
+        let mut i = match key {
+            Value::Nil => 0,
+            &Value::Integer(k) if k > 0 && k as usize <= self.array.len() => k as usize,
+            _ => match (self.map.position(key), key) {
+                (Some(p), _) => self.array.len() + p + 1,
+                // an array key removed with the trailing nils during the
+                // traversal, see new_index_array(). The capacity is kept.
+                (None, &Value::Integer(k)) if k > 0 && k as usize <= self.array.capacity() =>
+                    self.array.len(),
+                (None, _) => return Err(Error::runtime("invalid key to 'next'")),
+            }
+        };
+
+        while i < self.array.len() {
+            if self.array[i] != Value::Nil {
+                return Ok(Some((Value::Integer(i as i64 + 1), self.array[i].clone())));
+            }
+            i += 1;
+        }
+        Ok(self.map.next_from(i - self.array.len())
+            .map(|(k, v)| (k.clone(), v.clone())))
+    }
 }
 
 impl fmt::Display for Value {
-    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Value::Nil => write!(f, "nil"),
             Value::Boolean(b) => write!(f, "{b}"),
             Value::Integer(i) => write!(f, "{i}"),
-            Value::Float(n) => write!(f, "{n:?}"),
+            Value::Float(n) => write!(f, "{}", float_to_str(*n)),
             Value::ShortStr(len, buf) => write!(f, "{}", String::from_utf8_lossy(&buf[..*len as usize])),
             Value::MidStr(s) => write!(f, "{}", String::from_utf8_lossy(&s.1[..s.0 as usize])),
             Value::LongStr(s) => write!(f, "{}", String::from_utf8_lossy(s)),
-            Value::Table(t) => write!(f, "table: {:?}", Rc::as_ptr(t)),
+            Value::Table(t) => write!(f, "table: {:?}", t.as_ptr()),
             Value::RustFunction(_) => write!(f, "function"),
             Value::RustClosure(_) => write!(f, "function"),
             Value::LuaFunction(l) => write!(f, "function: {:?}", Rc::as_ptr(l)),
-            Value::LuaClosure(l) => write!(f, "function: {:?}", Rc::as_ptr(l)),
+            Value::LuaClosure(l) => write!(f, "function: {:?}", l.as_ptr()),
+            Value::Thread(t) => write!(f, "thread: {:?}", t.as_ptr()),
         }
     }
 }
 
 impl fmt::Debug for Value {
-    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Value::Nil => write!(f, "nil"),
             Value::Boolean(b) => write!(f, "{b}"),
@@ -109,6 +186,7 @@ impl fmt::Debug for Value {
             Value::RustClosure(_) => write!(f, "rust closure"),
             Value::LuaFunction(_) => write!(f, "Lua function"),
             Value::LuaClosure(_) => write!(f, "Lua closure"),
+            Value::Thread(_) => write!(f, "thread"),
         }
     }
 }
@@ -125,11 +203,12 @@ impl PartialEq for Value {
             (Value::ShortStr(len1, s1), Value::ShortStr(len2, s2)) => s1[..*len1 as usize] == s2[..*len2 as usize],
             (Value::MidStr(s1), Value::MidStr(s2)) => s1.1[..s1.0 as usize] == s2.1[..s2.0 as usize],
             (Value::LongStr(s1), Value::LongStr(s2)) => s1 == s2,
-            (Value::Table(t1), Value::Table(t2)) => Rc::as_ptr(t1) == Rc::as_ptr(t2),
+            (Value::Table(t1), Value::Table(t2)) => GcCell::ptr_eq(t1, t2),
             (Value::RustFunction(f1), Value::RustFunction(f2)) => std::ptr::eq(f1, f2),
             (Value::RustClosure(f1), Value::RustClosure(f2)) => Rc::as_ptr(f1) == Rc::as_ptr(f2),
             (Value::LuaFunction(f1), Value::LuaFunction(f2)) => Rc::as_ptr(f1) == Rc::as_ptr(f2),
-            (Value::LuaClosure(f1), Value::LuaClosure(f2)) => Rc::as_ptr(f1) == Rc::as_ptr(f2),
+            (Value::LuaClosure(f1), Value::LuaClosure(f2)) => Gc::ptr_eq(f1, f2),
+            (Value::Thread(t1), Value::Thread(t2)) => GcCell::ptr_eq(t1, t2),
             (_, _) => false,
         }
     }
@@ -166,75 +245,92 @@ impl PartialOrd for Value {
 
 impl Value {
     pub fn same(&self, other: &Self) -> bool {
-        // eliminate Integer and Float with same number value
-        mem::discriminant(self) == mem::discriminant(other) && self == other
+        match (self, other) {
+            // 0.0 and -0.0 are equal, but different in string
+            (Value::Float(f1), Value::Float(f2)) => f1.to_bits() == f2.to_bits(),
+            // eliminate Integer and Float with same number value
+            _ => mem::discriminant(self) == mem::discriminant(other) && self == other,
+        }
     }
     pub fn ty(&self) -> &'static str {
         match self {
-            &Value::Nil => "nil",
-            &Value::Boolean(_) => "boolean",
-            &Value::Integer(_) => "number",
-            &Value::Float(_) => "number",
-            &Value::ShortStr(_, _) => "string",
-            &Value::MidStr(_) => "string",
-            &Value::LongStr(_) => "string",
-            &Value::Table(_) => "table",
-            &Value::RustFunction(_) => "function",
-            &Value::RustClosure(_) => "function",
-            &Value::LuaFunction(_) => "function",
-            &Value::LuaClosure(_) => "function",
+            Value::Nil => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Integer(_) => "number",
+            Value::Float(_) => "number",
+            Value::ShortStr(_, _) => "string",
+            Value::MidStr(_) => "string",
+            Value::LongStr(_) => "string",
+            Value::Table(_) => "table",
+            Value::RustFunction(_) => "function",
+            Value::RustClosure(_) => "function",
+            Value::LuaFunction(_) => "function",
+            Value::LuaClosure(_) => "function",
+            Value::Thread(_) => "thread",
         }
     }
 
-    pub fn index(&self, key: &Value) -> Value {
-        match self {
-            Value::Table(t) => t.borrow().index(key).clone(),
-            _ => todo!("meta __index"),
-        }
-    }
-    pub fn index_array(&self, i: i64) -> Value {
-        match self {
-            Value::Table(t) => t.borrow().index_array(i).clone(),
-            _ => todo!("meta __index"),
-        }
+    pub fn is_string(&self) -> bool {
+        matches!(self, Value::ShortStr(_, _) | Value::MidStr(_) | Value::LongStr(_))
     }
 
-    pub fn new_index(&self, key: Value, value: Value) {
-        match self {
-            Value::Table(t) => t.borrow_mut().new_index(key, value),
-            _ => todo!("meta __index"),
-        }
+    pub fn is_function(&self) -> bool {
+        matches!(self, Value::RustFunction(_) | Value::RustClosure(_) |
+                Value::LuaFunction(_) | Value::LuaClosure(_))
     }
-    pub fn new_index_array(&self, i: i64, value: Value) {
+
+    // numbers, or strings converted by the numeral grammar
+    pub fn to_number(&self) -> Option<Value> {
         match self {
-            Value::Table(t) => t.borrow_mut().new_index_array(i, value),
-            _ => todo!("meta __newindex"),
+            Value::Integer(_) | Value::Float(_) => Some(self.clone()),
+            v if v.is_string() => std::str::from_utf8(v.as_ref()).ok().and_then(str_to_number),
+            _ => None,
         }
     }
 
-    pub fn concat(&self, v2: &Self) -> Self {
-        let s1: &[u8] = self.as_ref();
-
-        match v2 {
-            Value::Integer(_) => todo!("int"),
-            Value::Float(_) => todo!("float"),
-            _ => {
-                let s2: &[u8] = v2.as_ref();
+    pub fn concat(&self, v2: &Self) -> Result<Self> {
+        let is_str_num = |v: &Value| v.is_string() || matches!(v, Value::Integer(_) | Value::Float(_));
+        match (self, v2) {
+            (s1, s2) if !is_str_num(s1) || !is_str_num(s2) => {
+                let v = if is_str_num(s1) { s2 } else { s1 };
+                Err(Error::type_error(format!("attempt to concatenate a {} value", v.ty())))
+            }
+            // numbers are converted to strings
+            (s1, s2) if !s1.is_string() || !s2.is_string() => {
+                let tostr = |v: &Value| if v.is_string() { v.clone() } else { v.to_string().into() };
+                tostr(s1).concat(&tostr(s2))
+            }
+            (s1, s2) => {
+                let s1: &[u8] = s1.as_ref();
+                let s2: &[u8] = s2.as_ref();
                 let l1 = s1.len();
                 let l2 = s2.len();
                 if l1 + l2 < MID_STR_MAX {
                     let mut buf = [0; MID_STR_MAX];
                     buf[..l1].copy_from_slice(s1);
                     buf[l1..l1+l2].copy_from_slice(s2);
-                    buf[..l1+l2].into()
+                    Ok(buf[..l1+l2].into())
                 } else {
-                    [s1, s2].concat().into()
+                    Ok([s1, s2].concat().into())
                 }
             }
         }
     }
 }
 
+impl Trace for Value {
+    fn trace(&self, tracer: &mut Tracer) {
+        match self {
+            Value::Table(t) => t.trace(tracer),
+            Value::LuaClosure(c) => c.trace(tracer),
+            Value::Thread(t) => t.trace(tracer),
+            // strings and prototypes can not refer other values, and
+            // Rust closures are opaque
+            _ => (),
+        }
+    }
+}
+
 impl Hash for Value {
     fn hash<H: Hasher>(&self, state: &mut H) {
         match self {
@@ -250,11 +346,12 @@ impl Hash for Value {
             Value::ShortStr(len, buf) => buf[..*len as usize].hash(state),
             Value::MidStr(s) => s.1[..s.0 as usize].hash(state),
             Value::LongStr(s) => s.hash(state),
-            Value::Table(t) => Rc::as_ptr(t).hash(state),
+            Value::Table(t) => t.as_ptr().hash(state),
             Value::RustFunction(f) => (*f as *const usize).hash(state),
             Value::RustClosure(f) => Rc::as_ptr(f).hash(state),
             Value::LuaFunction(f) => Rc::as_ptr(f).hash(state),
-            Value::LuaClosure(f) => Rc::as_ptr(f).hash(state),
+            Value::LuaClosure(f) => f.as_ptr().hash(state),
+            Value::Thread(t) => t.as_ptr().hash(state),
         }
     }
 }
@@ -351,10 +448,12 @@ impl From<&Value> for i64 {
         match v {
             Value::Integer(i) => *i,
             Value::Float(f) => *f as i64,
-            Value::ShortStr(_, _) => todo!("tonumber"),
-            Value::MidStr(_) => todo!("tonumber"),
-            Value::LongStr(_) => todo!("tonumber"),
-            _ => panic!("invalid string Value"),
+            // like lua_tointeger(), 0 if not a number
+            v => match v.to_number() {
+                Some(Value::Integer(i)) => i,
+                Some(Value::Float(f)) => f as i64,
+                _ => 0,
+            },
         }
     }
 }
\ No newline at end of file
@@ -1,22 +1,109 @@
 use std::collections::HashMap;
-use std::hash::Hash;
+use crate::value::Value;
+use luargc::Trace;
 
-pub struct ValueHashMap<K, V> {
-    map: HashMap<K, V>,
+// The hash part of tables.
+//
+// Entries are kept in a vector in insertion order, and a hash map
+// maps keys to their positions. So the traversal by `next()` can be
+// resumed from any key in O(1).
+//
+// Assigning nil to an existing key leaves a dead entry in place, so
+// assigning existing fields (including clearing them) during a
+// traversal does not disturb it. Dead entries are dropped only when
+// the vector is full and a new key is added.
+#[derive(Trace, Default)]
+pub struct ValueHashMap {
+    index: HashMap<Value, usize>,
+    entries: Vec<(Value, Value)>,
+    ndead: usize,
 }
 
-impl<K: Eq + Hash + Clone, V: Clone> ValueHashMap<K, V> {
-    pub fn new() -> Self {
+impl ValueHashMap {
+    pub fn with_capacity(n: usize) -> Self {
         ValueHashMap {
-            map: HashMap::new(),
+            index: HashMap::with_capacity(n),
+            entries: Vec::with_capacity(n),
+            ndead: 0,
         }
     }
 
-    pub fn get_value(&mut self, key: K) -> Option<V> {
-        self.map.get(&key).cloned()
+    pub fn get(&self, key: &Value) -> Option<&Value> {
+        let &i = self.index.get(key)?;
+        match &self.entries[i].1 {
+            Value::Nil => None,
+            v => Some(v),
+        }
+    }
+
+    pub fn contains_key(&self, key: &Value) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn insert(&mut self, key: Value, value: Value) {
+        if let Some(&i) = self.index.get(&key) {
+            let old = &mut self.entries[i].1;
+            match (*old == Value::Nil, value == Value::Nil) {
+                (true, false) => self.ndead -= 1,
+                (false, true) => self.ndead += 1,
+                _ => (),
+            }
+            *old = value;
+        } else if value != Value::Nil {
+            // before growing, drop the dead entries if there are many
+            if self.entries.len() == self.entries.capacity()
+                    && self.ndead > 0 && self.ndead * 2 >= self.entries.len() {
+                self.compact();
+            }
+            self.index.insert(key.clone(), self.entries.len());
+            self.entries.push((key, value));
+        }
+    }
+
+    // clear @key and return its old value
+    pub fn remove(&mut self, key: &Value) -> Value {
+        match self.index.get(key) {
+            Some(&i) if self.entries[i].1 != Value::Nil => {
+                self.ndead += 1;
+                std::mem::replace(&mut self.entries[i].1, Value::Nil)
+            }
+            _ => Value::Nil,
+        }
+    }
+
+    // number of live entries
+    pub fn len(&self) -> usize {
+        self.entries.len() - self.ndead
     }
 
-    pub fn insert(&mut self, key: K, value: V) {
-        self.map.insert(key, value);
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
-}
\ No newline at end of file
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Value, &Value)> {
+        self.entries.iter()
+            .filter(|(_, v)| v != &Value::Nil)
+            .map(|(k, v)| (k, v))
+    }
+
+    // position of @key in the traversal order
+    pub fn position(&self, key: &Value) -> Option<usize> {
+        self.index.get(key).copied()
+    }
+
+    // the first live entry at or after position @i
+    pub fn next_from(&self, i: usize) -> Option<(&Value, &Value)> {
+        self.entries.get(i..)?.iter()
+            .find(|(_, v)| v != &Value::Nil)
+            .map(|(k, v)| (k, v))
+    }
+
+    fn compact(&mut self) {
+        self.entries.retain(|(_, v)| v != &Value::Nil);
+        self.index.clear();
+        for (i, (k, _)) in self.entries.iter().enumerate() {
+            self.index.insert(k.clone(), i);
+        }
+        self.ndead = 0;
+    }
+}
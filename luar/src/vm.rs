@@ -1,84 +1,299 @@
+use std::mem;
 use std::rc::Rc;
 use std::cell::RefCell;
 use std::cmp::Ordering;
-use crate::bytecode::ByteCode;
+use crate::bytecode::{Instruction, opcode, get_op, arg_a, arg_b, arg_c, arg_k, arg_bx, arg_sbx, arg_ax, arg_sj};
 use crate::value::{Value, Table};
 use crate::parse::{FuncProto, UpIndex};
-use crate::utils::{ftoi, set_vec};
-use crate::lualib::baselib::{lua_print,lua_type};
+use crate::error::{Error, Result, TraceFrame};
+use crate::utils::{ftoi, set_vec, int_idiv, int_mod, float_idiv, float_mod, shift_left};
+use luargc::{Context, GcCell, Trace};
+use crate::lualib::baselib::{lua_print,lua_type,lua_assert,lua_tonumber,lua_tostring};
+use crate::lualib::baselib::{lua_setmetatable,lua_getmetatable,lua_rawget,lua_rawset,lua_rawequal,lua_rawlen};
+use crate::lualib::baselib::{lua_collectgarbage,lua_next,lua_pairs};
+use crate::lualib::baselib::{lua_error,lua_pcall,lua_xpcall};
+use crate::lualib::baselib::{lua_load,lua_loadfile,lua_dofile};
 use crate::lualib::auxlib::{ipairs,test_new_counter};
+use crate::lualib::{strlib, tablib, mathlib, corolib};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Trace)]
 pub enum Upvalue {
-    Open(usize),
+    Open(usize), // refer to the stack, which is root
     Closed(Value),
 }
 
+impl Default for Upvalue {
+    fn default() -> Self {
+        Upvalue::Closed(Value::Nil)
+    }
+}
+
+// An open upvalue is always below the stack top for byte codes from the
+// parser, but maybe not for loaded binary chunks, so it is taken as nil
+// if above.
 impl Upvalue {
-    fn get<'a>(&'a self, stack: &'a Vec<Value>) -> &'a Value {
+    fn get<'a>(&'a self, stack: &'a [Value]) -> &'a Value {
         match self {
-            Upvalue::Open(i) => &stack[*i],
-            Upvalue::Closed(v) => &v,
+            Upvalue::Open(i) => stack.get(*i).unwrap_or(&Value::Nil),
+            Upvalue::Closed(v) => v,
         }
     }
-    fn set(&mut self, stack: &mut Vec<Value>, value: Value) {
+    fn set(&mut self, stack: &mut [Value], value: Value) {
         match self {
-            Upvalue::Open(i) => stack[*i] = value,
+            Upvalue::Open(i) => if let Some(slot) = stack.get_mut(*i) {
+                *slot = value;
+            }
             Upvalue::Closed(v) => *v = value,
         }
     }
 }
 
 // broker between local variables and open upvalues.
+#[derive(Trace)]
 struct OpenBroker {
     // @broker contains @ilocal, however, the duplicated @ilocal
     // exists for quick comparation
     ilocal: usize,
-    broker: Rc<RefCell<Upvalue>>,
+    broker: GcCell<Upvalue>,
 }
 
-impl From<usize> for OpenBroker {
-    fn from(ilocal: usize) -> Self {
+impl OpenBroker {
+    fn new(heap: &mut Context, ilocal: usize) -> Self {
         OpenBroker {
             ilocal,
-            broker: Rc::new(RefCell::new(Upvalue::Open(ilocal))),
+            broker: heap.alloc_cell(Upvalue::Open(ilocal)),
         }
     }
 }
 
+#[derive(Trace)]
 pub struct LuaClosure {
+    #[trace(skip)]
     proto: Rc<FuncProto>,
-    upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    upvalues: Vec<GcCell<Upvalue>>,
+}
+
+impl LuaClosure {
+    pub fn proto(&self) -> &Rc<FuncProto> {
+        &self.proto
+    }
+}
+
+// call frame of a Lua function
+#[derive(Trace)]
+struct CallFrame {
+    func: Value, // LuaFunction or LuaClosure
+    base: usize,
+    pc: usize, // at the calling byte code if it is not the top frame
+    varargs: Vec<Value>,
+
+    // open brokers between local variables and upvalues
+    open_brokers: Vec<OpenBroker>,
+
+    // stack indexes of to-be-closed variables, in order
+    tbc: Vec<usize>,
+
+    // the last byte code traced by line hook
+    hook_pc: usize,
+}
+
+// how the execution of a frame stops
+enum Step {
+    Call, // a new frame is pushed
+    Return(usize), // the number of return values at the stack top
+    Yield(usize), // the number of yielded values at the stack top
+}
+
+// events of hook, see ExeState::set_hook()
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HookEvent {
+    Call, // before a function starts
+    Return, // after a function returns
+    Line(usize), // a Lua function starts a new line, or jumps back
+    Count, // every `HookMask::count` byte codes
+}
+
+// which events trigger the hook
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct HookMask {
+    pub call: bool,
+    pub ret: bool,
+    pub line: bool,
+    pub count: usize, // 0 to disable
+}
+
+pub type Hook = Rc<RefCell<Box<dyn FnMut(&mut ExeState, HookEvent) -> Result<()>>>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ThreadStatus {
+    #[default]
+    Suspended,
+    Running,
+    Normal, // resuming another coroutine
+    Dead,
+}
+
+// coroutine. Its stack and frames are moved into ExeState while it
+// is running.
+#[derive(Trace, Default)]
+pub struct Thread {
+    stack: Vec<Value>,
+    base: usize, // 0 if not started
+    frames: Vec<CallFrame>,
+    nny: usize,
+    #[trace(skip)]
+    status: ThreadStatus,
+    #[trace(skip)]
+    error: Option<Error>, // the error which kills it
+}
+
+impl Thread {
+    pub fn status(&self) -> ThreadStatus {
+        self.status
+    }
+
+    // for a thread not running
+    pub fn is_yieldable(&self) -> bool {
+        self.nny == 0
+    }
+
+    // kill a suspended or dead coroutine, return the error which
+    // killed it if any
+    pub fn close(&mut self) -> Result<Option<Error>> {
+        match self.status {
+            ThreadStatus::Running => Err(Error::runtime("cannot close a running coroutine")),
+            ThreadStatus::Normal => Err(Error::runtime("cannot close a normal coroutine")),
+            _ => {
+                // the upvalues of a suspended coroutine are parked already
+                self.frames.clear();
+                self.stack.clear();
+                self.status = ThreadStatus::Dead;
+                Ok(self.error.take())
+            }
+        }
+    }
 }
 
+// Max depth of nested Rust calls, including resumes and Lua functions
+// called by Rust, each of which consumes the native stack. Each level
+// takes 7~13KB in debug build, e.g. a metamethod calling itself, or a
+// table.sort() comparator calling table.sort(), so 100 levels fit in the
+// 2MB stack of spawned threads.
+const MAX_RUST_CALLS: usize = 100;
+
+// default max depth of Lua call frames in each thread
+const MAX_FRAMES: usize = 200_000;
+
 // global execute state
 pub struct ExeState {
     stack: Vec::<Value>,
     base: usize, // stack base of current function
+    frames: Vec<CallFrame>, // of the running thread
+    nny: usize, // number of non-yieldable calls in the running thread
+    yielding: bool, // set by the Rust function which yields
+    threads: Vec<GcCell<Thread>>, // the main thread, and resumed coroutines
+    msgh: Value, // message handler of the innermost xpcall, or nil
+    ncalls: usize, // number of nested Rust calls
+    max_frames: usize,
+    globals: Value, // the default _ENV of loaded chunks
+    heap: Context,
+    string_meta: Option<GcCell<Table>>,
+
+    hook: Option<Hook>,
+    hook_mask: HookMask,
+    hook_instr: bool, // whether to trace each byte code, for line or count
+    hook_count: usize, // byte codes left before the next count event
+    allow_hook: bool, // false while running the hook
+}
+
+impl Default for ExeState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ExeState {
     pub fn new() -> Self {
         // TODO initilize the standard library outside
+        let mut heap = Context::new();
         let mut env = Table::new(0, 0);
         env.map.insert("print".into(), Value::RustFunction(lua_print));
         env.map.insert("type".into(), Value::RustFunction(lua_type));
+        env.map.insert("assert".into(), Value::RustFunction(lua_assert));
+        env.map.insert("tonumber".into(), Value::RustFunction(lua_tonumber));
+        env.map.insert("tostring".into(), Value::RustFunction(lua_tostring));
+        env.map.insert("setmetatable".into(), Value::RustFunction(lua_setmetatable));
+        env.map.insert("getmetatable".into(), Value::RustFunction(lua_getmetatable));
+        env.map.insert("rawget".into(), Value::RustFunction(lua_rawget));
+        env.map.insert("rawset".into(), Value::RustFunction(lua_rawset));
+        env.map.insert("rawequal".into(), Value::RustFunction(lua_rawequal));
+        env.map.insert("rawlen".into(), Value::RustFunction(lua_rawlen));
+        env.map.insert("collectgarbage".into(), Value::RustFunction(lua_collectgarbage));
         env.map.insert("ipairs".into(), Value::RustFunction(ipairs));
+        env.map.insert("next".into(), Value::RustFunction(lua_next));
+        env.map.insert("pairs".into(), Value::RustFunction(lua_pairs));
+        env.map.insert("error".into(), Value::RustFunction(lua_error));
+        env.map.insert("pcall".into(), Value::RustFunction(lua_pcall));
+        env.map.insert("xpcall".into(), Value::RustFunction(lua_xpcall));
+        env.map.insert("load".into(), Value::RustFunction(lua_load));
+        env.map.insert("loadfile".into(), Value::RustFunction(lua_loadfile));
+        env.map.insert("dofile".into(), Value::RustFunction(lua_dofile));
         env.map.insert("new_counter".into(), Value::RustFunction(test_new_counter));
 
-        ExeState {
-            // 0: un-used entry function, 1: `_ENV` argument
-            stack: vec![Value::Nil, Value::Table(Rc::new(RefCell::new(env)))],
+        let main = heap.alloc_cell(Thread {
+            status: ThreadStatus::Running,
+            ..Default::default()
+        });
+
+        let globals = Value::Table(heap.alloc_cell(env));
+        let mut state = ExeState {
+            // 0: un-used entry function
+            stack: vec![Value::Nil],
 
             // always an entry function, even not used
             base: 1,
-        }
+            frames: Vec::new(),
+            nny: 1, // the main thread can not yield
+            yielding: false,
+            threads: vec![main],
+            msgh: Value::Nil,
+            ncalls: 0,
+            max_frames: MAX_FRAMES,
+            globals,
+            heap,
+            string_meta: None,
+            hook: None,
+            hook_mask: HookMask::default(),
+            hook_instr: false,
+            hook_count: 0,
+            allow_hook: true,
+        };
+        strlib::open(&mut state);
+        tablib::open(&mut state);
+        mathlib::open(&mut state);
+        corolib::open(&mut state);
+        state
     }
 
-    pub fn execute(&mut self, proto: &FuncProto, upvalues: &Vec<Rc<RefCell<Upvalue>>>) -> usize {
+    // execute the main chunk @proto, whose _ENV is the global table if
+    // @upvalues is empty
+    pub fn execute(&mut self, proto: &FuncProto, upvalues: &[GcCell<Upvalue>]) -> Result<usize> {
+        let f = if upvalues.is_empty() {
+            self.new_chunk(proto.clone(), None)
+        } else {
+            let c = LuaClosure {
+                proto: Rc::new(proto.clone()),
+                upvalues: upvalues.to_vec(),
+            };
+            Value::LuaClosure(self.heap.alloc(c))
+        };
+        self.push_frame(f);
+        self.run(self.frames.len(), None)
+    }
 
-        // open brokers between local variables and upvalues
-        let mut open_brokers: Vec<OpenBroker> = Vec::new();
+    // new frame for Lua function @func, whose arguments are at @self.base
+    fn push_frame(&mut self, func: Value) {
+        let (proto, _) = lua_parts(&func);
 
         // fill nil if #argument < #parameter
         if self.stack.len() - self.base < proto.nparam {
@@ -92,90 +307,175 @@ impl ExeState {
             Vec::new()
         };
 
-        let mut pc = 0;
+        self.frames.push(CallFrame {
+            func,
+            base: self.base,
+            pc: 0,
+            varargs,
+            open_brokers: Vec::new(),
+            tbc: Vec::new(),
+            hook_pc: 0,
+        });
+    }
+
+    // Execute frames from the top one, until the frame at @depth returns
+    // or a Rust function yields. Lua functions are called by pushing new
+    // frames but not recursion, so the frames can be suspended by yield.
+    // If @nret is set, the top frame is waiting for @nret return values
+    // of its calling byte code.
+    //
+    // Return the number of return or yielded values at the stack top.
+    fn run(&mut self, depth: usize, mut nret: Option<usize>) -> Result<usize> {
+        loop {
+            let frame = self.frames.last().unwrap();
+            let func = frame.func.clone();
+            let (proto, upvalues) = lua_parts(&func);
+            let mut pc = frame.pc;
+            self.base = frame.base;
+
+            let step = match nret.take() {
+                Some(n) => match self.finish_call(proto, n, &mut pc) {
+                    Some(step) => Ok(step),
+                    None => {
+                        pc += 1;
+                        self.do_execute(proto, upvalues, &mut pc)
+                    }
+                }
+                // a new frame
+                None if pc == 0 && self.hook_mask.call => self.call_hook(HookEvent::Call)
+                    .and_then(|_| self.do_execute(proto, upvalues, &mut pc)),
+                None => self.do_execute(proto, upvalues, &mut pc),
+            };
+            let step = match step {
+                Ok(Step::Return(n)) if self.hook_mask.ret =>
+                    self.call_hook(HookEvent::Return).map(|_| Step::Return(n)),
+                step => step,
+            };
+
+            match step {
+                Ok(Step::Call) => (),
+                Ok(Step::Return(n)) => {
+                    self.frames.pop();
+                    if self.frames.len() < depth {
+                        return Ok(n);
+                    }
+                    nret = Some(n);
+                }
+                Ok(Step::Yield(n)) => {
+                    self.frames.last_mut().unwrap().pc = pc;
+                    return Ok(n);
+                }
+                Err(err) => {
+                    self.frames.last_mut().unwrap().pc = pc;
+
+                    // call the message handler before unwinding, only once
+                    let err = match mem::replace(&mut self.msgh, Value::Nil) {
+                        Value::Nil => err,
+                        msgh => self.handle_error(msgh, err),
+                    };
+                    return Err(self.unwind(err, depth));
+                }
+            }
+        }
+    }
+
+    // pop frames down to @depth when error raised
+    fn unwind(&mut self, mut err: Error, depth: usize) -> Error {
+        while self.frames.len() >= depth {
+            // error in __close replaces the original one
+            while let Err(e) = self.close_tbc(0, &err.to_value()) {
+                err = e;
+            }
+
+            let frame = self.frames.pop().unwrap();
+            close_brokers(&self.stack, frame.open_brokers);
+
+            let (proto, _) = lua_parts(&frame.func);
+            err = err.with_frame(TraceFrame {
+                source: proto.source.clone(),
+                line: proto.lineinfo.get(frame.pc).copied().unwrap_or(0),
+                name: func_desc(proto),
+            });
+        }
+        err
+    }
+
+    // execute the top frame, until it calls a Lua function, returns,
+    // or yields. @pc is left at the failed byte code if error raised
+    fn do_execute(&mut self, proto: &FuncProto, upvalues: &[GcCell<Upvalue>], pc: &mut usize) -> Result<Step> {
         loop {
-            println!("  [{pc}]\t{:?}", proto.byte_codes[pc]);
-            match proto.byte_codes[pc] {
+            if self.hook_instr {
+                self.instruction_hook(proto, *pc)?;
+            }
+            let code = proto.byte_codes[*pc];
+            match get_op(code) {
                 // local variable
-                ByteCode::LoadConst(dst, c) => {
+                opcode::LoadConst => {
+                    let (dst, c) = (arg_a(code), arg_bx(code));
+                    let v = proto.constants[c as usize].clone();
+                    self.set_stack(dst, v);
+                }
+                opcode::LoadConstX => {
+                    let dst = arg_a(code);
+                    let c = extra_arg(proto, pc);
                     let v = proto.constants[c as usize].clone();
                     self.set_stack(dst, v);
                 }
-                ByteCode::LoadNil(dst, n) => {
+                // always skipped by the previous code
+                opcode::ExtraArg => unreachable!("ExtraArg"),
+                opcode::LoadNil => {
+                    let (dst, n) = (arg_a(code), arg_b(code));
+                    // do not truncate the stack, since registers above
+                    // may be alive, e.g. assign nil to an existing local
                     let begin = self.base + dst as usize;
-                    if begin < self.stack.len() {
-                        self.stack[begin..].fill(Value::Nil);
+                    let end = begin + n as usize;
+                    if end > self.stack.len() {
+                        self.stack.resize(end, Value::Nil);
                     }
-                    self.fill_stack_nil(dst, n as usize);
+                    self.stack[begin..end].fill(Value::Nil);
                 }
-                ByteCode::LoadBool(dst, b) => {
+                opcode::LoadBool => {
+                    let (dst, b) = (arg_a(code), arg_k(code));
                     self.set_stack(dst, Value::Boolean(b));
                 }
-                ByteCode::LoadInt(dst, i) => {
+                opcode::LoadInt => {
+                    let (dst, i) = (arg_a(code), arg_sbx(code));
                     self.set_stack(dst, Value::Integer(i as i64));
                 }
-                ByteCode::Move(dst, src) => {
+                opcode::Move => {
+                    let (dst, src) = (arg_a(code), arg_b(code));
                     let v = self.get_stack(src).clone();
                     self.set_stack(dst, v);
                 }
 
                 // upvalues
-                ByteCode::GetUpvalue(dst, src) => {
+                opcode::GetUpvalue => {
+                    let (dst, src) = (arg_a(code), arg_b(code));
                     let v = upvalues[src as usize].borrow().get(&self.stack).clone();
                     self.set_stack(dst, v);
                 }
-                ByteCode::SetUpvalue(dst, src) => {
+                opcode::SetUpvalue => {
+                    let (dst, src) = (arg_a(code), arg_b(code));
                     let v = self.get_stack(src).clone();
                     upvalues[dst as usize].borrow_mut().set(&mut self.stack, v);
                 }
-                ByteCode::SetUpvalueConst(dst, src) => {
+                opcode::SetUpvalueConst => {
+                    let (dst, src) = (arg_a(code), arg_b(code));
                     let v = proto.constants[src as usize].clone();
                     upvalues[dst as usize].borrow_mut().set(&mut self.stack, v);
                 }
-                ByteCode::Close(ilocal) => {
-                    let ilocal = self.base + ilocal as usize;
-                    let from = open_brokers.binary_search_by_key(&ilocal, |b| b.ilocal)
-                        .unwrap_or_else(|i| i);
-                    self.close_brokers(open_brokers.drain(from..));
-                }
-
                 // table
-                ByteCode::NewTable(dst, narray, nmap) => {
+                opcode::NewTable => {
+                    let (dst, narray, nmap) = (arg_a(code), arg_b(code), arg_c(code));
                     let table = Table::new(narray as usize, nmap as usize);
-                    self.set_stack(dst, Value::Table(Rc::new(RefCell::new(table))));
-                }
-                ByteCode::SetTable(t, k, v) => {
-                    let key = self.get_stack(k).clone();
-                    let value = self.get_stack(v).clone();
-                    self.get_stack(t).new_index(key, value);
-                }
-                ByteCode::SetField(t, k, v) => {
-                    let key = proto.constants[k as usize].clone();
-                    let value = self.get_stack(v).clone();
-                    self.get_stack(t).new_index(key, value);
-                }
-                ByteCode::SetInt(t, i, v) => {
-                    let value = self.get_stack(v).clone();
-                    self.get_stack(t).new_index_array(i as i64, value);
-                }
-                ByteCode::SetTableConst(t, k, v) => {
-                    let key = self.get_stack(k).clone();
-                    let value = proto.constants[v as usize].clone();
-                    self.get_stack(t).new_index(key, value);
-                }
-                ByteCode::SetFieldConst(t, k, v) => {
-                    let key = proto.constants[k as usize].clone();
-                    let value = proto.constants[v as usize].clone();
-                    self.get_stack(t).new_index(key, value);
+                    let table = self.heap.alloc_cell(table);
+                    self.set_stack(dst, Value::Table(table));
                 }
-                ByteCode::SetIntConst(t, i, v) => {
-                    let value = proto.constants[v as usize].clone();
-                    self.get_stack(t).new_index_array(i as i64, value);
-                }
-                ByteCode::SetList(table, n) => {
+                opcode::SetList => {
+                    let (table, n) = (arg_a(code), arg_b(code));
                     let ivalue = self.base + table as usize + 1;
                     let Value::Table(table) = self.get_stack(table).clone() else {
-                        panic!("not table");
+                        return Err(Error::runtime("SetList on non-table"));
                     };
                     let end = if n == 0 {
                         // 0 is special, means all following values in stack
@@ -183,284 +483,108 @@ impl ExeState {
                     } else {
                         ivalue + n as usize
                     };
+                    if end > self.stack.len() {
+                        self.stack.resize(end, Value::Nil);
+                    }
                     let values = self.stack.drain(ivalue .. end);
                     table.borrow_mut().array.extend(values);
                 }
-                ByteCode::GetTable(dst, t, k) => {
-                    let key = self.get_stack(k);
-                    let value = self.get_stack(t).index(key);
-                    self.set_stack(dst, value);
-                }
-                ByteCode::GetField(dst, t, k) => {
-                    let key = &proto.constants[k as usize];
-                    let value = self.get_stack(t).index(key);
-                    self.set_stack(dst, value);
-                }
-                ByteCode::GetInt(dst, t, k) => {
-                    let value = self.get_stack(t).index_array(k as i64);
-                    self.set_stack(dst, value);
-                }
-                ByteCode::GetFieldSelf(dst, t, k) => {
-                    let table = self.get_stack(t).clone();
-                    let key = &proto.constants[k as usize];
-                    let value = table.index(key).clone();
-                    self.set_stack(dst, value);
-                    self.set_stack(dst+1, table);
-                }
-
-                // upvalue table
-                //
-                // The upvalue-table is get by:
-                //    `upvalues[t as usize].borrow().get(&self.stack)`
-                // I do not know how to move this piece of code into a
-                // function because of the `borrow()`.
-                ByteCode::SetUpField(t, k, v) => {
-                    let key = proto.constants[k as usize].clone();
-                    let value = self.get_stack(v).clone();
-                    upvalues[t as usize].borrow().get(&self.stack)
-                        .new_index(key, value);
-                }
-                ByteCode::SetUpFieldConst(t, k, v) => {
-                    let key = proto.constants[k as usize].clone();
-                    let value = proto.constants[v as usize].clone();
-                    upvalues[t as usize].borrow().get(&self.stack)
-                        .new_index(key, value);
-                }
-                ByteCode::GetUpField(dst, t, k) => {
-                    let key = &proto.constants[k as usize];
-                    let value = upvalues[t as usize].borrow().get(&self.stack)
-                        .index(key).clone();
-                    self.set_stack(dst, value);
-                }
-
                 // condition structures
-                ByteCode::Jump(jmp) => {
-                    pc = (pc as isize + jmp as isize) as usize;
-                }
-                ByteCode::TestAndJump(icondition, jmp) => {
-                    if self.get_stack(icondition).into() { // jump if true
-                        pc = (pc as isize + jmp as isize) as usize;
+                opcode::Jump => {
+                    let jmp = arg_sj(code);
+                    *pc = (*pc as isize + jmp as isize) as usize;
+                }
+                // the tests are followed by Jump, skip it if not jump
+                opcode::TestAndJump => {
+                    let icondition = arg_a(code);
+                    if !bool::from(self.get_stack(icondition)) {
+                        *pc += 1;
                     }
                 }
-                ByteCode::TestOrJump(icondition, jmp) => {
-                    if self.get_stack(icondition).into() {} else { // jump if false
-                        pc = (pc as isize + jmp as isize) as usize;
+                opcode::TestOrJump => {
+                    let icondition = arg_a(code);
+                    if self.get_stack(icondition).into() {
+                        *pc += 1;
                     }
                 }
-                ByteCode::TestAndSetJump(dst, icondition, jmp) => {
+                opcode::TestAndSetJump => {
+                    let (dst, icondition) = (arg_a(code), arg_b(code));
                     let condition = self.get_stack(icondition);
                     if condition.into() { // set and jump if true
                         self.set_stack(dst, condition.clone());
-                        pc += jmp as usize;
+                    } else {
+                        *pc += 1;
                     }
                 }
-                ByteCode::TestOrSetJump(dst, icondition, jmp) => {
+                opcode::TestOrSetJump => {
+                    let (dst, icondition) = (arg_a(code), arg_b(code));
                     let condition = self.get_stack(icondition);
-                    if condition.into() {} else { // set and jump if false
+                    if condition.into() {
+                        *pc += 1;
+                    } else { // set and jump if false
                         self.set_stack(dst, condition.clone());
-                        pc += jmp as usize;
                     }
                 }
 
                 // for-loop
-                ByteCode::ForPrepare(dst, jmp) => {
-                    // clear into 2 cases: integer and float
-                    // stack: i, limit, step
-                    if let (&Value::Integer(mut i), &Value::Integer(step)) =
-                            (self.get_stack(dst), self.get_stack(dst + 2)) {
-                        // integer case
-                        if step == 0 {
-                            panic!("0 step in numerical for");
-                        }
-                        let limit = match self.get_stack(dst + 1) {
-                            &Value::Integer(limit) => limit,
-                            &Value::Float(limit) => {
-                                let limit = for_int_limit(limit, step>0, &mut i);
-                                self.set_stack(dst+1, Value::Integer(limit));
-                                limit
-                            }
-                            // TODO convert string
-                            _ => panic!("invalid limit type"),
-                        };
-                        if !for_check(i, limit, step>0) {
-                            pc += jmp as usize;
-                        }
-                    } else {
-                        // float case
-                        let i = self.make_float(dst);
-                        let limit = self.make_float(dst+1);
-                        let step = self.make_float(dst+2);
-                        if step == 0.0 {
-                            panic!("0 step in numerical for");
-                        }
-                        if !for_check(i, limit, step>0.0) {
-                            pc += jmp as usize;
-                        }
-                    }
-                }
-                ByteCode::ForLoop(dst, jmp) => {
-                    // stack: i, limit, step
-                    match (self.get_stack(dst + 1), self.get_stack(dst + 2)) {
-                        (&Value::Integer(limit), &Value::Integer(step)) => {
-                            let Value::Integer(i) = self.get_stack_mut(dst) else {
-                                panic!("xxx");
-                            };
-                            *i += step;
-                            if for_check(*i, limit, step>0) {
-                                pc -= jmp as usize;
-                            }
-                        }
-                        (&Value::Float(limit), &Value::Float(step)) => {
-                            let Value::Float(i) = self.get_stack_mut(dst) else {
-                                panic!("xxx");
-                            };
-                            *i += step;
-                            if for_check(*i, limit, step>0.0) {
-                                pc -= jmp as usize;
-                            }
-                        }
-                        _ => panic!("xx"),
-                    }
-                }
-
-                ByteCode::ForCallLoop(iter, nvar, jmp) => {
+                opcode::ForCallLoop => {
+                    let iter = arg_a(code);
                     // stack:
-                    // - before call:
-                    //     iter-func, state, ctrl-var
+                    // - before call, copy them so the callee can not
+                    //   overwrite them:
+                    //     iter-func, state, ctrl-var, iter-func, state, ctrl-var
                     // - after call:
                     //     iter-func, state, ctrl-var, ..., return-values
                     // - update ctrl-var, and clear middle values
                     //     iter-func, state, ctrl-var*, return-values
-                    let nret = self.call_function(iter, 2+1);
-                    let iret = self.stack.len() - nret;
-
-                    if nret > 0 && self.stack[iret] != Value::Nil {
-                        // continue the loop
-                        // duplicate the first return value as ctrl-var,
-                        // so it could be changed during loop.
-                        let first_ret = self.stack[iret].clone();
-                        self.set_stack(iter + 2, first_ret);
-
-                        // move return values to @iter+3
-                        self.stack.drain(self.base + iter as usize + 3 .. iret);
-                        self.fill_stack_nil(iter + 3, nvar as usize);
-
-                        // jump back to loop
-                        pc -= jmp as usize;
-
-                    } else if jmp == 0 {
-                        // skip the following Jump
-                        pc += 1;
+                    let icall = self.base + iter as usize + 3;
+                    self.stack.resize(icall, Value::Nil);
+                    self.stack.extend_from_within(icall - 3 .. icall);
+                    if let Some(step) = self.call_function(proto, iter + 3, 2+1, pc)? {
+                        return Ok(step);
                     }
                 }
 
                 // define closure
-                ByteCode::Closure(dst, inner) => {
-                    let Value::LuaFunction(inner_proto) = proto.constants[inner as usize].clone() else {
-                        panic!("must be funcproto");
+                opcode::Closure | opcode::ClosureX => {
+                    let inner = match get_op(code) {
+                        opcode::Closure => arg_bx(code),
+                        _ => extra_arg(proto, pc),
                     };
-
-                    // generate upvalues
-                    let inner_upvalues = inner_proto.upindexes.iter().map(|up| match up {
-                        &UpIndex::Upvalue(iup) => upvalues[iup].clone(),
-                        &UpIndex::Local(ilocal) => {
-                            let ilocal = self.base + ilocal;
-                            let iob = open_brokers.binary_search_by_key(&ilocal, |b|b.ilocal)
-                                .unwrap_or_else(|i| {
-                                    open_brokers.insert(i, OpenBroker::from(ilocal));
-                                    i
-                                });
-                            open_brokers[iob].broker.clone()
-                        }
-                    }).collect();
-
-                    let c = LuaClosure {
-                        upvalues: inner_upvalues,
-                        proto: inner_proto,
-                    };
-                    self.set_stack(dst, Value::LuaClosure(Rc::new(c)))
+                    self.new_closure(arg_a(code), &proto.constants[inner as usize], upvalues);
                 }
 
                 // function call
-                ByteCode::Call(func, narg_plus, want_nret) => {
-                    let nret = self.call_function(func, narg_plus);
-
-                    // move return values to @func
-                    let iret = self.stack.len() - nret;
-                    self.stack.drain(self.base+func as usize .. iret);
-
-                    // want_nret==0 means 1.want all return values or 2.want no
-                    // return values, while we do not need handle in both cases;
-                    // otherwise, means @want_nret return values are need, and
-                    // we need to fill nil if necessary.
-                    let want_nret = want_nret as usize;
-                    if nret < want_nret {
-                        self.fill_stack_nil(func, want_nret);
-                    }
-                }
-                ByteCode::CallSet(dst, func, narg_plus) => {
-                    let nret = self.call_function(func, narg_plus);
-
-                    // set first return value to @dst directly
-                    if nret == 0 {
-                        self.set_stack(dst, Value::Nil);
-                    } else {
-                        // use swap() to avoid clone()
-                        let iret = self.stack.len() - nret;
-                        self.stack.swap(self.base+dst as usize, iret);
+                opcode::Call | opcode::CallSet => {
+                    let (func, narg_plus) = match get_op(code) {
+                        opcode::Call => (arg_a(code), arg_b(code)),
+                        _ => (arg_b(code), arg_c(code)),
+                    };
+                    if let Some(step) = self.call_function(proto, func, narg_plus, pc)? {
+                        return Ok(step);
                     }
-                    self.stack.truncate(self.base + func as usize + 1);
-                }
-
-                ByteCode::TailCall(func, narg_plus) => {
-                    self.close_brokers(open_brokers);
-
-                    // clear current call-frame, and move new function entry and
-                    // arguments (self.stack[@func ..]) into current call-frame
-                    self.stack.drain(self.base-1 .. self.base+func as usize);
-
-                    return self.do_call_function(narg_plus);
                 }
 
-                ByteCode::Return(iret, nret) => {
-                    self.close_brokers(open_brokers);
-
-                    // if nret==0, return stack[iret .. ];
-                    // otherwise, return stack[iret .. iret+nret] and truncate
-                    // the stack to make sure there is no more extra temprary
-                    // values, so:
-                    // - we can return @nret only (but no need @iret) to
-                    //   indicate the return values, so we get the same
-                    //   return type with RustFunction;
-                    // - the following byte code, including Return(_,0),
-                    //   Call(_,_,0) or SetList(_,0), can get the
-                    //   #return-values by stack top.
-                    let iret = self.base + iret as usize;
-                    if nret == 0 {
-                        return self.stack.len() - iret;
-                    } else {
-                        self.stack.truncate(iret + nret as usize);
-                        return nret as usize;
-                    }
-                }
-                ByteCode::Return0 => {
-                    self.close_brokers(open_brokers);
-                    return 0;
-                }
+                // return without temporary values, see exe_fallible_code()
+                opcode::TailCall => return self.tail_call(arg_a(code), arg_b(code), *pc),
+                opcode::Return => return self.return_values(arg_a(code), arg_b(code)),
+                opcode::Return0 => return self.close_frame().map(|_| Step::Return(0)),
 
-                ByteCode::VarArgs(dst, want) => {
+                opcode::VarArgs => {
+                    let (dst, want) = (arg_a(code), arg_b(code));
                     // truncate the stack to make sure there is no more
                     // extra temprary values, so the following byte code,
                     // including Return(_,0), Call(_,_,0) or SetList(_,0),
                     // can get the #varargs by stack top.
-                    self.stack.truncate(self.base + dst as usize);
+                    self.fill_stack_nil(dst, 0);
 
+                    let varargs = &self.frames.last().unwrap().varargs;
                     let len = varargs.len();
                     let want = want as usize;
                     if want == 0 { // 0 means all
-                        self.stack.extend_from_slice(&varargs);
+                        self.stack.extend_from_slice(varargs);
                     } else if want > len {
-                        self.stack.extend_from_slice(&varargs);
+                        self.stack.extend_from_slice(varargs);
                         self.fill_stack_nil(dst, want);
                     } else {
                         self.stack.extend_from_slice(&varargs[..want]);
@@ -468,458 +592,1339 @@ impl ExeState {
                 }
 
                 // unops
-                ByteCode::Neg(dst, src) => {
-                    let value = match &self.get_stack(src) {
-                        Value::Integer(i) => Value::Integer(-i),
-                        Value::Float(f) => Value::Float(-f),
-                        _ => panic!("invalid -"),
-                    };
-                    self.set_stack(dst, value);
-                }
-                ByteCode::Not(dst, src) => {
-                    let value = match &self.get_stack(src) {
+                opcode::Not => {
+                    let (dst, src) = (arg_a(code), arg_b(code));
+                    let value = match self.get_stack(src) {
                         Value::Nil => Value::Boolean(true),
                         Value::Boolean(b) => Value::Boolean(!b),
                         _ => Value::Boolean(false),
                     };
                     self.set_stack(dst, value);
                 }
-                ByteCode::BitNot(dst, src) => {
-                    let value = match &self.get_stack(src) {
-                        Value::Integer(i) => Value::Integer(!i),
-                        _ => panic!("invalid ~"),
-                    };
-                    self.set_stack(dst, value);
-                }
-                ByteCode::Len(dst, src) => {
-                    let value = match &self.get_stack(src) {
-                        Value::ShortStr(len, _) => Value::Integer(*len as i64),
-                        Value::MidStr(s) => Value::Integer(s.0 as i64),
-                        Value::LongStr(s) => Value::Integer(s.len() as i64),
-                        Value::Table(t) => Value::Integer(t.borrow().array.len() as i64),
-                        _ => panic!("invalid -"),
-                    };
-                    self.set_stack(dst, value);
-                }
-
-                // binops
-                ByteCode::Add(dst, a, b) => {
-                    let r = exe_binop(&self.get_stack(a), &self.get_stack(b), |a,b|a+b, |a,b|a+b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::AddConst(dst, a, b) => {
-                    let r = exe_binop(&self.get_stack(a), &proto.constants[b as usize], |a,b|a+b, |a,b|a+b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::AddInt(dst, a, i) => {
-                    let r = exe_binop_int(&self.get_stack(a), i, |a,b|a+b, |a,b|a+b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::Sub(dst, a, b) => {
-                    let r = exe_binop(&self.get_stack(a), &self.get_stack(b), |a,b|a-b, |a,b|a-b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::SubConst(dst, a, b) => {
-                    let r = exe_binop(&self.get_stack(a), &proto.constants[b as usize], |a,b|a-b, |a,b|a-b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::SubInt(dst, a, i) => {
-                    let r = exe_binop_int(&self.get_stack(a), i, |a,b|a-b, |a,b|a-b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::Mul(dst, a, b) => {
-                    let r = exe_binop(&self.get_stack(a), &self.get_stack(b), |a,b|a*b, |a,b|a*b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::MulConst(dst, a, b) => {
-                    let r = exe_binop(&self.get_stack(a), &proto.constants[b as usize], |a,b|a*b, |a,b|a*b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::MulInt(dst, a, i) => {
-                    let r = exe_binop_int(&self.get_stack(a), i, |a,b|a*b, |a,b|a*b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::Mod(dst, a, b) => {
-                    let r = exe_binop(&self.get_stack(a), &self.get_stack(b), |a,b|a%b, |a,b|a%b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::ModConst(dst, a, b) => {
-                    let r = exe_binop(&self.get_stack(a), &proto.constants[b as usize], |a,b|a%b, |a,b|a%b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::ModInt(dst, a, i) => {
-                    let r = exe_binop_int(&self.get_stack(a), i, |a,b|a%b, |a,b|a%b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::Idiv(dst, a, b) => {
-                    let r = exe_binop(&self.get_stack(a), &self.get_stack(b), |a,b|a/b, |a,b|a/b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::IdivConst(dst, a, b) => {
-                    let r = exe_binop(&self.get_stack(a), &proto.constants[b as usize], |a,b|a/b, |a,b|a/b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::IdivInt(dst, a, i) => {
-                    let r = exe_binop_int(&self.get_stack(a), i, |a,b|a/b, |a,b|a/b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::Div(dst, a, b) => {
-                    let r = exe_binop_f(&self.get_stack(a), &self.get_stack(b), |a,b|a/b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::DivConst(dst, a, b) => {
-                    let r = exe_binop_f(&self.get_stack(a), &proto.constants[b as usize], |a,b|a/b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::DivInt(dst, a, i) => {
-                    let r = exe_binop_int_f(&self.get_stack(a), i, |a,b|a/b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::Pow(dst, a, b) => {
-                    let r = exe_binop_f(&self.get_stack(a), &self.get_stack(b), |a,b|a.powf(b));
-                    self.set_stack(dst, r);
-                }
-                ByteCode::PowConst(dst, a, b) => {
-                    let r = exe_binop_f(&self.get_stack(a), &proto.constants[b as usize], |a,b|a.powf(b));
-                    self.set_stack(dst, r);
-                }
-                ByteCode::PowInt(dst, a, i) => {
-                    let r = exe_binop_int_f(&self.get_stack(a), i, |a,b|a.powf(b));
-                    self.set_stack(dst, r);
-                }
-                ByteCode::BitAnd(dst, a, b) => {
-                    let r = exe_binop_i(&self.get_stack(a), &self.get_stack(b), |a,b|a&b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::BitAndConst(dst, a, b) => {
-                    let r = exe_binop_i(&self.get_stack(a), &proto.constants[b as usize], |a,b|a&b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::BitAndInt(dst, a, i) => {
-                    let r = exe_binop_int_i(&self.get_stack(a), i, |a,b|a&b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::BitOr(dst, a, b) => {
-                    let r = exe_binop_i(&self.get_stack(a), &self.get_stack(b), |a,b|a|b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::BitOrConst(dst, a, b) => {
-                    let r = exe_binop_i(&self.get_stack(a), &proto.constants[b as usize], |a,b|a|b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::BitOrInt(dst, a, i) => {
-                    let r = exe_binop_int_i(&self.get_stack(a), i, |a,b|a|b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::BitXor(dst, a, b) => {
-                    let r = exe_binop_i(&self.get_stack(a), &self.get_stack(b), |a,b|a^b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::BitXorConst(dst, a, b) => {
-                    let r = exe_binop_i(&self.get_stack(a), &proto.constants[b as usize], |a,b|a^b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::BitXorInt(dst, a, i) => {
-                    let r = exe_binop_int_i(&self.get_stack(a), i, |a,b|a^b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::ShiftL(dst, a, b) => {
-                    let r = exe_binop_i(&self.get_stack(a), &self.get_stack(b), |a,b|a<<b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::ShiftLConst(dst, a, b) => {
-                    let r = exe_binop_i(&self.get_stack(a), &proto.constants[b as usize], |a,b|a<<b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::ShiftLInt(dst, a, i) => {
-                    let r = exe_binop_int_i(&self.get_stack(a), i, |a,b|a<<b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::ShiftR(dst, a, b) => {
-                    let r = exe_binop_i(&self.get_stack(a), &self.get_stack(b), |a,b|a>>b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::ShiftRConst(dst, a, b) => {
-                    let r = exe_binop_i(&self.get_stack(a), &proto.constants[b as usize], |a,b|a>>b);
-                    self.set_stack(dst, r);
-                }
-                ByteCode::ShiftRInt(dst, a, i) => {
-                    let r = exe_binop_int_i(&self.get_stack(a), i, |a,b|a>>b);
-                    self.set_stack(dst, r);
-                }
-
-                ByteCode::Equal(a, b, r) => {
-                    if (self.get_stack(a) == self.get_stack(b)) == r {
-                        pc += 1;
-                    }
-                }
-                ByteCode::EqualConst(a, b, r) => {
-                    if (self.get_stack(a) == &proto.constants[b as usize]) == r {
-                        pc += 1;
-                    }
-                }
-                ByteCode::EqualInt(a, i, r) => {
-                    if let &Value::Integer(ii) = self.get_stack(a) {
-                        if (ii == i as i64) == r {
-                            pc += 1;
-                        }
-                    }
-                }
-                ByteCode::NotEq(a, b, r) => {
-                    if (self.get_stack(a) != self.get_stack(b)) == r {
-                        pc += 1;
-                    }
-                }
-                ByteCode::NotEqConst(a, b, r) => {
-                    if (self.get_stack(a) != &proto.constants[b as usize]) == r {
-                        pc += 1;
-                    }
-                }
-                ByteCode::NotEqInt(a, i, r) => {
-                    if let &Value::Integer(ii) = self.get_stack(a) {
-                        if (ii != i as i64) == r {
-                            pc += 1;
-                        }
-                    }
-                }
-                ByteCode::LesEq(a, b, r) => {
-                    let cmp = self.get_stack(a).partial_cmp(self.get_stack(b)).unwrap();
-                    if !matches!(cmp, Ordering::Greater) == r {
-                        pc += 1;
-                    }
-                }
-                ByteCode::LesEqConst(a, b, r) => {
-                    let cmp = self.get_stack(a).partial_cmp(&proto.constants[b as usize]).unwrap();
-                    if !matches!(cmp, Ordering::Greater) == r {
-                        pc += 1;
-                    }
-                }
-                ByteCode::LesEqInt(a, i, r) => {
-                    let a = match self.get_stack(a) {
-                        &Value::Integer(i) => i,
-                        &Value::Float(f) => f as i64,
-                        _ => panic!("invalid compare"),
-                    };
-                    if (a <= i as i64) == r {
-                        pc += 1;
-                    }
-                }
-                ByteCode::GreEq(a, b, r) => {
-                    let cmp = self.get_stack(a).partial_cmp(self.get_stack(b)).unwrap();
-                    if !matches!(cmp, Ordering::Less) == r {
-                        pc += 1;
-                    }
-                }
-                ByteCode::GreEqConst(a, b, r) => {
-                    let cmp = self.get_stack(a).partial_cmp(&proto.constants[b as usize]).unwrap();
-                    if !matches!(cmp, Ordering::Less) == r {
-                        pc += 1;
-                    }
-                }
-                ByteCode::GreEqInt(a, i, r) => {
-                    let a = match self.get_stack(a) {
-                        &Value::Integer(i) => i,
-                        &Value::Float(f) => f as i64,
-                        _ => panic!("invalid compare"),
-                    };
-                    if (a >= i as i64) == r {
-                        pc += 1;
-                    }
-                }
-                ByteCode::Less(a, b, r) => {
-                    let cmp = self.get_stack(a).partial_cmp(self.get_stack(b)).unwrap();
-                    if matches!(cmp, Ordering::Less) == r {
-                        pc += 1;
-                    }
-                }
-                ByteCode::LessConst(a, b, r) => {
-                    let cmp = self.get_stack(a).partial_cmp(&proto.constants[b as usize]).unwrap();
-                    if matches!(cmp, Ordering::Less) == r {
-                        pc += 1;
-                    }
-                }
-                ByteCode::LessInt(a, i, r) => {
-                    let a = match self.get_stack(a) {
-                        &Value::Integer(i) => i,
-                        &Value::Float(f) => f as i64,
-                        _ => panic!("invalid compare"),
-                    };
-                    if (a < i as i64) == r {
-                        pc += 1;
-                    }
-                }
-                ByteCode::Greater(a, b, r) => {
-                    let cmp = self.get_stack(a).partial_cmp(self.get_stack(b)).unwrap();
-                    if matches!(cmp, Ordering::Greater) == r {
-                        pc += 1;
-                    }
-                }
-                ByteCode::GreaterConst(a, b, r) => {
-                    let cmp = self.get_stack(a).partial_cmp(&proto.constants[b as usize]).unwrap();
-                    if matches!(cmp, Ordering::Greater) == r {
-                        pc += 1;
-                    }
-                }
-                ByteCode::GreaterInt(a, i, r) => {
-                    let a = match self.get_stack(a) {
-                        &Value::Integer(i) => i,
-                        &Value::Float(f) => f as i64,
-                        _ => panic!("invalid compare"),
-                    };
-                    if (a > i as i64) == r {
-                        pc += 1;
-                    }
-                }
 
-                ByteCode::SetFalseSkip(dst) => {
+                opcode::SetFalseSkip => {
+                    let dst = arg_a(code);
                     self.set_stack(dst, Value::Boolean(false));
-                    pc += 1;
+                    *pc += 1;
                 }
 
-                ByteCode::Concat(dst, a, b) => {
-                    let r = self.get_stack(a).concat(self.get_stack(b));
-                    self.set_stack(dst, r);
-                }
+                // the others may raise errors or call metamethods
+                _ => self.exe_fallible_code(code, proto, upvalues, pc)?,
+
             }
 
-            pc += 1;
+            // wrap, since a loop at the function start jumps back to -1
+            *pc = pc.wrapping_add(1);
         }
     }
 
-    fn get_stack(&self, dst: u8) -> &Value {
-        &self.stack[self.base + dst as usize]
-    }
-    fn get_stack_mut(&mut self, dst: u8) -> &mut Value {
-        &mut self.stack[self.base + dst as usize]
-    }
-    fn set_stack(&mut self, dst: u8, v: Value) {
-        set_vec(&mut self.stack, self.base + dst as usize, v);
-    }
-    fn fill_stack_nil(&mut self, base: u8, to: usize) {
-        self.stack.resize(self.base + base as usize + to, Value::Nil);
-    }
+    fn tail_call(&mut self, func: u8, narg_plus: u8, pc: usize) -> Result<Step> {
+        let frame = self.frames.last_mut().unwrap();
+        frame.pc = pc;
+        close_brokers(&self.stack, frame.open_brokers.drain(..));
 
-    // call function
-    // return the number of return values which are at the stack end
-    fn call_function(&mut self, func: u8, narg_plus: u8) -> usize {
-        self.base += func as usize + 1; // get into new world
-        let nret = self.do_call_function(narg_plus);
-        self.base -= func as usize + 1; // come back
-        nret
+        // clear current call-frame, and move new function entry and
+        // arguments (self.stack[@func ..]) into current call-frame
+        let ifunc = self.base + func as usize;
+        if ifunc > self.stack.len() {
+            self.fill_stack_nil(func, 0);
+        }
+        self.stack.drain(self.base-1 .. ifunc);
+
+        match self.precall(narg_plus)? {
+            Some(nret) if self.yielding => Ok(Step::Yield(nret)),
+            Some(nret) => Ok(Step::Return(nret)),
+            None => {
+                // replace current frame by the new one
+                let iframe = self.frames.len() - 2;
+                self.frames.swap_remove(iframe);
+                Ok(Step::Call)
+            }
+        }
     }
 
-    // Before calling, the function entry is at @self.base-1, and the
+    fn return_values(&mut self, iret: u8, nret: u8) -> Result<Step> {
+        self.close_frame()?;
+
+        // if nret==0, return stack[iret .. ];
+        // otherwise, return stack[iret .. iret+nret] and truncate
+        // the stack to make sure there is no more extra temprary
+        // values, so:
+        // - we can return @nret only (but no need @iret) to
+        //   indicate the return values, so we get the same
+        //   return type with RustFunction;
+        // - the following byte code, including Return(_,0),
+        //   Call(_,_,0) or SetList(_,0), can get the
+        //   #return-values by stack top.
+        let iret = self.base + iret as usize;
+        if nret == 0 {
+            Ok(Step::Return(self.stack.len().saturating_sub(iret)))
+        } else {
+            self.stack.resize(iret + nret as usize, Value::Nil);
+            Ok(Step::Return(nret as usize))
+        }
+    }
+
+    // close all upvalues and to-be-closed variables of the top frame
+    fn close_frame(&mut self) -> Result<()> {
+        let open_brokers = &mut self.frames.last_mut().unwrap().open_brokers;
+        close_brokers(&self.stack, open_brokers.drain(..));
+        self.close_tbc(0, &Value::Nil)
+    }
+
+    // Execute the byte codes which may raise errors or call metamethods,
+    // but never stop the frame. Each kind is executed in a separate
+    // function, and their results share the return slot here, to keep
+    // the native stack frames small, since they are nested by calls from
+    // Rust, e.g. metamethods. See MAX_RUST_CALLS.
+    fn exe_fallible_code(&mut self, code: Instruction, proto: &FuncProto, upvalues: &[GcCell<Upvalue>], pc: &mut usize) -> Result<()> {
+        match get_op(code) {
+            opcode::Close => self.close_local(arg_a(code)),
+            opcode::Tbc => self.mark_tbc(arg_a(code), proto, *pc),
+
+            opcode::SetTable | opcode::SetField | opcode::SetInt |
+            opcode::SetTableConst | opcode::SetFieldConst | opcode::SetIntConst |
+            opcode::SetUpField | opcode::SetUpFieldConst |
+            opcode::GetTable | opcode::GetField | opcode::GetInt |
+            opcode::GetFieldSelf | opcode::GetUpField =>
+                self.exe_table_code(code, proto, upvalues),
+
+            opcode::ForPrepare => self.for_prepare(arg_a(code), arg_bx(code), pc),
+            opcode::ForLoop => self.for_loop(arg_a(code), arg_bx(code), pc),
+
+            opcode::Neg | opcode::BitNot | opcode::Len =>
+                self.exe_unop_code(code),
+
+            opcode::Add | opcode::AddConst | opcode::AddInt |
+            opcode::Sub | opcode::SubConst | opcode::SubInt |
+            opcode::Mul | opcode::MulConst | opcode::MulInt |
+            opcode::Mod | opcode::ModConst | opcode::ModInt |
+            opcode::Idiv | opcode::IdivConst | opcode::IdivInt |
+            opcode::Div | opcode::DivConst | opcode::DivInt |
+            opcode::Pow | opcode::PowConst | opcode::PowInt |
+            opcode::BitAnd | opcode::BitAndConst | opcode::BitAndInt |
+            opcode::BitOr | opcode::BitOrConst | opcode::BitOrInt |
+            opcode::BitXor | opcode::BitXorConst | opcode::BitXorInt |
+            opcode::ShiftL | opcode::ShiftLConst | opcode::ShiftLInt |
+            opcode::ShiftR | opcode::ShiftRConst | opcode::ShiftRInt |
+            opcode::Concat =>
+                self.exe_binop_code(code, proto),
+
+            opcode::Equal | opcode::EqualConst | opcode::EqualInt |
+            opcode::NotEq | opcode::NotEqConst | opcode::NotEqInt |
+            opcode::LesEq | opcode::LesEqConst | opcode::LesEqInt |
+            opcode::GreEq | opcode::GreEqConst | opcode::GreEqInt |
+            opcode::Less | opcode::LessConst | opcode::LessInt |
+            opcode::Greater | opcode::GreaterConst | opcode::GreaterInt =>
+                self.exe_compare_code(code, proto, pc),
+
+            // invalid opcodes are rejected by the parser and undump()
+            op => unreachable!("opcode {op} is executed by do_execute()"),
+        }
+    }
+
+    // close the upvalues and to-be-closed variables at or above @ilocal
+    fn close_local(&mut self, ilocal: u8) -> Result<()> {
+        let ilocal = self.base + ilocal as usize;
+        let open_brokers = &mut self.frames.last_mut().unwrap().open_brokers;
+        let from = open_brokers.binary_search_by_key(&ilocal, |b| b.ilocal)
+            .unwrap_or_else(|i| i);
+        close_brokers(&self.stack, open_brokers.drain(from..));
+        self.close_tbc(ilocal, &Value::Nil)
+    }
+
+    // mark the local variable @ilocal to be closed
+    fn mark_tbc(&mut self, ilocal: u8, proto: &FuncProto, pc: usize) -> Result<()> {
+        // nil and false are ignored
+        let v = self.get_stack(ilocal);
+        if !matches!(v, Value::Nil | Value::Boolean(false)) {
+            if self.get_metamethod(v, "__close") == Value::Nil {
+                let name = proto.local_name(ilocal as usize, pc).unwrap_or("?");
+                return Err(Error::runtime(format!("variable '{name}' got a non-closable value")));
+            }
+            let ilocal = self.base + ilocal as usize;
+            self.frames.last_mut().unwrap().tbc.push(ilocal);
+        }
+        Ok(())
+    }
+
+    // Each arm only picks the operands and the operation, and the
+    // metamethod is called at one place, to keep the native stack frame
+    // small, since it is nested in calls of metamethods.
+    fn exe_binop_code(&mut self, code: Instruction, proto: &FuncProto) -> Result<()> {
+        let int;
+        let (dst, a, b) = (arg_a(code), arg_b(code), arg_c(code));
+        let (v1, v2) = match get_op(code) {
+            opcode::Add | opcode::Sub | opcode::Mul | opcode::Mod |
+            opcode::Idiv | opcode::Div | opcode::Pow | opcode::BitAnd |
+            opcode::BitOr | opcode::BitXor | opcode::ShiftL | opcode::ShiftR |
+            opcode::Concat =>
+                (self.get_stack(a), self.get_stack(b)),
+            opcode::AddConst | opcode::SubConst | opcode::MulConst | opcode::ModConst |
+            opcode::IdivConst | opcode::DivConst | opcode::PowConst | opcode::BitAndConst |
+            opcode::BitOrConst | opcode::BitXorConst | opcode::ShiftLConst | opcode::ShiftRConst =>
+                (self.get_stack(a), &proto.constants[b as usize]),
+            opcode::AddInt | opcode::SubInt | opcode::MulInt | opcode::ModInt |
+            opcode::IdivInt | opcode::DivInt | opcode::PowInt | opcode::BitAndInt |
+            opcode::BitOrInt | opcode::BitXorInt | opcode::ShiftLInt | opcode::ShiftRInt => {
+                int = Value::Integer(b as i64);
+                (self.get_stack(a), &int)
+            }
+            _ => unreachable!("not binop"),
+        };
+
+        let (event, op): (_, fn(&Value, &Value) -> Result<Value>) = match get_op(code) {
+            opcode::Add | opcode::AddConst | opcode::AddInt =>
+                ("__add", |v1, v2| exe_binop(v1, v2, i64::wrapping_add, |a,b|a+b)),
+            opcode::Sub | opcode::SubConst | opcode::SubInt =>
+                ("__sub", |v1, v2| exe_binop(v1, v2, i64::wrapping_sub, |a,b|a-b)),
+            opcode::Mul | opcode::MulConst | opcode::MulInt =>
+                ("__mul", |v1, v2| exe_binop(v1, v2, i64::wrapping_mul, |a,b|a*b)),
+            opcode::Mod | opcode::ModConst | opcode::ModInt =>
+                ("__mod", |v1, v2| exe_binop_div(v1, v2, "%", int_mod, float_mod)),
+            opcode::Idiv | opcode::IdivConst | opcode::IdivInt =>
+                ("__idiv", |v1, v2| exe_binop_div(v1, v2, "//", int_idiv, float_idiv)),
+            opcode::Div | opcode::DivConst | opcode::DivInt =>
+                ("__div", |v1, v2| exe_binop_f(v1, v2, |a,b|a/b)),
+            opcode::Pow | opcode::PowConst | opcode::PowInt =>
+                ("__pow", |v1, v2| exe_binop_f(v1, v2, |a,b|a.powf(b))),
+            opcode::BitAnd | opcode::BitAndConst | opcode::BitAndInt =>
+                ("__band", |v1, v2| exe_binop_i(v1, v2, |a,b|a&b)),
+            opcode::BitOr | opcode::BitOrConst | opcode::BitOrInt =>
+                ("__bor", |v1, v2| exe_binop_i(v1, v2, |a,b|a|b)),
+            opcode::BitXor | opcode::BitXorConst | opcode::BitXorInt =>
+                ("__bxor", |v1, v2| exe_binop_i(v1, v2, |a,b|a^b)),
+            opcode::ShiftL | opcode::ShiftLConst | opcode::ShiftLInt =>
+                ("__shl", |v1, v2| exe_binop_i(v1, v2, shift_left)),
+            opcode::ShiftR | opcode::ShiftRConst | opcode::ShiftRInt =>
+                ("__shr", |v1, v2| exe_binop_i(v1, v2, |a,b|shift_left(a, b.wrapping_neg()))),
+            opcode::Concat => ("__concat", Value::concat),
+            _ => unreachable!("not binop"),
+        };
+
+        let r = match op(v1, v2) {
+            Ok(r) => r,
+            Err(err) => {
+                let (v1, v2) = (v1.clone(), v2.clone());
+                self.binop_meta(event, v1, v2, err)?
+            }
+        };
+        self.set_stack(dst, r);
+        Ok(())
+    }
+
+    fn exe_table_code(&mut self, code: Instruction, proto: &FuncProto, upvalues: &[GcCell<Upvalue>]) -> Result<()> {
+        let upvalue = |t: u8| upvalues[t as usize].borrow().get(&self.stack).clone();
+        let constant = |k: u8| proto.constants[k as usize].clone();
+        let (a, b, c) = (arg_a(code), arg_b(code), arg_c(code));
+        match get_op(code) {
+            opcode::GetTable | opcode::GetField | opcode::GetInt |
+            opcode::GetFieldSelf | opcode::GetUpField => {
+                let (table, key) = match get_op(code) {
+                    opcode::GetTable => (self.get_stack(b).clone(), self.get_stack(c).clone()),
+                    opcode::GetInt => (self.get_stack(b).clone(), Value::Integer(c as i64)),
+                    opcode::GetUpField => (upvalue(b), constant(c)),
+                    _ => (self.get_stack(b).clone(), constant(c)), // GetField, GetFieldSelf
+                };
+                let value = self.index(table.clone(), &key)?;
+                self.set_stack(a, value);
+                if get_op(code) == opcode::GetFieldSelf {
+                    self.set_stack(a+1, table);
+                }
+            }
+            op => {
+                let (table, key, value) = match op {
+                    opcode::SetTable => (self.get_stack(a).clone(), self.get_stack(b).clone(), self.get_stack(c).clone()),
+                    opcode::SetField => (self.get_stack(a).clone(), constant(b), self.get_stack(c).clone()),
+                    opcode::SetInt => (self.get_stack(a).clone(), Value::Integer(b as i64), self.get_stack(c).clone()),
+                    opcode::SetTableConst => (self.get_stack(a).clone(), self.get_stack(b).clone(), constant(c)),
+                    opcode::SetFieldConst => (self.get_stack(a).clone(), constant(b), constant(c)),
+                    opcode::SetIntConst => (self.get_stack(a).clone(), Value::Integer(b as i64), constant(c)),
+                    opcode::SetUpField => (upvalue(a), constant(b), self.get_stack(c).clone()),
+                    opcode::SetUpFieldConst => (upvalue(a), constant(b), constant(c)),
+                    _ => unreachable!("not table code"),
+                };
+                self.new_index(table, key, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn exe_unop_code(&mut self, code: Instruction) -> Result<()> {
+        let (dst, src) = (arg_a(code), arg_b(code));
+        let v = self.get_stack(src);
+
+        // strings are converted to numbers
+        let (event, r) = match get_op(code) {
+            opcode::Neg => ("__unm", match v.to_number() {
+                Some(Value::Integer(i)) => Ok(Value::Integer(i.wrapping_neg())),
+                Some(Value::Float(f)) => Ok(Value::Float(-f)),
+                _ => Err(arith_error(v)),
+            }),
+            opcode::BitNot => ("__bnot", match v.to_number() {
+                Some(Value::Integer(i)) => Ok(Value::Integer(!i)),
+                Some(Value::Float(f)) => float_to_int(f).map(|i| Value::Integer(!i)),
+                _ => Err(bitwise_error(v)),
+            }),
+            _ => {
+                let v = v.clone();
+                let value = self.len(&v)?;
+                self.set_stack(dst, value);
+                return Ok(());
+            }
+        };
+
+        let value = match r {
+            Ok(value) => value,
+            // "number has no integer representation" is not handled
+            Err(err) if v.to_number().is_some() => return Err(err),
+            Err(err) => {
+                let v = v.clone();
+                self.binop_meta(event, v.clone(), v, err)?
+            }
+        };
+        self.set_stack(dst, value);
+        Ok(())
+    }
+
+    fn for_prepare(&mut self, dst: u8, jmp: u32, pc: &mut usize) -> Result<()> {
+        // clear into 2 cases: integer and float
+        // stack: i, limit, step
+        if let (&Value::Integer(mut i), &Value::Integer(step)) =
+                (self.get_stack(dst), self.get_stack(dst + 2)) {
+            // integer case
+            if step == 0 {
+                return Err(Error::runtime("'for' step is zero"));
+            }
+            let limit = match self.get_stack(dst + 1).to_number() {
+                Some(Value::Integer(limit)) => limit,
+                Some(Value::Float(limit)) => for_int_limit(limit, step>0, &mut i),
+                _ => return Err(Error::type_error("'for' limit must be a number")),
+            };
+            self.set_stack(dst+1, Value::Integer(limit));
+            if !for_check(i, limit, step>0) {
+                *pc += jmp as usize;
+            }
+        } else {
+            // float case
+            let i = self.make_float(dst, "initial")?;
+            let limit = self.make_float(dst+1, "limit")?;
+            let step = self.make_float(dst+2, "step")?;
+            if step == 0.0 {
+                return Err(Error::runtime("'for' step is zero"));
+            }
+            if !for_check(i, limit, step>0.0) {
+                *pc += jmp as usize;
+            }
+        }
+        Ok(())
+    }
+
+    fn for_loop(&mut self, dst: u8, jmp: u32, pc: &mut usize) -> Result<()> {
+        // stack: i, limit, step
+        match (self.get_stack(dst + 1), self.get_stack(dst + 2)) {
+            (&Value::Integer(limit), &Value::Integer(step)) => {
+                let Value::Integer(i) = self.get_stack_mut(dst) else {
+                    return Err(Error::type_error("'for' control variable changed"));
+                };
+                // overflow means the limit is passed
+                if let Some(next) = i.checked_add(step).filter(|&n| for_check(n, limit, step>0)) {
+                    *i = next;
+                    *pc -= jmp as usize;
+                }
+            }
+            (&Value::Float(limit), &Value::Float(step)) => {
+                let Value::Float(i) = self.get_stack_mut(dst) else {
+                    return Err(Error::type_error("'for' control variable changed"));
+                };
+                *i += step;
+                if for_check(*i, limit, step>0.0) {
+                    *pc -= jmp as usize;
+                }
+            }
+            _ => return Err(Error::runtime("'for' loop is not prepared")),
+        }
+        Ok(())
+    }
+
+    // new closure of prototype @inner into @dst
+    fn new_closure(&mut self, dst: u8, inner: &Value, upvalues: &[GcCell<Upvalue>]) {
+        let Value::LuaFunction(inner_proto) = inner.clone() else {
+            unreachable!("Closure of non-function constant");
+        };
+
+        // generate upvalues
+        let inner_upvalues = inner_proto.upindexes.iter().map(|up| match *up {
+            UpIndex::Upvalue(iup) => upvalues[iup].clone(),
+            UpIndex::Local(ilocal) => {
+                let ilocal = self.base + ilocal;
+                let open_brokers = &mut self.frames.last_mut().unwrap().open_brokers;
+                let iob = open_brokers.binary_search_by_key(&ilocal, |b|b.ilocal)
+                    .unwrap_or_else(|i| {
+                        open_brokers.insert(i, OpenBroker::new(&mut self.heap, ilocal));
+                        i
+                    });
+                open_brokers[iob].broker.clone()
+            }
+        }).collect();
+
+        let c = LuaClosure {
+            upvalues: inner_upvalues,
+            proto: inner_proto,
+        };
+        let c = self.heap.alloc(c);
+        self.set_stack(dst, Value::LuaClosure(c))
+    }
+
+    // skip the following Jump if the test fails
+    fn exe_compare_code(&mut self, code: Instruction, proto: &FuncProto, pc: &mut usize) -> Result<()> {
+        let int;
+        let (a, b, r) = (arg_a(code), arg_b(code), arg_k(code));
+        let (v1, v2) = match get_op(code) {
+            opcode::Equal | opcode::NotEq | opcode::LesEq |
+            opcode::GreEq | opcode::Less | opcode::Greater =>
+                (self.get_stack(a), self.get_stack(b)),
+            opcode::EqualConst | opcode::NotEqConst | opcode::LesEqConst |
+            opcode::GreEqConst | opcode::LessConst | opcode::GreaterConst =>
+                (self.get_stack(a), &proto.constants[b as usize]),
+            opcode::EqualInt | opcode::NotEqInt | opcode::LesEqInt |
+            opcode::GreEqInt | opcode::LessInt | opcode::GreaterInt => {
+                int = Value::Integer(b as i64);
+                (self.get_stack(a), &int)
+            }
+            _ => unreachable!("not comparison"),
+        };
+
+        let test = match get_op(code) {
+            op @ (opcode::Equal | opcode::EqualConst | opcode::EqualInt |
+            opcode::NotEq | opcode::NotEqConst | opcode::NotEqInt) => {
+                let eq = match (v1, v2) {
+                    (Value::Table(_), Value::Table(_)) if v1 != v2 => {
+                        let (v1, v2) = (v1.clone(), v2.clone());
+                        self.equal_meta(v1, v2)?
+                    }
+                    _ => v1 == v2,
+                };
+                eq == matches!(op, opcode::Equal | opcode::EqualConst | opcode::EqualInt)
+            }
+            op => {
+                // a > b and a >= b are taken as b < a and b <= a by metamethods
+                let (event, swap, test): (_, _, fn(Ordering) -> bool) = match op {
+                    opcode::LesEq | opcode::LesEqConst | opcode::LesEqInt =>
+                        ("__le", false, Ordering::is_le),
+                    opcode::GreEq | opcode::GreEqConst | opcode::GreEqInt =>
+                        ("__le", true, Ordering::is_ge),
+                    opcode::Less | opcode::LessConst | opcode::LessInt =>
+                        ("__lt", false, Ordering::is_lt),
+                    opcode::Greater | opcode::GreaterConst | opcode::GreaterInt =>
+                        ("__lt", true, Ordering::is_gt),
+                    _ => unreachable!("not comparison"),
+                };
+                match compare(v1, v2) {
+                    Ok(cmp) => cmp.is_some_and(test),
+                    Err(err) => {
+                        let (v1, v2) = if swap { (v2.clone(), v1.clone()) } else { (v1.clone(), v2.clone()) };
+                        self.compare_meta(event, v1, v2, err)?
+                    }
+                }
+            }
+        };
+        if test == r {
+            *pc += 1;
+        }
+        Ok(())
+    }
+
+    // registers above the stack top are taken as nil
+    fn get_stack(&self, dst: u8) -> &Value {
+        self.stack.get(self.base + dst as usize).unwrap_or(&Value::Nil)
+    }
+    fn get_stack_mut(&mut self, dst: u8) -> &mut Value {
+        &mut self.stack[self.base + dst as usize]
+    }
+    fn set_stack(&mut self, dst: u8, v: Value) {
+        set_vec(&mut self.stack, self.base + dst as usize, v);
+    }
+    fn fill_stack_nil(&mut self, base: u8, to: usize) {
+        self.stack.resize(self.base + base as usize + to, Value::Nil);
+    }
+
+    // call function at @func in the current frame, and handle the return
+    // values if it finishes. Return the step if the frame stops
+    fn call_function(&mut self, proto: &FuncProto, func: u8, narg_plus: u8, pc: &mut usize) -> Result<Option<Step>> {
+        // the new frame returns to @pc
+        self.frames.last_mut().unwrap().pc = *pc;
+
+        let base = self.base;
+        self.base += func as usize + 1; // get into new world
+        match self.precall(narg_plus) {
+            Ok(Some(nret)) => {
+                self.base = base; // come back
+                if self.yielding {
+                    Ok(Some(Step::Yield(nret)))
+                } else {
+                    Ok(self.finish_call(proto, nret, pc))
+                }
+            }
+            Ok(None) => Ok(Some(Step::Call)),
+            Err(err) => {
+                self.base = base;
+                Err(err)
+            }
+        }
+    }
+
+    // handle the @nret return values of the calling byte code at @pc,
+    // which are at the stack top
+    fn finish_call(&mut self, proto: &FuncProto, nret: usize, pc: &mut usize) -> Option<Step> {
+        let code = proto.byte_codes[*pc];
+        match get_op(code) {
+            opcode::Call => {
+                let (func, want_nret) = (arg_a(code), arg_c(code));
+                // move return values to @func
+                let iret = self.stack.len() - nret;
+                self.stack.drain(self.base+func as usize .. iret);
+
+                // want_nret==0 means 1.want all return values or 2.want no
+                // return values, while we do not need handle in both cases;
+                // otherwise, means @want_nret return values are need, and
+                // we need to fill nil if necessary.
+                let want_nret = want_nret as usize;
+                if nret < want_nret {
+                    self.fill_stack_nil(func, want_nret);
+                }
+            }
+            opcode::CallSet => {
+                let (dst, func) = (arg_a(code), arg_b(code));
+                // set first return value to @dst directly
+                if nret == 0 {
+                    self.set_stack(dst, Value::Nil);
+                } else {
+                    // use replace() to avoid clone()
+                    let iret = self.stack.len() - nret;
+                    let v = mem::replace(&mut self.stack[iret], Value::Nil);
+                    self.set_stack(dst, v);
+                }
+                self.stack.truncate(self.base + func as usize + 1);
+            }
+            opcode::ForCallLoop => {
+                let (iter, nvar, jmp) = (arg_a(code), arg_b(code), arg_c(code));
+                let iret = self.stack.len() - nret;
+                if nret > 0 && self.stack[iret] != Value::Nil {
+                    // continue the loop
+                    // duplicate the first return value as ctrl-var,
+                    // so it could be changed during loop.
+                    let first_ret = self.stack[iret].clone();
+                    self.set_stack(iter + 2, first_ret);
+
+                    // move return values to @iter+3
+                    self.stack.drain(self.base + iter as usize + 3 .. iret);
+                    self.fill_stack_nil(iter + 3, nvar as usize);
+
+                    // jump back to loop
+                    *pc -= jmp as usize;
+
+                } else if jmp == 0 {
+                    // skip the following Jump
+                    *pc += 1;
+                }
+            }
+            // a Rust function called by tail call yielded, and now
+            // its frame returns the resume values
+            opcode::TailCall => return Some(Step::Return(nret)),
+            _ => unreachable!("not a call"),
+        }
+        None
+    }
+
+    // Before calling, the function entry is at @self.base-1, and the
     // arguments follows with:
     // - narg_plus==0 means variable arguments, and all stack values
     //   following the function entry are arguments;
     // - otherwise means (narg_plus-1) fixed arguments, and there may
     //   be temprary values following which need be truncated sometime.
     //
-    // After calling, the return values lay at the top of stack.
-    //
-    // Return the number of return values.
-    fn do_call_function(&mut self, narg_plus: u8) -> usize {
+    // A Rust function is called directly, and the number of return
+    // values, which lay at the top of stack, is returned. A new frame
+    // is pushed for Lua function, and None is returned.
+    fn precall(&mut self, narg_plus: u8) -> Result<Option<usize>> {
         // drop potential temprary stack usage, for get_top()
         if narg_plus != 0 {
             self.stack.truncate(self.base + narg_plus as usize - 1);
         }
+        // the function entry may be above the top, as get_stack()
+        if self.stack.len() < self.base {
+            self.fill_stack_nil(0, 0);
+        }
 
         match self.stack[self.base - 1].clone() {
-            Value::RustFunction(f) => f(self) as usize,
-            Value::RustClosure(c) => c.borrow_mut()(self) as usize,
-            Value::LuaFunction(f) => self.execute(&f, &Vec::new()),
-            Value::LuaClosure(c) => self.execute(&c.proto, &c.upvalues),
-            v => panic!("invalid function: {v:?}"),
+            Value::RustFunction(f) => self.call_rust(f),
+            Value::RustClosure(c) => self.call_rust(|state| c(state)),
+            f@(Value::LuaFunction(_) | Value::LuaClosure(_)) => {
+                if self.frames.len() >= self.max_frames {
+                    return Err(Error::runtime("stack overflow"));
+                }
+                self.push_frame(f);
+                Ok(None)
+            }
+            v => {
+                // call the __call metamethod, with the called value
+                // as the first argument
+                let mm = self.get_metamethod(&v, "__call");
+                if !mm.is_function() {
+                    return Err(Error::call(format!("attempt to call a {} value", v.ty())));
+                }
+                // the arguments end at the stack top after truncated
+                // above, so take them as variable arguments
+                self.stack.insert(self.base - 1, mm);
+                self.precall(0)
+            }
         }
     }
 
-    fn close_brokers(&self, open_brokers: impl IntoIterator<Item = OpenBroker>) {
-        for OpenBroker { ilocal, broker } in open_brokers {
-            let openi = broker.replace(Upvalue::Closed(self.stack[ilocal].clone()));
-            debug_assert_eq!(openi, Upvalue::Open(ilocal));
+    fn call_rust(&mut self, f: impl FnOnce(&mut Self) -> Result<i32>) -> Result<Option<usize>> {
+        if self.hook_mask.call {
+            self.call_hook(HookEvent::Call)?;
+        }
+        let nret = f(self).map_err(rust_frame)?;
+        if self.hook_mask.ret {
+            self.call_hook(HookEvent::Return)?;
         }
+        Ok(Some(nret as usize))
     }
 
-    fn make_float(&mut self, dst: u8) -> f64 {
-        match self.get_stack(dst) {
-            &Value::Float(f) => f,
-            &Value::Integer(i) => {
-                let f = i as f64;
-                self.set_stack(dst, Value::Float(f));
-                f
+    // call the function and execute it to finish, see precall()
+    fn do_call_function(&mut self, narg_plus: u8) -> Result<usize> {
+        match self.precall(narg_plus)? {
+            Some(nret) => Ok(nret),
+            None => self.run(self.frames.len(), None),
+        }
+    }
+
+    // nil if not found
+    fn get_metamethod(&self, v: &Value, event: &str) -> Value {
+        match self.get_metatable(v) {
+            Some(mt) => mt.borrow().map.get(&event.into()).cloned().unwrap_or(Value::Nil),
+            None => Value::Nil,
+        }
+    }
+
+    // try metamethod @event of @v1 or @v2, or return @err if not found
+    fn binop_meta(&mut self, event: &str, v1: Value, v2: Value, err: Error) -> Result<Value> {
+        let mm = match self.get_metamethod(&v1, event) {
+            Value::Nil => self.get_metamethod(&v2, event),
+            mm => mm,
+        };
+        if mm == Value::Nil {
+            return Err(err);
+        }
+        self.call(mm, &[v1, v2])
+    }
+
+    fn compare_meta(&mut self, event: &str, v1: Value, v2: Value, err: Error) -> Result<bool> {
+        let r = self.binop_meta(event, v1, v2, err)?;
+        Ok((&r).into())
+    }
+
+    // for 2 different tables
+    fn equal_meta(&mut self, v1: Value, v2: Value) -> Result<bool> {
+        let mm = match self.get_metamethod(&v1, "__eq") {
+            Value::Nil => self.get_metamethod(&v2, "__eq"),
+            mm => mm,
+        };
+        if mm == Value::Nil {
+            return Ok(false);
+        }
+        let r = self.call(mm, &[v1, v2])?;
+        Ok((&r).into())
+    }
+
+    // length of non-string value
+    fn len_meta(&mut self, v: Value) -> Result<Value> {
+        match self.get_metamethod(&v, "__len") {
+            Value::Nil => match &v {
+                Value::Table(t) => Ok(Value::Integer(t.borrow().len() as i64)),
+                _ => Err(Error::type_error(format!("attempt to get length of a {} value", v.ty()))),
+            }
+            mm => self.call(mm, &[v.clone(), v]),
+        }
+    }
+
+    // call @f by @args, and @take the return values
+    fn call_with<R>(&mut self, f: Value, args: &[Value], take: impl FnOnce(&[Value]) -> R) -> Result<R> {
+        if self.ncalls >= MAX_RUST_CALLS {
+            return Err(Error::runtime("C stack overflow"));
+        }
+        let ifunc = self.stack.len();
+        self.stack.push(f);
+        self.stack.extend_from_slice(args);
+
+        // can not yield across the Rust function calling @f
+        let base = self.base;
+        self.base = ifunc + 1;
+        self.nny += 1;
+        self.ncalls += 1;
+        // variable arguments, since @args end at the stack top and
+        // may be more than a byte count
+        let nret = self.do_call_function(0);
+        self.ncalls -= 1;
+        self.nny -= 1;
+        self.base = base;
+
+        let ret = nret.map(|nret| take(&self.stack[self.stack.len() - nret ..]));
+        self.stack.truncate(ifunc);
+        ret
+    }
+
+    // Call __close of the to-be-closed variables at or above stack index
+    // @from in the top frame, in reverse order, with the error object
+    // @err. Each variable is removed before called, so the following
+    // ones are still closed if error raised.
+    fn close_tbc(&mut self, from: usize, err: &Value) -> Result<()> {
+        loop {
+            let tbc = &mut self.frames.last_mut().unwrap().tbc;
+            let Some(i) = tbc.pop_if(|i| *i >= from) else {
+                return Ok(());
+            };
+            let v = self.stack.get(i).cloned().unwrap_or(Value::Nil);
+            let mm = self.get_metamethod(&v, "__close");
+            self.call(mm, &[v, err.clone()])?;
+        }
+    }
+
+    // call the hook, which is disabled while running to avoid recursion
+    fn call_hook(&mut self, event: HookEvent) -> Result<()> {
+        if !self.allow_hook {
+            return Ok(());
+        }
+        let Some(hook) = self.hook.clone() else {
+            return Ok(());
+        };
+        self.allow_hook = false;
+        let r = hook.borrow_mut()(self, event);
+        self.allow_hook = true;
+        r
+    }
+
+    // count and line events before executing the byte code at @pc
+    fn instruction_hook(&mut self, proto: &FuncProto, pc: usize) -> Result<()> {
+        if self.hook_mask.count > 0 {
+            self.hook_count -= 1;
+            if self.hook_count == 0 {
+                self.hook_count = self.hook_mask.count;
+                self.call_hook(HookEvent::Count)?;
+            }
+        }
+        if self.hook_mask.line {
+            let frame = self.frames.last_mut().unwrap();
+            let oldpc = mem::replace(&mut frame.hook_pc, pc);
+            // no line information in stripped binary chunks
+            let Some(&line) = proto.lineinfo.get(pc) else {
+                return Ok(());
+            };
+            if pc <= oldpc || line != proto.lineinfo[oldpc] {
+                self.call_hook(HookEvent::Line(line))?;
             }
-            // TODO convert string
-            ref v => panic!("not number {v:?}"),
         }
+        Ok(())
+    }
+
+    // call the message handler of xpcall with the error object
+    fn handle_error(&mut self, msgh: Value, err: Error) -> Error {
+        match self.call(msgh, &[err.to_value()]) {
+            Ok(v) => err.with_value(v),
+            Err(_) => err.with_value("error in error handling".into()),
+        }
+    }
+
+    // switch to coroutine @co, and the current thread becomes normal
+    fn switch_thread(&mut self, co: GcCell<Thread>) {
+        self.park_upvalues();
+        let current = self.threads.last().unwrap().clone();
+        self.swap_thread(&current, ThreadStatus::Normal);
+        self.swap_thread(&co, ThreadStatus::Running);
+        self.threads.push(co);
+        self.unpark_upvalues();
+    }
+
+    // switch back from the running coroutine, which becomes @status
+    fn switch_back(&mut self, status: ThreadStatus) {
+        self.park_upvalues();
+        let co = self.threads.pop().unwrap();
+        self.swap_thread(&co, status);
+        if status == ThreadStatus::Dead {
+            co.borrow_mut().stack.clear();
+        }
+        let current = self.threads.last().unwrap().clone();
+        self.swap_thread(&current, ThreadStatus::Running);
+        self.unpark_upvalues();
+    }
+
+    // exchange the running state with @t
+    fn swap_thread(&mut self, t: &GcCell<Thread>, status: ThreadStatus) {
+        let mut t = t.borrow_mut();
+        mem::swap(&mut self.stack, &mut t.stack);
+        mem::swap(&mut self.base, &mut t.base);
+        mem::swap(&mut self.frames, &mut t.frames);
+        mem::swap(&mut self.nny, &mut t.nny);
+        t.status = status;
+    }
+
+    // Open upvalues refer to the running thread's stack by index, so
+    // close them temporarily when the thread stops running, and reopen
+    // them when it runs again.
+    fn park_upvalues(&self) {
+        for frame in &self.frames {
+            for ob in &frame.open_brokers {
+                ob.broker.replace(Upvalue::Closed(Upvalue::Open(ob.ilocal).get(&self.stack).clone()));
+            }
+        }
+    }
+    fn unpark_upvalues(&mut self) {
+        for frame in &self.frames {
+            for ob in &frame.open_brokers {
+                if let Upvalue::Closed(v) = ob.broker.replace(Upvalue::Open(ob.ilocal)) {
+                    Upvalue::Open(ob.ilocal).set(&mut self.stack, v);
+                }
+            }
+        }
+    }
+
+    fn make_float(&mut self, dst: u8, name: &str) -> Result<f64> {
+        let f = match self.get_stack(dst).to_number() {
+            Some(Value::Float(f)) => f,
+            Some(Value::Integer(i)) => i as f64,
+            _ => return Err(Error::type_error(format!("'for' {name} value must be a number"))),
+        };
+        self.set_stack(dst, Value::Float(f));
+        Ok(f)
     }
 }
 
 // API
+impl ExeState {
+    // the garbage collector
+    pub fn heap(&mut self) -> &mut Context {
+        &mut self.heap
+    }
+
+    // call @f by @args, return the first return value
+    pub fn call(&mut self, f: Value, args: &[Value]) -> Result<Value> {
+        self.call_with(f, args, |rets| rets.first().cloned().unwrap_or(Value::Nil))
+    }
+
+    // call @f by @args, return all return values
+    pub fn call_multi(&mut self, f: Value, args: &[Value]) -> Result<Vec<Value>> {
+        self.call_with(f, args, <[Value]>::to_vec)
+    }
+
+    // Call @f in protected mode. If error raised, the message handler
+    // @msgh, if not nil, is called with the error object before the
+    // stack unwinding, and its return value becomes the error object.
+    pub fn pcall(&mut self, f: Value, args: &[Value], msgh: Value) -> Result<Vec<Value>> {
+        let prev = mem::replace(&mut self.msgh, msgh);
+        let r = self.call_multi(f, args);
+        let msgh = mem::replace(&mut self.msgh, prev);
+        match r {
+            // not handled yet if raised by Rust functions directly
+            Err(err) if msgh != Value::Nil => Err(self.handle_error(msgh, err)),
+            r => r,
+        }
+    }
+
+    // chunk name and line of the Lua function at @level, like
+    // luaL_where(). Level 1 is the function which calls the running Rust
+    // function. None if the function at @level is not a Lua function.
+    //
+    // Rust functions have no frames, so one is counted as a level where
+    // a frame does not call the function of the next inner level directly.
+    pub fn location(&self, mut level: i64) -> Option<(&str, usize)> {
+        let mut callee = self.base; // of the function at the current level
+        for frame in self.frames.iter().rev() {
+            if level <= 0 {
+                return None;
+            }
+            let (proto, _) = lua_parts(&frame.func);
+            let code = proto.byte_codes[frame.pc];
+            let called = match get_op(code) {
+                opcode::Call => frame.base + arg_a(code) as usize + 1 == callee,
+                opcode::CallSet => frame.base + arg_b(code) as usize + 1 == callee,
+                opcode::ForCallLoop => frame.base + arg_a(code) as usize + 4 == callee,
+                opcode::TailCall => frame.base == callee,
+                _ => false,
+            };
+            if !called {
+                // a Rust function between
+                level -= 1;
+                if level == 0 {
+                    return None;
+                }
+            }
+            level -= 1;
+            if level == 0 {
+                let line = proto.lineinfo.get(frame.pc).copied()?;
+                return Some((&proto.source, line));
+            }
+            callee = frame.base;
+        }
+        None
+    }
+
+    // new coroutine with body function @f
+    pub fn new_thread(&mut self, f: Value) -> GcCell<Thread> {
+        self.heap.alloc_cell(Thread {
+            stack: vec![f],
+            ..Default::default()
+        })
+    }
+
+    // the running coroutine, and whether it is the main thread
+    pub fn running(&self) -> (GcCell<Thread>, bool) {
+        (self.threads.last().unwrap().clone(), self.threads.len() == 1)
+    }
+
+    pub fn is_yieldable(&self) -> bool {
+        self.threads.len() > 1 && self.nny == 0
+    }
+
+    // resume coroutine @co by @args, return the values passed to yield,
+    // or returned by the body function
+    pub fn resume(&mut self, co: &GcCell<Thread>, args: &[Value]) -> Result<Vec<Value>> {
+        match co.borrow().status {
+            ThreadStatus::Suspended => (),
+            ThreadStatus::Dead => return Err(Error::runtime("cannot resume dead coroutine")),
+            _ => return Err(Error::runtime("cannot resume non-suspended coroutine")),
+        }
+        if self.ncalls >= MAX_RUST_CALLS {
+            return Err(Error::runtime("C stack overflow"));
+        }
+
+        // errors in coroutine are not handled by the message handler
+        // of current thread
+        let msgh = mem::replace(&mut self.msgh, Value::Nil);
+        self.switch_thread(co.clone());
+
+        self.stack.extend_from_slice(args);
+        self.ncalls += 1;
+        let r = if self.base == 0 {
+            // start the body function at stack[0]
+            self.base = 1;
+            self.do_call_function(0)
+        } else if self.frames.is_empty() {
+            // the body is a Rust function which yielded, and now it
+            // returns the resume values
+            Ok(args.len())
+        } else {
+            self.run(1, Some(args.len()))
+        };
+
+        self.ncalls -= 1;
+        let yielded = mem::take(&mut self.yielding);
+        let r = r.map(|n| self.stack.split_off(self.stack.len() - n));
+        match &r {
+            Ok(_) if yielded => self.switch_back(ThreadStatus::Suspended),
+            Ok(_) => self.switch_back(ThreadStatus::Dead),
+            Err(err) => {
+                self.switch_back(ThreadStatus::Dead);
+                co.borrow_mut().error = Some(err.clone());
+            }
+        }
+        self.msgh = msgh;
+        r
+    }
+
+    // Kill a suspended or dead coroutine, after closing its pending
+    // to-be-closed variables. Return the error which kills it if any.
+    pub fn close_thread(&mut self, co: &GcCell<Thread>) -> Result<Option<Error>> {
+        let pending = {
+            let co = co.borrow();
+            co.status == ThreadStatus::Suspended && co.frames.iter().any(|f| !f.tbc.is_empty())
+        };
+        if pending {
+            let msgh = mem::replace(&mut self.msgh, Value::Nil);
+            self.switch_thread(co.clone());
+
+            let mut err: Option<Error> = None;
+            while !self.frames.is_empty() {
+                loop {
+                    let ev = err.as_ref().map_or(Value::Nil, Error::to_value);
+                    match self.close_tbc(0, &ev) {
+                        Ok(()) => break,
+                        Err(e) => err = Some(e),
+                    }
+                }
+                let frame = self.frames.pop().unwrap();
+                close_brokers(&self.stack, frame.open_brokers);
+            }
+
+            self.switch_back(ThreadStatus::Suspended);
+            self.msgh = msgh;
+            if err.is_some() {
+                co.borrow_mut().error = err;
+            }
+        }
+        co.borrow_mut().close()
+    }
+
+    // Called by a Rust function before returning, to yield its @nret
+    // return values to resume(). The values passed to the next resume()
+    // are taken as its return values.
+    pub fn yield_values(&mut self, nret: i32) -> Result<i32> {
+        if self.threads.len() == 1 {
+            return Err(Error::runtime("attempt to yield from outside a coroutine"));
+        }
+        if self.nny > 0 {
+            return Err(Error::runtime("attempt to yield across a C-call boundary"));
+        }
+        self.yielding = true;
+        Ok(nret)
+    }
+
+    // set the hook called on the events in @mask, replacing the old one
+    pub fn set_hook(&mut self, mask: HookMask, hook: impl FnMut(&mut ExeState, HookEvent) -> Result<()> + 'static) {
+        self.hook = Some(Rc::new(RefCell::new(Box::new(hook))));
+        self.hook_mask = mask;
+        self.hook_instr = mask.line || mask.count > 0;
+        self.hook_count = mask.count;
+    }
+
+    pub fn clear_hook(&mut self) {
+        self.hook = None;
+        self.hook_mask = HookMask::default();
+        self.hook_instr = false;
+        self.hook_count = 0;
+    }
+
+    // the max depth of Lua call frames, beyond which "stack overflow"
+    // is raised
+    pub fn set_max_frames(&mut self, n: usize) {
+        self.max_frames = n;
+    }
+
+    // the global table, which is the default _ENV of loaded chunks
+    pub fn globals(&self) -> Value {
+        self.globals.clone()
+    }
+
+    // _ENV[name] = v, without metamethods
+    pub fn set_global(&mut self, name: &str, v: Value) {
+        let Value::Table(env) = &self.globals else {
+            unreachable!("_ENV is not a table");
+        };
+        env.borrow_mut().new_index(name.into(), v);
+    }
+
+    // function of the loaded chunk @proto. Its first upvalue, which is
+    // _ENV of main chunks, is set to @env or the global table, and the
+    // others are nil, e.g. of functions dumped by string.dump()
+    pub fn new_chunk(&mut self, proto: FuncProto, env: Option<Value>) -> Value {
+        if proto.upindexes.is_empty() {
+            return Value::LuaFunction(Rc::new(proto));
+        }
+        let env = env.unwrap_or_else(|| self.globals.clone());
+        let upvalues = (0..proto.upindexes.len())
+            .map(|i| {
+                let v = if i == 0 { env.clone() } else { Value::Nil };
+                self.heap.alloc_cell(Upvalue::Closed(v))
+            })
+            .collect();
+        let c = LuaClosure { proto: Rc::new(proto), upvalues };
+        Value::LuaClosure(self.heap.alloc(c))
+    }
+
+    pub fn get_metatable(&self, v: &Value) -> Option<GcCell<Table>> {
+        match v {
+            Value::Table(t) => t.borrow().metatable.clone(),
+            Value::ShortStr(..) | Value::MidStr(_) | Value::LongStr(_) => self.string_meta.clone(),
+            _ => None,
+        }
+    }
+
+    // the metatable shared by all strings
+    pub fn set_string_metatable(&mut self, mt: Option<GcCell<Table>>) {
+        self.string_meta = mt;
+    }
+
+    // t[key], with __index metamethod
+    pub fn index(&mut self, mut t: Value, key: &Value) -> Result<Value> {
+        for _ in 0..MAX_META_LOOP {
+            let handler = match &t {
+                Value::Table(table) => {
+                    let table = table.borrow();
+                    let v = table.index(key);
+                    if v != &Value::Nil {
+                        return Ok(v.clone());
+                    }
+                    let Some(mt) = &table.metatable else {
+                        return Ok(Value::Nil);
+                    };
+                    let handler = mt.borrow().map.get(&"__index".into()).cloned();
+                    match handler {
+                        None | Some(Value::Nil) => return Ok(Value::Nil),
+                        Some(h) => h,
+                    }
+                }
+                _ => match self.get_metamethod(&t, "__index") {
+                    Value::Nil => return Err(index_error(&t)),
+                    h => h,
+                }
+            };
+
+            if handler.is_function() {
+                return self.call(handler, &[t, key.clone()]);
+            }
+            t = handler;
+        }
+        Err(Error::runtime("'__index' chain too long; possibly a loop"))
+    }
+
+    // t[key] = value, with __newindex metamethod
+    pub fn new_index(&mut self, mut t: Value, key: Value, value: Value) -> Result<()> {
+        for _ in 0..MAX_META_LOOP {
+            let handler = match &t {
+                Value::Table(table) => {
+                    let handler = {
+                        let table = table.borrow();
+                        match &table.metatable {
+                            // __newindex is used only if the key is absent
+                            Some(mt) if table.index(&key) == &Value::Nil =>
+                                mt.borrow().map.get(&"__newindex".into()).cloned().unwrap_or(Value::Nil),
+                            _ => Value::Nil,
+                        }
+                    };
+                    if handler == Value::Nil {
+                        check_key(&key)?;
+                        table.borrow_mut().new_index(key, value);
+                        return Ok(());
+                    }
+                    handler
+                }
+                _ => match self.get_metamethod(&t, "__newindex") {
+                    Value::Nil => return Err(index_error(&t)),
+                    h => h,
+                }
+            };
+
+            if handler.is_function() {
+                self.call(handler, &[t, key, value])?;
+                return Ok(());
+            }
+            t = handler;
+        }
+        Err(Error::runtime("'__newindex' chain too long; possibly a loop"))
+    }
+
+    // v1 < v2, with __lt metamethod
+    pub fn less_than(&mut self, v1: &Value, v2: &Value) -> Result<bool> {
+        match compare(v1, v2) {
+            Ok(cmp) => Ok(matches!(cmp, Some(Ordering::Less))),
+            Err(err) => self.compare_meta("__lt", v1.clone(), v2.clone(), err),
+        }
+    }
+
+    // #v, with __len metamethod
+    pub fn len(&mut self, v: &Value) -> Result<Value> {
+        match v {
+            Value::ShortStr(len, _) => Ok(Value::Integer(*len as i64)),
+            Value::MidStr(s) => Ok(Value::Integer(s.0 as i64)),
+            Value::LongStr(s) => Ok(Value::Integer(s.len() as i64)),
+            Value::Table(t) if t.borrow().metatable.is_none() =>
+                Ok(Value::Integer(t.borrow().len() as i64)),
+            v => self.len_meta(v.clone()),
+        }
+    }
+
+    // convert any value to string, with __tostring and __name metafield
+    pub fn tostring(&mut self, v: &Value) -> Result<Value> {
+        if let Some(mt) = self.get_metatable(v) {
+            let mm = mt.borrow().map.get(&"__tostring".into()).cloned();
+            if let Some(mm) = mm {
+                let s = self.call(mm, std::slice::from_ref(v))?;
+                if !s.is_string() {
+                    return Err(Error::runtime("'__tostring' must return a string"));
+                }
+                return Ok(s);
+            }
+            if let Some(name) = mt.borrow().map.get(&"__name".into()) {
+                if let (true, Value::Table(t)) = (name.is_string(), v) {
+                    return Ok(format!("{name}: {:?}", t.as_ptr()).into());
+                }
+            }
+        }
+        if v.is_string() {
+            Ok(v.clone())
+        } else {
+            Ok(v.to_string().into())
+        }
+    }
+}
+
 impl<'a> ExeState {
     pub fn get_top(&self) -> usize {
         self.stack.len() - self.base
     }
+    // get the @i-th argument, nil if absent
     pub fn get<T>(&'a self, i: usize) -> T where T: From<&'a Value> {
-        (&self.stack[self.base + i - 1]).into()
+        const NIL: &Value = &Value::Nil;
+        self.stack.get(self.base + i - 1).unwrap_or(NIL).into()
     }
     pub fn push(&mut self, v: impl Into<Value>) {
         self.stack.push(v.into());
     }
 }
 
-fn exe_binop(v1: &Value, v2: &Value, arith_i: fn(i64,i64)->i64, arith_f: fn(f64,f64)->f64) -> Value {
-    match (v1, v2) {
+// argument of the ExtraArg following the current code, skipped then
+fn extra_arg(proto: &FuncProto, pc: &mut usize) -> u32 {
+    *pc += 1;
+    let code = proto.byte_codes[*pc];
+    match get_op(code) {
+        opcode::ExtraArg => arg_ax(code),
+        op => unreachable!("expect ExtraArg but opcode {op}"),
+    }
+}
+
+// prototype and upvalues of Lua function
+fn lua_parts(f: &Value) -> (&FuncProto, &[GcCell<Upvalue>]) {
+    match f {
+        Value::LuaFunction(proto) => (proto, &[]),
+        Value::LuaClosure(c) => (&c.proto, &c.upvalues),
+        _ => unreachable!("not Lua function"),
+    }
+}
+
+fn close_brokers(stack: &[Value], open_brokers: impl IntoIterator<Item = OpenBroker>) {
+    for OpenBroker { ilocal, broker } in open_brokers {
+        let openi = broker.replace(Upvalue::Closed(Upvalue::Open(ilocal).get(stack).clone()));
+        debug_assert_eq!(openi, Upvalue::Open(ilocal));
+    }
+}
+
+// describe the function in traceback
+fn func_desc(proto: &FuncProto) -> String {
+    if proto.linedefined == 0 {
+        "main chunk".into()
+    } else if proto.name.is_empty() {
+        format!("function <{}:{}>", proto.source, proto.linedefined)
+    } else {
+        format!("function '{}'", proto.name)
+    }
+}
+
+fn rust_frame(err: Error) -> Error {
+    err.with_frame(TraceFrame::rust())
+}
+
+// max length of __index and __newindex chain
+const MAX_META_LOOP: usize = 2000;
+
+fn index_error(v: &Value) -> Error {
+    Error::type_error(format!("attempt to index a {} value", v.ty()))
+}
+
+pub(crate) fn check_key(key: &Value) -> Result<()> {
+    match key {
+        Value::Nil => Err(Error::runtime("index is nil")),
+        Value::Float(f) if f.is_nan() => Err(Error::runtime("index is NaN")),
+        _ => Ok(()),
+    }
+}
+
+fn arith_error(v: &Value) -> Error {
+    Error::arith(format!("attempt to perform arithmetic on a {} value", v.ty()))
+}
+fn bitwise_error(v: &Value) -> Error {
+    Error::arith(format!("attempt to perform bitwise operation on a {} value", v.ty()))
+}
+
+// the operand which causes arithmetic error
+fn arith_error2(v1: &Value, v2: &Value) -> Error {
+    if v1.to_number().is_some() {
+        arith_error(v2)
+    } else {
+        arith_error(v1)
+    }
+}
+fn bitwise_error2(v1: &Value, v2: &Value) -> Error {
+    if v1.to_number().is_some() {
+        bitwise_error(v2)
+    } else {
+        bitwise_error(v1)
+    }
+}
+
+fn float_to_int(f: f64) -> Result<i64> {
+    ftoi(f).ok_or_else(|| Error::arith("number has no integer representation"))
+}
+
+fn exe_binop(v1: &Value, v2: &Value, arith_i: fn(i64,i64)->i64, arith_f: fn(f64,f64)->f64) -> Result<Value> {
+    let v = match (v1, v2) {
         (&Value::Integer(i1), &Value::Integer(i2)) => Value::Integer(arith_i(i1, i2)),
         (&Value::Integer(i1), &Value::Float(f2)) => Value::Float(arith_f(i1 as f64, f2)),
         (&Value::Float(f1), &Value::Float(f2)) => Value::Float(arith_f(f1, f2)),
         (&Value::Float(f1), &Value::Integer(i2)) => Value::Float(arith_f(f1, i2 as f64)),
-        (_, _) => todo!("meta"),
-    }
+        (_, _) => return match (v1.to_number(), v2.to_number()) {
+            (Some(n1), Some(n2)) => exe_binop(&n1, &n2, arith_i, arith_f),
+            _ => Err(arith_error2(v1, v2)),
+        },
+    };
+    Ok(v)
 }
-fn exe_binop_int(v1: &Value, i2: u8, arith_i: fn(i64,i64)->i64, arith_f: fn(f64,f64)->f64) -> Value {
-    match v1 {
-        &Value::Integer(i1) => Value::Integer(arith_i(i1, i2 as i64)),
-        &Value::Float(f1) => Value::Float(arith_f(f1, i2 as f64)),
-        _ => todo!("meta"),
+// integer division and modulo, raise error for integer zero divisor
+fn exe_binop_div(v1: &Value, v2: &Value, op: &str, arith_i: fn(i64,i64)->i64, arith_f: fn(f64,f64)->f64) -> Result<Value> {
+    match (v1, v2) {
+        (Value::Integer(_), Value::Integer(0)) =>
+            Err(Error::arith(format!("attempt to perform 'n{op}0'"))),
+        (Value::Integer(_) | Value::Float(_), Value::Integer(_) | Value::Float(_)) =>
+            exe_binop(v1, v2, arith_i, arith_f),
+        _ => match (v1.to_number(), v2.to_number()) {
+            (Some(n1), Some(n2)) => exe_binop_div(&n1, &n2, op, arith_i, arith_f),
+            _ => Err(arith_error2(v1, v2)),
+        },
     }
 }
 
-fn exe_binop_f(v1: &Value, v2: &Value, arith_f: fn(f64,f64)->f64) -> Value {
+fn exe_binop_f(v1: &Value, v2: &Value, arith_f: fn(f64,f64)->f64) -> Result<Value> {
     let (f1, f2) = match (v1, v2) {
         (&Value::Integer(i1), &Value::Integer(i2)) => (i1 as f64, i2 as f64),
         (&Value::Integer(i1), &Value::Float(f2)) => (i1 as f64, f2),
         (&Value::Float(f1), &Value::Float(f2)) => (f1, f2),
         (&Value::Float(f1), &Value::Integer(i2)) => (f1, i2 as f64),
-        (_, _) => todo!("meta"),
-    };
-    Value::Float(arith_f(f1, f2))
-}
-fn exe_binop_int_f(v1: &Value, i2: u8, arith_f: fn(f64,f64)->f64) -> Value {
-    let f1 = match v1 {
-        &Value::Integer(i1) => i1 as f64,
-        &Value::Float(f1) => f1,
-        _ => todo!("meta"),
+        (_, _) => return match (v1.to_number(), v2.to_number()) {
+            (Some(n1), Some(n2)) => exe_binop_f(&n1, &n2, arith_f),
+            _ => Err(arith_error2(v1, v2)),
+        },
     };
-    Value::Float(arith_f(f1, i2 as f64))
+    Ok(Value::Float(arith_f(f1, f2)))
 }
 
-fn exe_binop_i(v1: &Value, v2: &Value, arith_i: fn(i64,i64)->i64) -> Value {
+fn exe_binop_i(v1: &Value, v2: &Value, arith_i: fn(i64,i64)->i64) -> Result<Value> {
     let (i1, i2) = match (v1, v2) {
         (&Value::Integer(i1), &Value::Integer(i2)) => (i1, i2),
-        (&Value::Integer(i1), &Value::Float(f2)) => (i1, ftoi(f2).unwrap()),
-        (&Value::Float(f1), &Value::Float(f2)) => (ftoi(f1).unwrap(), ftoi(f2).unwrap()),
-        (&Value::Float(f1), &Value::Integer(i2)) => (ftoi(f1).unwrap(), i2),
-        (_, _) => todo!("meta"),
+        (&Value::Integer(i1), &Value::Float(f2)) => (i1, float_to_int(f2)?),
+        (&Value::Float(f1), &Value::Float(f2)) => (float_to_int(f1)?, float_to_int(f2)?),
+        (&Value::Float(f1), &Value::Integer(i2)) => (float_to_int(f1)?, i2),
+        (_, _) => return match (v1.to_number(), v2.to_number()) {
+            (Some(n1), Some(n2)) => exe_binop_i(&n1, &n2, arith_i),
+            _ => Err(bitwise_error2(v1, v2)),
+        },
     };
-    Value::Integer(arith_i(i1, i2))
+    Ok(Value::Integer(arith_i(i1, i2)))
 }
-fn exe_binop_int_i(v1: &Value, i2: u8, arith_i: fn(i64,i64)->i64) -> Value {
-    let i1 = match v1 {
-        &Value::Integer(i1) => i1,
-        &Value::Float(f1) => ftoi(f1).unwrap(),
-        _ => todo!("meta"),
-    };
-    Value::Integer(arith_i(i1, i2 as i64))
+
+// compare numbers or strings, return None if any NaN
+fn compare(v1: &Value, v2: &Value) -> Result<Option<Ordering>> {
+    match v1.partial_cmp(v2) {
+        Some(cmp) => Ok(Some(cmp)),
+        None if matches!((v1, v2), (Value::Integer(_) | Value::Float(_), Value::Integer(_) | Value::Float(_))) => Ok(None),
+        None => {
+            let (t1, t2) = (v1.ty(), v2.ty());
+            if t1 == t2 {
+                Err(Error::type_error(format!("attempt to compare two {t1} values")))
+            } else {
+                Err(Error::type_error(format!("attempt to compare {t1} with {t2}")))
+            }
+        }
+    }
 }
 
 fn for_check<T: PartialOrd>(i: T, limit: T, is_step_positive: bool) -> bool {
@@ -0,0 +1,6 @@
+#!/usr/bin/env luar
+local a, b = ...
+assert(a == "x" and b == "y")
+assert(arg[0] == "./tests/luas/args.lua" and arg[1] == "x" and arg[2] == "y" and #arg == 2)
+assert(arg[-1] == "from_e = 1" and arg[-2] == "-e")
+assert(from_e == 1)
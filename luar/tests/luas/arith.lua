@@ -0,0 +1,47 @@
+-- floor division and modulo
+local a, b = 7, -7
+assert(a // 2 == 3 and b // 2 == -4 and a // -2 == -4 and b // -2 == 3)
+assert(a % 3 == 1 and b % 3 == 2 and a % -3 == -2 and b % -3 == -1)
+assert(7.5 // 2 == 3.0 and -7.5 // 2 == -4.0 and math.type(7 // 2.0) == "float")
+assert(5.5 % 2 == 1.5 and -5.5 % 2 == 0.5 and 5.5 % -2 == -0.5)
+assert(-1 % math.huge == math.huge and 1 % -math.huge == -math.huge)
+assert(1 // 0.0 == math.huge and -1 // 0.0 == -math.huge)
+assert(-7 // 2 == -4 and -7 % 3 == 2) -- folded constants
+assert(math.mininteger // -1 == math.mininteger and math.mininteger % -1 == 0)
+
+local function check(f, msg)
+    local ok, err = pcall(f)
+    assert(not ok and string.find(err, msg, 1, true), err)
+end
+local zero = 0
+check(function() return a // zero end, "attempt to perform 'n//0'")
+check(function() return a % zero end, "attempt to perform 'n%0'")
+check(function() return a // 0 end, "attempt to perform 'n//0'")
+check(function() return a % 0 end, "attempt to perform 'n%0'")
+check(function() return "1" % "0" end, "attempt to perform 'n%0'")
+assert(a % 0.0 ~= a % 0.0) -- nan
+
+-- wrapping integer arithmetic
+local max, min = math.maxinteger, math.mininteger
+assert(max + 1 == min and min - 1 == max and -min == min)
+assert(max * 2 == -2 and min * -1 == min)
+assert(math.maxinteger + 1 == math.mininteger) -- folded constants
+
+-- shifts are logical, and shift out all bits beyond 63
+local one = 1
+assert(one << 63 == min and one << 64 == 0 and one << -1 == 0)
+assert(-1 >> 1 == max and -1 >> 64 == 0 and -1 >> -1 == -2)
+assert(-1 >> 63 == 1 and one >> -63 == min)
+
+-- bitwise on floats
+assert(3.0 | 0 == 3 and math.type(3.0 | 0) == "integer")
+check(function() return a | 1.5 end, "number has no integer representation")
+check(function() return ~(2^63) end, "number has no integer representation")
+
+-- for loop near the integer limits
+local n = 0
+for i = max - 2, max do n = n + 1 end
+assert(n == 3)
+n = 0
+for i = min + 2, min, -1 do n = n + 1 end
+assert(n == 3)
@@ -0,0 +1,119 @@
+-- <const>
+local N <const> = 10
+local S <const> = "str"
+assert(N * 2 == 20 and S .. "!" == "str!")
+local function f() return N + 1 end -- folded, no upvalue
+assert(f() == 11)
+local t <const> = {}
+t.x = 1 -- fields are still writable
+assert(t.x == 1)
+local a <const>, b = 1, 2
+b = 3
+assert(a == 1 and b == 3)
+
+-- <close>
+local log = {}
+local function closable(name)
+    return setmetatable({}, {__close = function(_, err)
+        log[#log + 1] = name .. ":" .. (err == nil and "nil" or err)
+    end})
+end
+local function check(expected)
+    assert(table.concat(log, " ") == expected, table.concat(log, " "))
+    log = {}
+end
+
+-- block exit, in reverse order
+do
+    local x <close> = closable("x")
+    local y <close> = closable("y")
+    local z <close> = nil -- ignored
+end
+check("y:nil x:nil")
+
+-- return, and the return values are kept
+local function ret()
+    local x <close> = closable("r")
+    local v = 42
+    return v, "two"
+end
+local v1, v2 = ret()
+assert(v1 == 42 and v2 == "two")
+check("r:nil")
+
+-- return a call, which is not a tail call
+local function tail()
+    local x <close> = closable("tail")
+    return ret()
+end
+assert(tail() == 42)
+check("r:nil tail:nil")
+
+-- break
+for i = 1, 3 do
+    local x <close> = closable("b")
+    if i == 2 then break end
+end
+check("b:nil b:nil")
+
+-- goto out of blocks
+do
+    local x <close> = closable("g1")
+    do
+        local y <close> = closable("g2")
+        goto out
+    end
+end
+::out::
+check("g2:nil g1:nil")
+
+-- loops
+local i = 0
+while i < 2 do
+    i = i + 1
+    local x <close> = closable("w")
+end
+check("w:nil w:nil")
+i = 0
+repeat
+    i = i + 1
+    local x <close> = closable("u")
+until i == 2
+check("u:nil u:nil")
+
+-- error unwinding, with the error object
+local ok, err = pcall(function()
+    local x <close> = closable("e")
+    error("boom", 0)
+end)
+assert(not ok and err == "boom")
+check("e:boom")
+
+-- error in __close
+ok, err = pcall(function()
+    local x <close> = closable("first")
+    local y <close> = setmetatable({}, {__close = function() error("in close", 0) end})
+end)
+assert(not ok and err == "in close")
+check("first:in close")
+
+-- coroutine.close
+local co = coroutine.create(function()
+    local x <close> = closable("co")
+    coroutine.yield()
+end)
+coroutine.resume(co)
+assert(coroutine.close(co))
+assert(coroutine.status(co) == "dead")
+check("co:nil")
+
+-- constant left operands with short-circuit right operands
+local e = nil
+assert("a" .. (e == nil and "b" or "c") == "ab")
+assert(N - (e ~= nil and 2 or 3) == 7)
+
+-- constant _ENV
+ok, err = pcall(load("local _ENV <const> = nil; x = 1"))
+assert(not ok and string.find(err, "attempt to index a nil value", 1, true))
+ok, err = pcall(load("local _ENV <const> = 3; return x"))
+assert(not ok and string.find(err, "attempt to index a number value", 1, true))
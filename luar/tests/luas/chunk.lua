@@ -0,0 +1,4 @@
+#!/usr/bin/env luar
+-- loaded by load.lua
+local a, b = ...
+return (a or 1) + (b or 2), x
@@ -0,0 +1,56 @@
+-- strings in arithmetic
+assert("10" + 1 == 11 and math.type("10" + 1) == "integer")
+assert("3.0" + 1 == 4.0 and math.type("3.0" + 1) == "float")
+assert(" 0x10 " * 2 == 32)
+assert(10 - "1e1" == 0.0)
+assert("7" // "2" == 3 and "7" % "4" == 3)
+assert("2" ^ "3" == 8.0 and "1" / "2" == 0.5)
+assert(-"2" == -2 and ~"0" == -1)
+assert("3" | 4 == 7 and "0xff" & "0x0f" == 15)
+assert("10" == 10 == false)
+
+local ok, err = pcall(function() return "abc" + 1 end)
+assert(not ok and string.find(err, "attempt to perform arithmetic on a string value", 1, true))
+ok, err = pcall(function() return "10" + {} end)
+assert(not ok and string.find(err, "on a table value", 1, true))
+ok, err = pcall(function() return "1.5" | 0 end)
+assert(not ok and string.find(err, "number has no integer representation", 1, true))
+
+-- numbers in concatenation
+assert(1 .. "" == "1" and "a" .. 2 == "a2" and 1 .. 2 == "12")
+assert(1.0 .. "" == "1.0" and -0.0 .. "" == "-0.0")
+assert(0.1 .. "" == "0.1" and 1/3 .. "" == "0.33333333333333")
+assert(1e15 .. "" == "1e+15" and 1e100 .. "" == "1e+100" and 2^63 .. "" == "9.2233720368548e+18")
+assert(123456789012345.0 .. "" == "1.2345678901234e+14")
+assert(1e-5 .. "" == "1e-05" and 0.0001 .. "" == "0.0001")
+assert(1/0 .. "" == "inf" and -1/0 .. "" == "-inf")
+assert(math.mininteger .. "" == "-9223372036854775808")
+
+-- for loop
+local n = 0
+for i = "1", "3" do n = n + i end
+assert(n == 6)
+
+-- tonumber
+assert(tonumber(10) == 10 and tonumber(" 0x10 ") == 16 and tonumber("1e1") == 10.0)
+assert(math.type(tonumber("1")) == "integer" and math.type(tonumber("1.")) == "float")
+assert(tonumber("abc") == nil and tonumber("") == nil and tonumber({}) == nil)
+assert(tonumber("10", 2) == 2 and tonumber("ff", 16) == 255 and tonumber("ZZ", 36) == 1295)
+assert(tonumber(" -7 ", 8) == -7 and tonumber("8", 8) == nil and tonumber("1.0", 10) == nil)
+assert(tonumber("ffffffffffffffff", 16) == -1)
+ok, err = pcall(tonumber, "10", 99)
+assert(not ok and err == "bad argument #2 to 'tonumber' (base out of range)")
+ok, err = pcall(tonumber, 10, 16)
+assert(not ok and err == "bad argument #1 to 'tonumber' (string expected, got number)")
+ok, err = pcall(tonumber)
+assert(not ok and err == "bad argument #1 to 'tonumber' (value expected)")
+
+-- tostring
+assert(tostring(nil) == "nil" and tostring(true) == "true")
+assert(tostring(10) == "10" and tostring(10.0) == "10.0" and tostring(-1.5) == "-1.5")
+assert(tostring("x") == "x")
+assert(tostring(setmetatable({}, {__tostring = function() return "T" end})) == "T")
+assert(string.find(tostring({}), "^table: "))
+
+-- library arguments
+assert(string.rep("a", "3") == "aaa" and math.floor("2.5") == 2)
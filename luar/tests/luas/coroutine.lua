@@ -0,0 +1,142 @@
+-- resume and yield
+local co = coroutine.create(function(a, b)
+    assert(a == 1 and b == 2)
+    local c, d = coroutine.yield(a + b)
+    assert(c == "x" and d == nil)
+    local e = coroutine.yield()
+    return e * 10, "end"
+end)
+assert(type(co) == "thread")
+assert(coroutine.status(co) == "suspended")
+local ok, r = coroutine.resume(co, 1, 2)
+assert(ok and r == 3)
+assert(table.pack(coroutine.resume(co, "x")).n == 1)
+local ok, r1, r2 = coroutine.resume(co, 5)
+assert(ok and r1 == 50 and r2 == "end")
+assert(coroutine.status(co) == "dead")
+ok, r = coroutine.resume(co)
+assert(not ok and r == "cannot resume dead coroutine")
+
+-- yield across Lua frames
+local function walk(t)
+    for _, v in ipairs(t) do
+        if type(v) == "table" then
+            walk(v)
+        else
+            coroutine.yield(v)
+        end
+    end
+end
+local gen = coroutine.wrap(function() walk({1, {2, {3, 4}}, 5}) end)
+local sum = 0
+for v in gen do
+    sum = sum + v
+end
+assert(sum == 15)
+
+-- wrap as generator of a generic for
+local function range(n)
+    return coroutine.wrap(function()
+        for i = 1, n do coroutine.yield(i, i * i) end
+    end)
+end
+local n = 0
+for i, sq in range(4) do
+    assert(sq == i * i)
+    n = n + 1
+end
+assert(n == 4)
+
+-- status, running and isyieldable
+local main, ismain = coroutine.running()
+assert(ismain and coroutine.status(main) == "running")
+assert(not coroutine.isyieldable())
+local outer
+outer = coroutine.create(function()
+    local me, ismain = coroutine.running()
+    assert(me == outer and not ismain)
+    assert(coroutine.status(outer) == "running")
+    assert(coroutine.isyieldable())
+    local inner = coroutine.create(function()
+        assert(coroutine.status(outer) == "normal")
+        coroutine.yield()
+    end)
+    coroutine.resume(inner)
+    assert(coroutine.status(inner) == "suspended")
+    local ok, err = coroutine.resume(outer)
+    assert(not ok and err == "cannot resume non-suspended coroutine")
+end)
+assert(coroutine.resume(outer))
+
+-- errors are caught by resume
+co = coroutine.create(function()
+    coroutine.yield(1)
+    assert(false, "boom")
+end)
+assert(coroutine.resume(co))
+ok, r = coroutine.resume(co)
+assert(not ok and string.find(r, "boom"))
+assert(coroutine.status(co) == "dead")
+ok, r = coroutine.close(co)
+assert(not ok and string.find(r, "boom"))
+
+-- close a suspended coroutine
+co = coroutine.create(function() coroutine.yield() end)
+coroutine.resume(co)
+assert(coroutine.close(co) == true)
+assert(coroutine.status(co) == "dead")
+
+-- upvalues shared between threads
+local count = 0
+local function counter()
+    local step = 1
+    local inc = coroutine.wrap(function()
+        while true do
+            count = count + step
+            step = coroutine.yield(count)
+        end
+    end)
+    return inc
+end
+local inc = counter()
+assert(inc() == 1)
+assert(inc(10) == 11)
+count = 100
+assert(inc(1) == 101)
+
+-- yield inside a coroutine local captured by a closure
+co = coroutine.wrap(function()
+    local x = 1
+    local get = function() return x end
+    coroutine.yield(get)
+    x = 2
+    coroutine.yield(get)
+end)
+local get1 = co()
+assert(get1() == 1)
+co()
+assert(get1() == 2)
+
+-- Rust function as body
+co = coroutine.create(coroutine.yield)
+ok, r = coroutine.resume(co, "a")
+assert(ok and r == "a")
+ok, r = coroutine.resume(co, "b")
+assert(ok and r == "b" and coroutine.status(co) == "dead")
+
+-- tail calls
+co = coroutine.wrap(function()
+    local function f(x) return coroutine.yield(x) end
+    return f(7)
+end)
+assert(co() == 7)
+assert(co(8) == 8)
+
+-- call the wrap function inside its own coroutine
+local f
+f = coroutine.wrap(function()
+    local ok, msg = pcall(f)
+    return ok, msg
+end)
+ok, r = f()
+assert(not ok and r:find("cannot resume non-suspended coroutine", 1, true))
@@ -3,4 +3,20 @@ print "\xE4\xBD\xA0\xE5\xA5\xBD" -- 你好
 print "\xE4\xBD" -- invalid UTF-8
 print "\72\101\108\108\111" -- Hello
 print "null: \0." -- '\0'
-print("你好")
\ No newline at end of file
+print("你好")
+-- \z skips the following white spaces, including newlines
+local s = "a\z
+           b\z   c"
+assert(s == "abc")
+
+-- line continuation
+s = "x\
+y"
+assert(s == "x\ny")
+
+-- UTF-8
+assert("\u{48}\u{49}" == "HI")
+assert("\u{4F60}\u{597D}" == "你好")
+assert("\u{10FFFF}" == "\xF4\x8F\xBF\xBF")
+assert(#"\u{7FFFFFFF}" == 6 and "\u{7FFFFFFF}" == "\xFD\xBF\xBF\xBF\xBF\xBF")
+assert("\u{0000000041}" == "A")
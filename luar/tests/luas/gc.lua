@@ -0,0 +1,19 @@
+-- reference cycles
+for i = 1, 100 do
+    local t = {}
+    t.self = t
+    local function f() return f end
+    local a, b = {}, {}
+    a.b, b.a = b, a
+end
+
+-- rooted cycle must survive
+keep = {}
+keep.self = keep
+local function g() return g end
+
+assert(collectgarbage() == 0)
+assert(collectgarbage("step") == true)
+assert(type(collectgarbage("count")) == "number")
+assert(keep.self == keep)
+assert(g() == g)
@@ -0,0 +1,49 @@
+-- string chunks
+local f = load("return 1 + ...")
+assert(f(2) == 3)
+assert(load("return ...")(1, 2) == 1)
+
+local f, msg = load("x = ")
+assert(f == nil and msg == '[string "x = "]:1: unexpected symbol near <eof>')
+local f, msg = load("local a\nx = ", "=chunk")
+assert(f == nil and msg == "chunk:2: unexpected symbol near <eof>")
+
+-- reader functions
+local parts = {"return ", "'a'", " .. 'b'"}
+local i = 0
+local f = load(function() i = i + 1; return parts[i] end)
+assert(f() == "ab")
+local f, msg = load(function() return 1 end)
+assert(f == nil and msg == "reader function must return a string")
+local f, msg = load(function() error("read") end)
+assert(f == nil and msg:find("read"))
+
+-- env
+x = 1
+local env = {x = 10}
+local f = load("x = x + 1; return x", "env", "t", env)
+assert(f() == 11 and env.x == 11 and x == 1)
+assert(load("return _ENV", "nil env", "t", nil)() == nil)
+assert(load("return _ENV")() == _ENV)
+assert(load("return function() return y end", "inner", "t", {y = 3})()() == 3)
+
+-- modes
+local bin = string.dump(load("return ... or 'bin'"))
+assert(load(bin)() == "bin" and load(bin, "bin", "b")(1) == 1)
+local f, msg = load(bin, "bin", "t")
+assert(f == nil and msg == "attempt to load a binary chunk (mode is 't')")
+local f, msg = load("return 1", "text", "b")
+assert(f == nil and msg == "attempt to load a text chunk (mode is 'b')")
+assert(not pcall(load, {}))
+
+-- files
+local f = loadfile("tests/luas/chunk.lua")
+assert(f() == 3 and f(10, 20) == 30)
+local n, y = loadfile("tests/luas/chunk.lua", "t", {x = "env"})()
+assert(n == 3 and y == "env")
+local f, msg = loadfile("tests/luas/no-such-file.lua")
+assert(f == nil and msg:find("cannot open tests/luas/no-such-file.lua", 1, true))
+
+local n, y = dofile("tests/luas/chunk.lua")
+assert(n == 3 and y == 1)
+assert(not pcall(dofile, "tests/luas/no-such-file.lua"))
@@ -0,0 +1,35 @@
+--[[ a long comment
+local x = error("not run")
+]]
+--[==[ with ]] and ]=] inside
+]==]
+--[ not a long comment
+--[= neither
+local a = [[
+first newline is skipped]]
+assert(a == "first newline is skipped")
+
+local b = [==[
+line 1
+]] ]=] ]===]
+line 3]==]
+assert(b == "line 1\n]] ]=] ]===]\nline 3")
+
+-- no escapes in long strings
+local c = [[\n\t"quote']]
+assert(#c == 11 and string.sub(c, 1, 2) == "\\n")
+
+-- line numbers are counted across long brackets
+local line = [[
+
+]]
+assert(line == "\n")
+local ok, err = pcall(error, "x")
+assert(err == "x")
+local ok, err = pcall(function() error("here") end)
+assert(err == "?:29: here")
+
+local t = {[1] = "a", [ [[k]] ] = "b"}
+assert(t[1] == "a" and t.k == "b")
+assert(#[[]] == 0 and [[]] .. [=[]=] == "")
+--[[ comment at end of file ]]
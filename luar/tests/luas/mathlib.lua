@@ -0,0 +1,79 @@
+-- integer/float distinction
+assert(math.type(1) == "integer")
+assert(math.type(1.0) == "float")
+assert(math.type("1") == nil)
+assert(math.abs(-3) == 3 and math.type(math.abs(-3)) == "integer")
+assert(math.abs(-3.5) == 3.5)
+assert(math.abs(math.mininteger) == math.mininteger)
+assert(math.floor(3.7) == 3 and math.type(math.floor(3.7)) == "integer")
+assert(math.floor(-3.5) == -4)
+assert(math.ceil(3.2) == 4 and math.type(math.ceil(3.2)) == "integer")
+assert(math.floor(5) == 5)
+assert(math.type(math.floor(1e100)) == "float")
+assert(math.tointeger(3.0) == 3 and math.type(math.tointeger(3.0)) == "integer")
+assert(math.tointeger(3.5) == nil)
+assert(math.tointeger("x") == nil)
+assert(math.type(math.huge) == "float" and math.huge > math.maxinteger)
+assert(-math.huge < math.mininteger)
+
+-- fmod and modf
+assert(math.fmod(7, 3) == 1 and math.type(math.fmod(7, 3)) == "integer")
+assert(math.fmod(-7, 3) == -1)
+assert(math.fmod(7, -3) == 1)
+assert(math.fmod(math.mininteger, -1) == 0)
+assert(math.fmod(7.5, 2) == 1.5)
+local ip, fp = math.modf(3.5)
+assert(ip == 3 and fp == 0.5 and math.type(ip) == "float")
+ip, fp = math.modf(-2.5)
+assert(ip == -2 and fp == -0.5)
+ip, fp = math.modf(5)
+assert(ip == 5 and math.type(ip) == "integer" and fp == 0)
+ip, fp = math.modf(math.huge)
+assert(ip == math.huge and fp == 0)
+
+-- functions on floats
+assert(math.sqrt(16) == 4)
+assert(math.exp(0) == 1)
+assert(math.log(1) == 0)
+assert(math.log(8, 2) == 3)
+assert(math.log(100, 10) == 2)
+assert(math.sin(0) == 0 and math.cos(0) == 1 and math.tan(0) == 0)
+assert(math.abs(math.asin(1) - math.pi / 2) < 1e-12)
+assert(math.acos(1) == 0)
+assert(math.atan(1, 1) == math.pi / 4)
+assert(math.atan(0) == 0)
+assert(math.deg(math.pi) == 180 and math.rad(180) == math.pi)
+
+-- ult, max and min
+assert(math.ult(1, 2))
+assert(math.ult(1, -1))
+assert(not math.ult(-1, 1))
+assert(math.max(1, 5, 3) == 5)
+assert(math.min(4, 2.5, 3) == 2.5)
+assert(math.type(math.max(1, 2.0)) == "float")
+assert(math.type(math.max(2, 1.0)) == "integer")
+
+-- random numbers
+for _ = 1, 100 do
+    local f = math.random()
+    assert(f >= 0 and f < 1 and math.type(f) == "float")
+    local i = math.random(6)
+    assert(i >= 1 and i <= 6 and math.type(i) == "integer")
+    i = math.random(-3, 3)
+    assert(i >= -3 and i <= 3)
+end
+assert(math.random(5, 5) == 5)
+assert(math.type(math.random(0)) == "integer")
+assert(math.random(math.mininteger, math.maxinteger))
+
+-- seeded runs are reproducible
+local a, b = math.randomseed(42)
+assert(a == 42 and b == 0)
+local s1 = {math.random(1000), math.random(1000), math.random(), math.random(0)}
+math.randomseed(42)
+local s2 = {math.random(1000), math.random(1000), math.random(), math.random(0)}
+for i = 1, 4 do
+    assert(s1[i] == s2[i])
+end
+math.randomseed(43)
+assert(math.random(0) ~= s1[1] or math.random(0) ~= s1[2])
@@ -0,0 +1,67 @@
+-- class-style OOP
+local Point = {}
+Point.__index = Point
+
+function Point.new(x, y)
+    return setmetatable({x = x, y = y}, Point)
+end
+
+function Point:len2()
+    return self.x * self.x + self.y * self.y
+end
+
+Point.__add = function(a, b) return Point.new(a.x + b.x, a.y + b.y) end
+Point.__eq = function(a, b) return a.x == b.x and a.y == b.y end
+Point.__lt = function(a, b) return a:len2() < b:len2() end
+Point.__le = function(a, b) return a:len2() <= b:len2() end
+Point.__unm = function(a) return Point.new(-a.x, -a.y) end
+Point.__len = function(a) return 2 end
+Point.__concat = function(a, b) return "point" end
+Point.__call = function(self, dx) return self.x + dx end
+Point.__tostring = function(p) return "Point" end
+
+local p = Point.new(1, 2)
+local q = Point.new(3, 4)
+assert(getmetatable(p) == Point)
+assert(p:len2() == 5)
+
+local r = p + q
+assert(r.x == 4 and r.y == 6)
+assert(p == Point.new(1, 2))
+assert(p ~= q)
+assert(p < q and q > p)
+assert(p <= q and q >= p)
+assert((-p).x == -1)
+assert(#p == 2)
+assert(p .. q == "point")
+assert(p(10) == 11)
+print(p)
+
+-- inheritance by chained __index
+local Base = {hello = function() return "base" end}
+local Derived = setmetatable({}, {__index = Base})
+local obj = setmetatable({}, {__index = Derived})
+assert(obj.hello() == "base")
+
+-- __index and __newindex functions
+local log = {}
+local proxy = setmetatable({}, {
+    __index = function(t, k) return k .. "!" end,
+    __newindex = function(t, k, v) rawset(log, k, v) end,
+})
+assert(proxy.abc == "abc!")
+proxy.x = 10
+assert(rawget(proxy, "x") == nil)
+assert(log.x == 10)
+
+-- raw access
+local t = setmetatable({}, {__index = function() return 1 end})
+assert(t[1] == 1)
+assert(rawget(t, 1) == nil)
+assert(rawequal(t, t))
+assert(rawlen({1, 2, 3}) == 3)
+assert(rawlen("abcd") == 4)
+
+-- protected metatable
+local prot = setmetatable({}, {__metatable = "locked"})
+assert(getmetatable(prot) == "locked")
@@ -0,0 +1,18 @@
+-- decimal
+assert(1-2 == -1)
+assert(3e2 == 300.0 and 3E-2 == 0.03 and 1e+1 == 10)
+assert(.5 == 0.5 and 5. == 5.0 and 0.5e1 == 5)
+assert(math.type(100) == "integer" and math.type(1e2) == "float")
+assert(math.type(9223372036854775807) == "integer")
+assert(math.type(9223372036854775808) == "float")
+assert(9223372036854775808 == 2^63)
+assert(-9223372036854775808 == -2^63)
+
+-- hexadecimal
+assert(0xff == 255 and 0XA == 10 and 0x0 == 0)
+assert(0x7fffffffffffffff == math.maxinteger)
+assert(0xffffffffffffffff == -1)
+assert(0x10000000000000000 == 0) -- wrap around
+assert(math.type(0x1p4) == "float" and 0x1p4 == 16.0)
+assert(0x.8 == 0.5 and 0xA.8p1 == 21.0 and 0x1P-2 == 0.25)
+assert(0x1e == 30 and 0x1e+1 == 31)
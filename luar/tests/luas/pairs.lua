@@ -0,0 +1,82 @@
+-- next and pairs over both parts of tables
+local t = {10, 20, 30, x = "a", y = "b"}
+t.z = "c"
+local n, sum, keys = 0, 0, ""
+for k, v in pairs(t) do
+    n = n + 1
+    if type(k) == "number" then
+        sum = sum + v
+    else
+        keys = keys .. k
+    end
+end
+assert(n == 6 and sum == 60 and keys == "xyz")
+
+assert(next({}) == nil)
+local k, v = next({5})
+assert(k == 1 and v == 5)
+assert(next({5}, 1) == nil)
+
+-- holes in the array part are skipped
+local h = {1, nil, 3}
+n = 0
+for _ in pairs(h) do
+    n = n + 1
+end
+assert(n == 2)
+
+-- assigning and clearing existing fields during traversal
+local m = {a = 1, b = 2, c = 3, d = 4}
+n = 0
+for k in pairs(m) do
+    n = n + 1
+    if k == "a" or k == "c" then
+        m[k] = nil
+    else
+        m[k] = m[k] * 10
+    end
+end
+assert(n == 4)
+assert(m.a == nil and m.b == 20 and m.c == nil and m.d == 40)
+assert(next(m) ~= nil)
+
+-- clearing the array part, which shrinks at the trailing nils
+local a = {1, 2, 3, 4, 5, x = 6}
+n = 0
+for k in pairs(a) do
+    n = n + 1
+    a[k] = nil
+end
+assert(n == 6 and next(a) == nil and #a == 0)
+
+-- integer keys moved from the hash part to the array part
+local g = {}
+g[3] = "c"
+g[1] = "a"
+g[2] = "b"
+n = 0
+for _, v in pairs(g) do
+    n = n + 1
+end
+assert(n == 3 and #g == 3 and g[3] == "c")
+
+-- __pairs
+local p = setmetatable({}, {__pairs = function(t)
+    local i = 0
+    return function()
+        i = i + 1
+        if i <= 3 then
+            return i, i * i
+        end
+    end, t, nil
+end})
+sum = 0
+for k, v in pairs(p) do
+    sum = sum + v
+end
+assert(sum == 14)
+
+-- the iterator of ipairs checks its arguments
+local f = ipairs({})
+local ok, err = pcall(f, {1}, {})
+assert(not ok and err == "bad argument #2 to 'ipairs' (number expected, got table)")
@@ -0,0 +1,113 @@
+-- errors raised by error()
+local ok, err = pcall(error, "plain")
+assert(not ok and err == "plain")
+ok, err = pcall(function() error("at line") end)
+assert(not ok and err == "?:4: at line")
+ok, err = pcall(function() error("no position", 0) end)
+assert(not ok and err == "no position")
+local function raise() error("level 2", 2) end
+ok, err = pcall(function()
+    raise()
+end)
+assert(not ok and err == "?:10: level 2")
+
+-- arbitrary values
+local e = {code = 42}
+ok, err = pcall(error, e)
+assert(not ok and err == e)
+ok, err = pcall(error)
+assert(not ok and err == nil)
+ok, err = pcall(assert, false, e)
+assert(not ok and err == e)
+
+-- errors raised by the VM and libraries
+ok, err = pcall(function() local t = nil; return t.x end)
+assert(not ok and string.find(err, "attempt to index a nil value"))
+ok, err = pcall(string.rep)
+assert(not ok and string.find(err, "bad argument #1 to 'rep'"))
+ok, err = pcall(function()
+    setmetatable(1)
+end)
+assert(not ok and err == "?:29: bad argument #1 to 'setmetatable' (table expected, got number)")
+ok, err = pcall(function() error("msg", 2) end) -- level 2 is pcall
+assert(not ok and err == "msg")
+local function lvl2() error("msg", 2) end
+ok, err = pcall(function() lvl2() end)
+assert(not ok and err == "?:35: msg")
+ok, err = pcall(5)
+assert(not ok and err == "attempt to call a number value")
+
+-- results
+local a, b, c = pcall(function(x, y) return x + y, x * y end, 3, 4)
+assert(a == true and b == 7 and c == 12)
+assert(table.pack(pcall(function() end)).n == 1)
+
+-- upvalues are closed when unwinding
+local getters = {}
+ok = pcall(function()
+    for i = 1, 3 do
+        local v = i * 10
+        getters[i] = function() return v end
+        if i == 3 then error("stop") end
+    end
+end)
+assert(not ok and getters[1]() == 10 and getters[3]() == 30)
+
+-- the state is usable after errors are caught
+local sum = 0
+for i = 1, 100 do
+    local ok = pcall(function() if i % 2 == 0 then error(i) end sum = sum + i end)
+    assert(ok == (i % 2 == 1))
+end
+assert(sum == 2500)
+
+-- nested
+ok, err = pcall(function()
+    local ok2, err2 = pcall(error, "inner")
+    assert(not ok2 and err2 == "inner")
+    error("outer", 0)
+end)
+assert(not ok and err == "outer")
+
+-- xpcall
+ok, err = xpcall(function() error({1}) end, function(e) return #e + 1 end)
+assert(not ok and err == 2)
+ok, err = xpcall(error, function(e) return "handled: " .. e end, "x")
+assert(not ok and err == "handled: x")
+local r1, r2 = xpcall(function(x) return x end, print, "arg")
+assert(r1 == true and r2 == "arg")
+
+-- the handler runs before unwinding, while upvalues are still open
+local depth
+ok, err = xpcall(function()
+    local level = 1
+    depth = function() return level end
+    level = 2
+    error("x")
+end, function() return depth() end)
+assert(not ok and err == 2)
+
+-- errors inside pcall are not seen by the outer handler
+local handled = 0
+ok = xpcall(function()
+    pcall(error, "ignored")
+    error("seen")
+end, function(e) handled = handled + 1 return e end)
+assert(not ok and handled == 1)
+
+-- errors in the handler
+ok, err = xpcall(error, function() error("again") end)
+assert(not ok and err == "error in error handling")
+
+-- errors through coroutines
+local co = coroutine.create(function() error({tag = "co"}) end)
+ok, err = coroutine.resume(co)
+assert(not ok and err.tag == "co")
+ok, err = pcall(coroutine.wrap(function() error("wrapped", 0) end))
+assert(not ok and err == "wrapped")
+co = coroutine.wrap(function()
+    local ok, err = pcall(error, "in coroutine")
+    coroutine.yield(err)
+    return "done"
+end)
+assert(co() == "in coroutine" and co() == "done")
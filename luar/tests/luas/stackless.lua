@@ -0,0 +1,69 @@
+-- deep recursion does not consume the native stack
+local function sum(n)
+    if n == 0 then return 0 end
+    return n + sum(n - 1)
+end
+assert(sum(100000) == 5000050000)
+
+-- tail calls do not grow the call stack
+local function loop(n, acc)
+    if n == 0 then return acc end
+    return loop(n - 1, acc + 1)
+end
+assert(loop(1000000, 0) == 1000000)
+
+-- infinite recursion raises error
+local function inf(n) return 1 + inf(n) end
+local ok, err = pcall(inf, 1)
+assert(not ok and string.find(err, "stack overflow", 1, true))
+
+-- and so through metamethods, which are called by Rust
+local t = setmetatable({}, {__index = function(t, k) return t[k] end})
+ok, err = pcall(function() return t.x end)
+assert(not ok and string.find(err, "stack overflow", 1, true))
+
+-- and through pcall
+local function deep()
+    local _, e = pcall(deep)
+    err = err or e
+end
+err = nil
+deep()
+assert(string.find(err, "stack overflow", 1, true))
+
+-- and through other metamethods and library functions calling Lua
+local function overflow(f, ...)
+    local ok, err = pcall(f, ...)
+    assert(not ok and string.find(err, "stack overflow", 1, true), err)
+end
+local mt = {}
+mt.__add = function(a, b) return a + b end
+mt.__lt = function(a, b) return a < b end
+mt.__eq = function(a, b) return a == b end
+mt.__concat = function(a, b) return a .. b end
+mt.__unm = function(a) return -a end
+mt.__len = function(a) return #a end
+mt.__newindex = function(t, k, v) t[k] = v end
+mt.__tostring = function(a) return tostring(a) end
+local a, b = setmetatable({}, mt), setmetatable({}, mt)
+overflow(function() return a + 1 end)
+overflow(function() return a < b end)
+overflow(function() return a == b end)
+overflow(function() return a .. "x" end)
+overflow(function() return -a end)
+overflow(function() return #a end)
+overflow(function() a.x = 1 end)
+overflow(tostring, a)
+
+local function cmp(x, y) table.sort({3, 2, 1}, cmp) return x < y end
+overflow(table.sort, {3, 2, 1}, cmp)
+local function rep() return (string.gsub("a", "a", rep)) end
+overflow(rep)
+local function resume()
+    local _, e = coroutine.resume(coroutine.create(resume))
+    return e
+end
+assert(string.find(resume(), "stack overflow", 1, true))
+
+-- the stack is still usable after overflow
+assert(sum(100) == 5050)
@@ -0,0 +1,141 @@
+-- basic functions
+assert(string.len("hello") == 5)
+assert(("hello"):len() == 5)
+assert(#"" == 0)
+assert(string.sub("hello", 2, 4) == "ell")
+assert(("hello"):sub(-3) == "llo")
+assert(("hello"):sub(0) == "hello")
+assert(("hello"):sub(4, 2) == "")
+assert(("hello"):sub(-100, 100) == "hello")
+assert(("Hello"):upper() == "HELLO")
+assert(("Hello"):lower() == "hello")
+assert(("ab"):rep(3) == "ababab")
+assert(("ab"):rep(3, ",") == "ab,ab,ab")
+assert(("ab"):rep(0) == "")
+assert(("abc"):reverse() == "cba")
+assert(("ABC"):byte() == 65)
+local a, b, c = ("ABC"):byte(1, -1)
+assert(a == 65 and b == 66 and c == 67)
+assert(string.char(72, 105) == "Hi")
+assert(string.char() == "")
+
+-- find
+assert(("hello world"):find("o w") == 5)
+local i, j = ("hello world"):find("wor")
+assert(i == 7 and j == 9)
+assert(("hello"):find("l", 1, true) == 3)
+assert(("hello"):find("xyz") == nil)
+assert(("hello"):find("") == 1)
+assert(("hello"):find("", 10) == nil)
+assert(("a.b"):find(".", 1, true) == 2)
+i, j = ("hello"):find("l+")
+assert(i == 3 and j == 4)
+i, j, c = ("key=val"):find("(%w+)=")
+assert(i == 1 and j == 4 and c == "key")
+
+-- match
+assert(("hello 123 world"):match("%d+") == "123")
+assert(("  trim  "):match("^%s*(.-)%s*$") == "trim")
+local k, v = ("name = luar"):match("(%w+)%s*=%s*(%w+)")
+assert(k == "name" and v == "luar")
+assert(("hello"):match("()ll()") == 3)
+assert(("hello"):match("^h") == "h")
+assert(("hello"):match("^e") == nil)
+assert(("hello"):match("o$") == "o")
+assert(("[[x]]"):match("%[(.*)%]") == "[x]")
+assert(("f(a(b)c)d"):match("%b()") == "(a(b)c)")
+assert(("THE (quick) fox"):match("%f[%a]%a+", 5) == "quick")
+assert(("abcabc"):match("(abc)%1") == "abc")
+assert(("x = 0x1F"):match("0x(%x+)") == "1F")
+assert(("a-b"):match("[%w-]+") == "a-b")
+assert(("]"):match("[]]") == "]")
+assert(("^"):match("[%^]") == "^")
+assert(("hello"):match("[^aeiou]+") == "h")
+assert(("2024"):match("[0-9]+") == "2024")
+assert(("aaa"):match("a-b") == nil)
+assert(("aaab"):match("a-b") == "aaab")
+assert(("ab"):match("a?b") == "ab")
+assert(("b"):match("a?b") == "b")
+
+-- gmatch
+local words = {}
+for w in ("one two  three"):gmatch("%a+") do
+    words[#words + 1] = w
+end
+assert(#words == 3 and words[1] == "one" and words[3] == "three")
+local n = 0
+for k, v in ("a=1, b=2"):gmatch("(%w+)=(%w+)") do
+    n = n + 1
+    assert((k == "a" and v == "1") or (k == "b" and v == "2"))
+end
+assert(n == 2)
+n = 0
+for _ in ("abc"):gmatch("") do
+    n = n + 1
+end
+assert(n == 4)
+
+-- gsub
+local s, r = ("hello world"):gsub("o", "0")
+assert(s == "hell0 w0rld" and r == 2)
+assert(("hello world"):gsub("o", "0", 1) == "hell0 world")
+assert(("hello world"):gsub("(%w+)", "<%1>") == "<hello> <world>")
+assert(("hello"):gsub("", "-") == "-h-e-l-l-o-")
+assert(("abc"):gsub("%w", "%0%0") == "aabbcc")
+assert(("abc"):gsub("b", "%%") == "a%c")
+assert(("$name is $age"):gsub("%$(%w+)", {name = "luar", age = "1"}) == "luar is 1")
+assert(("abc"):gsub("%w", function(c) return c:upper() end) == "ABC")
+assert(("abc"):gsub("%w", function(c) if c == "b" then return false end return "x" end) == "xbx")
+assert(("hello world"):gsub("^h", "H") == "Hello world")
+
+-- format
+assert(string.format("%d", 42) == "42")
+assert(string.format("%5d|%-5d|%05d", 42, 42, 42) == "   42|42   |00042")
+assert(string.format("%+d %+d", 1, -1) == "+1 -1")
+assert(string.format("%.3d", 7) == "007")
+assert(string.format("%x %X %#x %o", 255, 255, 255, 8) == "ff FF 0xff 10")
+assert(string.format("%x", -1) == "ffffffffffffffff")
+assert(string.format("%c%c", 76, 117) == "Lu")
+assert(string.format("%s and %s", "this", "that") == "this and that")
+assert(string.format("%10s|%-10s|", "right", "left") == "     right|left      |")
+assert(string.format("%.2s", "abc") == "ab")
+assert(string.format("%s", 12) == "12")
+assert(string.format("%5.1f", 3.14159) == "  3.1")
+assert(string.format("%.3f", 2) == "2.000")
+assert(string.format("%e", 12345.678) == "1.234568e+04")
+assert(string.format("%.2E", 0.000123) == "1.23E-04")
+assert(string.format("%g", 100000) == "100000")
+assert(string.format("%g", 1000000) == "1e+06")
+assert(string.format("%g", 0.0001) == "0.0001")
+assert(string.format("%g", 0.00001) == "1e-05")
+assert(string.format("%g", 0.5) == "0.5")
+assert(string.format("%.3g", 3.14159) == "3.14")
+assert(string.format("%#.3g", 1) == "1.00")
+assert(string.format("%a", 1) == "0x1p+0")
+assert(string.format("%a", 0.5) == "0x1p-1")
+assert(string.format("%A", 255.5) == "0X1.FFP+7")
+assert(string.format("%.1a", 1.96875) == "0x2.0p+0")
+assert(string.format("%q", 'a "quoted"\nline\0') == '"a \\"quoted\\"\\\nline\\0"')
+assert(string.format("%q", 1) == "1")
+assert(string.format("%q", 0.5) == "0x1p-1")
+assert(string.format("%5.1f%%", 99.5) == " 99.5%")
+assert(string.format("%s", setmetatable({}, {__tostring = function() return "obj" end})) == "obj")
+
+-- pack and unpack
+assert(string.packsize("i4") == 4)
+assert(string.packsize("!8 b d") == 16)
+assert(string.pack("<i2", 1) == "\1\0")
+assert(string.pack(">i2", 1) == "\0\1")
+assert(string.pack("<I3", 658188) == "\x0c\x0b\x0a")
+assert(string.unpack("<i2", "\255\255") == -1)
+assert(string.unpack("<I2", "\255\255") == 65535)
+local x, y, pos = string.unpack("<i4 i4", string.pack("<i4 i4", 100, -200))
+assert(x == 100 and y == -200 and pos == 9)
+assert(string.unpack("z", "hello\0world") == "hello")
+assert(string.unpack("s1", string.pack("s1", "abc")) == "abc")
+assert(string.pack("c5", "ab") == "ab\0\0\0")
+assert(string.unpack("d", string.pack("d", 3.5)) == 3.5)
+assert(string.unpack("f", string.pack("f", 0.25)) == 0.25)
+assert(string.unpack("<i16", string.pack("<i16", -3)) == -3)
+assert(#string.pack("!4 b i4", 1, 2) == 8)
+assert(getmetatable("").__index == string)
@@ -0,0 +1,79 @@
+-- insert and remove
+local t = {}
+table.insert(t, "a")
+table.insert(t, "c")
+table.insert(t, 2, "b")
+assert(#t == 3 and t[1] == "a" and t[2] == "b" and t[3] == "c")
+table.insert(t, 1, "z")
+assert(#t == 4 and t[1] == "z" and t[4] == "c")
+assert(table.remove(t, 1) == "z")
+assert(#t == 3 and t[1] == "a")
+assert(table.remove(t) == "c")
+assert(#t == 2 and t[3] == nil)
+assert(table.remove({}) == nil)
+
+-- concat
+assert(table.concat({1, 2, 3}) == "123")
+assert(table.concat({"a", "b", "c"}, ", ") == "a, b, c")
+assert(table.concat({"a", "b", "c"}, "-", 2) == "b-c")
+assert(table.concat({"a", "b", "c"}, "-", 2, 2) == "b")
+assert(table.concat({}, "x") == "")
+
+-- pack and unpack
+local p = table.pack(10, nil, 30)
+assert(p.n == 3 and p[1] == 10 and p[3] == 30)
+local a, b, c = table.unpack({1, 2, 3})
+assert(a == 1 and b == 2 and c == 3)
+b, c = table.unpack({1, 2, 3}, 2)
+assert(b == 2 and c == 3)
+assert(table.unpack({1, 2, 3}, 3, 2) == nil)
+
+-- move
+local m = table.move({1, 2, 3}, 1, 3, 2)
+assert(m[1] == 1 and m[2] == 1 and m[3] == 2 and m[4] == 3)
+m = table.move({1, 2, 3, 4}, 2, 4, 1)
+assert(m[1] == 2 and m[2] == 3 and m[3] == 4 and m[4] == 4)
+local dst = table.move({1, 2}, 1, 2, 3, {"a", "b"})
+assert(table.concat(dst, ",") == "a,b,1,2")
+
+-- sort
+local s = {5, 2, 8, 1, 9, 3}
+table.sort(s)
+assert(table.concat(s, ",") == "1,2,3,5,8,9")
+table.sort(s, function(x, y) return x > y end)
+assert(table.concat(s, ",") == "9,8,5,3,2,1")
+local names = {"bob", "alice", "carol"}
+table.sort(names)
+assert(table.concat(names, " ") == "alice bob carol")
+
+local big = {}
+for i = 1, 500 do
+    big[i] = (i * 7919) % 503
+end
+table.sort(big)
+for i = 2, 500 do
+    assert(big[i - 1] <= big[i])
+end
+
+local recs = {{k = 3}, {k = 1}, {k = 2}}
+table.sort(recs, function(x, y) return x.k < y.k end)
+assert(recs[1].k == 1 and recs[2].k == 2 and recs[3].k == 3)
+
+-- __lt is used without comparator
+local V = {}
+V.__lt = function(x, y) return x.v < y.v end
+local vs = {setmetatable({v = 2}, V), setmetatable({v = 1}, V)}
+table.sort(vs)
+assert(vs[1].v == 1)
+
+-- __index and __newindex are respected
+local log = {}
+local proxy = setmetatable({}, {
+    __index = function(_, k) return log[k] end,
+    __newindex = function(_, k, v) log[k] = v end,
+    __len = function() return #log end,
+})
+table.insert(proxy, "x")
+table.insert(proxy, "y")
+assert(#log == 2 and log[2] == "y")
+assert(table.concat(proxy, "+") == "x+y")
@@ -0,0 +1,9 @@
+use std::fs::File;
+use luar::{vm, parse};
+use luar::vm_exec_input;
+
+#[test]
+fn test_arith() {
+    let file = File::open("./tests/luas/arith.lua").unwrap();
+    vm_exec_input!(file);
+}
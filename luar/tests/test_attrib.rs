@@ -0,0 +1,35 @@
+use std::fs::File;
+use std::io::Cursor;
+use luar::{vm, parse};
+use luar::{vm_exec_input, vm_exec_error};
+
+#[test]
+fn test_attrib() {
+    let file = File::open("./tests/luas/attrib.lua").unwrap();
+    vm_exec_input!(file);
+}
+
+#[test]
+fn test_attrib_error() {
+    let load = |src: &str| parse::load(Cursor::new(src.to_string())).unwrap_err();
+
+    let err = load("local x <const> = 1; x = 2");
+    assert_eq!(err.msg(), "attempt to assign to const variable 'x'");
+    let err = load("local x <const> = {}; local y; y, x = 1, 2");
+    assert_eq!(err.msg(), "attempt to assign to const variable 'x'");
+    let err = load("local x <const> = f(); function g() x = 1 end");
+    assert_eq!(err.msg(), "attempt to assign to const variable 'x'");
+    let err = load("local x <close> = nil; function x() end");
+    assert_eq!(err.msg(), "attempt to assign to const variable 'x'");
+    let err = load("local x <static> = 1");
+    assert_eq!(err.msg(), "unknown attribute 'static'");
+    let err = load("local x <close>, y <close> = 1, 2");
+    assert_eq!(err.msg(), "multiple to-be-closed variables in local list");
+
+    let err = vm_exec_error!("local x <close> = {}");
+    assert_eq!(err.msg(), "variable 'x' got a non-closable value");
+    let err = vm_exec_error!("local a, b = 1, 2; do local c; local y <close> = 3 end");
+    assert_eq!(err.msg(), "variable 'y' got a non-closable value");
+    let err = vm_exec_error!("local function f(p, q) local z <close> = p end f(true)");
+    assert_eq!(err.msg(), "variable 'z' got a non-closable value");
+}
@@ -0,0 +1,63 @@
+use std::io::Cursor;
+use luar::bytecode::{ByteCode, MAX_AX, MAX_BX};
+use luar::value::Value;
+use luar::parse::{self, FuncProto};
+
+fn roundtrip(code: ByteCode) {
+    let inst = code.encode().unwrap();
+    assert_eq!(ByteCode::decode(inst), Some(code), "{inst:#010x}");
+}
+
+#[test]
+fn test_encode_decode() {
+    roundtrip(ByteCode::Return0);
+    roundtrip(ByteCode::Move(255, 0));
+    roundtrip(ByteCode::Concat(1, 2, 255));
+    roundtrip(ByteCode::LoadBool(3, true));
+    roundtrip(ByteCode::LoadBool(3, false));
+    roundtrip(ByteCode::LessInt(255, 255, true));
+    roundtrip(ByteCode::LoadInt(7, i16::MIN));
+    roundtrip(ByteCode::LoadInt(7, i16::MAX));
+    roundtrip(ByteCode::LoadConst(255, MAX_BX));
+    roundtrip(ByteCode::ForLoop(0, 0));
+    roundtrip(ByteCode::Jump(0));
+    roundtrip(ByteCode::Jump(-1));
+    roundtrip(ByteCode::Jump((MAX_AX >> 1) as i32));
+    roundtrip(ByteCode::Jump(-((MAX_AX >> 1) as i32)));
+    roundtrip(ByteCode::ExtraArg(MAX_AX));
+
+    // out of range
+    assert_eq!(ByteCode::LoadConst(0, MAX_BX + 1).encode(), None);
+    assert_eq!(ByteCode::Jump(MAX_AX as i32).encode(), None);
+    assert_eq!(ByteCode::Jump(-(MAX_AX as i32)).encode(), None);
+    assert_eq!(ByteCode::ExtraArg(MAX_AX + 1).encode(), None);
+
+    // invalid opcode
+    assert_eq!(ByteCode::decode(0x7f), None);
+}
+
+// all instructions of the parsed functions are decoded and encoded back
+fn check_proto(proto: &FuncProto) {
+    for &inst in proto.byte_codes.iter() {
+        assert_eq!(ByteCode::decode(inst).and_then(ByteCode::encode), Some(inst));
+    }
+    for v in proto.constants.iter() {
+        if let Value::LuaFunction(f) = v {
+            check_proto(f);
+        }
+    }
+}
+
+#[test]
+fn test_parsed_instructions() {
+    let src = std::fs::read_to_string("tests/luas/stackless.lua").unwrap();
+    check_proto(&parse::load(Cursor::new(src)).unwrap());
+
+    let proto = parse::load(Cursor::new("
+        local a, b = ...
+        local x = a and b or -1
+        for i = 1, 10 do if i > 5 or not a then x = x + i end end
+        return function() return x, b end
+    ")).unwrap();
+    check_proto(&proto);
+}
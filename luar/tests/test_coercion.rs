@@ -0,0 +1,9 @@
+use std::fs::File;
+use luar::{vm, parse};
+use luar::vm_exec_input;
+
+#[test]
+fn test_coercion() {
+    let file = File::open("./tests/luas/coercion.lua").unwrap();
+    vm_exec_input!(file);
+}
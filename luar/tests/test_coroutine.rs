@@ -0,0 +1,32 @@
+use std::fs::File;
+use luar::{vm, parse};
+use luar::{vm_exec_input, vm_exec_error};
+
+#[test]
+fn test_coroutine() {
+    let file = File::open("./tests/luas/coroutine.lua").unwrap();
+    vm_exec_input!(file);
+}
+
+#[test]
+fn test_coroutine_error() {
+    let err = vm_exec_error!("coroutine.yield(1)");
+    assert_eq!(err.msg(), "attempt to yield from outside a coroutine");
+
+    let err = vm_exec_error!("coroutine.resume(1)");
+    assert_eq!(err.msg(), "bad argument #1 to 'resume' (coroutine expected, got number)");
+
+    let err = vm_exec_error!("coroutine.wrap()");
+    assert_eq!(err.msg(), "bad argument #1 to 'wrap' (function expected, got no value)");
+
+    // errors in wrapped coroutines are propagated
+    let err = vm_exec_error!("coroutine.wrap(function() assert(false, 'boom') end)()");
+    assert!(err.msg().contains("boom"));
+
+    // can not yield across Rust functions calling back into Lua
+    let err = vm_exec_error!("coroutine.wrap(function() table.sort({1, 2}, function() coroutine.yield() end) end)()");
+    assert_eq!(err.msg(), "attempt to yield across a C-call boundary");
+
+    let err = vm_exec_error!("local co = coroutine.running() coroutine.close(co)");
+    assert_eq!(err.msg(), "cannot close a running coroutine");
+}
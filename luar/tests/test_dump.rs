@@ -0,0 +1,161 @@
+use std::io::Cursor;
+use luar::{vm, parse};
+use luar::bytecode::ByteCode;
+use luar::error::Error;
+use luar::vm::HookMask;
+use luar::parse::FuncProto;
+use luar::value::Value;
+
+const SRC: &str = "
+    local up = 10
+    local function f(a, ...)
+        local t = {...}
+        return a + up, #t, 'long string constant, longer than the middle string'
+    end
+    local n, c, s = f(1, 2, 3)
+    assert(n == 11 and c == 2 and #s > 40)
+    assert(2^53 == 9007199254740992.0 and 1 << 62 == 4611686018427387904)
+    assert(string.dump(f):sub(1, 4) == '\\27Lua')
+    assert(#string.dump(f, true) < #string.dump(f))
+    assert(not pcall(string.dump, print))
+    error('boom')
+";
+
+fn dump(proto: &FuncProto, strip: bool) -> Vec<u8> {
+    let mut buf = Vec::new();
+    proto.dump(&mut buf, strip).unwrap();
+    buf
+}
+
+fn run(proto: &FuncProto) -> String {
+    vm::ExeState::new().execute(proto, &[]).unwrap_err().to_string()
+}
+
+#[test]
+fn test_dump_undump() {
+    let proto = parse::load_with_name(Cursor::new(SRC), "dump.lua").unwrap();
+    assert_eq!(run(&proto), "dump.lua:13: boom");
+
+    let chunk = dump(&proto, false);
+    let loaded = FuncProto::undump(Cursor::new(&chunk)).unwrap();
+    assert_eq!(loaded.source, "dump.lua");
+    assert_eq!(loaded.lineinfo, proto.lineinfo);
+    assert_eq!(loaded.locvars, proto.locvars);
+    assert_eq!(dump(&loaded, false), chunk);
+    assert_eq!(run(&loaded), "dump.lua:13: boom");
+
+    // nested function
+    let Value::LuaFunction(f) = &loaded.constants.iter().find(|v| matches!(v, Value::LuaFunction(_))).unwrap() else {
+        unreachable!();
+    };
+    assert_eq!((f.source.as_str(), f.linedefined, f.name.as_str()), ("dump.lua", 3, "f"));
+
+    // stripped
+    let loaded = parse::load(Cursor::new(dump(&proto, true))).unwrap();
+    assert_eq!(loaded.source, "?");
+    assert!(loaded.lineinfo.is_empty());
+    assert!(loaded.locvars.is_empty());
+    assert_eq!(run(&loaded), "boom");
+}
+
+#[test]
+fn test_load_mode() {
+    let proto = parse::load(Cursor::new("return 1")).unwrap();
+    let chunk = dump(&proto, false);
+
+    assert!(parse::load_with_mode(Cursor::new(&chunk), "?", "b").is_ok());
+    assert!(parse::load_with_mode(Cursor::new("return 1"), "?", "t").is_ok());
+    let err = parse::load_with_mode(Cursor::new(&chunk), "?", "t").unwrap_err();
+    assert_eq!(err.msg(), "attempt to load a binary chunk (mode is 't')");
+    let err = parse::load_with_mode(Cursor::new("return 1"), "?", "b").unwrap_err();
+    assert_eq!(err.msg(), "attempt to load a text chunk (mode is 'b')");
+}
+
+#[test]
+fn test_bad_chunk() {
+    let proto = parse::load(Cursor::new("return 1")).unwrap();
+    let chunk = dump(&proto, false);
+
+    let load_err = |chunk: &[u8]| parse::load(Cursor::new(chunk)).unwrap_err().msg().to_string();
+    assert_eq!(load_err(&chunk[..chunk.len() - 1]), "bad binary format (truncated chunk)");
+    assert_eq!(load_err(&chunk[..3]), "bad binary format (truncated chunk)");
+
+    let mut bad = chunk.clone();
+    bad[4] = 0x53;
+    assert_eq!(load_err(&bad), "bad binary format (version mismatch)");
+
+    // integer of other endianness
+    let mut bad = chunk.clone();
+    bad[15..23].reverse();
+    assert_eq!(load_err(&bad), "bad binary format (integer format mismatch)");
+}
+
+#[test]
+fn test_bad_operands() {
+    let proto = parse::load(Cursor::new("local a = 'k' return a")).unwrap();
+    let load_err = |codes: &[ByteCode]| {
+        let mut bad = proto.clone();
+        bad.byte_codes = codes.iter().map(|c| c.encode().unwrap()).collect();
+        bad.lineinfo.clear();
+        bad.locvars.clear();
+        parse::load(Cursor::new(dump(&bad, false))).unwrap_err().msg().to_string()
+    };
+
+    assert_eq!(load_err(&[]), "bad binary format (no byte codes)");
+    assert_eq!(load_err(&[ByteCode::LoadConst(0, 9), ByteCode::Return0]),
+        "bad binary format (invalid constant index)");
+    assert_eq!(load_err(&[ByteCode::Closure(0, 0), ByteCode::Return0]),
+        "bad binary format (invalid function index)");
+    assert_eq!(load_err(&[ByteCode::GetUpvalue(0, 1), ByteCode::Return0]),
+        "bad binary format (invalid upvalue index)");
+    assert_eq!(load_err(&[ByteCode::Move(255, 0), ByteCode::Return0]),
+        "bad binary format (invalid register)");
+    assert_eq!(load_err(&[ByteCode::ForPrepare(253, 0), ByteCode::Return0]),
+        "bad binary format (invalid register)");
+    assert_eq!(load_err(&[ByteCode::LoadConstX(0), ByteCode::Return0]),
+        "bad binary format (missing ExtraArg)");
+    assert_eq!(load_err(&[ByteCode::ExtraArg(0), ByteCode::Return0]),
+        "bad binary format (misplaced ExtraArg)");
+    assert_eq!(load_err(&[ByteCode::Jump(5), ByteCode::Return0]),
+        "bad binary format (invalid jump)");
+    assert_eq!(load_err(&[ByteCode::ForLoop(0, 3), ByteCode::Return0]),
+        "bad binary format (invalid jump)");
+    assert_eq!(load_err(&[ByteCode::LoadNil(0, 1)]),
+        "bad binary format (invalid jump)");
+    assert_eq!(load_err(&[ByteCode::TestAndJump(0), ByteCode::Return0]),
+        "bad binary format (invalid jump)");
+}
+
+// corrupted chunks raise errors but never panic
+#[test]
+fn test_corrupted_chunk() {
+    let src = "
+        local up, t = 0, {}
+        local function f(a, ...)
+            up = up + a
+            for i = 1, 3 do t[i] = i * a end
+            for k, v in pairs(t) do t[k] = v .. 'x' end
+            local s <close> = nil
+            return #{...}, table.concat(t), {...}
+        end
+        return f(1, 2, 3), string.rep('x', 3), -up, #t, up < 1 and 'y'
+    ";
+    let proto = parse::load(Cursor::new(src)).unwrap();
+    assert!(vm::ExeState::new().execute(&proto, &[]).is_ok());
+    let chunk = dump(&proto, false);
+
+    for i in 0..chunk.len() {
+        for b in [0, 0xff, chunk[i] ^ 1, chunk[i] ^ 0x80] {
+            let mut bad = chunk.clone();
+            bad[i] = b;
+            let Ok(proto) = parse::load(Cursor::new(bad)) else {
+                continue;
+            };
+            let mut state = vm::ExeState::new();
+            // stop infinite loops
+            state.set_hook(HookMask { count: 10000, ..Default::default() },
+                |_, _| Err(Error::runtime("too many steps")));
+            let _ = state.execute(&proto, &[]);
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::io::Cursor;
+use luar::{vm, parse};
+use luar::error::Error;
+
+fn run(src: &str) -> Result<usize, Error> {
+    let proto = parse::load(Cursor::new(src.to_string()))?;
+    vm::ExeState::new().execute(&proto, &[])
+}
+
+#[test]
+fn test_syntax_error() {
+    let err = run("local a = = 1").unwrap_err();
+    assert!(matches!(err, Error::Syntax { line: 1, .. }), "{err:?}");
+
+    let err = run("print 'ok'\nlocal s = 'abc").unwrap_err();
+    assert!(matches!(err, Error::Syntax { line: 2, .. }), "{err:?}");
+
+    let err = run("goto nowhere").unwrap_err();
+    assert!(matches!(err, Error::Syntax { .. }), "{err:?}");
+
+    // a goto leaving a block still can not jump into the scope of a local
+    let err = run("do local a goto l end local b ::l:: print(b)").unwrap_err();
+    assert!(matches!(err, Error::Syntax { .. }), "{err:?}");
+}
+
+#[test]
+fn test_syntax_error_token() {
+    let msg = |src: &str| run(src).unwrap_err().msg().to_string();
+    assert_eq!(msg("x = = 1"), "unexpected symbol near '='");
+    assert_eq!(msg("x ="), "unexpected symbol near <eof>");
+    assert_eq!(msg("if x then"), "'end' expected near <eof>");
+    assert_eq!(msg("local function f(a b) end"), "')' expected near 'b'");
+    assert_eq!(msg("for k v in t do end"), "'in' expected near 'v'");
+    assert_eq!(msg("x, y 1"), "'=' expected near '1'");
+    assert_eq!(msg("t = {1 2}"), "'}' expected near '2'");
+    assert_eq!(msg("t = {1 2.5}"), "'}' expected near '2.5'");
+    assert_eq!(msg("t = {'a' 'b'}"), "'}' expected near '\"b\"'");
+}
+
+#[test]
+fn test_syntax_error_eof() {
+    // incomplete, more input may complete it
+    for src in ["if x then", "x = 1 +", "f(", "t = {1,", "s = [[abc", "--[==[ comment"] {
+        let err = run(src).unwrap_err();
+        assert!(err.is_eof(), "{src}: {err:?}");
+    }
+    for src in ["x = = 1", "if x then end end", "goto nowhere", "s = 'abc"] {
+        let err = run(src).unwrap_err();
+        assert!(!err.is_eof(), "{src}: {err:?}");
+    }
+}
+
+#[test]
+fn test_runtime_error() {
+    let err = run("local t = nil; t.x = 1").unwrap_err();
+    assert!(matches!(err, Error::Type { .. }), "{err:?}");
+    assert_eq!(err.msg(), "attempt to index a nil value");
+
+    let err = run("local f = 1; f()").unwrap_err();
+    assert!(matches!(err, Error::Call { .. }), "{err:?}");
+
+    let err = run("local a = {} + 1").unwrap_err();
+    assert!(matches!(err, Error::Arith { .. }), "{err:?}");
+
+    let err = run("local a = {} < 1").unwrap_err();
+    assert_eq!(err.msg(), "attempt to compare table with number");
+
+    let err = run("for i = 1, 10, 0 do end").unwrap_err();
+    assert_eq!(err.msg(), "'for' step is zero");
+
+    let err = run("assert(false, 'boom')").unwrap_err();
+    assert_eq!(err.msg(), "boom");
+}
+
+#[test]
+fn test_traceback() {
+    let src = "
+local function inner()
+  local t = nil
+  return t.x
+end
+function outer()
+  inner()
+end
+outer()
+";
+    let proto = parse::load_with_name(Cursor::new(src), "tb.lua").unwrap();
+    assert_eq!(proto.lineinfo.len(), proto.byte_codes.len());
+
+    let err = vm::ExeState::new().execute(&proto, &[]).unwrap_err();
+    assert_eq!(err.line(), 4);
+
+    let frames: Vec<_> = err.traceback().frames.iter()
+        .map(|f| (f.source.as_str(), f.line, f.name.as_str()))
+        .collect();
+    assert_eq!(frames, vec![
+        ("tb.lua", 4, "function 'inner'"),
+        ("tb.lua", 7, "function 'outer'"),
+        ("tb.lua", 9, "main chunk"),
+    ]);
+    assert_eq!(err.traceback().to_string(), "stack traceback:
+\ttb.lua:4: in function 'inner'
+\ttb.lua:7: in function 'outer'
+\ttb.lua:9: in main chunk");
+
+    // errors raised by Rust functions are positioned at the caller
+    let err = run("\n\nassert(false)").unwrap_err();
+    assert_eq!(err.line(), 3);
+    assert_eq!(err.to_string(), "?:3: assertion failed!");
+    assert_eq!(err.traceback().frames[0].source, "[Rust]");
+    assert_eq!(err.traceback().frames[1].line, 3);
+
+    // but not if the caller is also a Rust function
+    let err = run("local ok, err = pcall(setmetatable, 1)\nerror(err, 0)").unwrap_err();
+    assert_eq!(err.to_string(), "bad argument #1 to 'setmetatable' (table expected, got number)");
+}
@@ -0,0 +1,26 @@
+use std::fs::File;
+use std::io::Cursor;
+use luar::{vm, parse};
+use luar::vm_exec_input;
+
+#[test]
+fn test_escape() {
+    let file = File::open("./tests/luas/escape.lua").unwrap();
+    vm_exec_input!(file);
+}
+
+#[test]
+fn test_escape_error() {
+    let load = |src: &str| parse::load(Cursor::new(src.to_string())).unwrap_err();
+
+    let err = load("s = 'ok'\ns = '\\q'");
+    assert_eq!(err.msg(), "invalid escape sequence '\\q'");
+    assert_eq!(err.line(), 2);
+
+    assert_eq!(load("s = '\\u{FFFFFFFFF}'").msg(), "UTF-8 value too large");
+    assert_eq!(load("s = '\\u41'").msg(), "missing '{' in \\u{xxxx}");
+    assert_eq!(load("s = '\\u{41'").msg(), "missing '}' in \\u{xxxx}");
+    assert_eq!(load("s = '\\u{}'").msg(), "hexadecimal digit expected");
+    assert_eq!(load("s = '\\xZZ'").msg(), "hexadecimal digit expected");
+    assert_eq!(load("s = '\\256'").msg(), "decimal escape too large near '\\256'");
+}
@@ -0,0 +1,52 @@
+use std::fs::File;
+use std::io::{BufReader, Cursor};
+use luar::{vm, parse};
+use luar::value::Value;
+
+#[test]
+fn test_collect_cycles() {
+    let file = File::open("./tests/luas/gc.lua").unwrap();
+    let proto = parse::load(BufReader::new(file)).unwrap();
+
+    let mut state = vm::ExeState::new();
+    state.heap().set_auto(false);
+    // `_ENV` and the standard library
+    let nbase = state.heap().len();
+
+    state.execute(&proto, &[]).unwrap();
+
+    // `keep`, and `g` with its upvalue
+    state.heap().collect();
+    assert_eq!(state.heap().len(), nbase + 3);
+}
+
+// collect with the argument table mutably borrowed
+fn collect_borrowed(state: &mut vm::ExeState) -> luar::error::Result<i32> {
+    let Value::Table(t) = state.get::<&Value>(1).clone() else {
+        unreachable!();
+    };
+    let _guard = t.borrow_mut();
+    let n = state.heap().collect();
+    state.push(n as i64);
+    Ok(1)
+}
+
+#[test]
+fn test_collect_while_borrowed() {
+    let src = "
+        collectgarbage()
+        local t = {}
+        t.self = t
+        t = nil
+        local held = {}
+        held.self = held
+        assert(collect_borrowed(held) == 1)
+        assert(held.self == held)
+    ";
+    let proto = parse::load(Cursor::new(src)).unwrap();
+
+    let mut state = vm::ExeState::new();
+    state.heap().set_auto(false);
+    state.set_global("collect_borrowed", Value::RustFunction(collect_borrowed));
+    state.execute(&proto, &[]).unwrap();
+}
@@ -8,8 +8,8 @@ use luar::parse;
 fn hello_once() {
     let file = File::open("./tests/luas/hello.lua").unwrap();
     // let res = vm_exec_input!(file);
-    let proto = parse::load(BufReader::new(file));
-    vm::ExeState::new().execute(&proto, &Vec::new());
+    let proto = parse::load(BufReader::new(file)).unwrap();
+    vm::ExeState::new().execute(&proto, &[]).unwrap();
 }
 
 #[test]
@@ -0,0 +1,69 @@
+use std::cell::RefCell;
+use std::io::Cursor;
+use std::rc::Rc;
+use luar::{vm, parse};
+use luar::vm::{HookEvent, HookMask};
+use luar::error::Error;
+
+fn run_with_hook(src: &str, mask: HookMask) -> (Vec<HookEvent>, Result<usize, Error>) {
+    let proto = parse::load(Cursor::new(src.to_string())).unwrap();
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let mut state = vm::ExeState::new();
+    let events1 = events.clone();
+    state.set_hook(mask, move |_, event| {
+        events1.borrow_mut().push(event);
+        Ok(())
+    });
+    let r = state.execute(&proto, &[]);
+    let events = events.borrow().clone();
+    (events, r)
+}
+
+#[test]
+fn test_line_hook() {
+    let src = "local a = 1
+local b = 2
+for i = 1, 2 do
+  a = a + i
+end";
+    let mask = HookMask { line: true, ..Default::default() };
+    let (events, r) = run_with_hook(src, mask);
+    r.unwrap();
+    let lines: Vec<usize> = events.iter().map(|e| match e {
+        HookEvent::Line(line) => *line,
+        _ => panic!("unexpected event {e:?}"),
+    }).collect();
+    // the loop jumps back at line 5
+    assert_eq!(lines, [1, 2, 3, 4, 5, 4, 5]);
+}
+
+#[test]
+fn test_call_hook() {
+    let src = "local function f() return 1 end
+f()
+print()";
+    let mask = HookMask { call: true, ret: true, ..Default::default() };
+    let (events, r) = run_with_hook(src, mask);
+    r.unwrap();
+    use HookEvent::*;
+    // main chunk, f() and print()
+    assert_eq!(events, [Call, Call, Return, Call, Return, Return]);
+}
+
+#[test]
+fn test_count_hook() {
+    let src = "local n = 0
+for i = 1, 10 do n = n + i end";
+    let mask = HookMask { count: 10, ..Default::default() };
+    let (events, r) = run_with_hook(src, mask);
+    r.unwrap();
+    assert!(!events.is_empty() && events.iter().all(|e| *e == HookEvent::Count));
+
+    // abort the execution by raising error in hook
+    let proto = parse::load(Cursor::new("while true do end".to_string())).unwrap();
+    let mut state = vm::ExeState::new();
+    state.set_hook(HookMask { count: 1000, ..Default::default() },
+        |_, _| Err(Error::runtime("timeout")));
+    let err = state.execute(&proto, &[]).unwrap_err();
+    assert_eq!(err.msg(), "timeout");
+}
@@ -0,0 +1,126 @@
+use std::io::Cursor;
+use std::fmt::Write;
+use luar::{vm, parse};
+
+fn exec(src: String) {
+    let proto = parse::load(Cursor::new(src)).unwrap();
+    if let Err(err) = vm::ExeState::new().execute(&proto, &[]) {
+        panic!("{err}");
+    }
+}
+
+fn load_err(src: String) -> String {
+    parse::load(Cursor::new(src)).unwrap_err().msg().to_string()
+}
+
+#[test]
+fn test_many_constants() {
+    // more than 256 constants, so the keys and operands at the end do
+    // not fit in u8
+    let mut src = String::from("local t = {\n");
+    for i in 0..1000 {
+        writeln!(src, "k{i} = {i}.5, [\"s{i}\"] = 's{i}',").unwrap();
+    }
+    src.push_str("}\n");
+    src.push_str("
+        assert(t.k999 == 999.5 and t['s999'] == 's999')
+        assert(t.k999 + 0.25 == 999.75 and t.k998 - 998.5 == 0)
+        assert(t.k999 ~= 999.25 and t.s999 == 's999' and t.s998 < 's999')
+        t.k999 = 's1' t['k998'] = 1.5 t.k997 = t.k996
+        assert(t.k999 == 's1' and t.k998 == 1.5 and t.k997 == 996.5)
+        g999 = 1.25 g998 = g999
+        assert(g998 == 1.25 and _ENV.g999 == 1.25)
+        function t:m999() return self.k996 end
+        assert(t:m999() == 996.5)
+        function g997(x) return x * 0.75 end
+        assert(g997(2) == 1.5)
+        local function f() return g999 + t.k996 end
+        assert(f() == 997.75)
+        local c <const> = 2 ^ 53 + 0.5
+        assert(c - 2 ^ 53 == 0)
+
+        -- keys using temporary registers, with the table not in stack
+        g996 = {}
+        g996[t.k1 + t.k2 + t.k3] = 1
+        g996[#g996 + 1] = 2
+        assert(g996[7.5] == 1 and g996[1] == 2)
+    ");
+    exec(src);
+}
+
+#[test]
+fn test_large_constant_table() {
+    // constants are de-duplicated in constant time, or this takes long
+    let n = 100000;
+    let mut src = String::from("local t = {");
+    for i in 0..n {
+        write!(src, "'s{i}', {i}.5, 's{i}', ").unwrap();
+    }
+    src.push_str("}\n");
+    let proto = parse::load(Cursor::new(src.clone())).unwrap();
+    assert_eq!(proto.constants.len(), 2 * n);
+    write!(src, "assert(#t == {} and t[{}] == 's{}' and t[{}] == {}.5)", 3 * n, 3 * n, n - 1, 3 * n - 1, n - 1).unwrap();
+    exec(src);
+
+    // integer and float of same value are different constants
+    let proto = parse::load(Cursor::new("local a, b, c, d = 1, 1.0, 0.0, -0.0".to_string())).unwrap();
+    assert_eq!(proto.constants.len(), 3);
+}
+
+#[test]
+fn test_long_jumps() {
+    // long blocks and expressions, beyond i16 and u8 distance of jumps
+    let stats: String = (0..20000).map(|i| format!("n = n + t[{}]\n", i % 10 + 1)).collect();
+    let exps: String = (0..20000).map(|i| format!("t[{}],", i % 10 + 1)).collect();
+    exec(format!("
+        local t = {{1, 1, 1, 1, 1, 1, 1, 1, 1, 1}}
+        local n = 0
+        if n == 0 then {stats} else error('else') end
+        assert(n == 20000)
+        while n < 60000 do {stats} end
+        assert(n == 60000)
+        for i = 1, 2 do {stats} end
+        assert(n == 100000)
+        for _, v in ipairs({{1, 2}}) do {stats} end
+        assert(n == 140000)
+        repeat {stats} until n > 150000
+        assert(n == 160000)
+
+        local x = nil
+        local y = x or {{ {exps} }}
+        assert(#y == 20000)
+        local z = n > 0 and (x or {{ {exps} }})
+        assert(#z == 20000)
+    "));
+}
+
+#[test]
+fn test_limit_errors() {
+    let locals: Vec<String> = (0..201).map(|i| format!("a{i}")).collect();
+    let msg = load_err(format!("local {} = 1", locals.join(",")));
+    assert_eq!(msg, "too many local variables (limit is 200)");
+
+    let msg = load_err(format!("function f({}) end", locals.join(",")));
+    assert_eq!(msg, "too many local variables (limit is 200)");
+
+    let args: Vec<String> = (0..300).map(|i| i.to_string()).collect();
+    let msg = load_err(format!("print({})", args.join(",")));
+    assert_eq!(msg, "function or expression needs too many registers");
+
+    let vars: Vec<String> = (0..300).map(|i| format!("g{i}")).collect();
+    let msg = load_err(format!("{} = f()", vars.join(",")));
+    assert_eq!(msg, "function or expression needs too many registers");
+
+    // 300 upvalues through 2 levels
+    let mut src = String::new();
+    for i in 0..300 {
+        writeln!(src, "local a{i} = {i}").unwrap();
+        if i == 149 {
+            src.push_str("local function f()\n");
+        }
+    }
+    let uses: Vec<String> = (0..300).map(|i| format!("a{i}")).collect();
+    writeln!(src, "return function() return {} end end", uses.join("+")).unwrap();
+    let msg = load_err(src);
+    assert_eq!(msg, "too many upvalues (limit is 255)");
+}
@@ -0,0 +1,25 @@
+use std::fs::File;
+use std::io::Cursor;
+use luar::{vm, parse};
+use luar::vm_exec_input;
+
+#[test]
+fn test_longstring() {
+    let file = File::open("./tests/luas/longstring.lua").unwrap();
+    vm_exec_input!(file);
+}
+
+#[test]
+fn test_longstring_error() {
+    let load = |src: &str| parse::load(Cursor::new(src.to_string())).unwrap_err();
+
+    let err = load("local s = [[abc\n\n");
+    assert_eq!(err.msg(), "unfinished long string (starting at line 1)");
+    assert_eq!(err.line(), 3);
+
+    let err = load("print(1)\n--[==[ comment ]=]");
+    assert_eq!(err.msg(), "unfinished long comment (starting at line 2)");
+
+    let err = load("local s = [==abc]==]");
+    assert_eq!(err.msg(), "invalid long string delimiter");
+}
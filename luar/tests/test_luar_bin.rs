@@ -0,0 +1,53 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+const LUAR: &str = env!("CARGO_BIN_EXE_luar");
+
+#[test]
+fn test_script_args() {
+    let output = Command::new(LUAR)
+        .args(["-e", "from_e = 1", "./tests/luas/args.lua", "x", "y"])
+        .output().unwrap();
+    assert!(output.status.success());
+}
+
+#[test]
+fn test_script_error() {
+    let output = Command::new(LUAR).args(["-e", "error('boom')"]).output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("luar: (command line):1: boom\n"));
+}
+
+#[test]
+fn test_syntax_error() {
+    let fname = std::env::temp_dir().join(format!("luar_syntax_{}.lua", std::process::id()));
+    std::fs::write(&fname, "x = 1\ny = = 2\n").unwrap();
+    let output = Command::new(LUAR).arg(&fname).output().unwrap();
+    std::fs::remove_file(&fname).unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let expect = format!("luar: {}:2: unexpected symbol near '='\n", fname.display());
+    assert!(stderr.starts_with(&expect), "{stderr}");
+
+    let output = Command::new(LUAR).args(["-e", "x = = 1"]).output().unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("luar: (command line):1: unexpected symbol near '='\n"), "{stderr}");
+}
+
+#[test]
+fn test_repl() {
+    let mut child = Command::new(LUAR).arg("-i")
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+        .spawn().unwrap();
+    child.stdin.take().unwrap()
+        .write_all(b"x = 1 +\n2\n=x * 10\nx, x + 1\nlocal t = {} .. 1\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.lines().any(|l| l.ends_with("30")));
+    assert!(stdout.lines().any(|l| l.ends_with("3\t4")));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("attempt to concatenate a table value"));
+}
@@ -0,0 +1,36 @@
+use std::process::Command;
+
+const LUARC: &str = env!("CARGO_BIN_EXE_luarc");
+
+#[test]
+fn test_listing() {
+    let output = Command::new(LUARC).arg("./tests/luas/hello.lua").output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("main <./tests/luas/hello.lua:0>"));
+    assert!(stdout.contains("; 'print'"));
+}
+
+#[test]
+fn test_load_error() {
+    let output = Command::new(LUARC).arg("./tests/luas/no-such-file.lua").output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("luarc: cannot open ./tests/luas/no-such-file.lua"));
+}
+
+#[test]
+fn test_output() {
+    let out = std::env::temp_dir().join(format!("luarc-test-{}.out", std::process::id()));
+    let status = Command::new(LUARC).args(["-s", "-o"]).arg(&out)
+        .arg("./tests/luas/hello.lua").status().unwrap();
+    assert!(status.success());
+
+    // list the binary chunk, stripped
+    let output = Command::new(LUARC).arg(&out).output().unwrap();
+    std::fs::remove_file(&out).unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("main <?:0>"));
+    assert!(stdout.contains("; 'print'"));
+}
@@ -0,0 +1,41 @@
+use std::fs::File;
+use std::io::Cursor;
+use luar::{vm, parse};
+use luar::{vm_exec_input, vm_exec_error};
+
+#[test]
+fn test_mathlib() {
+    let file = File::open("./tests/luas/mathlib.lua").unwrap();
+    vm_exec_input!(file);
+}
+
+#[test]
+fn test_mathlib_error() {
+    let err = vm_exec_error!("math.floor('x')");
+    assert_eq!(err.msg(), "bad argument #1 to 'floor' (number expected, got string)");
+
+    let err = vm_exec_error!("math.fmod(1, 0)");
+    assert_eq!(err.msg(), "bad argument #2 to 'fmod' (zero)");
+
+    let err = vm_exec_error!("math.random(2, 1)");
+    assert_eq!(err.msg(), "bad argument #1 to 'random' (interval is empty)");
+
+    let err = vm_exec_error!("math.random(1, 2, 3)");
+    assert_eq!(err.msg(), "wrong number of arguments");
+
+    let err = vm_exec_error!("math.max()");
+    assert_eq!(err.msg(), "bad argument #1 to 'max' (number expected, got no value)");
+}
+
+// the same seed gives the same sequence in different states
+#[test]
+fn test_random_seed() {
+    let src = "math.randomseed(2024) return math.random(0)";
+    let run = || {
+        let proto = parse::load(Cursor::new(src.to_string())).unwrap();
+        let mut state = vm::ExeState::new();
+        state.execute(&proto, &[]).unwrap();
+        state.get::<i64>(1)
+    };
+    assert_eq!(run(), run());
+}
@@ -0,0 +1,27 @@
+use std::fs::File;
+use luar::{vm, parse};
+use luar::{vm_exec_input, vm_exec_error};
+
+#[test]
+fn test_metatable() {
+    let file = File::open("./tests/luas/metatable.lua").unwrap();
+    vm_exec_input!(file);
+}
+
+#[test]
+fn test_metatable_error() {
+    let err = vm_exec_error!("local t = setmetatable({}, {}); t()");
+    assert_eq!(err.msg(), "attempt to call a table value");
+
+    let err = vm_exec_error!("local t = setmetatable({}, {}); local x = t + 1");
+    assert_eq!(err.msg(), "attempt to perform arithmetic on a table value");
+
+    let err = vm_exec_error!("local t = setmetatable({}, {__metatable = 1}); setmetatable(t, {})");
+    assert_eq!(err.msg(), "cannot change a protected metatable");
+
+    let err = vm_exec_error!("setmetatable(1, {})");
+    assert_eq!(err.msg(), "bad argument #1 to 'setmetatable' (table expected, got number)");
+
+    let err = vm_exec_error!("local t = {}; setmetatable(t, {__index = t}); local x = t.x");
+    assert_eq!(err.msg(), "'__index' chain too long; possibly a loop");
+}
@@ -0,0 +1,22 @@
+use std::fs::File;
+use std::io::Cursor;
+use luar::{vm, parse};
+use luar::vm_exec_input;
+
+#[test]
+fn test_numeral() {
+    let file = File::open("./tests/luas/numeral.lua").unwrap();
+    vm_exec_input!(file);
+}
+
+#[test]
+fn test_malformed_number() {
+    let load = |src: &str| parse::load(Cursor::new(src.to_string())).unwrap_err();
+
+    assert_eq!(load("x = 3x").msg(), "malformed number near '3x'");
+    assert_eq!(load("x = 1e").msg(), "malformed number near '1e'");
+    assert_eq!(load("x = 0x").msg(), "malformed number near '0x'");
+    assert_eq!(load("x = 1.2.3").msg(), "malformed number near '1.2.3'");
+    assert_eq!(load("x = 0x1p").msg(), "malformed number near '0x1p'");
+    assert_eq!(load("\nx = 08f").line(), 2);
+}
@@ -0,0 +1,21 @@
+use std::fs::File;
+use luar::{vm, parse};
+use luar::{vm_exec_input, vm_exec_error};
+
+#[test]
+fn test_pairs() {
+    let file = File::open("./tests/luas/pairs.lua").unwrap();
+    vm_exec_input!(file);
+}
+
+#[test]
+fn test_pairs_error() {
+    let err = vm_exec_error!("next({}, 'x')");
+    assert_eq!(err.msg(), "invalid key to 'next'");
+
+    let err = vm_exec_error!("pairs()");
+    assert_eq!(err.msg(), "bad argument #1 to 'pairs' (value expected)");
+
+    let err = vm_exec_error!("for k in pairs(5) do end");
+    assert_eq!(err.msg(), "bad argument #1 to 'next' (table expected, got number)");
+}
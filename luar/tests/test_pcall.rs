@@ -0,0 +1,44 @@
+use std::fs::File;
+use std::io::Cursor;
+use luar::{vm, parse};
+use luar::{vm_exec_input, vm_exec_error};
+
+#[test]
+fn test_pcall() {
+    let file = File::open("./tests/luas/pcall.lua").unwrap();
+    vm_exec_input!(file);
+}
+
+#[test]
+fn test_error_value() {
+    let err = vm_exec_error!("error('boom')");
+    assert_eq!(err.msg(), "?:1: boom");
+    assert_eq!(err.to_value(), "?:1: boom".into());
+
+    let err = vm_exec_error!("error({})");
+    assert_eq!(err.msg(), "(error object is a table value)");
+
+    let err = vm_exec_error!("xpcall(print)");
+    assert_eq!(err.msg(), "bad argument #2 to 'xpcall' (function expected, got no value)");
+
+    let err = vm_exec_error!("pcall()");
+    assert_eq!(err.msg(), "bad argument #1 to 'pcall' (value expected)");
+}
+
+#[test]
+fn test_pcall_many_args() {
+    let src = "
+local t = {}
+for i = 1, 300 do t[i] = i end
+local function count(...) local a = {...} return #a, a[#a] end
+for _, n in ipairs({254, 255, 256, 300}) do
+    local ok, got, last = pcall(count, table.unpack(t, 1, n))
+    assert(ok and got == n and last == n)
+end
+local ok, got = xpcall(count, print, table.unpack(t))
+assert(ok and got == 300)
+local f = coroutine.wrap(count)
+assert(f(table.unpack(t)) == 300)
+";
+    vm_exec_input!(Cursor::new(src));
+}
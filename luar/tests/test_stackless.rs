@@ -0,0 +1,26 @@
+use std::fs::File;
+use std::io::Cursor;
+use luar::{vm, parse};
+use luar::vm_exec_input;
+
+#[test]
+fn test_stackless() {
+    // nested calls from Rust, e.g. metamethods and pcall, still consume
+    // the native stack, but are limited to fit in the default 2MB stack
+    // of the test thread
+    let file = File::open("./tests/luas/stackless.lua").unwrap();
+    vm_exec_input!(file);
+}
+
+#[test]
+fn test_max_frames() {
+    let src = "local function f(n) if n > 0 then return 1 + f(n - 1) end return 0 end
+        assert(f(90) == 90)
+        f(100)";
+    let proto = parse::load(Cursor::new(src.to_string())).unwrap();
+    let mut state = vm::ExeState::new();
+    state.set_max_frames(100);
+    let err = state.execute(&proto, &[]).unwrap_err();
+    assert_eq!(err.msg(), "stack overflow");
+    assert_eq!(err.line(), 1);
+}
@@ -0,0 +1,48 @@
+use std::fs::File;
+use luar::{vm, parse};
+use luar::{vm_exec_input, vm_exec_error};
+
+#[test]
+fn test_strlib() {
+    let file = File::open("./tests/luas/strlib.lua").unwrap();
+    vm_exec_input!(file);
+}
+
+#[test]
+fn test_strlib_error() {
+    let err = vm_exec_error!("string.rep()");
+    assert_eq!(err.msg(), "bad argument #1 to 'rep' (string expected, got no value)");
+
+    let err = vm_exec_error!("string.sub('abc', {})");
+    assert_eq!(err.msg(), "bad argument #2 to 'sub' (number expected, got table)");
+
+    let err = vm_exec_error!("string.char(256)");
+    assert_eq!(err.msg(), "bad argument #1 to 'char' (value out of range)");
+
+    let err = vm_exec_error!("string.find('abc', '[a')");
+    assert_eq!(err.msg(), "malformed pattern (missing ']')");
+
+    let err = vm_exec_error!("string.find('abc', '%')");
+    assert_eq!(err.msg(), "malformed pattern (ends with '%')");
+
+    let err = vm_exec_error!("string.find('abc', '(a')");
+    assert_eq!(err.msg(), "unfinished capture");
+
+    let err = vm_exec_error!("string.gsub('abc', 'a', '%2')");
+    assert_eq!(err.msg(), "invalid capture index %2");
+
+    let err = vm_exec_error!("string.format('%d', 1.5)");
+    assert_eq!(err.msg(), "bad argument #2 to 'format' (number has no integer representation)");
+
+    let err = vm_exec_error!("string.format('%d')");
+    assert_eq!(err.msg(), "bad argument #2 to 'format' (no value)");
+
+    let err = vm_exec_error!("string.format('%y', 1)");
+    assert_eq!(err.msg(), "invalid conversion '%y' to 'format'");
+
+    let err = vm_exec_error!("string.pack('i17', 1)");
+    assert_eq!(err.msg(), "integral size (17) out of limits [1,16]");
+
+    let err = vm_exec_error!("string.pack('i1', 200)");
+    assert_eq!(err.msg(), "bad argument #2 to 'pack' (integer overflow)");
+}
@@ -0,0 +1,30 @@
+use std::fs::File;
+use luar::{vm, parse};
+use luar::{vm_exec_input, vm_exec_error};
+
+#[test]
+fn test_tablib() {
+    let file = File::open("./tests/luas/tablib.lua").unwrap();
+    vm_exec_input!(file);
+}
+
+#[test]
+fn test_tablib_error() {
+    let err = vm_exec_error!("table.insert({}, 5, 1)");
+    assert_eq!(err.msg(), "bad argument #2 to 'insert' (position out of bounds)");
+
+    let err = vm_exec_error!("table.insert({}, 1, 2, 3)");
+    assert_eq!(err.msg(), "wrong number of arguments to 'insert'");
+
+    let err = vm_exec_error!("table.concat({1, {}, 3})");
+    assert_eq!(err.msg(), "invalid value (at index 2) in table for 'concat'");
+
+    let err = vm_exec_error!("table.sort({3, 'a', 1})");
+    assert_eq!(err.msg(), "attempt to compare string with number");
+
+    let err = vm_exec_error!("table.sort({1, 2, 3}, 1)");
+    assert_eq!(err.msg(), "bad argument #2 to 'sort' (function expected, got number)");
+
+    let err = vm_exec_error!("local t = {} for i = 1, 200 do t[i] = i end table.sort(t, function() return true end)");
+    assert_eq!(err.msg(), "invalid order function for sorting");
+}
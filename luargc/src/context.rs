@@ -1,5 +1,133 @@
+use std::mem;
+use std::rc::{Rc, Weak};
+use crate::gc::{Gc, GcBox, GcCell, GcCellBox};
+use crate::trace::{addr_of, Collect, Phase, Trace, Tracer};
 
+// collect automatically when the number of objects grows to this
+// times of that after last collection
+const GC_PAUSE: usize = 2;
+const GC_MIN_THRESHOLD: usize = 1024;
 
-struct Context {
-    
-}
\ No newline at end of file
+struct Object {
+    ptr: Weak<dyn Collect>,
+    size: usize,
+}
+
+// the heap, which allocates and collects objects
+pub struct Context {
+    objects: Vec<Object>,
+    threshold: usize,
+    auto: bool,
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Context {
+    pub fn new() -> Self {
+        Context {
+            objects: Vec::new(),
+            threshold: GC_MIN_THRESHOLD,
+            auto: true,
+        }
+    }
+
+    pub fn alloc<T: Trace + 'static>(&mut self, v: T) -> Gc<T> {
+        let rc = Rc::new(GcBox(v));
+        self.add(rc.clone(), mem::size_of::<GcBox<T>>());
+        Gc(rc)
+    }
+
+    pub fn alloc_cell<T: Trace + Default + 'static>(&mut self, v: T) -> GcCell<T> {
+        let rc = Rc::new(GcCellBox(v.into()));
+        self.add(rc.clone(), mem::size_of::<GcCellBox<T>>());
+        GcCell(rc)
+    }
+
+    fn add(&mut self, rc: Rc<dyn Collect>, size: usize) {
+        if self.auto && self.objects.len() >= self.threshold {
+            self.collect();
+        }
+        self.objects.push(Object { ptr: Rc::downgrade(&rc), size });
+    }
+
+    // enable or disable automatic collection
+    pub fn set_auto(&mut self, auto: bool) {
+        self.auto = auto;
+    }
+    pub fn is_auto(&self) -> bool {
+        self.auto
+    }
+
+    // number of live objects
+    pub fn len(&self) -> usize {
+        self.objects.iter().filter(|o| o.ptr.strong_count() > 0).count()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // estimated memory in use, in bytes
+    pub fn memory(&self) -> usize {
+        self.objects.iter()
+            .filter(|o| o.ptr.strong_count() > 0)
+            .map(|o| o.size)
+            .sum()
+    }
+
+    // full collection, return the number of freed objects
+    //
+    // A mutably borrowed `GcCell` can not be traced, so it is kept as
+    // a root, with all objects it refers to. The others are collected
+    // as usual.
+    pub fn collect(&mut self) -> usize {
+        // remove freed objects
+        self.objects.retain(|o| o.ptr.strong_count() > 0);
+        let before = self.objects.len();
+
+        // count the references from inside heap
+        let mut tracer = Tracer::new();
+        for o in self.objects.iter() {
+            if let Some(rc) = o.ptr.upgrade() {
+                rc.trace_inner(&mut tracer);
+            }
+        }
+
+        // roots are objects with references from outside heap, and
+        // the pinned ones
+        tracer.phase = Phase::Mark;
+        let counts = mem::take(&mut tracer.counts);
+        let pinned = mem::take(&mut tracer.pinned);
+        for o in self.objects.iter() {
+            let addr = addr_of(o.ptr.as_ptr());
+            let internal = counts.get(&addr).copied().unwrap_or(0);
+            let root = o.ptr.strong_count() > internal || pinned.contains(&addr);
+            if root && tracer.marked.insert(addr) {
+                if let Some(rc) = o.ptr.upgrade() {
+                    tracer.pending.push(rc);
+                }
+            }
+        }
+
+        // mark all reachable objects
+        while let Some(rc) = tracer.pending.pop() {
+            rc.trace_inner(&mut tracer);
+        }
+
+        // sweep: clear the unreachable objects to break the cycles
+        for o in self.objects.iter() {
+            if !tracer.marked.contains(&addr_of(o.ptr.as_ptr())) {
+                if let Some(rc) = o.ptr.upgrade() {
+                    rc.clear();
+                }
+            }
+        }
+
+        self.objects.retain(|o| o.ptr.strong_count() > 0);
+        self.threshold = (self.objects.len() * GC_PAUSE).max(GC_MIN_THRESHOLD);
+        before - self.objects.len()
+    }
+}
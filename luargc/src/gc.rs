@@ -0,0 +1,112 @@
+use std::cell::{Ref, RefCell, RefMut};
+use std::fmt;
+use std::ops::Deref;
+use std::rc::Rc;
+use crate::trace::{addr_of, Collect, Trace, Tracer};
+
+// immutable garbage collected pointer, allocated by `Context::alloc()`
+pub struct Gc<T: Trace + 'static>(pub(crate) Rc<GcBox<T>>);
+
+pub(crate) struct GcBox<T>(pub(crate) T);
+
+impl<T: Trace> Collect for GcBox<T> {
+    fn trace_inner(&self, tracer: &mut Tracer) {
+        self.0.trace(tracer);
+    }
+    fn clear(&self) {
+        // nothing to do, because immutable objects can not be part of
+        // a reference cycle by themselves. The cycle must go through
+        // some `GcCell`, which will be cleared.
+    }
+}
+
+impl<T: Trace> Gc<T> {
+    pub fn as_ptr(&self) -> *const T {
+        &self.0.0
+    }
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        Rc::ptr_eq(&this.0, &other.0)
+    }
+}
+
+impl<T: Trace> Deref for Gc<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0.0
+    }
+}
+
+impl<T: Trace> Clone for Gc<T> {
+    fn clone(&self) -> Self {
+        Gc(self.0.clone())
+    }
+}
+
+impl<T: Trace> Trace for Gc<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.visit(&self.0);
+    }
+}
+
+impl<T: Trace + fmt::Debug> fmt::Debug for Gc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.0.fmt(f)
+    }
+}
+
+// mutable garbage collected pointer, allocated by `Context::alloc_cell()`
+//
+// The value will be reset to default if it's collected as part of
+// a reference cycle.
+pub struct GcCell<T: Trace + Default + 'static>(pub(crate) Rc<GcCellBox<T>>);
+
+pub(crate) struct GcCellBox<T>(pub(crate) RefCell<T>);
+
+impl<T: Trace + Default> Collect for GcCellBox<T> {
+    fn trace_inner(&self, tracer: &mut Tracer) {
+        match self.0.try_borrow() {
+            Ok(v) => v.trace(tracer),
+            Err(_) => tracer.pin(addr_of(self)),
+        }
+    }
+    fn clear(&self) {
+        let v = self.0.take();
+        drop(v); // this may free other objects
+    }
+}
+
+impl<T: Trace + Default> GcCell<T> {
+    pub fn borrow(&self) -> Ref<'_, T> {
+        self.0.0.borrow()
+    }
+    pub fn borrow_mut(&self) -> RefMut<'_, T> {
+        self.0.0.borrow_mut()
+    }
+    pub fn replace(&self, v: T) -> T {
+        self.0.0.replace(v)
+    }
+    pub fn as_ptr(&self) -> *const T {
+        self.0.0.as_ptr()
+    }
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        Rc::ptr_eq(&this.0, &other.0)
+    }
+}
+
+impl<T: Trace + Default> Clone for GcCell<T> {
+    fn clone(&self) -> Self {
+        GcCell(self.0.clone())
+    }
+}
+
+impl<T: Trace + Default> Trace for GcCell<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.visit(&self.0);
+    }
+}
+
+impl<T: Trace + Default + fmt::Debug> fmt::Debug for GcCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.0.fmt(f)
+    }
+}
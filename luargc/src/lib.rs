@@ -1,5 +1,26 @@
+// A garbage collector for Luar.
+//
+// Objects are allocated by `Context` and referred by `Gc<T>` (immutable)
+// or `GcCell<T>` (mutable). They are reference counted, so most garbage
+// is freed as soon as the last reference is dropped. The `Context` keeps
+// track of all allocated objects to collect the reference cycles, which
+// the reference counting can not free.
+//
+// The collection is a mark-and-sweep. Roots are the objects referred
+// from outside of the heap, e.g. by the VM stack or by local variables
+// in Rust code. They are found without explicit registration, by
+// comparing each object's strong count with the number of references
+// from other objects in the heap. Objects not reachable from roots are
+// cleared, which breaks the cycles and frees them. A mutably borrowed
+// `GcCell`, which can not be traced, is taken as a root.
+//
+// Use `#[derive(Trace)]` to implement `Trace` for your types.
 
+mod gc;
+mod trace;
+mod context;
 
-pub fn add(a:u8, b:u8) -> u8 {
-    a+b
-}
\ No newline at end of file
+pub use gc::{Gc, GcCell};
+pub use trace::{Trace, Tracer, NoDrop};
+pub use luargc_derive::Trace;
+pub use context::Context;
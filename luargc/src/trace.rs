@@ -0,0 +1,164 @@
+use std::collections::{HashMap, HashSet, BTreeMap, VecDeque};
+use std::rc::Rc;
+
+// Types which may contain `Gc` or `GcCell` pointers.
+//
+// The `trace()` must visit all pointers owned by the value, by calling
+// `trace()` on them. Missing a pointer may make it be freed while still
+// being referred, which is safe but leaves a cleared object.
+pub trait Trace {
+    fn trace(&self, tracer: &mut Tracer);
+}
+
+// Implemented by `#[derive(Trace)]`, to reject types with `Drop`,
+// which could access cleared objects in `drop()`.
+#[doc(hidden)]
+pub trait NoDrop {}
+
+#[allow(drop_bounds)]
+impl<T: Drop> NoDrop for T {}
+
+// object in heap, type erased
+pub(crate) trait Collect {
+    fn trace_inner(&self, tracer: &mut Tracer);
+    fn clear(&self);
+}
+
+pub(crate) fn addr_of<T: ?Sized>(p: *const T) -> usize {
+    p as *const () as usize
+}
+
+// Visitor of pointers. It works in 2 phases:
+// - Count: count the references to each object from inside heap;
+// - Mark: mark objects reachable from roots.
+pub struct Tracer {
+    pub(crate) phase: Phase,
+    pub(crate) counts: HashMap<usize, usize>,
+    pub(crate) pinned: HashSet<usize>,
+    pub(crate) marked: HashSet<usize>,
+    pub(crate) pending: Vec<Rc<dyn Collect>>,
+}
+
+#[derive(PartialEq)]
+pub(crate) enum Phase {
+    Count,
+    Mark,
+}
+
+impl Tracer {
+    pub(crate) fn new() -> Self {
+        Tracer {
+            phase: Phase::Count,
+            counts: HashMap::new(),
+            pinned: HashSet::new(),
+            marked: HashSet::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    pub(crate) fn visit<T: Collect + 'static>(&mut self, obj: &Rc<T>) {
+        let addr = addr_of(Rc::as_ptr(obj));
+        match self.phase {
+            Phase::Count => *self.counts.entry(addr).or_insert(0) += 1,
+            Phase::Mark => if self.marked.insert(addr) {
+                self.pending.push(obj.clone());
+            }
+        }
+    }
+
+    // the object at @addr can not be traced now, e.g. mutably borrowed,
+    // so keep it as a root. The objects it refers to are roots too,
+    // since their references from it are not counted.
+    pub(crate) fn pin(&mut self, addr: usize) {
+        self.pinned.insert(addr);
+    }
+}
+
+// values without pointers
+macro_rules! empty_trace {
+    ($($t:ty),*) => {
+        $(
+            impl Trace for $t {
+                fn trace(&self, _: &mut Tracer) {}
+            }
+        )*
+    };
+}
+empty_trace!((), bool, char, u8, u16, u32, u64, u128, usize,
+    i8, i16, i32, i64, i128, isize, f32, f64, String, str);
+
+impl<T: Trace + ?Sized> Trace for Box<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        (**self).trace(tracer);
+    }
+}
+
+impl<T: Trace> Trace for Option<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Some(v) = self {
+            v.trace(tracer);
+        }
+    }
+}
+
+impl<T: Trace> Trace for [T] {
+    fn trace(&self, tracer: &mut Tracer) {
+        for v in self.iter() {
+            v.trace(tracer);
+        }
+    }
+}
+
+impl<T: Trace, const N: usize> Trace for [T; N] {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.as_slice().trace(tracer);
+    }
+}
+
+impl<T: Trace> Trace for Vec<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.as_slice().trace(tracer);
+    }
+}
+
+impl<T: Trace> Trace for VecDeque<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        for v in self.iter() {
+            v.trace(tracer);
+        }
+    }
+}
+
+impl<K: Trace, V: Trace, S> Trace for HashMap<K, V, S> {
+    fn trace(&self, tracer: &mut Tracer) {
+        for (k, v) in self.iter() {
+            k.trace(tracer);
+            v.trace(tracer);
+        }
+    }
+}
+
+impl<K: Trace, V: Trace> Trace for BTreeMap<K, V> {
+    fn trace(&self, tracer: &mut Tracer) {
+        for (k, v) in self.iter() {
+            k.trace(tracer);
+            v.trace(tracer);
+        }
+    }
+}
+
+macro_rules! tuple_trace {
+    ($($t:ident),*) => {
+        impl<$($t: Trace),*> Trace for ($($t,)*) {
+            #[allow(non_snake_case)]
+            fn trace(&self, tracer: &mut Tracer) {
+                let ($($t,)*) = self;
+                $($t.trace(tracer);)*
+            }
+        }
+    };
+}
+tuple_trace!(A);
+tuple_trace!(A, B);
+tuple_trace!(A, B, C);
+tuple_trace!(A, B, C, D);
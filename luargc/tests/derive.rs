@@ -0,0 +1,88 @@
+use std::rc::Rc;
+use std::cell::Cell;
+use luargc::{Context, GcCell, Trace};
+
+#[derive(Trace, Default)]
+struct Entity {
+    name: String,
+    parent: Option<GcCell<Entity>>,
+    children: Vec<GcCell<Entity>>,
+    #[trace(skip)]
+    drops: Option<Rc<DropCounter>>,
+}
+
+#[derive(Trace, Default)]
+enum Slot {
+    #[default]
+    Empty,
+    One(GcCell<Entity>),
+    Pair { a: GcCell<Entity>, b: GcCell<Entity> },
+}
+
+#[derive(Trace, Default)]
+struct Wrapper<T>(T, #[trace(skip)] usize);
+
+// the counter is not traced, so it may implement Drop
+struct DropCounter(Rc<Cell<usize>>);
+
+impl Drop for DropCounter {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() + 1);
+    }
+}
+
+fn new_entity(cx: &mut Context, drops: &Rc<Cell<usize>>) -> GcCell<Entity> {
+    let counter = Rc::new(DropCounter(drops.clone()));
+    cx.alloc_cell(Entity {
+        name: "e".into(),
+        drops: Some(counter),
+        ..Default::default()
+    })
+}
+
+#[test]
+fn derive_struct() {
+    let drops = Rc::new(Cell::new(0));
+    let mut cx = Context::new();
+
+    let parent = new_entity(&mut cx, &drops);
+    for _ in 0..3 {
+        let child = new_entity(&mut cx, &drops);
+        child.borrow_mut().parent = Some(parent.clone());
+        parent.borrow_mut().children.push(child);
+    }
+    assert_eq!(cx.collect(), 0);
+    assert_eq!(parent.borrow().children.len(), 3);
+    assert_eq!(parent.borrow().name, "e");
+
+    drop(parent);
+    assert_eq!(cx.collect(), 4);
+    assert_eq!(drops.get(), 4);
+}
+
+#[test]
+fn derive_enum_and_generic() {
+    let drops = Rc::new(Cell::new(0));
+    let mut cx = Context::new();
+
+    let a = new_entity(&mut cx, &drops);
+    let b = new_entity(&mut cx, &drops);
+    let pair = cx.alloc_cell(Slot::Pair { a: a.clone(), b: b.clone() });
+    let one = cx.alloc_cell(Slot::One(a.clone()));
+    let wrapper = cx.alloc_cell(Wrapper(Some(pair.clone()), 0));
+    drop((a, b, pair, one));
+
+    // entities are referred by `wrapper` through `pair`
+    assert_eq!(cx.collect(), 0);
+    assert_eq!(drops.get(), 0);
+
+    drop(wrapper);
+    assert_eq!(drops.get(), 2);
+    assert_eq!(cx.len(), 0);
+}
+
+#[test]
+fn compile_fail() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
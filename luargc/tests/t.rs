@@ -1,6 +1,105 @@
-    use luargc::add;
-    #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
-    }
\ No newline at end of file
+use std::rc::Rc;
+use std::cell::Cell;
+use luargc::{Context, Gc, GcCell, Trace, Tracer};
+
+#[derive(Default)]
+struct Node {
+    next: Option<GcCell<Node>>,
+    drops: Option<Rc<Cell<usize>>>, // count the drops
+}
+
+impl Trace for Node {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.next.trace(tracer);
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        if let Some(drops) = &self.drops {
+            drops.set(drops.get() + 1);
+        }
+    }
+}
+
+fn new_node(cx: &mut Context, drops: &Rc<Cell<usize>>) -> GcCell<Node> {
+    cx.alloc_cell(Node { next: None, drops: Some(drops.clone()) })
+}
+
+#[test]
+fn collect_cycle() {
+    let drops = Rc::new(Cell::new(0));
+    let mut cx = Context::new();
+
+    let a = new_node(&mut cx, &drops);
+    let b = new_node(&mut cx, &drops);
+    a.borrow_mut().next = Some(b.clone());
+    b.borrow_mut().next = Some(a.clone());
+    drop((a, b));
+
+    assert_eq!(drops.get(), 0); // leaked by reference counting
+    assert_eq!(cx.len(), 2);
+    assert_eq!(cx.collect(), 2);
+    assert_eq!(drops.get(), 2);
+    assert!(cx.is_empty());
+}
+
+#[test]
+fn keep_rooted() {
+    let drops = Rc::new(Cell::new(0));
+    let mut cx = Context::new();
+
+    // cycle referred by a local variable
+    let a = new_node(&mut cx, &drops);
+    let b = new_node(&mut cx, &drops);
+    a.borrow_mut().next = Some(b.clone());
+    b.borrow_mut().next = Some(a.clone());
+    drop(b);
+
+    // chain referred by an immutable object
+    let c = new_node(&mut cx, &drops);
+    let holder: Gc<Vec<GcCell<Node>>> = cx.alloc(vec![c.clone()]);
+    drop(c);
+
+    assert_eq!(cx.collect(), 0);
+    assert_eq!(drops.get(), 0);
+    assert!(a.borrow().next.is_some());
+    assert!(holder[0].borrow().next.is_none());
+
+    drop(a);
+    assert_eq!(cx.collect(), 2);
+    assert_eq!(drops.get(), 2);
+
+    drop(holder);
+    assert_eq!(drops.get(), 3);
+}
+
+#[test]
+fn keep_borrowed() {
+    let drops = Rc::new(Cell::new(0));
+    let mut cx = Context::new();
+
+    // a -> b -> a, with a mutably borrowed
+    let a = new_node(&mut cx, &drops);
+    let b = new_node(&mut cx, &drops);
+    a.borrow_mut().next = Some(b.clone());
+    b.borrow_mut().next = Some(a.clone());
+    drop(b);
+    let a2 = a.clone();
+    let guard = a2.borrow_mut();
+    drop(a);
+
+    // other cycles are still collected
+    let c = new_node(&mut cx, &drops);
+    c.borrow_mut().next = Some(c.clone());
+    drop(c);
+
+    assert_eq!(cx.collect(), 1);
+    assert_eq!(drops.get(), 1);
+    assert!(guard.next.as_ref().unwrap().borrow().next.is_some());
+
+    drop(guard);
+    drop(a2);
+    assert_eq!(cx.collect(), 2);
+    assert_eq!(drops.get(), 3);
+}
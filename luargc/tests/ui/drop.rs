@@ -0,0 +1,16 @@
+use luargc::{GcCell, Trace};
+
+#[derive(Trace, Default)]
+struct Resurrect {
+    next: Option<GcCell<Resurrect>>,
+}
+
+impl Drop for Resurrect {
+    fn drop(&mut self) {
+        if let Some(next) = &self.next {
+            next.borrow_mut();
+        }
+    }
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use luargc::Trace;
+
+#[derive(Trace)]
+union Bits {
+    i: u32,
+    f: f32,
+}
+
+fn main() {}
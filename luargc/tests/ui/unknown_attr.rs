@@ -0,0 +1,9 @@
+use luargc::Trace;
+
+#[derive(Trace)]
+struct Config {
+    #[trace(ignore)]
+    path: String,
+}
+
+fn main() {}
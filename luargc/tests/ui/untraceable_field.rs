@@ -0,0 +1,9 @@
+use std::fs::File;
+use luargc::Trace;
+
+#[derive(Trace)]
+struct Handle {
+    file: File,
+}
+
+fn main() {}
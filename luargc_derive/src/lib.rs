@@ -1,14 +1,113 @@
-pub fn add(left: usize, right: usize) -> usize {
-    left + right
+// #[derive(Trace)] for luargc
+//
+// All fields are traced except those marked by `#[trace(skip)]`, which
+// must not contain any `Gc` or `GcCell` pointers. Types with `Drop` are
+// rejected, because `drop()` could access objects which have been
+// cleared by the collector.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote, quote_spanned};
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Fields, Error};
+use syn::spanned::Spanned;
+
+#[proc_macro_derive(Trace, attributes(trace))]
+pub fn derive_trace(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(ts) => ts.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+fn expand(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let (pattern, traces) = trace_fields(&data.fields)?;
+            quote! {
+                let #name #pattern = self;
+                #(#traces)*
+            }
+        }
+        Data::Enum(data) => {
+            let mut arms = Vec::new();
+            for variant in data.variants.iter() {
+                let vname = &variant.ident;
+                let (pattern, traces) = trace_fields(&variant.fields)?;
+                arms.push(quote! {
+                    #name::#vname #pattern => { #(#traces)* }
+                });
+            }
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => {
+            return Err(Error::new_spanned(&input.ident, "Trace can not be derived for unions"));
+        }
+    };
+
+    // trace generic type parameters
+    for param in input.generics.type_params_mut() {
+        param.bounds.push(parse_quote!(::luargc::Trace));
+    }
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::luargc::Trace for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn trace(&self, tracer: &mut ::luargc::Tracer) {
+                #body
+            }
+        }
+
+        // conflicts with `impl<T: Drop> NoDrop for T` if #name has Drop
+        impl #impl_generics ::luargc::NoDrop for #name #ty_generics #where_clause {}
+    })
+}
+
+// return destructuring pattern of @fields, and trace statements
+fn trace_fields(fields: &Fields) -> syn::Result<(TokenStream2, Vec<TokenStream2>)> {
+    let mut bindings = Vec::new();
+    let mut traces = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        // not use field names directly, which may conflict with `tracer`
+        let binding = format_ident!("__binding_{}", i);
+        if !is_skipped(field)? {
+            // report untraceable field at its type
+            traces.push(quote_spanned! {field.ty.span()=>
+                ::luargc::Trace::trace(#binding, tracer);
+            });
+        }
+        bindings.push(match &field.ident {
+            Some(ident) => quote! { #ident: #binding },
+            None => quote! { #binding },
+        });
+    }
+
+    let pattern = match fields {
+        Fields::Named(_) => quote! { { #(#bindings),* } },
+        Fields::Unnamed(_) => quote! { ( #(#bindings),* ) },
+        Fields::Unit => quote! {},
+    };
+    Ok((pattern, traces))
+}
 
-    #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
+fn is_skipped(field: &syn::Field) -> syn::Result<bool> {
+    let mut skip = false;
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("trace")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                skip = true;
+                Ok(())
+            } else {
+                Err(meta.error("unknown trace attribute, expected `skip`"))
+            }
+        })?;
     }
+    Ok(skip)
 }